@@ -1,3 +1,12 @@
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use md5::Md5;
+
+use crate::error::Result;
+use crate::secret::Secret;
+use crate::types::Capabilities;
+
 /// This trait allows for pluggable authentication schemes. It is used by `Client::authenticate` to
 /// [authenticate using SASL](https://tools.ietf.org/html/rfc3501#section-6.2.2).
 pub trait Authenticator {
@@ -6,5 +15,215 @@ pub trait Authenticator {
 
     /// Each base64-decoded server challenge is passed to `process`.
     /// The returned byte-string is base64-encoded and then sent back to the server.
+    ///
+    /// This is called once for every continuation request until the server completes the
+    /// `AUTHENTICATE` command, so mechanisms with several rounds such as `CRAM-MD5` or `SCRAM`
+    /// keep their state between calls. An empty continuation (`+ `) is passed as an empty
+    /// challenge, e.g. for the initial response of a client-first mechanism.
     fn process(&mut self, challenge: &[u8]) -> Self::Response;
+
+    /// Called after each challenge was processed. An error cancels the exchange, and
+    /// `Client::authenticate` fails with it. By default, the exchange always goes on.
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once the server accepted the credentials. Mechanisms that authenticate the server
+    /// as well return an error here if it did not, and `Client::authenticate` fails with it.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// An authentication mechanism that [`Client::authenticate_auto`](crate::Client::authenticate_auto)
+/// can pick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AuthMechanism {
+    /// The [`XOAUTH2` SASL mechanism](https://developers.google.com/gmail/imap/xoauth2-protocol),
+    /// which sends an OAuth 2.0 access token. Requires `AUTH=XOAUTH2`.
+    XOAuth2,
+    /// The [`SCRAM-SHA-256` SASL mechanism](https://tools.ietf.org/html/rfc7677), see
+    /// [`ScramSha256`](crate::ScramSha256). Requires `AUTH=SCRAM-SHA-256`.
+    ScramSha256,
+    /// The [`PLAIN` SASL mechanism](https://tools.ietf.org/html/rfc4616), which sends the
+    /// password. Requires `AUTH=PLAIN`.
+    Plain,
+    /// The [`CRAM-MD5` SASL mechanism](https://tools.ietf.org/html/rfc2195), which proves
+    /// knowledge of the password with an HMAC-MD5 digest of a server challenge. It is weak and
+    /// requires the server to store the password in the clear, but some servers offer nothing
+    /// else. Requires `AUTH=CRAM-MD5`.
+    CramMd5,
+    /// The [`LOGIN` command](https://tools.ietf.org/html/rfc3501#section-6.2.3), which sends the
+    /// password. Available unless the server advertises `LOGINDISABLED`.
+    Login,
+}
+
+impl AuthMechanism {
+    /// The order [`Client::authenticate_auto`](crate::Client::authenticate_auto) tries the
+    /// mechanisms in: tokens before passwords, mechanisms that do not send the password before
+    /// those that do, and SASL before the `LOGIN` command. The weak `CRAM-MD5` is only used if
+    /// `LOGIN` is all that is left.
+    pub const DEFAULT_PREFERENCE: &'static [AuthMechanism] = &[
+        AuthMechanism::XOAuth2,
+        AuthMechanism::ScramSha256,
+        AuthMechanism::Plain,
+        AuthMechanism::CramMd5,
+        AuthMechanism::Login,
+    ];
+
+    /// The capability the server advertises the mechanism with, e.g. `AUTH=PLAIN`.
+    pub fn capability(self) -> &'static str {
+        match self {
+            AuthMechanism::XOAuth2 => "AUTH=XOAUTH2",
+            AuthMechanism::ScramSha256 => "AUTH=SCRAM-SHA-256",
+            AuthMechanism::Plain => "AUTH=PLAIN",
+            AuthMechanism::CramMd5 => "AUTH=CRAM-MD5",
+            AuthMechanism::Login => "LOGIN",
+        }
+    }
+
+    /// Whether a server with `capabilities` supports the mechanism.
+    pub(crate) fn is_supported(self, capabilities: &Capabilities) -> bool {
+        match self {
+            AuthMechanism::Login => !capabilities.has_str("LOGINDISABLED"),
+            _ => capabilities.has_str(self.capability()),
+        }
+    }
+}
+
+/// What a user can authenticate with, for
+/// [`Client::authenticate_auto`](crate::Client::authenticate_auto).
+pub struct Credentials {
+    pub(crate) username: String,
+    pub(crate) password: Option<Secret<String>>,
+    pub(crate) access_token: Option<Secret<String>>,
+}
+
+impl Credentials {
+    /// Creates credentials for `username` without any secrets yet.
+    pub fn new<U: Into<String>>(username: U) -> Self {
+        Credentials {
+            username: username.into(),
+            password: None,
+            access_token: None,
+        }
+    }
+
+    /// Adds a password, used with [`AuthMechanism::ScramSha256`], [`AuthMechanism::Plain`],
+    /// [`AuthMechanism::CramMd5`] and [`AuthMechanism::Login`].
+    pub fn password<P: Into<String>>(mut self, password: P) -> Self {
+        self.password = Some(Secret(password.into()));
+        self
+    }
+
+    /// Adds an OAuth 2.0 access token, used with [`AuthMechanism::XOAuth2`].
+    pub fn access_token<S: Into<String>>(mut self, token: S) -> Self {
+        self.access_token = Some(Secret(token.into()));
+        self
+    }
+
+    /// Whether the credentials hold what `mechanism` needs.
+    pub(crate) fn allow(&self, mechanism: AuthMechanism) -> bool {
+        match mechanism {
+            AuthMechanism::XOAuth2 => self.access_token.is_some(),
+            AuthMechanism::ScramSha256
+            | AuthMechanism::Plain
+            | AuthMechanism::CramMd5
+            | AuthMechanism::Login => self.password.is_some(),
+        }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field(
+                "access_token",
+                &self.access_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+/// The built-in [`Authenticator`] for `AUTH=PLAIN`.
+pub(crate) struct PlainAuthenticator<'a> {
+    pub(crate) username: &'a str,
+    pub(crate) password: &'a str,
+}
+
+impl Authenticator for PlainAuthenticator<'_> {
+    type Response = Vec<u8>;
+
+    fn process(&mut self, _: &[u8]) -> Self::Response {
+        format!("\0{}\0{}", self.username, self.password).into_bytes()
+    }
+}
+
+/// The built-in [`Authenticator`] for `AUTH=CRAM-MD5`.
+pub(crate) struct CramMd5Authenticator<'a> {
+    pub(crate) username: &'a str,
+    pub(crate) password: &'a str,
+}
+
+impl Authenticator for CramMd5Authenticator<'_> {
+    type Response = Vec<u8>;
+
+    fn process(&mut self, challenge: &[u8]) -> Self::Response {
+        let mut mac = Hmac::<Md5>::new_from_slice(self.password.as_bytes())
+            .expect("HMAC takes keys of any size");
+        mac.update(challenge);
+        let digest: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        format!("{} {}", self.username, digest).into_bytes()
+    }
+}
+
+/// The built-in [`Authenticator`] for `AUTH=XOAUTH2`.
+pub(crate) struct XOAuth2Authenticator<'a> {
+    pub(crate) username: &'a str,
+    pub(crate) access_token: &'a str,
+    pub(crate) sent: bool,
+}
+
+impl Authenticator for XOAuth2Authenticator<'_> {
+    type Response = Vec<u8>;
+
+    fn process(&mut self, _: &[u8]) -> Self::Response {
+        // A second challenge carries the error details; an empty response makes the server
+        // complete the command with NO.
+        if std::mem::replace(&mut self.sent, true) {
+            return Vec::new();
+        }
+        format!(
+            "user={}\x01auth=Bearer {}\x01\x01",
+            self.username, self.access_token
+        )
+        .into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cram_md5() {
+        // The example exchange of RFC 2195, section 2.
+        let mut authenticator = CramMd5Authenticator {
+            username: "tim",
+            password: "tanstaaftanstaaf",
+        };
+        let response = authenticator.process(b"<1896.697170952@postoffice.reston.mci.net>");
+        assert_eq!(
+            base64::encode(response),
+            "dGltIGI5MTNhNjAyYzdlZGE3YTQ5NWI0ZTZlNzMzNGQzODkw"
+        );
+    }
 }
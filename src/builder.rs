@@ -0,0 +1,771 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use async_native_tls::{TlsConnector, TlsStream};
+use async_std::io::{self, Read, Write};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::types::UnsolicitedPolicy;
+
+/// Configures and opens a connection to an IMAP server, as an alternative to [`connect`] for
+/// setups that need more than a direct TLS connection.
+///
+/// ```no_run
+/// use async_imap::{ClientBuilder, Proxy};
+///
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// let client = ClientBuilder::new("imap.example.org", 993)
+///     .proxy(Proxy::socks5("proxy.example.org:1080").credentials("user", "pass"))
+///     .connect()
+///     .await?;
+/// # Ok(())
+/// # }) }
+/// ```
+///
+/// [`connect`]: crate::connect
+#[derive(Debug)]
+pub struct ClientBuilder {
+    host: String,
+    port: u16,
+    starttls: bool,
+    starttls_strict: bool,
+    tls: Option<TlsConnector>,
+    proxy: Option<Proxy>,
+    unsolicited_capacity: Option<usize>,
+    unsolicited_policy: Option<UnsolicitedPolicy>,
+    keepalive: Option<TcpKeepalive>,
+    greeting_timeout: Option<Duration>,
+}
+
+impl ClientBuilder {
+    /// Creates a builder for a connection to the IMAP server at `host` and `port`. By default the
+    /// connection uses implicit TLS (usually on port 993) and validates the server certificate
+    /// against `host`.
+    pub fn new<S: Into<String>>(host: S, port: u16) -> Self {
+        ClientBuilder {
+            host: host.into(),
+            port,
+            starttls: false,
+            starttls_strict: true,
+            tls: None,
+            proxy: None,
+            unsolicited_capacity: None,
+            unsolicited_policy: None,
+            keepalive: None,
+            greeting_timeout: None,
+        }
+    }
+
+    /// Connects in plaintext (usually on port 143) and upgrades the connection with the
+    /// [`STARTTLS` command](https://tools.ietf.org/html/rfc3501#section-6.2.1) instead of using
+    /// implicit TLS.
+    ///
+    /// By default, connecting fails with [`Error::MissingCapability`] if the server does not
+    /// advertise `STARTTLS`, see [`Client::starttls`]. Earlier versions sent the command
+    /// regardless, which [`ClientBuilder::starttls_strict`] can go back to.
+    pub fn starttls(mut self) -> Self {
+        self.starttls = true;
+        self
+    }
+
+    /// Sets whether connecting with [`ClientBuilder::starttls`] fails if the server does not
+    /// advertise `STARTTLS`, which protects against a man in the middle stripping it from the
+    /// capabilities. Turned off, the command is sent regardless, as with [`Client::secure`], for
+    /// servers that support it without advertising it. Either way the connection never goes on
+    /// in plaintext.
+    ///
+    /// This is on by default.
+    pub fn starttls_strict(mut self, strict: bool) -> Self {
+        self.starttls_strict = strict;
+        self
+    }
+
+    /// Uses `tls` for the TLS handshake instead of a default [`TlsConnector`].
+    pub fn tls(mut self, tls: TlsConnector) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Dials the server through `proxy`. TLS, implicit or with `STARTTLS`, is layered on top of
+    /// the proxied stream, so the certificate is still validated against the IMAP server's host.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Sets how many unsolicited responses the session keeps, see
+    /// [`Client::set_unsolicited_capacity`].
+    pub fn unsolicited_capacity(mut self, capacity: usize) -> Self {
+        self.unsolicited_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets what happens to the unsolicited responses of the session, see
+    /// [`Client::set_unsolicited_policy`].
+    pub fn unsolicited_policy(mut self, policy: UnsolicitedPolicy) -> Self {
+        self.unsolicited_policy = Some(policy);
+        self
+    }
+
+    /// Enables TCP keepalive on the socket, so that the operating system notices when the
+    /// server can no longer be reached, e.g. because a NAT dropped a connection that was idle.
+    /// Reads from such a connection then fail instead of waiting forever.
+    pub fn tcp_keepalive(mut self, keepalive: TcpKeepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Fails connecting with [`Error::Timeout`] if the server does not send its greeting within
+    /// `timeout` after the connection was opened, instead of waiting for it forever, e.g. behind a
+    /// load balancer that accepts connections for a server that is down.
+    pub fn greeting_timeout(mut self, timeout: Duration) -> Self {
+        self.greeting_timeout = Some(timeout);
+        self
+    }
+
+    /// Opens the connection, performs the TLS handshake and reads the server greeting.
+    ///
+    /// The returned [`Client`] is unauthenticated; to access session-related methods (through
+    /// [`Session`](crate::Session)), use [`Client::login`] or [`Client::authenticate`].
+    pub async fn connect(mut self) -> Result<Client<TlsStream<TcpStream>>> {
+        let stream = self.connect_tcp().await?;
+        let tls = self.tls.take().unwrap_or_default();
+
+        let mut client = if self.starttls {
+            let mut client = Client::new(stream);
+            self.read_greeting(&mut client).await?;
+            if self.starttls_strict {
+                client.starttls(&self.host, tls).await?.0
+            } else {
+                client.secure(&self.host, tls).await?
+            }
+        } else {
+            let stream = tls.connect(&self.host, stream).await?;
+            let mut client = Client::new(stream);
+            self.read_greeting(&mut client).await?;
+            client
+        };
+        if let Some(capacity) = self.unsolicited_capacity {
+            client.set_unsolicited_capacity(capacity);
+        }
+        if let Some(policy) = self.unsolicited_policy {
+            client.set_unsolicited_policy(policy);
+        }
+        Ok(client)
+    }
+
+    /// Reads the server greeting, within the greeting timeout if one is set.
+    async fn read_greeting<T>(&self, client: &mut Client<T>) -> Result<()>
+    where
+        T: Read + Write + Unpin + fmt::Debug + Send,
+    {
+        match self.greeting_timeout {
+            Some(timeout) => async_std::future::timeout(timeout, client.read_greeting())
+                .await
+                .map_err(|_| Error::Timeout)?,
+            None => client.read_greeting().await,
+        }
+    }
+
+    /// Opens a TCP connection to the server, through the proxy if one is configured, without
+    /// speaking any IMAP on it.
+    ///
+    /// This is useful to layer a different TLS implementation on top of the stream, see
+    /// [`Client::new`].
+    pub async fn connect_tcp(&self) -> Result<TcpStream> {
+        let stream = match &self.proxy {
+            None => TcpStream::connect((self.host.as_str(), self.port)).await?,
+            Some(proxy) => {
+                let mut stream = TcpStream::connect(proxy.addr.as_str()).await?;
+                match proxy.kind {
+                    ProxyKind::Socks5 => {
+                        proxy
+                            .socks5_handshake(&mut stream, &self.host, self.port)
+                            .await?
+                    }
+                    ProxyKind::HttpConnect => {
+                        proxy
+                            .http_connect_handshake(&mut stream, &self.host, self.port)
+                            .await?
+                    }
+                }
+                stream
+            }
+        };
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.apply(&stream)?;
+        }
+
+        Ok(stream)
+    }
+}
+
+/// TCP keepalive settings, see [`ClientBuilder::tcp_keepalive`]. Settings that are not set are
+/// left at the defaults of the operating system.
+///
+/// ```
+/// use std::time::Duration;
+/// use async_imap::TcpKeepalive;
+///
+/// let keepalive = TcpKeepalive::new()
+///     .idle(Duration::from_secs(60))
+///     .interval(Duration::from_secs(10))
+///     .retries(5);
+/// ```
+///
+/// The interval is ignored on platforms that cannot configure it, e.g. OpenBSD, and so is the
+/// number of retries, which Windows does not support either.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    idle: Option<Duration>,
+    interval: Option<Duration>,
+    retries: Option<u32>,
+}
+
+impl TcpKeepalive {
+    /// Creates settings that enable keepalive with the defaults of the operating system.
+    pub fn new() -> Self {
+        TcpKeepalive::default()
+    }
+
+    /// How long the connection must be idle before the first probe is sent.
+    pub fn idle(mut self, idle: Duration) -> Self {
+        self.idle = Some(idle);
+        self
+    }
+
+    /// How long to wait between probes that are not answered.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// How many probes may go unanswered before the connection is considered dead.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Enables keepalive on the socket of `stream`.
+    fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        let mut keepalive = socket2::TcpKeepalive::new();
+        if let Some(idle) = self.idle {
+            keepalive = keepalive.with_time(idle);
+        }
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux",
+            target_os = "netbsd",
+            target_vendor = "apple",
+            windows,
+        ))]
+        if let Some(interval) = self.interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux",
+            target_os = "netbsd",
+            target_vendor = "apple",
+        ))]
+        if let Some(retries) = self.retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+        socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)
+    }
+}
+
+/// A proxy to dial the IMAP server through, see [`ClientBuilder::proxy`].
+#[derive(Clone)]
+pub struct Proxy {
+    kind: ProxyKind,
+    addr: String,
+    credentials: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyKind {
+    Socks5,
+    HttpConnect,
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("kind", &self.kind)
+            .field("addr", &self.addr)
+            .field(
+                "credentials",
+                &self.credentials.as_ref().map(|_| "<hidden>"),
+            )
+            .finish()
+    }
+}
+
+impl Proxy {
+    /// A [SOCKS5](https://tools.ietf.org/html/rfc1928) proxy listening at `addr`
+    /// (`host:port`). The IMAP server's host name is resolved by the proxy.
+    pub fn socks5<S: Into<String>>(addr: S) -> Self {
+        Proxy {
+            kind: ProxyKind::Socks5,
+            addr: addr.into(),
+            credentials: None,
+        }
+    }
+
+    /// An HTTP proxy listening at `addr` (`host:port`) that supports the
+    /// [`CONNECT` method](https://tools.ietf.org/html/rfc7231#section-4.3.6).
+    pub fn http_connect<S: Into<String>>(addr: S) -> Self {
+        Proxy {
+            kind: ProxyKind::HttpConnect,
+            addr: addr.into(),
+            credentials: None,
+        }
+    }
+
+    /// Authenticates with the proxy, using [username/password
+    /// authentication](https://tools.ietf.org/html/rfc1929) for SOCKS5 and `Basic`
+    /// authentication for HTTP.
+    pub fn credentials<U: Into<String>, P: Into<String>>(
+        mut self,
+        username: U,
+        password: P,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    async fn socks5_handshake<S: Read + Write + Unpin>(
+        &self,
+        stream: &mut S,
+        host: &str,
+        port: u16,
+    ) -> Result<()> {
+        const NO_AUTH: u8 = 0x00;
+        const USERNAME_PASSWORD: u8 = 0x02;
+
+        let method = if self.credentials.is_some() {
+            USERNAME_PASSWORD
+        } else {
+            NO_AUTH
+        };
+        stream.write_all(&[0x05, 1, method]).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply != [0x05, method] {
+            return Err(proxy_error(
+                "SOCKS5 proxy rejected the authentication method",
+            ));
+        }
+
+        if let Some((username, password)) = &self.credentials {
+            let mut request = vec![0x01];
+            for field in &[username, password] {
+                let len = u8::try_from(field.len())
+                    .map_err(|_| proxy_error("SOCKS5 credentials too long"))?;
+                request.push(len);
+                request.extend_from_slice(field.as_bytes());
+            }
+            stream.write_all(&request).await?;
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0x00 {
+                return Err(proxy_error("SOCKS5 proxy rejected the credentials"));
+            }
+        }
+
+        let mut request = vec![0x05, 0x01, 0x00];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(0x01);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(0x04);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let len =
+                    u8::try_from(host.len()).map_err(|_| proxy_error("host name too long"))?;
+                request.push(0x03);
+                request.push(len);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != 0x05 || reply[1] != 0x00 {
+            return Err(proxy_error(&format!(
+                "SOCKS5 proxy could not connect to {}:{} (reply {})",
+                host, port, reply[1]
+            )));
+        }
+        // The address the proxy bound to, which is of no interest.
+        let addr_len = match reply[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).await?;
+                len[0] as usize
+            }
+            _ => return Err(proxy_error("invalid SOCKS5 reply")),
+        };
+        let mut bound = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut bound).await?;
+
+        Ok(())
+    }
+
+    async fn http_connect_handshake<S: Read + Write + Unpin>(
+        &self,
+        stream: &mut S,
+        host: &str,
+        port: u16,
+    ) -> Result<()> {
+        let target = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+            _ => format!("{}:{}", host, port),
+        };
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+        if let Some((username, password)) = &self.credentials {
+            let auth = base64::encode(format!("{}:{}", username, password));
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", auth));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read the response headers byte by byte, so that nothing the IMAP server sends is lost.
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() > 8 * 1024 {
+                return Err(proxy_error("HTTP proxy response too long"));
+            }
+            stream.read_exact(&mut byte).await?;
+            response.push(byte[0]);
+        }
+
+        let status_line = response.split(|&b| b == b'\r').next().unwrap_or_default();
+        let status = String::from_utf8_lossy(status_line);
+        match status.split(' ').nth(1) {
+            Some(code) if status.starts_with("HTTP/1.") && code.starts_with('2') => Ok(()),
+            _ => Err(proxy_error(&format!(
+                "HTTP proxy could not connect to {}: {}",
+                target, status
+            ))),
+        }
+    }
+}
+
+fn proxy_error(message: &str) -> crate::error::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, message.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::net::TcpListener;
+    use async_std::task;
+
+    use crate::mock_stream::{tls_acceptor, tls_connector};
+
+    /// Starts a mock IMAP server that greets, answers a single `NOOP` and closes the connection.
+    async fn mock_imap_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"* OK IMAP4rev1 ready\r\n").await.unwrap();
+            let mut line = String::new();
+            io::BufReader::new(&stream)
+                .read_line(&mut line)
+                .await
+                .unwrap();
+            assert_eq!(line, "A0001 NOOP\r\n");
+            stream
+                .write_all(b"A0001 OK NOOP completed\r\n")
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    /// Starts a mock IMAP server that accepts a TLS handshake and then greets.
+    async fn mock_tls_imap_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor().await.accept(stream).await.unwrap();
+            stream.write_all(b"* OK IMAP4rev1 ready\r\n").await.unwrap();
+            stream.flush().await.unwrap();
+        });
+        addr
+    }
+
+    /// Starts a mock proxy that goes through the `exchanges` of the handshake, checking each
+    /// request it receives and answering with the given reply, and then forwards all data to
+    /// `target`.
+    async fn mock_proxy(exchanges: Vec<(Vec<u8>, Vec<u8>)>, target: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        task::spawn(async move {
+            let (client, _) = listener.accept().await.unwrap();
+            for (request, reply) in exchanges {
+                let mut received = vec![0u8; request.len()];
+                (&client).read_exact(&mut received).await.unwrap();
+                assert_eq!(
+                    String::from_utf8_lossy(&received),
+                    String::from_utf8_lossy(&request)
+                );
+                (&client).write_all(&reply).await.unwrap();
+            }
+
+            let server = TcpStream::connect(target).await.unwrap();
+            let upstream = io::copy(&client, &server);
+            let downstream = io::copy(&server, &client);
+            let _ = upstream.race(downstream).await;
+        });
+        addr
+    }
+
+    /// Starts a mock IMAP server that greets, answers each of the command lines in `script` with
+    /// its reply, and then accepts a TLS handshake if `tls` is set.
+    async fn mock_script_server(script: Vec<(&'static str, &'static str)>, tls: bool) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"* OK IMAP4rev1 ready\r\n").await.unwrap();
+            for (command, reply) in script {
+                let mut line = String::new();
+                io::BufReader::new(&stream)
+                    .read_line(&mut line)
+                    .await
+                    .unwrap();
+                assert_eq!(line, format!("{}\r\n", command));
+                stream.write_all(reply.as_bytes()).await.unwrap();
+            }
+            if tls {
+                tls_acceptor().await.accept(stream).await.unwrap();
+            }
+        });
+        port
+    }
+
+    async fn noop_through(builder: ClientBuilder) {
+        let stream = builder.connect_tcp().await.unwrap();
+        let mut client = Client::new(stream);
+        client.read_greeting().await.unwrap();
+        client.run_command_and_check_ok("NOOP", None).await.unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[async_std::test]
+    async fn tcp_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let stream = ClientBuilder::new("127.0.0.1", port)
+            .tcp_keepalive(
+                TcpKeepalive::new()
+                    .idle(Duration::from_secs(60))
+                    .interval(Duration::from_secs(10))
+                    .retries(5),
+            )
+            .connect_tcp()
+            .await
+            .unwrap();
+        let socket = socket2::SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(60));
+        assert_eq!(
+            socket.keepalive_interval().unwrap(),
+            Duration::from_secs(10)
+        );
+        assert_eq!(socket.keepalive_retries().unwrap(), 5);
+
+        let stream = ClientBuilder::new("127.0.0.1", port)
+            .connect_tcp()
+            .await
+            .unwrap();
+        assert!(!socket2::SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    #[async_std::test]
+    async fn greeting_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Accepts the connection, but never greets.
+        let server = task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            task::sleep(Duration::from_secs(5)).await;
+            drop(stream);
+        });
+
+        let connect = ClientBuilder::new("127.0.0.1", port)
+            .starttls()
+            .greeting_timeout(Duration::from_millis(50))
+            .connect();
+        match async_std::future::timeout(Duration::from_secs(2), connect).await {
+            Ok(Err(Error::Timeout)) => {}
+            other => panic!("unexpected result: {:?}", other.map(|res| res.map(|_| ()))),
+        }
+        server.cancel().await;
+    }
+
+    #[async_std::test]
+    async fn starttls_strict() {
+        let script = vec![(
+            "A0001 CAPABILITY",
+            "* CAPABILITY IMAP4rev1\r\nA0001 OK CAPABILITY completed\r\n",
+        )];
+        let port = mock_script_server(script, false).await;
+        let connect = ClientBuilder::new("localhost", port)
+            .starttls()
+            .tls(tls_connector())
+            .connect();
+        match connect.await {
+            Err(Error::MissingCapability(capability)) => assert_eq!(capability, "STARTTLS"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Not strict, the command is sent although it was not advertised.
+        let script = vec![("A0001 STARTTLS", "A0001 OK Begin TLS negotiation\r\n")];
+        let port = mock_script_server(script, true).await;
+        let client = ClientBuilder::new("localhost", port)
+            .starttls()
+            .starttls_strict(false)
+            .tls(tls_connector())
+            .connect()
+            .await
+            .unwrap();
+        assert!(client.tls_info().unwrap().peer_certificate.is_some());
+    }
+
+    #[async_std::test]
+    async fn socks5() {
+        let imap = mock_imap_server().await;
+        let mut connect = vec![0x05, 0x01, 0x00, 0x03, 16];
+        connect.extend_from_slice(b"imap.example.org");
+        connect.extend_from_slice(&993u16.to_be_bytes());
+        let exchanges = vec![
+            (vec![0x05, 0x01, 0x02], vec![0x05, 0x02]),
+            (b"\x01\x04user\x04pass".to_vec(), vec![0x01, 0x00]),
+            (
+                connect,
+                vec![0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x12, 0x34],
+            ),
+        ];
+        let proxy = mock_proxy(exchanges, imap).await;
+
+        noop_through(
+            ClientBuilder::new("imap.example.org", 993)
+                .proxy(Proxy::socks5(proxy).credentials("user", "pass")),
+        )
+        .await;
+    }
+
+    #[async_std::test]
+    async fn http_connect() {
+        let imap = mock_imap_server().await;
+        let request = b"CONNECT imap.example.org:143 HTTP/1.1\r\n\
+            Host: imap.example.org:143\r\n\
+            Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+            .to_vec();
+        let reply = b"HTTP/1.1 200 Connection established\r\n\r\n".to_vec();
+        let proxy = mock_proxy(vec![(request, reply)], imap).await;
+
+        noop_through(
+            ClientBuilder::new("imap.example.org", 143)
+                .starttls()
+                .proxy(Proxy::http_connect(proxy).credentials("user", "pass")),
+        )
+        .await;
+    }
+
+    #[async_std::test]
+    async fn socks5_tls() {
+        let imap = mock_tls_imap_server().await;
+        let mut connect = vec![0x05, 0x01, 0x00, 0x03, 16];
+        connect.extend_from_slice(b"imap.example.org");
+        connect.extend_from_slice(&993u16.to_be_bytes());
+        let exchanges = vec![
+            (vec![0x05, 0x01, 0x00], vec![0x05, 0x00]),
+            (
+                connect,
+                vec![0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x12, 0x34],
+            ),
+        ];
+        let proxy = mock_proxy(exchanges, imap).await;
+
+        // The certificate is validated against the IMAP server, not the proxy.
+        let client = ClientBuilder::new("imap.example.org", 993)
+            .proxy(Proxy::socks5(proxy))
+            .tls(tls_connector())
+            .connect()
+            .await
+            .unwrap();
+        assert!(client.tls_info().unwrap().peer_certificate.is_some());
+    }
+
+    #[async_std::test]
+    async fn http_connect_starttls() {
+        let script = vec![
+            (
+                "A0001 CAPABILITY",
+                "* CAPABILITY IMAP4rev1 STARTTLS\r\nA0001 OK CAPABILITY completed\r\n",
+            ),
+            ("A0002 STARTTLS", "A0002 OK Begin TLS negotiation\r\n"),
+        ];
+        let imap = format!("127.0.0.1:{}", mock_script_server(script, true).await);
+        let request = b"CONNECT imap.example.org:143 HTTP/1.1\r\n\
+            Host: imap.example.org:143\r\n\r\n"
+            .to_vec();
+        let reply = b"HTTP/1.1 200 Connection established\r\n\r\n".to_vec();
+        let proxy = mock_proxy(vec![(request, reply)], imap).await;
+
+        let client = ClientBuilder::new("imap.example.org", 143)
+            .starttls()
+            .proxy(Proxy::http_connect(proxy))
+            .tls(tls_connector())
+            .connect()
+            .await
+            .unwrap();
+        assert!(client.tls_info().unwrap().peer_certificate.is_some());
+    }
+
+    #[async_std::test]
+    async fn http_connect_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 256];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let err = ClientBuilder::new("imap.example.org", 993)
+            .proxy(Proxy::http_connect(addr))
+            .connect_tcp()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("403 Forbidden"), "{}", err);
+    }
+}
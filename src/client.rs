@@ -1,8 +1,10 @@
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::str;
+use std::time::{Duration, Instant};
 
 use async_native_tls::{TlsConnector, TlsStream};
 use async_std::channel;
@@ -12,12 +14,20 @@ use async_std::prelude::*;
 use extensions::quota::parse_get_quota_root;
 use imap_proto::{RequestId, Response};
 
-use super::authenticator::Authenticator;
-use super::error::{Error, ParseError, Result, ValidateError};
+use super::authenticator::{
+    AuthMechanism, Authenticator, CramMd5Authenticator, Credentials, PlainAuthenticator,
+    XOAuth2Authenticator,
+};
+use super::error::{Error, ParseError, ResponseCode, Result, ValidateError};
 use super::parse::*;
 use super::types::*;
-use crate::extensions::{self, quota::parse_get_quota};
-use crate::imap_stream::ImapStream;
+use crate::extensions::list_extended::{ListReturn, ListSelection};
+use crate::extensions::notify::{NotifyGroup, NotifyMailboxes};
+use crate::extensions::{self, gmail::LabelsOperation, quota::parse_get_quota};
+use crate::imap_stream::{AlertHandler, ImapStream};
+use crate::scram::ScramSha256;
+use crate::secret::Secret;
+use crate::utf7;
 
 macro_rules! quote {
     ($x:expr) => {
@@ -32,18 +42,85 @@ macro_rules! quote {
 /// a selected mailbox whose status has changed. See the note on [unilateral server responses
 /// in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7). Any such messages are parsed out
 /// and sent on `Session::unsolicited_responses`.
+///
+/// Call [`Session::logout`] when done: the `LOGOUT` command can not be sent when the session is
+/// dropped, which leaves the server to time the connection out. Debug builds log a warning for
+/// sessions that are dropped without logging out.
+///
+/// # Cancellation
+///
+/// The futures and streams of commands may be dropped before they complete, e.g. when another
+/// branch of a `futures::select!` finishes first. The part of the command line that was not
+/// written yet stays buffered in the session, and the next command first sends it and reads the
+/// rest of the responses to the dropped command. Its untagged responses, like the messages of a
+/// dropped `FETCH`, are sent on `Session::unsolicited_responses` and its result is discarded.
+///
+/// This does not hold for commands that wait for the server to accept a literal or to end a
+/// mode: [`Session::append`] and its variants, [`Session::run_command_with_literals`],
+/// `AUTHENTICATE` and `IDLE`. Dropping them while the server waits for the rest of the command
+/// leaves the connection unusable.
 // Both `Client` and `Session` deref to [`Connection`](struct.Connection.html), the underlying
 // primitives type.
 #[derive(Debug)]
 pub struct Session<T: Read + Write + Unpin + fmt::Debug> {
     pub(crate) conn: Connection<T>,
-    pub(crate) unsolicited_responses_tx: channel::Sender<UnsolicitedResponse>,
+    pub(crate) unsolicited_responses_tx: UnsolicitedSender,
+    /// The capabilities returned by the last `CAPABILITY` command.
+    pub(crate) cached_capabilities: Option<Capabilities>,
+    /// Whether `UTF8=ACCEPT` is enabled, see [`Session::enable`].
+    pub(crate) utf8_accept: bool,
+    /// Whether mailbox names are encoded in modified UTF-7, see
+    /// [`Session::set_encode_mailbox_names`].
+    pub(crate) encode_mailbox_names: bool,
+    /// Whether to create missing mailboxes, see [`Session::set_create_on_trycreate`].
+    pub(crate) create_on_trycreate: bool,
+    /// Whether to check keywords before storing them, see [`Session::set_validate_keywords`].
+    pub(crate) validate_keywords: bool,
+    /// The number of changes to the messages of the mailbox that were seen when a search result
+    /// was saved as `$`, see [`Session::search_save`].
+    pub(crate) saved_search: Option<u64>,
+    /// The name and state of the selected mailbox, see [`Session::selected`].
+    pub(crate) selected: Option<(String, Mailbox)>,
+    pub(crate) state: SessionState,
+    pub(crate) logout_guard: LogoutGuard,
 
     /// Server responses that are not related to the current command. See also the note on
     /// [unilateral server responses in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7).
+    ///
+    /// The channel is bounded, see [`Client::set_unsolicited_capacity`].
     pub unsolicited_responses: channel::Receiver<UnsolicitedResponse>,
 }
 
+/// The [states](https://tools.ietf.org/html/rfc3501#section-3) a [`Session`] can be in, which
+/// decide the commands it may send. Before authentication, it is a [`Client`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SessionState {
+    Authenticated,
+    Selected,
+    Logout,
+}
+
+/// Warns in debug builds when the [`Session`] holding it is dropped without logging out.
+#[derive(Debug, Default)]
+pub(crate) struct LogoutGuard {
+    disarmed: bool,
+}
+
+impl LogoutGuard {
+    /// Silences the warning, e.g. because the connection was lost anyway.
+    pub(crate) fn disarm(&mut self) {
+        self.disarmed = true;
+    }
+}
+
+impl Drop for LogoutGuard {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) && !self.disarmed && !std::thread::panicking() {
+            log::warn!("session dropped without logging out, call `Session::logout` first");
+        }
+    }
+}
+
 impl<T: Read + Write + Unpin + fmt::Debug> Unpin for Session<T> {}
 impl<T: Read + Write + Unpin + fmt::Debug> Unpin for Client<T> {}
 impl<T: Read + Write + Unpin + fmt::Debug> Unpin for Connection<T> {}
@@ -67,6 +144,20 @@ pub struct Connection<T: Read + Write + Unpin + fmt::Debug> {
 
     /// Manages the request ids.
     pub(crate) request_ids: IdGenerator,
+
+    /// The name of the last tagged command, used to annotate errors.
+    pub(crate) current_command: Option<String>,
+
+    /// Whether the server is treated as an IMAP4rev2 server, see [`Session::set_imap4rev2`].
+    pub(crate) imap4rev2: bool,
+
+    /// The capacity of the unsolicited responses channel of the session, see
+    /// [`Client::set_unsolicited_capacity`].
+    pub(crate) unsolicited_capacity: usize,
+
+    /// What happens to the unsolicited responses of the session, see
+    /// [`Client::set_unsolicited_policy`].
+    pub(crate) unsolicited_policy: UnsolicitedPolicy,
 }
 
 // `Deref` instances are so we can make use of the same underlying primitives in `Client` and
@@ -128,35 +219,88 @@ pub async fn connect<A: ToSocketAddrs, S: AsRef<str>>(
     let ssl_stream = ssl_connector.connect(domain.as_ref(), stream).await?;
 
     let mut client = Client::new(ssl_stream);
-    let _greeting = match client.read_response().await {
-        Some(greeting) => greeting,
-        None => {
-            return Err(Error::Bad(
-                "could not read server Greeting after connect".into(),
-            ));
-        }
-    };
+    client.read_greeting().await?;
 
     Ok(client)
 }
 
+/// Connect to a server in plaintext and upgrade the connection with `STARTTLS`, see
+/// [`Client::starttls`]. Returns the client together with the capabilities the server advertises
+/// over TLS.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+///
+/// let tls = async_native_tls::TlsConnector::new();
+/// let (client, capabilities) =
+///     async_imap::secure_connect(("imap.example.org", 143), "imap.example.org", tls).await?;
+///
+/// # Ok(())
+/// # }) }
+/// ```
+pub async fn secure_connect<A: ToSocketAddrs, S: AsRef<str>>(
+    addr: A,
+    domain: S,
+    ssl_connector: TlsConnector,
+) -> Result<(Client<TlsStream<TcpStream>>, Capabilities)> {
+    let stream = TcpStream::connect(addr).await?;
+
+    let mut client = Client::new(stream);
+    client.read_greeting().await?;
+    client.starttls(domain, ssl_connector).await
+}
+
 impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
     /// This will upgrade an IMAP client from using a regular TCP connection to use TLS.
     ///
     /// The domain parameter is required to perform hostname verification.
+    ///
+    /// The command is sent without checking that the server supports it; see
+    /// [`Client::starttls`] for that.
     pub async fn secure<S: AsRef<str>>(
         mut self,
         domain: S,
         ssl_connector: TlsConnector,
     ) -> Result<Client<TlsStream<T>>> {
         self.run_command_and_check_ok("STARTTLS", None).await?;
+        let alert_handler = self.conn.stream.alert_handler.take();
+        let wire_tracer = self.conn.stream.wire_tracer.take();
+        let lenient = self.conn.stream.lenient;
         let ssl_stream = ssl_connector
             .connect(domain.as_ref(), self.conn.stream.into_inner())
             .await?;
 
-        let client = Client::new(ssl_stream);
+        let mut client = Client::new(ssl_stream);
+        client.conn.unsolicited_capacity = self.conn.unsolicited_capacity;
+        client.conn.unsolicited_policy = self.conn.unsolicited_policy;
+        client.conn.stream.alert_handler = alert_handler;
+        client.conn.stream.wire_tracer = wire_tracer;
+        client.conn.stream.lenient = lenient;
         Ok(client)
     }
+
+    /// Upgrades the connection to TLS like [`Client::secure`], after checking that the server
+    /// advertises `STARTTLS`. If it does not, this fails with [`Error::MissingCapability`]
+    /// instead of going on in plaintext, as a man in the middle could have stripped it from the
+    /// capabilities to read the credentials.
+    ///
+    /// Capabilities received before the upgrade can not be trusted, so they are requested again
+    /// over TLS and returned together with the client.
+    pub async fn starttls<S: AsRef<str>>(
+        mut self,
+        domain: S,
+        ssl_connector: TlsConnector,
+    ) -> Result<(Client<TlsStream<T>>, Capabilities)> {
+        if !self.capabilities().await?.has_str("STARTTLS") {
+            return Err(Error::MissingCapability("STARTTLS".into()));
+        }
+        let mut client = self.secure(domain, ssl_connector).await?;
+        let capabilities = client.capabilities().await?;
+        Ok((client, capabilities))
+    }
 }
 
 // As the pattern of returning the unauthenticated `Client` (a.k.a. `self`) back with a login error
@@ -190,10 +334,48 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
             conn: Connection {
                 stream,
                 request_ids: IdGenerator::new(),
+                current_command: None,
+                imap4rev2: false,
+                unsolicited_capacity: DEFAULT_UNSOLICITED_CAPACITY,
+                unsolicited_policy: UnsolicitedPolicy::default(),
             },
         }
     }
 
+    /// Sets how many unsolicited responses the [`Session`] created by logging in keeps in
+    /// [`Session::unsolicited_responses`] until they are received, 100 by default.
+    ///
+    /// Once the channel is full, the oldest response is dropped for each new one, so that a slow
+    /// consumer neither holds up the connection nor makes the channel grow without limit. The
+    /// number of dropped responses is returned by [`Session::unsolicited_dropped`].
+    pub fn set_unsolicited_capacity(&mut self, capacity: usize) {
+        self.conn.unsolicited_capacity = capacity;
+    }
+
+    /// Sets what happens to the unsolicited responses of the [`Session`] created by logging in,
+    /// [`UnsolicitedPolicy::Buffer`] by default. Sessions that never receive from
+    /// [`Session::unsolicited_responses`] can use [`UnsolicitedPolicy::Drop`] so that the
+    /// responses are not kept at all. Either way, sending them never fails or waits, and the
+    /// session keeps track of the changes they report, e.g. for [`Session::selected`].
+    pub fn set_unsolicited_policy(&mut self, policy: UnsolicitedPolicy) {
+        self.conn.unsolicited_policy = policy;
+    }
+
+    /// Reads the greeting the server sends when the connection is opened.
+    pub(crate) async fn read_greeting(&mut self) -> Result<()> {
+        match self.read_response().await {
+            Some(greeting) => {
+                if let Ok(greeting) = greeting {
+                    self.conn.imap4rev2 = is_imap4rev2_greeting(greeting.parsed());
+                }
+                Ok(())
+            }
+            None => Err(Error::Bad(
+                "could not read server Greeting after connect".into(),
+            )),
+        }
+    }
+
     /// Convert this Client into the raw underlying stream.
     pub fn into_inner(self) -> T {
         let Self { conn, .. } = self;
@@ -231,20 +413,32 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
     /// # Ok(())
     /// # }) }
     /// ```
+    ///
+    /// If the server includes its capabilities when completing the command, they are available
+    /// from [`Session::cached_capabilities`] right away.
+    ///
+    /// With the `zeroize` feature enabled, the buffers used to send the credentials are
+    /// overwritten with zeroes once the command has been sent. Pass the password as a
+    /// `Zeroizing<String>` (re-exported as `async_imap::Zeroizing`) to have the caller's copy
+    /// wiped as well.
     pub async fn login<U: AsRef<str>, P: AsRef<str>>(
         mut self,
         username: U,
         password: P,
     ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
-        let u = ok_or_unauth_client_err!(validate_str(username.as_ref()), self);
-        let p = ok_or_unauth_client_err!(validate_str(password.as_ref()), self);
-        ok_or_unauth_client_err!(
-            self.run_command_and_check_ok(&format!("LOGIN {} {}", u, p), None)
-                .await,
+        let u = Secret(ok_or_unauth_client_err!(
+            validate_str(username.as_ref()),
             self
-        );
+        ));
+        let p = Secret(ok_or_unauth_client_err!(
+            validate_str(password.as_ref()),
+            self
+        ));
+        let command = Secret(format!("LOGIN {} {}", *u, *p));
+        let id = ok_or_unauth_client_err!(self.run_command(&command).await, self);
+        let done = ok_or_unauth_client_err!(self.check_done_ok(&id, None).await, self);
 
-        Ok(Session::new(self.conn))
+        Ok(Session::from_login(self.conn, &done, None))
     }
 
     /// Authenticate with the server using the given custom `authenticator` to handle the server's
@@ -290,6 +484,10 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
     /// # Ok(())
     /// # }) }
     /// ```
+    ///
+    /// With the `zeroize` feature enabled, the encoded responses are overwritten with zeroes once
+    /// they have been sent. Use `Zeroizing<Vec<u8>>` as the [`Authenticator::Response`] to have
+    /// the raw responses wiped as well.
     pub async fn authenticate<A: Authenticator, S: AsRef<str>>(
         mut self,
         auth_type: S,
@@ -304,12 +502,114 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
         Ok(session)
     }
 
+    /// Asks the server for its capabilities before logging in, e.g. to find out which `AUTH=`
+    /// mechanisms it supports. They usually change once logged in, so they are not cached.
+    pub async fn capabilities(&mut self) -> Result<Capabilities> {
+        // Untagged data the server sends before logging in is of no use to a session.
+        let (unsolicited, _) = UnsolicitedSender::new(1);
+        let id = self.run_command("CAPABILITY").await?;
+        parse_capabilities(&mut self.conn.stream, unsolicited, id).await
+    }
+
+    /// Authenticates with the strongest mechanism that both the server and `credentials`
+    /// support, trying them in the order of [`AuthMechanism::DEFAULT_PREFERENCE`]. Returns the
+    /// mechanism that was used together with the session.
+    ///
+    /// ```no_run
+    /// use async_imap::Credentials;
+    ///
+    /// # fn main() -> async_imap::error::Result<()> {
+    /// # async_std::task::block_on(async {
+    /// let domain = "imap.example.com";
+    /// let tls = async_native_tls::TlsConnector::new();
+    /// let client = async_imap::connect((domain, 993), domain, tls).await?;
+    ///
+    /// let credentials = Credentials::new("me@example.com")
+    ///     .password("secret")
+    ///     .access_token("<access_token>");
+    /// let (session, mechanism) = client
+    ///     .authenticate_auto(&credentials)
+    ///     .await
+    ///     .map_err(|(err, _client)| err)?;
+    /// println!("authenticated with {:?}", mechanism);
+    /// # Ok(())
+    /// # }) }
+    /// ```
+    ///
+    /// Fails with [`Error::MissingCapability`] without sending any credentials if no mechanism
+    /// fits.
+    pub async fn authenticate_auto(
+        self,
+        credentials: &Credentials,
+    ) -> ::std::result::Result<(Session<T>, AuthMechanism), (Error, Client<T>)> {
+        self.authenticate_auto_with(credentials, AuthMechanism::DEFAULT_PREFERENCE)
+            .await
+    }
+
+    /// Like [`Client::authenticate_auto`], but tries the mechanisms in the order of
+    /// `preference`, leaving out those not listed.
+    pub async fn authenticate_auto_with(
+        mut self,
+        credentials: &Credentials,
+        preference: &[AuthMechanism],
+    ) -> ::std::result::Result<(Session<T>, AuthMechanism), (Error, Client<T>)> {
+        let capabilities = ok_or_unauth_client_err!(self.capabilities().await, self);
+        let mechanism = preference
+            .iter()
+            .copied()
+            .find(|m| credentials.allow(*m) && m.is_supported(&capabilities));
+        let mechanism = match mechanism {
+            Some(mechanism) => mechanism,
+            None => {
+                let wanted: Vec<_> = preference.iter().map(|m| m.capability()).collect();
+                return Err((Error::MissingCapability(wanted.join(" or ")), self));
+            }
+        };
+
+        let username = &credentials.username;
+        let session = match mechanism {
+            AuthMechanism::XOAuth2 => {
+                let authenticator = XOAuth2Authenticator {
+                    username,
+                    access_token: credentials.access_token.as_deref().unwrap(),
+                    sent: false,
+                };
+                self.authenticate("XOAUTH2", authenticator).await?
+            }
+            AuthMechanism::ScramSha256 => {
+                let password = credentials.password.as_deref().unwrap();
+                let authenticator = ScramSha256::new(username.as_str(), password.as_str());
+                self.authenticate("SCRAM-SHA-256", authenticator).await?
+            }
+            AuthMechanism::Plain => {
+                let authenticator = PlainAuthenticator {
+                    username,
+                    password: credentials.password.as_deref().unwrap(),
+                };
+                self.authenticate("PLAIN", authenticator).await?
+            }
+            AuthMechanism::CramMd5 => {
+                let authenticator = CramMd5Authenticator {
+                    username,
+                    password: credentials.password.as_deref().unwrap(),
+                };
+                self.authenticate("CRAM-MD5", authenticator).await?
+            }
+            AuthMechanism::Login => {
+                self.login(username, credentials.password.as_deref().unwrap())
+                    .await?
+            }
+        };
+        Ok((session, mechanism))
+    }
+
     /// This func does the handshake process once the authenticate command is made.
     async fn do_auth_handshake<A: Authenticator>(
         mut self,
         id: RequestId,
         mut authenticator: A,
     ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        let mut capabilities = None;
         // explicit match blocks neccessary to convert error to tuple and not bind self too
         // early (see also comment on `login`)
         loop {
@@ -328,19 +628,37 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
                             Vec::new()
                         };
                         let raw_response = &mut authenticator.process(&challenge);
-                        let auth_response = base64::encode(raw_response);
+                        if let Err(err) = authenticator.check() {
+                            // The server completes the cancelled command with BAD.
+                            ok_or_unauth_client_err!(
+                                self.conn.run_command_untagged("*").await,
+                                self
+                            );
+                            let _ = self.check_done_ok(&id, None).await;
+                            return Err((err, self));
+                        }
+                        let auth_response = Secret(base64::encode(raw_response));
 
                         ok_or_unauth_client_err!(
                             self.conn.run_command_untagged(&auth_response).await,
                             self
                         );
                     }
-                    _ => {
-                        ok_or_unauth_client_err!(
+                    Response::Done { .. } => {
+                        let done = ok_or_unauth_client_err!(
                             self.check_done_ok_from(&id, None, res).await,
                             self
                         );
-                        return Ok(Session::new(self.conn));
+                        ok_or_unauth_client_err!(authenticator.finish(), self);
+                        return Ok(Session::from_login(self.conn, &done, capabilities));
+                    }
+                    // Untagged responses are not part of the exchange, and must not be taken for
+                    // its end: continuations may still follow them.
+                    _ => {
+                        ok_or_unauth_client_err!(check_bye(&res), self);
+                        if let Some(caps) = parse_untagged_capabilities(&res) {
+                            capabilities = Some(caps);
+                        }
                     }
                 }
             } else {
@@ -359,12 +677,40 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
 
     // not public, just to avoid duplicating the channel creation code
     fn new(conn: Connection<T>) -> Self {
-        let (tx, rx) = channel::bounded(100);
+        let (tx, rx) = UnsolicitedSender::new(conn.unsolicited_capacity);
+        tx.set_policy(conn.unsolicited_policy);
         Session {
             conn,
             unsolicited_responses: rx,
             unsolicited_responses_tx: tx,
+            cached_capabilities: None,
+            utf8_accept: false,
+            encode_mailbox_names: false,
+            create_on_trycreate: false,
+            validate_keywords: false,
+            saved_search: None,
+            selected: None,
+            state: SessionState::Authenticated,
+            logout_guard: LogoutGuard::default(),
+        }
+    }
+
+    /// Creates the session after `LOGIN` or `AUTHENTICATE` completed with `done`, caching the
+    /// capabilities if the server included them in it, or else sent them in `untagged` while
+    /// the command ran.
+    fn from_login(
+        conn: Connection<T>,
+        done: &ResponseData,
+        untagged: Option<Capabilities>,
+    ) -> Self {
+        let mut session = Session::new(conn);
+        if let Some(caps) = parse_capability_code(done).or(untagged) {
+            if caps.is_imap4rev2_only() {
+                session.conn.imap4rev2 = true;
+            }
+            session.cached_capabilities = Some(caps);
         }
+        session
     }
 
     /// Selects a mailbox
@@ -385,45 +731,135 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// [`Connection::run_command_and_read_response`], you *may* see additional untagged `RECENT`,
     /// `EXISTS`, `FETCH`, and `EXPUNGE` responses. You can get them from the
     /// `unsolicited_responses` channel of the [`Session`](struct.Session.html).
+    ///
+    /// To look into a mailbox without changing it, e.g. to preview it, open it with
+    /// [`Session::examine`] instead.
     pub async fn select<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
-        // TODO: also note READ/WRITE vs READ-only mode!
-        let id = self
-            .run_command(&format!("SELECT {}", validate_str(mailbox_name.as_ref())?))
-            .await?;
-        let mbox = parse_mailbox(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        let command = format!("SELECT {}", self.quote_mailbox(mailbox_name.as_ref())?);
+        self.run_select(mailbox_name.as_ref(), &command).await
+    }
 
-        Ok(mbox)
+    /// Selects a mailbox like [`Session::select`], and enables the [`CONDSTORE`
+    /// extension](https://tools.ietf.org/html/rfc7162#section-3.1.8) with it, without a separate
+    /// [`Session::enable`]. The server then reports the mod-sequence of messages, and the returned
+    /// [`Mailbox::highest_modseq`] is set unless the mailbox does not support mod-sequences.
+    ///
+    /// This requires the `CONDSTORE` capability.
+    pub async fn select_condstore<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
+        self.ensure_capability("CONDSTORE").await?;
+        let command = format!(
+            "SELECT {} (CONDSTORE)",
+            self.quote_mailbox(mailbox_name.as_ref())?
+        );
+        self.run_select(mailbox_name.as_ref(), &command).await
     }
 
     /// The `EXAMINE` command is identical to [`Session::select`] and returns the same output;
     /// however, the selected mailbox is identified as read-only. No changes to the permanent state
     /// of the mailbox, including per-user state, will happen in a mailbox opened with `examine`;
-    /// in particular, messagess cannot lose [`Flag::Recent`] in an examined mailbox.
+    /// in particular, messages cannot lose [`Flag::Recent`] in an examined mailbox.
     pub async fn examine<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
-        let id = self
-            .run_command(&format!("EXAMINE {}", validate_str(mailbox_name.as_ref())?))
-            .await?;
+        let command = format!("EXAMINE {}", self.quote_mailbox(mailbox_name.as_ref())?);
+        self.run_select(mailbox_name.as_ref(), &command).await
+    }
+
+    /// Runs a `SELECT` or `EXAMINE` command and reads the state of the mailbox it opens.
+    async fn run_select(&mut self, mailbox_name: &str, command: &str) -> Result<Mailbox> {
+        let id = self.run_command(command).await?;
         let mbox = parse_mailbox(
             &mut self.conn.stream,
             self.unsolicited_responses_tx.clone(),
             id,
         )
-        .await?;
-
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()));
+        let mut mbox = match mbox {
+            Ok(mbox) => mbox,
+            Err(err) => {
+                self.track_completion(command, false);
+                return Err(err);
+            }
+        };
+        // The server must send `READ-ONLY` for `EXAMINE`, but the mailbox is read-only either
+        // way.
+        if command_name(command) == "EXAMINE" {
+            mbox.read_only = true;
+        }
+        self.unsolicited_responses_tx
+            .set_selected(mbox.exists, mbox.uid_validity);
+        self.selected = Some((mailbox_name.to_string(), mbox.clone()));
         Ok(mbox)
     }
 
+    /// The mailbox that was last opened with [`Session::select`] or [`Session::examine`], or
+    /// `None` if none is, e.g. after [`Session::close`].
+    ///
+    /// Its [`Mailbox::exists`] is kept up to date with the `EXISTS`, `EXPUNGE` and `VANISHED`
+    /// responses the server sent since, whether they were returned by [`Session::expunge`] or
+    /// unsolicited, and whether or not they were taken from [`Session::unsolicited_responses`].
+    /// The other fields are as they were when the mailbox was opened.
+    ///
+    /// This takes `&mut self` because the responses may have been read by a stream that has
+    /// since been dropped, so [`Mailbox::exists`] is brought up to date here.
+    pub fn selected(&mut self) -> Option<&Mailbox> {
+        let exists = self.unsolicited_responses_tx.exists();
+        let (_, mailbox) = self.selected.as_mut()?;
+        mailbox.exists = exists;
+        Some(mailbox)
+    }
+
+    /// The name of the mailbox returned by [`Session::selected`].
+    pub fn selected_name(&self) -> Option<&str> {
+        self.selected.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Whether the [`Uid`]s of the messages in the selected mailbox are kept track of, see
+    /// [`Session::set_track_uids`].
+    pub fn track_uids(&self) -> bool {
+        self.unsolicited_responses_tx.tracks_uids()
+    }
+
+    /// Turns on or off keeping track of the [`Uid`] of each message in the selected mailbox by
+    /// its [`Seq`], see [`Session::uid`] and [`Session::seq`]. The [`Uid`]s are taken from all
+    /// `FETCH` responses, and the sequence numbers of the later messages are shifted down as
+    /// messages are expunged, whether the `EXPUNGE` and `VANISHED` responses are returned by
+    /// [`Session::expunge`] or unsolicited.
+    ///
+    /// This is off by default, as it keeps a [`Uid`] for every message of the mailbox.
+    pub fn set_track_uids(&mut self, enabled: bool) {
+        self.unsolicited_responses_tx.set_track_uids(enabled);
+    }
+
+    /// The [`Uid`] of message `seq` in the selected mailbox, if it was seen since the mailbox was
+    /// selected and [`Session::set_track_uids`] is turned on. See also [`SeqMap::uid`].
+    pub fn uid(&self, seq: Seq) -> Option<Uid> {
+        self.selected.as_ref()?;
+        self.unsolicited_responses_tx.uid(seq)
+    }
+
+    /// The sequence number of the message with `uid` in the selected mailbox, if the [`Uid`] is
+    /// known, see [`Session::uid`].
+    pub fn seq(&self, uid: Uid) -> Option<Seq> {
+        self.selected.as_ref()?;
+        self.unsolicited_responses_tx.seq(uid)
+    }
+
     /// Fetch retreives data associated with a set of messages in the mailbox.
     ///
+    /// `sequence_set` can be given either as a string (e.g. `"1,3,5:9"`) or as a [`SequenceSet`],
+    /// which renders the set in its most compact form.
+    ///
+    /// `UID` is added to `query` if it does not ask for it, so that [`Fetch::uid`] is set on every
+    /// message and the results can still be matched to messages once sequence numbers shifted
+    /// because of an expunge. The same holds for the other methods that fetch by sequence number.
+    ///
     /// Note that the server *is* allowed to unilaterally include `FETCH` responses for other
     /// messages in the selected mailbox whose status has changed. See the note on [unilateral
     /// server responses in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7).
     ///
+    /// `query` can be given either as a string or as [`FetchItems`], which renders a deduplicated
+    /// list of data items that always includes `UID`.
+    ///
     /// `query` is a list of "data items" (space-separated in parentheses if `>1`). There are three
     /// "macro items" which specify commonly-used sets of data items, and can be used instead of
     /// data items.  A macro must be used by itself, and not in conjunction with other macros or
@@ -482,23 +918,11 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         query: S2,
     ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send>
     where
-        S1: AsRef<str>,
-        S2: AsRef<str>,
+        S1: fmt::Display,
+        S2: fmt::Display,
     {
-        let id = self
-            .run_command(&format!(
-                "FETCH {} {}",
-                sequence_set.as_ref(),
-                query.as_ref()
-            ))
-            .await?;
-        let res = parse_fetches(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
-
-        Ok(res)
+        self.fetch_with("FETCH", sequence_set, &query.to_string())
+            .await
     }
 
     /// Equivalent to [`Session::fetch`], except that all identifiers in `uid_set` are
@@ -509,104 +933,440 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         query: S2,
     ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
     where
-        S1: AsRef<str>,
-        S2: AsRef<str>,
+        S1: fmt::Display,
+        S2: fmt::Display,
     {
         let id = self
-            .run_command(&format!(
-                "UID FETCH {} {}",
-                uid_set.as_ref(),
-                query.as_ref()
-            ))
+            .run_command(&format!("UID FETCH {} {}", uid_set, query))
             .await?;
-        let res = parse_fetches(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
-        Ok(res)
+        Ok(self.fetch_responses(id))
     }
 
-    /// Noop always succeeds, and it does nothing.
-    pub async fn noop(&mut self) -> Result<()> {
-        let id = self.run_command("NOOP").await?;
-        parse_noop(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
-        Ok(())
+    /// Like [`Session::fetch`], but collects all messages. If the server fails the command with a
+    /// `NO` or `BAD` response after it has returned some of the messages, for example because
+    /// one of them was expunged in the meantime, those messages are returned along with the
+    /// failure in [`Error::PartialFetch`], so that a bulk download can make progress.
+    pub async fn fetch_all<S1, S2>(&mut self, sequence_set: S1, query: S2) -> Result<Vec<Fetch>>
+    where
+        S1: fmt::Display,
+        S2: fmt::Display,
+    {
+        let fetches = self.fetch(sequence_set, query).await?;
+        collect_fetches(fetches).await
     }
 
-    /// Logout informs the server that the client is done with the connection.
-    pub async fn logout(&mut self) -> Result<()> {
-        self.run_command_and_check_ok("LOGOUT").await?;
-        Ok(())
+    /// Equivalent to [`Session::fetch_all`], except that all identifiers in `uid_set` are
+    /// [`Uid`]s.
+    pub async fn uid_fetch_all<S1, S2>(&mut self, uid_set: S1, query: S2) -> Result<Vec<Fetch>>
+    where
+        S1: fmt::Display,
+        S2: fmt::Display,
+    {
+        let fetches = self.uid_fetch(uid_set, query).await?;
+        collect_fetches(fetches).await
     }
 
-    /// The [`CREATE` command](https://tools.ietf.org/html/rfc3501#section-6.3.3) creates a mailbox
-    /// with the given name.  `Ok` is returned only if a new mailbox with that name has been
-    /// created.  It is an error to attempt to create `INBOX` or a mailbox with a name that
-    /// refers to an extant mailbox.  Any error in creation will return [`Error::No`].
-    ///
-    /// If the mailbox name is suffixed with the server's hierarchy separator character (as
-    /// returned from the server by [`Session::list`]), this is a declaration that the client
-    /// intends to create mailbox names under this name in the hierarchy.  Servers that do not
-    /// require this declaration will ignore the declaration.  In any case, the name created is
-    /// without the trailing hierarchy delimiter.
+    /// Fetches `query` for all messages in `uids` with a single `UID FETCH`, and returns the
+    /// results by [`Uid`], e.g. to show the results of [`Session::uid_thread`] or
+    /// [`Session::uid_sort`] with their envelopes. A [`Uid`] can be given any number of times,
+    /// and is fetched once; messages that were expunged in the meantime are missing.
     ///
-    /// If the server's hierarchy separator character appears elsewhere in the name, the server
-    /// will generally create any superior hierarchical names that are needed for the `CREATE`
-    /// command to be successfully completed.  In other words, an attempt to create `foo/bar/zap`
-    /// on a server in which `/` is the hierarchy separator character will usually create `foo/`
-    /// and `foo/bar/` if they do not already exist.
+    /// ```no_run
+    /// use async_imap::{types::Thread, Session, error::Result};
+    /// use async_std::net::TcpStream;
     ///
-    /// If a new mailbox is created with the same name as a mailbox which was deleted, its unique
-    /// identifiers will be greater than any unique identifiers used in the previous incarnation of
-    /// the mailbox UNLESS the new incarnation has a different unique identifier validity value.
-    /// See the description of the [`UID`
-    /// command](https://tools.ietf.org/html/rfc3501#section-6.4.8) for more detail.
-    pub async fn create<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<()> {
-        self.run_command_and_check_ok(&format!("CREATE {}", validate_str(mailbox_name.as_ref())?))
-            .await?;
-
-        Ok(())
+    /// async fn show(s: &mut Session<TcpStream>) -> Result<()> {
+    ///     let threads = s.uid_thread("REFERENCES", "ALL").await?;
+    ///     let fetches = s
+    ///         .uid_fetch_map(threads.iter().flat_map(Thread::ids), "ENVELOPE")
+    ///         .await?;
+    ///     for thread in &threads {
+    ///         let envelope = thread
+    ///             .id
+    ///             .and_then(|uid| fetches.get(&uid)?.envelope());
+    ///         println!("{:?}", envelope.and_then(|envelope| envelope.subject.as_ref()));
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn uid_fetch_map<I, S>(&mut self, uids: I, query: S) -> Result<HashMap<Uid, Fetch>>
+    where
+        I: IntoIterator<Item = Uid>,
+        S: fmt::Display,
+    {
+        let uid_set: SequenceSet = uids.into_iter().collect();
+        if uid_set.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let fetches = self.uid_fetch_all(&uid_set, query).await?;
+        // Unsolicited `FETCH` responses for other messages may come along.
+        Ok(fetches
+            .into_iter()
+            .filter_map(|fetch| Some((fetch.uid?, fetch)))
+            .filter(|(uid, _)| uid_set.contains(*uid, Uid::MAX))
+            .collect())
     }
 
-    /// The [`DELETE` command](https://tools.ietf.org/html/rfc3501#section-6.3.4) permanently
-    /// removes the mailbox with the given name.  `Ok` is returned only if the mailbox has been
-    /// deleted.  It is an error to attempt to delete `INBOX` or a mailbox name that does not
-    /// exist.
-    ///
-    /// The `DELETE` command will not remove inferior hierarchical names. For example, if a mailbox
-    /// `foo` has an inferior `foo.bar` (assuming `.` is the hierarchy delimiter character),
-    /// removing `foo` will not remove `foo.bar`.  It is an error to attempt to delete a name that
-    /// has inferior hierarchical names and also has [`NameAttribute::NoSelect`].
+    /// Fetches the message `uid` with `BODY.PEEK[]`, without marking it as [`Flag::Seen`], to be
+    /// parsed with [`Fetch::mail`], which borrows the fetched bytes. Returns `None` if there is
+    /// no such message. Requires the `mail` feature.
     ///
-    /// It is permitted to delete a name that has inferior hierarchical names and does not have
-    /// [`NameAttribute::NoSelect`].  In this case, all messages in that mailbox are removed, and
-    /// the name will acquire [`NameAttribute::NoSelect`].
+    /// ```no_run
+    /// use async_imap::{types::Uid, Session, error::Result};
+    /// use async_std::net::TcpStream;
     ///
-    /// The value of the highest-used unique identifier of the deleted mailbox will be preserved so
-    /// that a new mailbox created with the same name will not reuse the identifiers of the former
-    /// incarnation, UNLESS the new incarnation has a different unique identifier validity value.
-    /// See the description of the [`UID`
-    /// command](https://tools.ietf.org/html/rfc3501#section-6.4.8) for more detail.
-    pub async fn delete<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<()> {
-        self.run_command_and_check_ok(&format!("DELETE {}", validate_str(mailbox_name.as_ref())?))
-            .await?;
-
-        Ok(())
+    /// async fn show(uid: Uid, s: &mut Session<TcpStream>) -> Result<()> {
+    ///     if let Some(fetch) = s.uid_fetch_mail(uid).await? {
+    ///         let mail = fetch.mail().expect("BODY[] was fetched")?;
+    ///         println!("{:?}", mail.headers);
+    ///         for part in &mail.subparts {
+    ///             println!("{:?}", part.get_body());
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    #[cfg(feature = "mail")]
+    pub async fn uid_fetch_mail(&mut self, uid: Uid) -> Result<Option<Fetch>> {
+        let fetches = self.uid_fetch_all(uid, "BODY.PEEK[]").await?;
+        Ok(fetches
+            .into_iter()
+            .find(|fetch| fetch.uid == Some(uid) && fetch.body().is_some()))
     }
 
-    /// The [`RENAME` command](https://tools.ietf.org/html/rfc3501#section-6.3.5) changes the name
-    /// of a mailbox.  `Ok` is returned only if the mailbox has been renamed.  It is an error to
-    /// attempt to rename from a mailbox name that does not exist or to a mailbox name that already
-    /// exists.  Any error in renaming will return [`Error::No`].
+    /// Pages through the messages of the selected mailbox that match the search `query`, e.g.
+    /// `ALL`, in windows of `page_size` [`Uid`]s, fetching `items` of each, e.g. `(FLAGS
+    /// ENVELOPE)`. The messages of each page are in `order`; the last page may be shorter, after
+    /// which the stream ends.
     ///
-    /// If the name has inferior hierarchical names, then the inferior hierarchical names will also
-    /// be renamed.  For example, a rename of `foo` to `zap` will rename `foo/bar` (assuming `/` is
+    /// The messages are searched with `UID SEARCH` right away, and each page is fetched with
+    /// `UID FETCH` once it is polled, so a message list can load pages as it is scrolled. Once
+    /// messages are added to or expunged from the mailbox, the search is repeated for the pages
+    /// that were not returned yet; messages newer than the first page do not show up when paging
+    /// [`PageOrder::NewestFirst`]. If the server reports a new `UIDVALIDITY` in between, the
+    /// stream ends with [`Error::UidValidityChanged`], and the list has to be loaded again.
+    pub async fn uid_pages<S1, S2>(
+        &mut self,
+        query: S1,
+        items: S2,
+        page_size: usize,
+        order: PageOrder,
+    ) -> Result<impl Stream<Item = Result<Vec<Fetch>>> + '_ + Send + Unpin>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let mut pages = Pages {
+            uid_validity: self
+                .selected
+                .as_ref()
+                .and_then(|(_, mailbox)| mailbox.uid_validity),
+            session: self,
+            query: query.as_ref().to_string(),
+            items: items.as_ref().to_string(),
+            page_size: page_size.max(1),
+            order,
+            uids: VecDeque::new(),
+            cursor: None,
+            changes: 0,
+        };
+        pages.search().await?;
+        let pages = futures::stream::unfold(Some(pages), |pages| async move {
+            let mut pages = pages?;
+            match pages.next_page().await {
+                Ok(Some(page)) => Some((Ok(page), Some(pages))),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), None)),
+            }
+        });
+        Ok(futures::StreamExt::boxed(pages))
+    }
+
+    /// Fetches the given body `section` of a set of messages with any content transfer encoding
+    /// (e.g. base64) already removed by the server, using the [`BINARY`
+    /// extension](https://tools.ietf.org/html/rfc3516). The decoded bytes are available through
+    /// [`Fetch::binary`].
+    ///
+    /// `section` is a part number such as `1` or `1.2`, or empty for the whole message. The
+    /// section is fetched with `BINARY.PEEK`, so like `BODY.PEEK[<section>]` this does not set
+    /// [`Flag::Seen`]; fetch `BINARY[<section>]` with [`Session::fetch`] to set it.
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the `BINARY` capability,
+    /// in which case [`Session::fetch`] has to be used and the content decoded locally. If the
+    /// server cannot decode the content transfer encoding of the section the stream yields
+    /// [`Error::UnknownCte`].
+    pub async fn binary_fetch<S1, S2>(
+        &mut self,
+        sequence_set: S1,
+        section: S2,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
+    where
+        S1: fmt::Display,
+        S2: AsRef<str>,
+    {
+        self.ensure_capability("BINARY").await?;
+        self.fetch_with(
+            "FETCH",
+            sequence_set,
+            &format!("BINARY.PEEK[{}]", section.as_ref()),
+        )
+        .await
+    }
+
+    /// Equivalent to [`Session::binary_fetch`], except that all identifiers in `uid_set` are
+    /// [`Uid`]s.
+    pub async fn uid_binary_fetch<S1, S2>(
+        &mut self,
+        uid_set: S1,
+        section: S2,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
+    where
+        S1: fmt::Display,
+        S2: AsRef<str>,
+    {
+        self.ensure_capability("BINARY").await?;
+        self.fetch_with(
+            "UID FETCH",
+            uid_set,
+            &format!("BINARY.PEEK[{}]", section.as_ref()),
+        )
+        .await
+    }
+
+    /// Fetches the size of the given body `section` of a set of messages after the content
+    /// transfer encoding has been removed, using the [`BINARY`
+    /// extension](https://tools.ietf.org/html/rfc3516). The sizes are available through
+    /// [`Fetch::binary_size`].
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the `BINARY`
+    /// capability.
+    pub async fn binary_size<S1, S2>(
+        &mut self,
+        sequence_set: S1,
+        section: S2,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
+    where
+        S1: fmt::Display,
+        S2: AsRef<str>,
+    {
+        self.ensure_capability("BINARY").await?;
+        self.fetch_with(
+            "FETCH",
+            sequence_set,
+            &format!("BINARY.SIZE[{}]", section.as_ref()),
+        )
+        .await
+    }
+
+    /// Equivalent to [`Session::binary_size`], except that all identifiers in `uid_set` are
+    /// [`Uid`]s.
+    pub async fn uid_binary_size<S1, S2>(
+        &mut self,
+        uid_set: S1,
+        section: S2,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
+    where
+        S1: fmt::Display,
+        S2: AsRef<str>,
+    {
+        self.ensure_capability("BINARY").await?;
+        self.fetch_with(
+            "UID FETCH",
+            uid_set,
+            &format!("BINARY.SIZE[{}]", section.as_ref()),
+        )
+        .await
+    }
+
+    /// Fetches the `EMAILID` and `THREADID` of a set of messages, ids assigned by the server that
+    /// stay the same when messages are moved, using the [`OBJECTID`
+    /// extension](https://tools.ietf.org/html/rfc8474). The ids are available through
+    /// [`Fetch::email_id`] and [`Fetch::thread_id`].
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the `OBJECTID`
+    /// capability.
+    pub async fn fetch_object_ids<S>(
+        &mut self,
+        sequence_set: S,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
+    where
+        S: fmt::Display,
+    {
+        self.ensure_capability("OBJECTID").await?;
+        self.fetch_with("FETCH", sequence_set, "(EMAILID THREADID)")
+            .await
+    }
+
+    /// Equivalent to [`Session::fetch_object_ids`], except that all identifiers in `uid_set` are
+    /// [`Uid`]s.
+    pub async fn uid_fetch_object_ids<S>(
+        &mut self,
+        uid_set: S,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
+    where
+        S: fmt::Display,
+    {
+        self.ensure_capability("OBJECTID").await?;
+        self.fetch_with("UID FETCH", uid_set, "(EMAILID THREADID)")
+            .await
+    }
+
+    async fn fetch_with<S: fmt::Display>(
+        &mut self,
+        command: &str,
+        set: S,
+        query: &str,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin> {
+        let query = match command {
+            "FETCH" => with_uid(query),
+            _ => query.to_string(),
+        };
+        let id = self
+            .run_command(&format!("{} {} {}", command, set, query))
+            .await?;
+        Ok(self.fetch_responses(id))
+    }
+
+    /// Parses the `FETCH` responses to the command `id`.
+    fn fetch_responses(
+        &mut self,
+        id: RequestId,
+    ) -> impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin {
+        let command = self.conn.current_command.clone();
+        let fetches = parse_fetches(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        );
+        futures::StreamExt::map(fetches, move |res| {
+            res.map_err(|err| err.with_command(command.as_deref()))
+        })
+    }
+
+    /// Noop always succeeds, and it does nothing.
+    pub async fn noop(&mut self) -> Result<()> {
+        let id = self.run_command("NOOP").await?;
+        parse_noop(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .await?;
+        self.update_capabilities();
+        Ok(())
+    }
+
+    /// Measures the round-trip time to the server, from sending a `NOOP` until its tagged `OK`.
+    ///
+    /// As with [`Session::noop`], the selected mailbox stays selected, and any updates the server
+    /// sends along are passed on to [`Session::unsolicited_responses`].
+    pub async fn ping(&mut self) -> Result<Duration> {
+        let start = Instant::now();
+        self.noop().await?;
+        Ok(start.elapsed())
+    }
+
+    /// Logout informs the server that the client is done with the connection. The session can not
+    /// be used any further afterwards, and should be dropped.
+    pub async fn logout(&mut self) -> Result<()> {
+        // Even if this fails, the connection is of no further use.
+        self.logout_guard.disarm();
+        let id = self.run_command("LOGOUT").await?;
+        loop {
+            // The server always says `BYE` before completing `LOGOUT`.
+            match self
+                .conn
+                .check_done_ok(&id, Some(self.unsolicited_responses_tx.clone()))
+                .await
+            {
+                Err(Error::Bye(_)) => continue,
+                res => return res.map(drop),
+            }
+        }
+    }
+
+    /// The [`CREATE` command](https://tools.ietf.org/html/rfc3501#section-6.3.3) creates a mailbox
+    /// with the given name.  `Ok` is returned only if a new mailbox with that name has been
+    /// created.  It is an error to attempt to create `INBOX` or a mailbox with a name that
+    /// refers to an extant mailbox.  Any error in creation will return [`Error::No`].
+    ///
+    /// If the mailbox name is suffixed with the server's hierarchy separator character (as
+    /// returned from the server by [`Session::list`]), this is a declaration that the client
+    /// intends to create mailbox names under this name in the hierarchy.  Servers that do not
+    /// require this declaration will ignore the declaration.  In any case, the name created is
+    /// without the trailing hierarchy delimiter.
+    ///
+    /// If the server's hierarchy separator character appears elsewhere in the name, the server
+    /// will generally create any superior hierarchical names that are needed for the `CREATE`
+    /// command to be successfully completed.  In other words, an attempt to create `foo/bar/zap`
+    /// on a server in which `/` is the hierarchy separator character will usually create `foo/`
+    /// and `foo/bar/` if they do not already exist.
+    ///
+    /// If a new mailbox is created with the same name as a mailbox which was deleted, its unique
+    /// identifiers will be greater than any unique identifiers used in the previous incarnation of
+    /// the mailbox UNLESS the new incarnation has a different unique identifier validity value.
+    /// See the description of the [`UID`
+    /// command](https://tools.ietf.org/html/rfc3501#section-6.4.8) for more detail.
+    ///
+    /// Some servers include the `UIDVALIDITY` of the new mailbox when completing the command,
+    /// which is then returned.
+    pub async fn create<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Option<u32>> {
+        let id = self
+            .run_command(&format!(
+                "CREATE {}",
+                self.quote_mailbox(mailbox_name.as_ref())?
+            ))
+            .await?;
+        let done = self
+            .conn
+            .check_done_ok(&id, Some(self.unsolicited_responses_tx.clone()))
+            .await?;
+
+        match done.parsed() {
+            Response::Done {
+                code: Some(imap_proto::ResponseCode::UidValidity(uid_validity)),
+                ..
+            } => Ok(Some(*uid_validity)),
+            _ => Ok(None),
+        }
+    }
+
+    /// The [`DELETE` command](https://tools.ietf.org/html/rfc3501#section-6.3.4) permanently
+    /// removes the mailbox with the given name.  `Ok` is returned only if the mailbox has been
+    /// deleted.  It is an error to attempt to delete `INBOX` or a mailbox name that does not
+    /// exist.
+    ///
+    /// The `DELETE` command will not remove inferior hierarchical names. For example, if a mailbox
+    /// `foo` has an inferior `foo.bar` (assuming `.` is the hierarchy delimiter character),
+    /// removing `foo` will not remove `foo.bar`.  It is an error to attempt to delete a name that
+    /// has inferior hierarchical names and also has [`NameAttribute::NoSelect`].
+    ///
+    /// It is permitted to delete a name that has inferior hierarchical names and does not have
+    /// [`NameAttribute::NoSelect`].  In this case, all messages in that mailbox are removed, and
+    /// the name will acquire [`NameAttribute::NoSelect`].
+    ///
+    /// The value of the highest-used unique identifier of the deleted mailbox will be preserved so
+    /// that a new mailbox created with the same name will not reuse the identifiers of the former
+    /// incarnation, UNLESS the new incarnation has a different unique identifier validity value.
+    /// See the description of the [`UID`
+    /// command](https://tools.ietf.org/html/rfc3501#section-6.4.8) for more detail.
+    pub async fn delete<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<()> {
+        self.run_command_and_check_ok(&format!(
+            "DELETE {}",
+            self.quote_mailbox(mailbox_name.as_ref())?
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    /// The [`RENAME` command](https://tools.ietf.org/html/rfc3501#section-6.3.5) changes the name
+    /// of a mailbox.  `Ok` is returned only if the mailbox has been renamed.  It is an error to
+    /// attempt to rename from a mailbox name that does not exist or to a mailbox name that already
+    /// exists.  Any error in renaming will return [`Error::No`].
+    ///
+    /// If the name has inferior hierarchical names, then the inferior hierarchical names will also
+    /// be renamed.  For example, a rename of `foo` to `zap` will rename `foo/bar` (assuming `/` is
     /// the hierarchy delimiter character) to `zap/bar`.
     ///
     /// If the server's hierarchy separator character appears in the name, the server will
@@ -624,12 +1384,18 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// Renaming `INBOX` is permitted, and has special behavior.  It moves all messages in `INBOX`
     /// to a new mailbox with the given name, leaving `INBOX` empty.  If the server implementation
     /// supports inferior hierarchical names of `INBOX`, these are unaffected by a rename of
-    /// `INBOX`.
+    /// `INBOX`.  So unlike other renames, `from` still exists afterwards; a client that caches the
+    /// mailbox list should add `to` rather than replace `INBOX` with it.  `INBOX` is matched
+    /// case-insensitively by the server, so this applies to e.g. `inbox` as well.
+    ///
+    /// Both names are encoded in modified UTF-7 like all mailbox names if
+    /// [`Session::set_encode_mailbox_names`] is turned on.  The server's response is returned as
+    /// is, e.g. [`Error::No`] with [`ResponseCode::AlreadyExists`] if `to` exists.
     pub async fn rename<S1: AsRef<str>, S2: AsRef<str>>(&mut self, from: S1, to: S2) -> Result<()> {
         self.run_command_and_check_ok(&format!(
             "RENAME {} {}",
-            quote!(from.as_ref()),
-            quote!(to.as_ref())
+            self.quote_mailbox(from.as_ref())?,
+            self.quote_mailbox(to.as_ref())?
         ))
         .await?;
 
@@ -645,8 +1411,11 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// However, it will not unilaterally remove an existing mailbox name from the subscription
     /// list even if a mailbox by that name no longer exists.
     pub async fn subscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<()> {
-        self.run_command_and_check_ok(&format!("SUBSCRIBE {}", quote!(mailbox.as_ref())))
-            .await?;
+        self.run_command_and_check_ok(&format!(
+            "SUBSCRIBE {}",
+            self.quote_mailbox(mailbox.as_ref())?
+        ))
+        .await?;
         Ok(())
     }
 
@@ -655,14 +1424,20 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// returned by [`Session::lsub`].  This command returns `Ok` only if the unsubscription is
     /// successful.
     pub async fn unsubscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<()> {
-        self.run_command_and_check_ok(&format!("UNSUBSCRIBE {}", quote!(mailbox.as_ref())))
-            .await?;
+        self.run_command_and_check_ok(&format!(
+            "UNSUBSCRIBE {}",
+            self.quote_mailbox(mailbox.as_ref())?
+        ))
+        .await?;
         Ok(())
     }
 
     /// The [`CAPABILITY` command](https://tools.ietf.org/html/rfc3501#section-6.1.1) requests a
     /// listing of capabilities that the server supports.  The server will include "IMAP4rev1" as
     /// one of the listed capabilities. See [`Capabilities`] for further details.
+    ///
+    /// If the server only advertises "IMAP4rev2", the session switches to IMAP4rev2 mode, see
+    /// [`Session::set_imap4rev2`].
     pub async fn capabilities(&mut self) -> Result<Capabilities> {
         let id = self.run_command("CAPABILITY").await?;
         let c = parse_capabilities(
@@ -671,9 +1446,227 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
             id,
         )
         .await?;
+        if c.is_imap4rev2_only() {
+            self.conn.imap4rev2 = true;
+        }
+        self.cached_capabilities = Some(c.clone());
         Ok(c)
     }
 
+    /// The capabilities the server advertised last, without asking it again: the result of the
+    /// last call to [`Session::capabilities`], the capabilities the server included when
+    /// completing `LOGIN` or `AUTHENTICATE`, or those it announced with an untagged `CAPABILITY`
+    /// while an earlier command ran. `None` if none of these is known.
+    ///
+    /// Announced capabilities are taken over as the command they came with completes if it
+    /// returns no data, e.g. [`Session::noop`], and otherwise when the next command is sent.
+    pub fn cached_capabilities(&self) -> Option<&Capabilities> {
+        self.cached_capabilities.as_ref()
+    }
+
+    /// Replaces the cached capabilities with those the server announced in the meantime, if any.
+    fn update_capabilities(&mut self) {
+        if let Some(caps) = self.unsolicited_responses_tx.take_capabilities() {
+            if caps.is_imap4rev2_only() {
+                self.conn.imap4rev2 = true;
+            }
+            self.cached_capabilities = Some(caps);
+        }
+    }
+
+    /// The number of unsolicited responses [`Session::unsolicited_responses`] holds, see
+    /// [`Client::set_unsolicited_capacity`].
+    pub fn unsolicited_capacity(&self) -> usize {
+        self.unsolicited_responses_tx.capacity()
+    }
+
+    /// Returns a new channel that receives a copy of every unsolicited response from now on, like
+    /// [`Session::unsolicited_responses`], so that several parts of an application can each
+    /// react to changes of the mailbox. Responses received while idling are included. A
+    /// subscriber that does not keep up loses its oldest responses, which are counted in
+    /// [`Session::unsolicited_subscribers_dropped`]; it never holds up the connection.
+    pub fn subscribe_unsolicited(&self) -> channel::Receiver<UnsolicitedResponse> {
+        self.unsolicited_responses_tx.subscribe()
+    }
+
+    /// The number of unsolicited responses that were dropped because the channel of a
+    /// subscriber was full or closed, see [`Session::subscribe_unsolicited`]. These are not
+    /// included in [`Session::unsolicited_dropped`].
+    pub fn unsolicited_subscribers_dropped(&self) -> u64 {
+        self.unsolicited_responses_tx.subscribers_dropped()
+    }
+
+    /// What happens to unsolicited responses, see [`Client::set_unsolicited_policy`].
+    pub fn unsolicited_policy(&self) -> UnsolicitedPolicy {
+        self.unsolicited_responses_tx.policy()
+    }
+
+    /// Sets what happens to the unsolicited responses received from now on, see
+    /// [`Client::set_unsolicited_policy`].
+    pub fn set_unsolicited_policy(&mut self, policy: UnsolicitedPolicy) {
+        self.unsolicited_responses_tx.set_policy(policy);
+    }
+
+    /// The number of unsolicited responses that were dropped because
+    /// [`Session::unsolicited_responses`] was full or closed, or because of
+    /// [`UnsolicitedPolicy::Drop`], including those received while idling. If it grows, updates
+    /// were missed and any state kept from them should be resynced.
+    pub fn unsolicited_dropped(&self) -> u64 {
+        self.unsolicited_responses_tx.dropped()
+    }
+
+    /// Whether the session is in IMAP4rev2 mode, see [`Session::set_imap4rev2`].
+    pub fn imap4rev2(&self) -> bool {
+        self.conn.imap4rev2
+    }
+
+    /// Turns IMAP4rev2 ([RFC 9051](https://tools.ietf.org/html/rfc9051)) mode on or off. It is
+    /// turned on automatically if the server advertises `IMAP4rev2` but not `IMAP4rev1`, either
+    /// in its greeting or in response to [`Session::capabilities`].
+    ///
+    /// In this mode mailbox names are sent and received as UTF-8, the extensions that are part
+    /// of IMAP4rev2, such as `ESEARCH` and `MOVE`, are assumed to be supported, and the `ESEARCH`
+    /// responses that `SEARCH` then returns are expected.
+    pub fn set_imap4rev2(&mut self, enabled: bool) {
+        self.conn.imap4rev2 = enabled;
+    }
+
+    /// Whether copies and moves create a missing destination mailbox, see
+    /// [`Session::set_create_on_trycreate`].
+    pub fn create_on_trycreate(&self) -> bool {
+        self.create_on_trycreate
+    }
+
+    /// Turns on or off creating the destination mailbox of [`Session::copy`], [`Session::mv`]
+    /// and their `UID` variants when the server rejects them with [`ResponseCode::TryCreate`]
+    /// because it does not exist. The mailbox is then created and the command retried once.
+    ///
+    /// This is off by default, so that a misspelled name does not silently create a mailbox.
+    pub fn set_create_on_trycreate(&mut self, enabled: bool) {
+        self.create_on_trycreate = enabled;
+    }
+
+    /// Whether keywords are checked before they are stored, see
+    /// [`Session::set_validate_keywords`].
+    pub fn validate_keywords(&self) -> bool {
+        self.validate_keywords
+    }
+
+    /// Turns on or off checking the keywords that [`Session::store`], [`Session::uid_store`] and
+    /// [`Session::uid_store_flags`] add or set against the `PERMANENTFLAGS` of the selected
+    /// mailbox, see [`Mailbox::can_store_keyword`]. A keyword the mailbox can not store
+    /// permanently then fails with [`Error::UnsupportedKeyword`] without sending the command.
+    ///
+    /// This is off by default, as servers may still keep such keywords for the session.
+    pub fn set_validate_keywords(&mut self, enabled: bool) {
+        self.validate_keywords = enabled;
+    }
+
+    /// Fails with [`Error::UnsupportedKeyword`] if keywords are validated and the `STORE` `query`
+    /// adds one that the selected mailbox can not store permanently.
+    fn check_keywords(&self, query: &str) -> Result<()> {
+        if !self.validate_keywords {
+            return Ok(());
+        }
+        let mailbox = match &self.selected {
+            Some((_, mailbox)) => mailbox,
+            None => return Ok(()),
+        };
+        match stored_keywords(query).find(|keyword| !mailbox.can_store_keyword(keyword)) {
+            Some(keyword) => Err(Error::UnsupportedKeyword(keyword.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns an error unless the server advertises `capability`. The capabilities are requested
+    /// from the server unless already known.
+    pub(crate) async fn ensure_capability(&mut self, capability: &str) -> Result<()> {
+        self.update_capabilities();
+        if self.conn.imap4rev2
+            && IMAP4REV2_EXTENSIONS
+                .iter()
+                .any(|ext| ext.eq_ignore_ascii_case(capability))
+        {
+            return Ok(());
+        }
+        let supported = match &self.cached_capabilities {
+            Some(caps) => caps.has_str(capability),
+            None => self.capabilities().await?.has_str(capability),
+        };
+        if supported {
+            Ok(())
+        } else {
+            Err(Error::MissingCapability(capability.to_string()))
+        }
+    }
+
+    /// The [`ENABLE` command](https://tools.ietf.org/html/rfc5161) turns on extensions that
+    /// change how the server behaves, e.g. `UTF8=ACCEPT` or `CONDSTORE`. Returns the
+    /// capabilities that the server actually enabled.
+    ///
+    /// Once `UTF8=ACCEPT` ([RFC 6855](https://tools.ietf.org/html/rfc6855)) is enabled, mailbox
+    /// names are sent and received as UTF-8 even if [`Session::set_encode_mailbox_names`] is
+    /// turned on, and [`Session::append`]
+    /// sends messages with UTF-8 headers as `UTF8` data.
+    pub async fn enable<S: AsRef<str>>(&mut self, capabilities: &[S]) -> Result<Capabilities> {
+        let capabilities: Vec<_> = capabilities.iter().map(AsRef::as_ref).collect();
+        let id = self
+            .run_command(&format!("ENABLE {}", capabilities.join(" ")))
+            .await?;
+        let enabled = parse_capabilities(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .await?;
+        if enabled.has_str("UTF8=ACCEPT") {
+            self.utf8_accept = true;
+        }
+
+        Ok(enabled)
+    }
+
+    /// Whether mailbox names are UTF-8 rather than modified UTF-7, i.e. once `UTF8=ACCEPT` is
+    /// enabled or in IMAP4rev2 mode.
+    fn utf8_names(&self) -> bool {
+        self.utf8_accept || self.conn.imap4rev2
+    }
+
+    /// Whether mailbox names are encoded and decoded, see [`Session::set_encode_mailbox_names`].
+    pub fn encode_mailbox_names(&self) -> bool {
+        self.encode_mailbox_names
+    }
+
+    /// Turns on or off encoding the mailbox names passed to commands in modified UTF-7 ([RFC 3501
+    /// section 5.1.3](https://tools.ietf.org/html/rfc3501#section-5.1.3)), and decoding the
+    /// names returned by [`Session::list`] and [`Session::lsub`]. Names are passed as they are
+    /// once `UTF8=ACCEPT` is enabled or in IMAP4rev2 mode.
+    ///
+    /// This is off by default, so that names the caller already encoded are not encoded again.
+    pub fn set_encode_mailbox_names(&mut self, enabled: bool) {
+        self.encode_mailbox_names = enabled;
+    }
+
+    /// Whether names returned by the server are in modified UTF-7 and are to be decoded.
+    fn decode_names(&self) -> bool {
+        self.encode_mailbox_names && !self.utf8_names()
+    }
+
+    /// Encodes a mailbox name for the server in modified UTF-7 if that is turned on and names are
+    /// not UTF-8, see [`Session::set_encode_mailbox_names`], or else passes it as it is.
+    pub(crate) fn encode_mailbox<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if !self.decode_names() || name.is_ascii() && !name.contains('&') {
+            Cow::Borrowed(name)
+        } else {
+            Cow::Owned(utf7::encode(name))
+        }
+    }
+
+    /// Encodes a mailbox name like [`Session::encode_mailbox`] and quotes it.
+    pub(crate) fn quote_mailbox(&self, name: &str) -> Result<String> {
+        validate_str(&self.encode_mailbox(name))
+    }
+
     /// The [`EXPUNGE` command](https://tools.ietf.org/html/rfc3501#section-6.4.3) permanently
     /// removes all messages that have [`Flag::Deleted`] set from the currently selected mailbox.
     /// The message sequence number of each message that is removed is returned.
@@ -709,12 +1702,15 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     ///
     /// Alternatively, the client may fall back to using just [`Session::expunge`], risking the
     /// unintended removal of some messages.
-    pub async fn uid_expunge<S: AsRef<str>>(
+    ///
+    /// Like [`Session::expunge`], the message sequence number of each message that is removed is
+    /// returned, as the server reports removals with `EXPUNGE` responses either way.
+    pub async fn uid_expunge<S: fmt::Display>(
         &mut self,
         uid_set: S,
-    ) -> Result<impl Stream<Item = Result<Uid>> + '_ + Send> {
+    ) -> Result<impl Stream<Item = Result<Seq>> + '_ + Send> {
         let id = self
-            .run_command(&format!("UID EXPUNGE {}", uid_set.as_ref()))
+            .run_command(&format!("UID EXPUNGE {}", uid_set))
             .await?;
         let res = parse_expunge(
             &mut self.conn.stream,
@@ -789,6 +1785,10 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     ///
     /// In all cases, `<flag list>` is a space-separated list enclosed in parentheses.
     ///
+    /// Once `CONDSTORE` is enabled, e.g. with [`Session::select_condstore`], the returned
+    /// [`Fetch`]es also carry the new [`Fetch::modseq`] of each changed message, so a cache can be
+    /// updated without fetching the flags again.
+    ///
     /// # Examples
     ///
     /// Delete a message:
@@ -811,22 +1811,14 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         query: S2,
     ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send>
     where
-        S1: AsRef<str>,
+        S1: fmt::Display,
         S2: AsRef<str>,
     {
+        self.check_keywords(query.as_ref())?;
         let id = self
-            .run_command(&format!(
-                "STORE {} {}",
-                sequence_set.as_ref(),
-                query.as_ref()
-            ))
+            .run_command(&format!("STORE {} {}", sequence_set, query.as_ref()))
             .await?;
-        let res = parse_fetches(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
-        Ok(res)
+        Ok(self.fetch_responses(id))
     }
 
     /// Equivalent to [`Session::store`], except that all identifiers in `sequence_set` are
@@ -837,22 +1829,88 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         query: S2,
     ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send>
     where
-        S1: AsRef<str>,
+        S1: fmt::Display,
         S2: AsRef<str>,
     {
+        self.check_keywords(query.as_ref())?;
         let id = self
-            .run_command(&format!(
-                "UID STORE {} {}",
-                uid_set.as_ref(),
-                query.as_ref()
-            ))
+            .run_command(&format!("UID STORE {} {}", uid_set, query.as_ref()))
             .await?;
-        let res = parse_fetches(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
-        Ok(res)
+        Ok(self.fetch_responses(id))
+    }
+
+    /// Adds, removes or replaces the `flags` of all messages in `uids` with a single `UID STORE`,
+    /// instead of one command for each message, and returns the new flags the server echoed.
+    /// Consecutive [`Uid`]s are sent as ranges, see [`SequenceSet`].
+    ///
+    /// ```no_run
+    /// use async_imap::{types::{Flag, FlagsOperation, Uid}, Session, error::Result};
+    /// use async_std::net::TcpStream;
+    ///
+    /// async fn mark_read(uids: Vec<Uid>, s: &mut Session<TcpStream>) -> Result<()> {
+    ///     s.uid_store_flags(uids, FlagsOperation::Add, &[Flag::Seen]).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// No command is sent if `uids` is empty. If the command fails after the server echoed some
+    /// of the flags, [`Error::PartialFetch`] carries them.
+    pub async fn uid_store_flags<I>(
+        &mut self,
+        uids: I,
+        operation: FlagsOperation,
+        flags: &[Flag<'_>],
+    ) -> Result<Vec<Fetch>>
+    where
+        I: IntoIterator<Item = Uid>,
+    {
+        let uid_set: SequenceSet = uids.into_iter().collect();
+        if uid_set.is_empty() {
+            return Ok(Vec::new());
+        }
+        let query = flags_query(operation, flags)?;
+        self.check_keywords(&query)?;
+        let fetches = self.fetch_with("UID STORE", uid_set, &query).await?;
+        collect_fetches(fetches).await
+    }
+
+    /// Adds, removes or replaces the Gmail labels of a set of messages, using the [Gmail IMAP
+    /// extensions](crate::extensions::gmail). Label names are encoded in modified UTF-7, so they
+    /// can be given as-is. The updated labels are returned as if a `FETCH` of `X-GM-LABELS` was
+    /// done, and are available through [`Fetch::gmail_labels`].
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the `X-GM-EXT-1`
+    /// capability.
+    pub async fn store_gmail_labels<S, L>(
+        &mut self,
+        sequence_set: S,
+        operation: LabelsOperation,
+        labels: &[L],
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
+    where
+        S: fmt::Display,
+        L: AsRef<str>,
+    {
+        self.ensure_capability("X-GM-EXT-1").await?;
+        let query = gmail_labels_query(operation, labels)?;
+        self.fetch_with("STORE", sequence_set, &query).await
+    }
+
+    /// Equivalent to [`Session::store_gmail_labels`], except that all identifiers in `uid_set`
+    /// are [`Uid`]s.
+    pub async fn uid_store_gmail_labels<S, L>(
+        &mut self,
+        uid_set: S,
+        operation: LabelsOperation,
+        labels: &[L],
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
+    where
+        S: fmt::Display,
+        L: AsRef<str>,
+    {
+        self.ensure_capability("X-GM-EXT-1").await?;
+        let query = gmail_labels_query(operation, labels)?;
+        self.fetch_with("UID STORE", uid_set, &query).await
     }
 
     /// The [`COPY` command](https://tools.ietf.org/html/rfc3501#section-6.4.7) copies the
@@ -862,36 +1920,39 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     ///
     /// If the `COPY` command is unsuccessful for any reason, the server restores the destination
     /// mailbox to its state before the `COPY` attempt.
-    pub async fn copy<S1: AsRef<str>, S2: AsRef<str>>(
+    ///
+    /// If the server supports the [`UIDPLUS` extension](https://tools.ietf.org/html/rfc4315), the
+    /// [`Uid`]s of the copies and the `UIDVALIDITY` of the destination mailbox are returned. Check
+    /// the latter against the cached one before relying on the former.
+    ///
+    /// To create the destination mailbox if it does not exist, see
+    /// [`Session::set_create_on_trycreate`].
+    pub async fn copy<S1: fmt::Display, S2: AsRef<str>>(
         &mut self,
         sequence_set: S1,
         mailbox_name: S2,
-    ) -> Result<()> {
-        self.run_command_and_check_ok(&format!(
+    ) -> Result<Option<CopyUid>> {
+        let command = format!(
             "COPY {} {}",
-            sequence_set.as_ref(),
-            mailbox_name.as_ref()
-        ))
-        .await?;
-
-        Ok(())
+            sequence_set,
+            self.quote_mailbox(mailbox_name.as_ref())?
+        );
+        self.run_copy(&command, mailbox_name.as_ref()).await
     }
 
     /// Equivalent to [`Session::copy`], except that all identifiers in `sequence_set` are
     /// [`Uid`]s. See also the [`UID` command](https://tools.ietf.org/html/rfc3501#section-6.4.8).
-    pub async fn uid_copy<S1: AsRef<str>, S2: AsRef<str>>(
+    pub async fn uid_copy<S1: fmt::Display, S2: AsRef<str>>(
         &mut self,
         uid_set: S1,
         mailbox_name: S2,
-    ) -> Result<()> {
-        self.run_command_and_check_ok(&format!(
+    ) -> Result<Option<CopyUid>> {
+        let command = format!(
             "UID COPY {} {}",
-            uid_set.as_ref(),
-            mailbox_name.as_ref()
-        ))
-        .await?;
-
-        Ok(())
+            uid_set,
+            self.quote_mailbox(mailbox_name.as_ref())?
+        );
+        self.run_copy(&command, mailbox_name.as_ref()).await
     }
 
     /// The [`MOVE` command](https://tools.ietf.org/html/rfc6851#section-3.1) takes two
@@ -924,38 +1985,82 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// orphaned).  The server will generally not leave any message in both mailboxes (it would be
     /// bad for a partial failure to result in a bunch of duplicate messages).  This is true even
     /// if the server returns with [`Error::No`].
-    pub async fn mv<S1: AsRef<str>, S2: AsRef<str>>(
+    ///
+    /// Like [`Session::copy`], returns the [`Uid`]s of the moved messages in the target mailbox and
+    /// its `UIDVALIDITY` if the server supports the `UIDPLUS` extension.
+    pub async fn mv<S1: fmt::Display, S2: AsRef<str>>(
         &mut self,
         sequence_set: S1,
         mailbox_name: S2,
-    ) -> Result<()> {
-        self.run_command_and_check_ok(&format!(
+    ) -> Result<Option<CopyUid>> {
+        let command = format!(
             "MOVE {} {}",
-            sequence_set.as_ref(),
-            validate_str(mailbox_name.as_ref())?
-        ))
-        .await?;
-
-        Ok(())
+            sequence_set,
+            self.quote_mailbox(mailbox_name.as_ref())?
+        );
+        self.run_copy(&command, mailbox_name.as_ref()).await
     }
 
     /// Equivalent to [`Session::copy`], except that all identifiers in `sequence_set` are
     /// [`Uid`]s. See also the [`UID` command](https://tools.ietf.org/html/rfc3501#section-6.4.8)
     /// and the [semantics of `MOVE` and `UID
     /// MOVE`](https://tools.ietf.org/html/rfc6851#section-3.3).
-    pub async fn uid_mv<S1: AsRef<str>, S2: AsRef<str>>(
+    pub async fn uid_mv<S1: fmt::Display, S2: AsRef<str>>(
         &mut self,
         uid_set: S1,
         mailbox_name: S2,
-    ) -> Result<()> {
-        self.run_command_and_check_ok(&format!(
+    ) -> Result<Option<CopyUid>> {
+        let command = format!(
             "UID MOVE {} {}",
-            uid_set.as_ref(),
-            validate_str(mailbox_name.as_ref())?
-        ))
-        .await?;
+            uid_set,
+            self.quote_mailbox(mailbox_name.as_ref())?
+        );
+        self.run_copy(&command, mailbox_name.as_ref()).await
+    }
 
-        Ok(())
+    /// Runs a `COPY` or `MOVE` command to `mailbox_name`, creating the mailbox and retrying if
+    /// the server asks to and [`Session::set_create_on_trycreate`] is on.
+    async fn run_copy(&mut self, command: &str, mailbox_name: &str) -> Result<Option<CopyUid>> {
+        match self.run_copy_once(command).await {
+            Err(Error::No(res))
+                if self.create_on_trycreate && res.code == Some(ResponseCode::TryCreate) =>
+            {
+                self.create(mailbox_name).await?;
+                self.run_copy_once(command).await
+            }
+            res => res,
+        }
+    }
+
+    /// Runs a `COPY` or `MOVE` command, returning the `COPYUID` code of either the tagged response
+    /// or, as servers send it for `MOVE`, an untagged `OK`.
+    async fn run_copy_once(&mut self, command: &str) -> Result<Option<CopyUid>> {
+        let id = self.run_command(command).await?;
+        let mut copy_uid = None;
+        while let Some(res) = self.conn.stream.next().await {
+            let res = res?;
+            let untagged = match res.parsed() {
+                Response::Data {
+                    status: imap_proto::Status::Ok,
+                    code: Some(code),
+                    ..
+                } => CopyUid::from_code(code)?,
+                _ => None,
+            };
+            if untagged.is_some() {
+                copy_uid = untagged;
+                continue;
+            }
+            let sender = self.unsolicited_responses_tx.clone();
+            let done = self.conn.check_done_ok_from(&id, Some(sender), res).await?;
+            return Ok(match done.parsed() {
+                Response::Done {
+                    code: Some(code), ..
+                } => CopyUid::from_code(code)?.or(copy_uid),
+                _ => copy_uid,
+            });
+        }
+        Err(Error::ConnectionLost)
     }
 
     /// The [`LIST` command](https://tools.ietf.org/html/rfc3501#section-6.3.8) returns a subset of
@@ -989,6 +2094,8 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// arguments with wildcards.  The criteria for omitting `INBOX` is whether `SELECT INBOX` will
     /// return failure; it is not relevant whether the user's real `INBOX` resides on this or some
     /// other server.
+    ///
+    /// To show the names as a hierarchy, collect them into a [`MailboxTree`].
     pub async fn list(
         &mut self,
         reference_name: Option<&str>,
@@ -997,15 +2104,80 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(&format!(
                 "LIST {} {}",
-                quote!(reference_name.unwrap_or("")),
-                mailbox_pattern.unwrap_or("\"\"")
+                self.quote_mailbox(reference_name.unwrap_or(""))?,
+                self.encode_mailbox(mailbox_pattern.unwrap_or("\"\""))
             ))
             .await?;
+        let decode_utf7 = self.decode_names();
+
+        Ok(parse_names(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+            decode_utf7,
+        ))
+    }
+
+    /// Like [`Session::list`], with the selection and return options of the [`LIST-EXTENDED`
+    /// extension](https://tools.ietf.org/html/rfc5258), e.g. to list only the subscribed
+    /// mailboxes with [`ListSelection::Subscribed`] instead of using [`Session::lsub`]:
+    ///
+    /// ```no_run
+    /// use async_imap::extensions::list_extended::{ListReturn, ListSelection};
+    /// use futures::TryStreamExt;
+    /// # fn main() -> async_imap::error::Result<()> {
+    /// # async_std::task::block_on(async {
+    /// # let tls = async_native_tls::TlsConnector::new();
+    /// # let client = async_imap::connect(("imap.example.org", 993), "imap.example.org", tls).await?;
+    /// # let mut session = client.login("user", "pass").await.map_err(|e| e.0)?;
+    /// let names: Vec<_> = session
+    ///     .list_extended(
+    ///         &[ListSelection::Subscribed],
+    ///         None,
+    ///         Some("*"),
+    ///         &[ListReturn::Children],
+    ///     )
+    ///     .await?
+    ///     .try_collect()
+    ///     .await?;
+    /// # Ok(())
+    /// # }) }
+    /// ```
+    ///
+    /// Fails with [`Error::MissingCapability`] unless the server advertises `LIST-EXTENDED`.
+    pub async fn list_extended(
+        &mut self,
+        selection: &[ListSelection],
+        reference_name: Option<&str>,
+        mailbox_pattern: Option<&str>,
+        return_options: &[ListReturn],
+    ) -> Result<impl Stream<Item = Result<Name>> + '_ + Send> {
+        self.ensure_capability("LIST-EXTENDED").await?;
+        let mut command = "LIST ".to_string();
+        if !selection.is_empty() {
+            let selection: Vec<_> = selection.iter().map(|option| option.as_str()).collect();
+            command.push_str(&format!("({}) ", selection.join(" ")));
+        }
+        command.push_str(&format!(
+            "{} {}",
+            self.quote_mailbox(reference_name.unwrap_or(""))?,
+            self.encode_mailbox(mailbox_pattern.unwrap_or("\"\""))
+        ));
+        if !return_options.is_empty() {
+            let options: Vec<_> = return_options
+                .iter()
+                .map(|option| option.as_str())
+                .collect();
+            command.push_str(&format!(" RETURN ({})", options.join(" ")));
+        }
+        let id = self.run_command(&command).await?;
+        let decode_utf7 = self.decode_names();
 
         Ok(parse_names(
             &mut self.conn.stream,
             self.unsolicited_responses_tx.clone(),
             id,
+            decode_utf7,
         ))
     }
 
@@ -1032,14 +2204,16 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(&format!(
                 "LSUB {} {}",
-                quote!(reference_name.unwrap_or("")),
-                mailbox_pattern.unwrap_or("")
+                self.quote_mailbox(reference_name.unwrap_or(""))?,
+                self.encode_mailbox(mailbox_pattern.unwrap_or(""))
             ))
             .await?;
+        let decode_utf7 = self.decode_names();
         let names = parse_names(
             &mut self.conn.stream,
             self.unsolicited_responses_tx.clone(),
             id,
+            decode_utf7,
         );
 
         Ok(names)
@@ -1087,7 +2261,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(&format!(
                 "STATUS {} {}",
-                validate_str(mailbox_name.as_ref())?,
+                self.quote_mailbox(mailbox_name.as_ref())?,
                 data_items.as_ref()
             ))
             .await?;
@@ -1096,10 +2270,42 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
             self.unsolicited_responses_tx.clone(),
             id,
         )
-        .await?;
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()))?;
         Ok(mbox)
     }
 
+    /// Gets the state of a mailbox that a client needs to decide whether to resynchronize it,
+    /// with a single [`STATUS` command](https://tools.ietf.org/html/rfc3501#section-6.3.10)
+    /// instead of selecting the mailbox: its `MESSAGES`, `UIDNEXT` and `UIDVALIDITY`, and its
+    /// `HIGHESTMODSEQ` if the server supports the `CONDSTORE` capability.
+    ///
+    /// Like [`Session::status`], this SHOULD NOT be used on the currently selected mailbox.
+    pub async fn sync_snapshot<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<SyncSnapshot> {
+        let condstore = match self.ensure_capability("CONDSTORE").await {
+            Ok(()) => true,
+            Err(Error::MissingCapability(_)) => false,
+            Err(err) => return Err(err),
+        };
+        let items = if condstore {
+            "(MESSAGES UIDNEXT UIDVALIDITY HIGHESTMODSEQ)"
+        } else {
+            "(MESSAGES UIDNEXT UIDVALIDITY)"
+        };
+        let name = self.encode_mailbox(mailbox_name.as_ref()).into_owned();
+        let id = self
+            .run_command(&format!("STATUS {} {}", validate_str(&name)?, items))
+            .await?;
+        parse_sync_snapshot(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+            &name,
+        )
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()))
+    }
+
     /// This method returns a handle that lets you use the [`IDLE`
     /// command](https://tools.ietf.org/html/rfc2177#section-3) to listen for changes to the
     /// currently selected mailbox.
@@ -1122,6 +2328,84 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         extensions::idle::Handle::new(self)
     }
 
+    /// The [`NOTIFY` command](https://tools.ietf.org/html/rfc5465) asks the server to report the
+    /// events of `groups` as they happen, also for mailboxes other than the selected one, which
+    /// `IDLE` can not. It replaces the groups of an earlier `notify`.
+    ///
+    /// The events arrive on [`Session::unsolicited_responses`] in response to any command,
+    /// including `IDLE`. New and expunged messages of mailboxes other than the selected one are
+    /// reported as [`UnsolicitedResponse::Status`] naming the mailbox, and those of the selected
+    /// mailbox as usual, e.g. as [`UnsolicitedResponse::Exists`]. If `status` is set, the server
+    /// first sends the status of each mailbox other than the selected one, to start from.
+    ///
+    /// ```no_run
+    /// use async_imap::extensions::notify::{NotifyEvent, NotifyGroup, NotifyMailboxes};
+    /// # fn main() -> async_imap::error::Result<()> {
+    /// # async_std::task::block_on(async {
+    /// # let tls = async_native_tls::TlsConnector::new();
+    /// # let client = async_imap::connect(("imap.example.org", 993), "imap.example.org", tls).await?;
+    /// # let mut session = client.login("user", "pass").await.map_err(|e| e.0)?;
+    /// let events = [NotifyEvent::MessageNew, NotifyEvent::MessageExpunge];
+    /// session
+    ///     .notify(
+    ///         &[
+    ///             NotifyGroup::new(NotifyMailboxes::Selected, &events),
+    ///             NotifyGroup::new(NotifyMailboxes::Subscribed, &events),
+    ///         ],
+    ///         true,
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }) }
+    /// ```
+    ///
+    /// Once the server can not keep up, it sends an `OK` with a `NOTIFICATIONOVERFLOW` code and
+    /// stops reporting events until `notify` is called again. Fails with
+    /// [`Error::MissingCapability`] unless the server advertises `NOTIFY`.
+    pub async fn notify(&mut self, groups: &[NotifyGroup], status: bool) -> Result<()> {
+        self.ensure_capability("NOTIFY").await?;
+        let mut command = "NOTIFY SET".to_string();
+        if status {
+            command.push_str(" STATUS");
+        }
+        for group in groups {
+            let mailboxes = match &group.mailboxes {
+                NotifyMailboxes::Selected => "SELECTED".to_string(),
+                NotifyMailboxes::SelectedDelayed => "SELECTED-DELAYED".to_string(),
+                NotifyMailboxes::Inboxes => "INBOXES".to_string(),
+                NotifyMailboxes::Personal => "PERSONAL".to_string(),
+                NotifyMailboxes::Subscribed => "SUBSCRIBED".to_string(),
+                NotifyMailboxes::Subtree(names) => format!("SUBTREE {}", self.mailbox_list(names)?),
+                NotifyMailboxes::Mailboxes(names) => {
+                    format!("MAILBOXES {}", self.mailbox_list(names)?)
+                }
+            };
+            let events = if group.events.is_empty() {
+                "NONE".to_string()
+            } else {
+                let events: Vec<_> = group.events.iter().map(|event| event.as_str()).collect();
+                format!("({})", events.join(" "))
+            };
+            command.push_str(&format!(" ({} {})", mailboxes, events));
+        }
+        self.run_command_and_check_ok(&command).await
+    }
+
+    /// Asks the server to stop reporting the events set with [`Session::notify`].
+    pub async fn notify_none(&mut self) -> Result<()> {
+        self.ensure_capability("NOTIFY").await?;
+        self.run_command_and_check_ok("NOTIFY NONE").await
+    }
+
+    /// Quotes `names` as a parenthesized list of mailboxes.
+    fn mailbox_list(&self, names: &[String]) -> Result<String> {
+        let names = names
+            .iter()
+            .map(|name| self.quote_mailbox(name))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(format!("({})", names.join(" ")))
+    }
+
     /// The [`APPEND` command](https://tools.ietf.org/html/rfc3501#section-6.3.11) appends
     /// `content` as a new message to the end of the specified destination `mailbox`.  This
     /// argument SHOULD be in the format of an [RFC-2822](https://tools.ietf.org/html/rfc2822)
@@ -1135,40 +2419,135 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// the append attempt; no partial appending will happen.
     ///
     /// If the destination `mailbox` does not exist, the server returns an error, and does not
-    /// automatically create the mailbox.
+    /// automatically create the mailbox. The server may also refuse the message before it is sent,
+    /// e.g. because it is too large, in which case that tagged `NO` or `BAD` is returned.
     ///
     /// If the mailbox is currently selected, the normal new message actions will generally occur.
     /// Specifically, the server will generally notify the client immediately via an untagged
     /// `EXISTS` response.  If the server does not do so, the client MAY issue a `NOOP` command (or
     /// failing that, a `CHECK` command) after one or more `APPEND` commands.
+    ///
+    /// If the server supports the [`UIDPLUS` extension](https://tools.ietf.org/html/rfc4315), the
+    /// [`Uid`] of the new message and the `UIDVALIDITY` of the mailbox are returned. Check the
+    /// latter against the cached one before relying on the former.
     pub async fn append<S: AsRef<str>, B: AsRef<[u8]>>(
         &mut self,
         mailbox: S,
         content: B,
-    ) -> Result<()> {
+    ) -> Result<Option<AppendUid>> {
         let content = content.as_ref();
-        self.run_command(&format!(
-            "APPEND \"{}\" {{{}}}",
-            mailbox.as_ref(),
-            content.len()
-        ))
-        .await?;
+        let mailbox = self.quote_mailbox(mailbox.as_ref())?;
+        // Once `UTF8=ACCEPT` is enabled, messages with UTF-8 headers are sent as `UTF8` data,
+        // see RFC 6855 section 4.
+        let utf8 = self.utf8_accept && !content.is_ascii();
+        let id = if utf8 {
+            self.run_command(&format!("APPEND {} UTF8 (~{{{}}}", mailbox, content.len()))
+                .await?
+        } else {
+            self.run_command(&format!("APPEND {} {{{}}}", mailbox, content.len()))
+                .await?
+        };
 
-        match self.read_response().await {
-            Some(Ok(res)) => {
-                if let Response::Continue { .. } = res.parsed() {
-                    self.stream.as_mut().write_all(content).await?;
-                    self.stream.as_mut().write_all(b"\r\n").await?;
-                    self.stream.flush().await?;
-                    self.read_response().await.transpose()?;
-                    Ok(())
-                } else {
-                    Err(Error::Append)
+        // The server may reject the literal, e.g. with `[TOOBIG]`, instead of asking for it.
+        let sender = self.unsolicited_responses_tx.clone();
+        self.wait_for_continuation(&id, Some(sender)).await?;
+        self.stream.as_mut().write_all(content).await?;
+        if utf8 {
+            self.stream.as_mut().write_all(b")").await?;
+        }
+        self.stream.as_mut().write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+
+        let sender = self.unsolicited_responses_tx.clone();
+        let done = self.check_done_ok(&id, Some(sender)).await?;
+        append_uid(&done)
+    }
+
+    /// Appends a message like [`Session::append`], and also returns the number of messages the
+    /// server reported with an `EXISTS` if `mailbox` is the selected one, see
+    /// [`Session::selected`]. Without a concurrent append by another client, that is the
+    /// [`Seq`] of the new message, which is useful without `UIDPLUS`.
+    pub async fn append_with_exists<S: AsRef<str>, B: AsRef<[u8]>>(
+        &mut self,
+        mailbox: S,
+        content: B,
+    ) -> Result<Appended> {
+        let selected = match self.selected_name() {
+            Some(name) => {
+                name == mailbox.as_ref()
+                    || name.eq_ignore_ascii_case("INBOX")
+                        && mailbox.as_ref().eq_ignore_ascii_case("INBOX")
+            }
+            None => false,
+        };
+        let updates = self.unsolicited_responses_tx.exists_updates();
+        let uid = self.append(mailbox, content).await?;
+        let exists = if selected && self.unsolicited_responses_tx.exists_updates() != updates {
+            Some(self.unsolicited_responses_tx.exists())
+        } else {
+            None
+        };
+        Ok(Appended { uid, exists })
+    }
+
+    /// Appends a new message to the end of `mailbox` that the server assembles from `parts`, using
+    /// the [`CATENATE` extension](https://tools.ietf.org/html/rfc4469) to
+    /// [`APPEND`](Session::append). Parts referenced by URL are copied on the server, so they do
+    /// not have to be downloaded and uploaded again, e.g. to resend a message with a new header.
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the `CATENATE`
+    /// capability.
+    pub async fn append_catenate<S: AsRef<str>>(
+        &mut self,
+        mailbox: S,
+        parts: &[extensions::catenate::CatenatePart<'_>],
+    ) -> Result<Option<AppendUid>> {
+        use extensions::catenate::CatenatePart;
+
+        self.ensure_capability("CATENATE").await?;
+
+        let mut command = format!(
+            "APPEND {} CATENATE (",
+            self.quote_mailbox(mailbox.as_ref())?
+        );
+        let mut id = None;
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                command.push(' ');
+            }
+            match part {
+                CatenatePart::Url(url) => {
+                    command.push_str("URL ");
+                    command.push_str(&validate_str(url)?);
+                }
+                CatenatePart::Text(text) => {
+                    command.push_str(&format!("TEXT {{{}}}", text.len()));
+                    let id = match &id {
+                        Some(id) => {
+                            self.run_command_untagged(&command).await?;
+                            id
+                        }
+                        None => &*id.insert(self.run_command(&command).await?),
+                    };
+                    let sender = self.unsolicited_responses_tx.clone();
+                    self.wait_for_continuation(id, Some(sender)).await?;
+                    self.stream.as_mut().write_all(text).await?;
+                    command.clear();
                 }
             }
-            Some(Err(err)) => Err(err.into()),
-            _ => Err(Error::Append),
         }
+        command.push(')');
+
+        let id = match id {
+            Some(id) => {
+                self.run_command_untagged(&command).await?;
+                id
+            }
+            None => self.run_command(&command).await?,
+        };
+        let sender = self.unsolicited_responses_tx.clone();
+        let done = self.check_done_ok(&id, Some(sender)).await?;
+        append_uid(&done)
     }
 
     /// The [`SEARCH` command](https://tools.ietf.org/html/rfc3501#section-6.4.4) searches the
@@ -1191,6 +2570,11 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// In all search keys that use strings, a message matches the key if the string is a substring
     /// of the field.  The matching is case-insensitive.
     ///
+    /// If `query` contains non-ASCII text, `CHARSET UTF-8` is sent with it, unless it starts with
+    /// a `CHARSET` already. If the server does not support the charset, the returned
+    /// [`Error::No`] has a [`ResponseCode::BadCharset`](crate::error::ResponseCode::BadCharset)
+    /// code that lists the charsets it does support.
+    ///
     /// Below is a selection of common search keys.  The full list can be found in the
     /// specification of the [`SEARCH command`](https://tools.ietf.org/html/rfc3501#section-6.4.4).
     ///
@@ -1217,14 +2601,15 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     ///  - `SINCE <date>`: Messages whose internal date (disregarding time and timezone) is within or later than the specified date.
     pub async fn search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Seq>> {
         let id = self
-            .run_command(&format!("SEARCH {}", query.as_ref()))
+            .run_command(&format!("SEARCH {}", search_keys(query.as_ref())))
             .await?;
         let seqs = parse_ids(
             &mut self.conn.stream,
             self.unsolicited_responses_tx.clone(),
             id,
         )
-        .await?;
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()))?;
 
         Ok(seqs)
     }
@@ -1234,94 +2619,531 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// command](https://tools.ietf.org/html/rfc3501#section-6.4.8).
     pub async fn uid_search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Uid>> {
         let id = self
-            .run_command(&format!("UID SEARCH {}", query.as_ref()))
+            .run_command(&format!("UID SEARCH {}", search_keys(query.as_ref())))
             .await?;
         let uids = parse_ids(
             &mut self.conn.stream,
             self.unsolicited_responses_tx.clone(),
             id,
         )
-        .await?;
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()))?;
 
         Ok(uids)
     }
 
-    /// The [`GETQUOTA` command](https://tools.ietf.org/html/rfc2087#section-4.2)
-    pub async fn get_quota(&mut self, quota_root: &str) -> Result<Quota> {
-        let id = self
-            .run_command(format!("GETQUOTA {}", quote!(quota_root)))
-            .await?;
-        let c = parse_get_quota(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
-        Ok(c)
+    /// Searches the mailbox like [`Session::search`], but saves the result on the server as `$`
+    /// instead of returning it, using the [`SEARCHRES`
+    /// extension](https://tools.ietf.org/html/rfc5182). `$` can then take the place of the
+    /// sequence set of later commands, e.g. `session.fetch("$", "FLAGS")`, or of a search key.
+    ///
+    /// The result no longer matches the mailbox once messages are added to or expunged from it,
+    /// as seen in [`UnsolicitedResponse::Exists`], [`UnsolicitedResponse::Expunge`] or `VANISHED`
+    /// responses, or when the session expunges, moves or selects itself. Commands that use `$`
+    /// then fail with [`Error::SavedSearchInvalid`] instead of being sent, until the search is
+    /// saved again. See [`Session::saved_search_valid`].
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the `SEARCHRES`
+    /// capability.
+    pub async fn search_save<S: AsRef<str>>(&mut self, query: S) -> Result<()> {
+        self.ensure_capability("SEARCHRES").await?;
+        self.run_command_and_check_ok(format!(
+            "SEARCH RETURN (SAVE) {}",
+            search_keys(query.as_ref())
+        ))
+        .await
     }
 
-    /// The [`GETQUOTAROOT` command](https://tools.ietf.org/html/rfc2087#section-4.3)
-    pub async fn get_quota_root(
-        &mut self,
-        mailbox_name: &str,
-    ) -> Result<(Vec<QuotaRoot>, Vec<Quota>)> {
-        let id = self
-            .run_command(format!("GETQUOTAROOT {}", quote!(mailbox_name)))
-            .await?;
-        let c = parse_get_quota_root(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
-        Ok(c)
+    /// Equivalent to [`Session::search_save`], except that the saved result is a set of [`Uid`]s,
+    /// to be used with the `UID` commands, e.g. [`Session::uid_fetch`].
+    pub async fn uid_search_save<S: AsRef<str>>(&mut self, query: S) -> Result<()> {
+        self.ensure_capability("SEARCHRES").await?;
+        self.run_command_and_check_ok(format!(
+            "UID SEARCH RETURN (SAVE) {}",
+            search_keys(query.as_ref())
+        ))
+        .await
     }
 
-    // these are only here because they are public interface, the rest is in `Connection`
-    /// Runs a command and checks if it returns OK.
-    pub async fn run_command_and_check_ok<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
-        self.conn
-            .run_command_and_check_ok(
-                command.as_ref(),
-                Some(self.unsolicited_responses_tx.clone()),
-            )
-            .await?;
+    /// Whether the search result saved as `$` by [`Session::search_save`] still matches the
+    /// mailbox, so that commands can use it.
+    pub fn saved_search_valid(&self) -> bool {
+        self.saved_search == Some(self.unsolicited_responses_tx.changes())
+    }
 
+    /// Fails with [`Error::InvalidState`] if `command` is not valid in the state of the session,
+    /// and keeps track of the commands that change it.
+    fn track_state(&mut self, command: &str) -> Result<()> {
+        let name = command_name(command);
+        let valid = match self.state {
+            SessionState::Authenticated => !requires_selected(&name),
+            SessionState::Selected => true,
+            SessionState::Logout => false,
+        };
+        if !valid {
+            return Err(Error::InvalidState(name));
+        }
+        match name.as_str() {
+            // The previous mailbox is closed even if the command fails, see `track_completion`.
+            "SELECT" | "EXAMINE" => {
+                self.state = SessionState::Selected;
+                self.selected = None;
+            }
+            "CLOSE" | "UNSELECT" => {
+                self.state = SessionState::Authenticated;
+                self.selected = None;
+            }
+            "LOGOUT" => self.state = SessionState::Logout,
+            _ => {}
+        }
         Ok(())
     }
 
-    /// Runs any command passed to it.
-    pub async fn run_command<S: AsRef<str>>(&mut self, command: S) -> Result<RequestId> {
-        let id = self.conn.run_command(command.as_ref()).await?;
-
-        Ok(id)
+    /// Keeps track of the state of the session once `command` completed, `ok` telling whether it
+    /// succeeded: no mailbox is selected after a failed `SELECT` or `EXAMINE`.
+    fn track_completion(&mut self, command: &str, ok: bool) {
+        if !ok && matches!(command_name(command).as_str(), "SELECT" | "EXAMINE") {
+            self.state = SessionState::Authenticated;
+            self.selected = None;
+        }
     }
 
-    /// Runs an arbitrary command, without adding a tag to it.
-    pub async fn run_command_untagged<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
-        self.conn.run_command_untagged(command.as_ref()).await?;
-
+    /// Fails if `command` uses the saved search result `$` while it is not valid, and keeps
+    /// track of the commands that save it or make it invalid.
+    fn track_saved_search(&mut self, command: &str) -> Result<()> {
+        if uses_saved_search(command) && !self.saved_search_valid() {
+            return Err(Error::SavedSearchInvalid);
+        }
+        match command_name(command).as_str() {
+            "SEARCH" | "UID SEARCH" if saves_search(command) => {
+                self.saved_search = Some(self.unsolicited_responses_tx.changes());
+            }
+            "SELECT" | "EXAMINE" | "CLOSE" | "UNSELECT" | "EXPUNGE" | "UID EXPUNGE" | "MOVE"
+            | "UID MOVE" => self.saved_search = None,
+            _ => {}
+        }
         Ok(())
     }
 
-    /// Read the next response on the connection.
-    pub async fn read_response(&mut self) -> Option<io::Result<ResponseData>> {
-        self.conn.read_response().await
+    /// Searches the mailbox like [`Session::search`], but returns the results in the extended
+    /// form of the [`ESEARCH` extension](https://tools.ietf.org/html/rfc4731): the lowest and
+    /// highest matching [`Seq`], the number of matches and all matches as a [`SequenceSet`].
+    ///
+    /// `query` may start with a `RETURN` option to only request some of these, e.g.
+    /// `RETURN (COUNT) UNSEEN`, otherwise all of them are requested.
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the `ESEARCH`
+    /// capability, unless the session is in [IMAP4rev2 mode](Session::set_imap4rev2).
+    pub async fn esearch<S: AsRef<str>>(&mut self, query: S) -> Result<ESearch> {
+        self.run_esearch("SEARCH", query.as_ref()).await
     }
-}
-
-impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
-    unsafe_pinned!(stream: ImapStream<T>);
 
-    /// Convert this connection into the raw underlying stream.
-    pub fn into_inner(self) -> T {
-        let Self { stream, .. } = self;
-        stream.into_inner()
+    /// Equivalent to [`Session::esearch`], except that the results are [`Uid`]s instead of
+    /// [`Seq`]s.
+    pub async fn uid_esearch<S: AsRef<str>>(&mut self, query: S) -> Result<ESearch> {
+        self.run_esearch("UID SEARCH", query.as_ref()).await
     }
 
-    /// Read the next response on the connection.
-    pub async fn read_response(&mut self) -> Option<io::Result<ResponseData>> {
-        self.stream.next().await
+    /// Searches the mailbox like [`Session::esearch`], but only returns the window `range` of
+    /// the matching [`Seq`]s as [`ESearch::partial`], together with the number of matches, using
+    /// the [`PARTIAL` extension](https://tools.ietf.org/html/rfc9394). This allows paging
+    /// through large results.
+    ///
+    /// `range` is e.g. `1:50` for the first 50 matches, or `-1:-50` for the last 50.
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the `PARTIAL`
+    /// capability.
+    pub async fn esearch_partial<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        range: S1,
+        query: S2,
+    ) -> Result<ESearch> {
+        self.ensure_capability("PARTIAL").await?;
+        self.read_esearch(&format!(
+            "SEARCH RETURN (PARTIAL {} COUNT) {}",
+            range.as_ref(),
+            search_keys(query.as_ref())
+        ))
+        .await
+    }
+
+    /// Equivalent to [`Session::esearch_partial`], except that the results are [`Uid`]s instead
+    /// of [`Seq`]s.
+    pub async fn uid_esearch_partial<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        range: S1,
+        query: S2,
+    ) -> Result<ESearch> {
+        self.ensure_capability("PARTIAL").await?;
+        self.read_esearch(&format!(
+            "UID SEARCH RETURN (PARTIAL {} COUNT) {}",
+            range.as_ref(),
+            search_keys(query.as_ref())
+        ))
+        .await
+    }
+
+    async fn run_esearch(&mut self, command: &str, query: &str) -> Result<ESearch> {
+        self.ensure_capability("ESEARCH").await?;
+        let has_return =
+            matches!(query.get(..7), Some(prefix) if prefix.eq_ignore_ascii_case("RETURN "));
+        let command = match query.find(')') {
+            Some(end) if has_return => format!(
+                "{} {} {}",
+                command,
+                &query[..=end],
+                search_keys(query[end + 1..].trim_start())
+            ),
+            _ if has_return => format!("{} {}", command, query),
+            _ => format!(
+                "{} RETURN (MIN MAX ALL COUNT) {}",
+                command,
+                search_keys(query)
+            ),
+        };
+        self.read_esearch(&command).await
+    }
+
+    async fn read_esearch(&mut self, command: &str) -> Result<ESearch> {
+        let id = self.run_command(command).await?;
+        parse_esearch(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()))
+    }
+
+    /// The [`SORT` command](https://tools.ietf.org/html/rfc5256#section-3) searches the mailbox
+    /// like [`Session::search`] and returns the matching [`Seq`]s ordered by `criteria`, e.g.
+    /// `REVERSE DATE` or `SUBJECT FROM`. The search is done with the `UTF-8` charset.
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the `SORT`
+    /// capability.
+    pub async fn sort<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        criteria: S1,
+        query: S2,
+    ) -> Result<Vec<Seq>> {
+        self.run_sort("SORT", criteria.as_ref(), query.as_ref())
+            .await
+    }
+
+    /// Equivalent to [`Session::sort`], except that the returned identifiers are [`Uid`]
+    /// instead of [`Seq`].
+    pub async fn uid_sort<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        criteria: S1,
+        query: S2,
+    ) -> Result<Vec<Uid>> {
+        self.run_sort("UID SORT", criteria.as_ref(), query.as_ref())
+            .await
+    }
+
+    async fn run_sort(&mut self, command: &str, criteria: &str, query: &str) -> Result<Vec<u32>> {
+        self.ensure_capability("SORT").await?;
+        let id = self
+            .run_command(&format!("{} ({}) UTF-8 {}", command, criteria, query))
+            .await?;
+        parse_sort(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()))
+    }
+
+    /// The [`THREAD` command](https://tools.ietf.org/html/rfc5256#section-3) searches the mailbox
+    /// like [`Session::search`] and returns the matching messages grouped into [`Thread`]s of
+    /// [`Seq`]s, using `algorithm`, e.g. `REFERENCES` or `ORDEREDSUBJECT`. The search is done
+    /// with the `UTF-8` charset.
+    ///
+    /// Returns [`Error::MissingCapability`] if the server does not support the
+    /// `THREAD=<algorithm>` capability.
+    pub async fn thread<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        algorithm: S1,
+        query: S2,
+    ) -> Result<Vec<Thread>> {
+        self.run_thread("THREAD", algorithm.as_ref(), query.as_ref())
+            .await
+    }
+
+    /// Equivalent to [`Session::thread`], except that the threads are made of [`Uid`]s
+    /// instead of [`Seq`]s.
+    pub async fn uid_thread<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        algorithm: S1,
+        query: S2,
+    ) -> Result<Vec<Thread>> {
+        self.run_thread("UID THREAD", algorithm.as_ref(), query.as_ref())
+            .await
+    }
+
+    async fn run_thread(
+        &mut self,
+        command: &str,
+        algorithm: &str,
+        query: &str,
+    ) -> Result<Vec<Thread>> {
+        self.ensure_capability(&format!("THREAD={}", algorithm))
+            .await?;
+        let id = self
+            .run_command(&format!("{} {} UTF-8 {}", command, algorithm, query))
+            .await?;
+        parse_thread(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()))
+    }
+
+    /// The [`GETQUOTA` command](https://tools.ietf.org/html/rfc2087#section-4.2)
+    pub async fn get_quota(&mut self, quota_root: &str) -> Result<Quota> {
+        let id = self
+            .run_command(format!("GETQUOTA {}", quote!(quota_root)))
+            .await?;
+        let c = parse_get_quota(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()))?;
+        Ok(c)
+    }
+
+    /// The [`GETQUOTAROOT` command](https://tools.ietf.org/html/rfc2087#section-4.3)
+    ///
+    /// Empty vectors are returned if the server has no quota root configured for the mailbox,
+    /// while a tagged `NO` or `BAD` is returned as [`Error::No`] or [`Error::Bad`].
+    pub async fn get_quota_root(
+        &mut self,
+        mailbox_name: &str,
+    ) -> Result<(Vec<QuotaRoot>, Vec<Quota>)> {
+        let id = self
+            .run_command(format!(
+                "GETQUOTAROOT {}",
+                self.quote_mailbox(mailbox_name)?
+            ))
+            .await?;
+        let c = parse_get_quota_root(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .await
+        .map_err(|err| err.with_command(self.conn.current_command.as_deref()))?;
+        Ok(c)
+    }
+
+    /// How full the selected mailbox is, as the percentage of the limit used of each resource,
+    /// e.g. 87 for [`QuotaResourceName::Storage`] when 87% of the allowed storage is used. The
+    /// quota roots of the mailbox are looked up with [`Session::get_quota_root`] and, unless the
+    /// server returned them along, their quotas with [`Session::get_quota`]. If several roots
+    /// limit the same resource, the highest percentage is returned, and a limit of 0 counts as
+    /// 100%.
+    ///
+    /// Returns `Ok(None)` if no quota applies to the mailbox, [`Error::InvalidState`] if no
+    /// mailbox is selected and [`Error::MissingCapability`] if the server does not support the
+    /// `QUOTA` capability.
+    pub async fn current_quota(&mut self) -> Result<Option<HashMap<QuotaResourceName, u64>>> {
+        let mailbox_name = match self.selected_name() {
+            Some(name) => name.to_string(),
+            None => return Err(Error::InvalidState("GETQUOTAROOT".to_string())),
+        };
+        self.ensure_capability("QUOTA").await?;
+        let (roots, mut quotas) = self.get_quota_root(&mailbox_name).await?;
+        for root in roots.iter().flat_map(|root| &root.quota_root_names) {
+            if !quotas.iter().any(|quota| &quota.root_name == root) {
+                let quota = self.get_quota(root).await?;
+                quotas.push(quota);
+            }
+        }
+        let mut usage = HashMap::new();
+        for resource in quotas.into_iter().flat_map(|quota| quota.resources) {
+            let percentage = resource.get_usage_percentage();
+            let highest = usage.entry(resource.name).or_insert(percentage);
+            *highest = percentage.max(*highest);
+        }
+        Ok(if usage.is_empty() { None } else { Some(usage) })
+    }
+
+    // these are only here because they are public interface, the rest is in `Connection`
+    /// Runs a command and checks if it returns OK.
+    pub async fn run_command_and_check_ok<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
+        let id = self.run_command(command.as_ref()).await?;
+        let sender = self.unsolicited_responses_tx.clone();
+        let res = self.conn.check_done_ok(&id, Some(sender)).await;
+        self.update_capabilities();
+        self.track_completion(command.as_ref(), res.is_ok());
+        res?;
+
+        Ok(())
+    }
+
+    /// Runs any command passed to it.
+    ///
+    /// Returns [`Error::InvalidState`] without sending the command if it is not valid in the
+    /// state of the session, e.g. `FETCH` before a mailbox was selected, and
+    /// [`Error::SavedSearchInvalid`] if it uses the search result saved as `$` while that is no
+    /// longer valid, see [`Session::search_save`].
+    pub async fn run_command<S: AsRef<str>>(&mut self, command: S) -> Result<RequestId> {
+        let sender = self.unsolicited_responses_tx.clone();
+        self.conn.finish_abandoned(Some(sender)).await?;
+        self.update_capabilities();
+        self.track_state(command.as_ref())?;
+        self.track_saved_search(command.as_ref())?;
+        let id = self.conn.run_command(command.as_ref()).await?;
+
+        Ok(id)
+    }
+
+    /// Runs an arbitrary command, without adding a tag to it.
+    pub async fn run_command_untagged<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
+        self.conn.run_command_untagged(command.as_ref()).await?;
+
+        Ok(())
+    }
+
+    /// Runs an arbitrary command with literal arguments, see
+    /// [`Connection::run_command_with_literals`], checking and keeping track of the state of the
+    /// session like [`Session::run_command`].
+    pub async fn run_command_with_literals<S: AsRef<str>, B: AsRef<[u8]>>(
+        &mut self,
+        command: S,
+        literals: &[B],
+    ) -> Result<Vec<ResponseData>> {
+        let id = self.run_command(command.as_ref()).await?;
+        let res = self.conn.send_literals(&id, literals).await;
+        self.track_completion(command.as_ref(), completed_ok(&res));
+        res
+    }
+
+    /// Runs an arbitrary command and returns every response the server sends until the command
+    /// completes, see [`Connection::run_command_and_read_responses`], checking and keeping track
+    /// of the state of the session like [`Session::run_command`].
+    pub async fn run_command_and_read_responses<S: AsRef<str>>(
+        &mut self,
+        command: S,
+    ) -> Result<Vec<ResponseData>> {
+        let id = self.run_command(command.as_ref()).await?;
+        let res = self.conn.read_responses(&id).await;
+        self.track_completion(command.as_ref(), completed_ok(&res));
+        res
+    }
+
+    /// Read the next response on the connection.
+    pub async fn read_response(&mut self) -> Option<io::Result<ResponseData>> {
+        self.conn.read_response().await
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
+    unsafe_pinned!(stream: ImapStream<T>);
+
+    /// Convert this connection into the raw underlying stream.
+    pub fn into_inner(self) -> T {
+        let Self { stream, .. } = self;
+        stream.into_inner()
+    }
+
+    /// The details of the TLS session, such as the certificate the server presented, or `None`
+    /// if the connection is not encrypted. See [`TlsPeerInfo`] for streams other than those
+    /// returned by [`connect`].
+    pub fn tls_info(&self) -> Option<TlsInfo>
+    where
+        T: TlsPeerInfo,
+    {
+        self.stream.inner.tls_info()
+    }
+
+    /// Sets a function that is called with the text of every `[ALERT]` the server sends, e.g.
+    /// `Mailbox nearly full`. [RFC 3501](https://tools.ietf.org/html/rfc3501#section-7.1)
+    /// requires clients to show alerts to the user. They can come with the completion of any
+    /// command, or unsolicited; the responses are processed as usual otherwise.
+    ///
+    /// Set it before logging in to also see alerts sent in response to the login, e.g. about an
+    /// expiring password.
+    pub fn set_alert_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(&str) + Send + Sync + 'static,
+    {
+        self.stream.alert_handler = Some(AlertHandler(Box::new(handler)));
+    }
+
+    /// Sets a function that is shown the traffic of the connection, to debug problems with a
+    /// server: each command line sent, including its tag and `CRLF`, and each response received,
+    /// including any literals, as the server sent it once it has been read.
+    ///
+    /// Credentials are redacted from the command lines: the arguments of `LOGIN`, and the base64
+    /// responses to the challenges of `AUTHENTICATE`. The messages sent by [`Session::append`] are
+    /// not shown.
+    ///
+    /// ```no_run
+    /// # fn main() -> async_imap::error::Result<()> {
+    /// # async_std::task::block_on(async {
+    /// let tls = async_native_tls::TlsConnector::new();
+    /// let mut client = async_imap::connect(("imap.example.org", 993), "imap.example.org", tls).await?;
+    /// client.set_wire_tracer(|direction, data| {
+    ///     eprint!("{:?}: {}", direction, String::from_utf8_lossy(data));
+    /// });
+    /// # Ok(())
+    /// # }) }
+    /// ```
+    pub fn set_wire_tracer<F>(&mut self, tracer: F)
+    where
+        F: FnMut(Direction, &[u8]) + Send + Sync + 'static,
+    {
+        self.stream.wire_tracer = Some(WireTracer::new(tracer));
+    }
+
+    /// Enables or disables lenient parsing, which is disabled by default. With it, a response that
+    /// fails to parse is retried after fixing deviations from the grammar that some servers make,
+    /// instead of failing with [`ParseError::Malformed`]:
+    ///
+    /// - a literal that is not preceded by a space, e.g. `BODY[]{5}`,
+    /// - spaces in or after the length of a literal, e.g. `{ 5 } \r\n`,
+    /// - repeated spaces, and spaces after `(` or before `)`,
+    /// - spaces at the end of a line,
+    /// - a status response without text, e.g. `A1 OK\r\n`,
+    /// - a bare `\n` as the line ending.
+    ///
+    /// Tokens such as `OK` or `NIL` are accepted in any case either way. Each response that is
+    /// fixed is logged as a warning.
+    pub fn set_lenient_parsing(&mut self, lenient: bool) {
+        self.stream.lenient = lenient;
+    }
+
+    /// Whether lenient parsing is enabled, see [`Connection::set_lenient_parsing`].
+    pub fn lenient_parsing(&self) -> bool {
+        self.stream.lenient
+    }
+
+    /// Sets the prefix of the tags of the following commands, e.g. `S1A` for `S1A1`, `S1A2` and
+    /// so on, to tell sessions apart in logs. Tags are numbered from 1 and keep increasing when
+    /// the prefix is changed, also across reconnects of a
+    /// [`ReconnectingSession`](crate::reconnect::ReconnectingSession).
+    ///
+    /// Returns [`Error::Validate`] if `prefix` contains a character that is not allowed in a tag.
+    pub fn set_tag_prefix<S: Into<String>>(&mut self, prefix: S) -> Result<()> {
+        let prefix = prefix.into();
+        if let Some(c) = prefix.chars().find(|&c| {
+            !c.is_ascii_graphic() || matches!(c, '(' | ')' | '{' | '%' | '*' | '"' | '\\' | '+')
+        }) {
+            return Err(Error::Validate(ValidateError(c)));
+        }
+        self.request_ids.set_prefix(prefix);
+        Ok(())
+    }
+
+    /// Read the next response on the connection.
+    pub async fn read_response(&mut self) -> Option<io::Result<ResponseData>> {
+        self.stream.next().await
     }
 
     pub(crate) async fn run_command_untagged(&mut self, command: &str) -> Result<()> {
@@ -1333,7 +3155,9 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
     }
 
     pub(crate) async fn run_command(&mut self, command: &str) -> Result<RequestId> {
+        self.finish_abandoned(None).await?;
         let request_id = self.request_ids.next().unwrap(); // safe: never returns Err
+        self.current_command = Some(command_name(command));
         self.stream
             .encode(Request(Some(request_id.clone()), command.as_bytes().into()))
             .await?;
@@ -1341,23 +3165,199 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
         Ok(request_id)
     }
 
+    /// Reads the remaining responses of a command whose future was dropped before it completed,
+    /// so that the next command starts after its completion. Untagged responses are sent to
+    /// `unsolicited`, if given, and the completion is discarded.
+    pub(crate) async fn finish_abandoned(
+        &mut self,
+        unsolicited: Option<UnsolicitedSender>,
+    ) -> Result<()> {
+        if self.stream.in_flight.is_none() {
+            return Ok(());
+        }
+        // The server only answers once the rest of the command line was sent.
+        self.stream.flush().await?;
+        while self.stream.in_flight.is_some() {
+            let res = match self.stream.next().await {
+                Some(res) => res?,
+                None => return Err(Error::ConnectionLost),
+            };
+            if let Response::Done { tag, status, .. } = res.parsed() {
+                log::debug!(
+                    "discarding completion of abandoned command {}: {:?}",
+                    tag.0,
+                    status
+                );
+                continue;
+            }
+            check_bye(&res)?;
+            if let Some(unsolicited) = unsolicited.clone() {
+                handle_unilateral(res, unsolicited).await?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Execute a command and check that the next response is a matching done.
+    ///
+    /// Unsolicited responses are sent to `unsolicited` once the command completed, up to the
+    /// [unsolicited capacity](Client::set_unsolicited_capacity) of the connection; older ones are
+    /// dropped.
     pub async fn run_command_and_check_ok(
         &mut self,
         command: &str,
         unsolicited: Option<channel::Sender<UnsolicitedResponse>>,
     ) -> Result<()> {
         let id = self.run_command(command).await?;
-        self.check_done_ok(&id, unsolicited).await?;
+        let tx = match unsolicited {
+            Some(tx) => tx,
+            None => {
+                self.check_done_ok(&id, None).await?;
+                return Ok(());
+            }
+        };
+        let (sender, rx) = UnsolicitedSender::new(self.unsolicited_capacity);
+        let res = self.check_done_ok(&id, Some(sender)).await;
+        while let Ok(response) = rx.try_recv() {
+            if tx.send(response).await.is_err() {
+                break;
+            }
+        }
+        res?;
 
         Ok(())
     }
 
-    pub(crate) async fn check_done_ok(
+    /// Runs an arbitrary command and returns every response the server sends until the command
+    /// completes, for extensions this crate does not support. The tagged completion of the
+    /// command is the last response; its status is not checked. No responses are sent to the
+    /// unsolicited responses channel. On a [`Session`], use
+    /// [`Session::run_command_and_read_responses`], which keeps track of its state.
+    pub async fn run_command_and_read_responses<S: AsRef<str>>(
+        &mut self,
+        command: S,
+    ) -> Result<Vec<ResponseData>> {
+        let id = self.run_command(command.as_ref()).await?;
+        self.read_responses(&id).await
+    }
+
+    /// Returns every response up to the completion of the command `id`, see
+    /// [`Connection::run_command_and_read_responses`].
+    pub(crate) async fn read_responses(&mut self, id: &RequestId) -> Result<Vec<ResponseData>> {
+        let mut responses = Vec::new();
+        while let Some(res) = self.stream.next().await {
+            let res = res?;
+            let done = matches!(res.parsed(), Response::Done { tag, .. } if tag == id);
+            responses.push(res);
+            if done {
+                return Ok(responses);
+            }
+        }
+
+        Err(Error::ConnectionLost)
+    }
+
+    /// Runs an arbitrary command with literal arguments, like
+    /// [`Connection::run_command_and_read_responses`], for extensions this crate does not
+    /// support. `command` is the first line of the command, up to the announcement of its first
+    /// literal, e.g. `X-NOTE {5}`. Each continuation request of the server is answered with the
+    /// next of `literals`, followed by CRLF: an entry is a literal and the rest of the line after
+    /// it, e.g. `hello`, or `hello {3}` if it announces another literal.
+    ///
+    /// The continuation requests are returned with the other responses. If the server asks for
+    /// more literals than given, [`ParseError::Unexpected`] is returned, and the connection can
+    /// not be used for other commands.
+    pub async fn run_command_with_literals<S: AsRef<str>, B: AsRef<[u8]>>(
+        &mut self,
+        command: S,
+        literals: &[B],
+    ) -> Result<Vec<ResponseData>> {
+        let id = self.run_command(command.as_ref()).await?;
+        self.send_literals(&id, literals).await
+    }
+
+    /// Answers each continuation request for the command `id` with the next of `literals`, and
+    /// returns all responses up to its completion, see
+    /// [`Connection::run_command_with_literals`].
+    pub(crate) async fn send_literals<B: AsRef<[u8]>>(
         &mut self,
         id: &RequestId,
-        unsolicited: Option<channel::Sender<UnsolicitedResponse>>,
+        literals: &[B],
+    ) -> Result<Vec<ResponseData>> {
+        let mut literals = literals.iter();
+        let mut responses = Vec::new();
+        while let Some(res) = self.stream.next().await {
+            let res = res?;
+            match res.parsed() {
+                Response::Done { tag, .. } if tag == id => {
+                    responses.push(res);
+                    return Ok(responses);
+                }
+                Response::Continue { .. } => {
+                    let literal = literals.next().ok_or_else(|| {
+                        Error::Parse(ParseError::Unexpected(
+                            "continuation request without a literal left to send".into(),
+                        ))
+                    })?;
+                    self.stream
+                        .encode(Request(None, literal.as_ref().to_vec()))
+                        .await?;
+                    self.stream.flush().await?;
+                }
+                _ => {}
+            }
+            responses.push(res);
+        }
+
+        Err(Error::ConnectionLost)
+    }
+
+    /// Waits for the continuation request that allows sending a literal announced by the command
+    /// `id`. If the server completes the command instead, e.g. because it rejects the literal,
+    /// its error is returned.
+    pub(crate) async fn wait_for_continuation(
+        &mut self,
+        id: &RequestId,
+        unsolicited: Option<UnsolicitedSender>,
     ) -> Result<()> {
+        while let Some(res) = self.stream.next().await {
+            let res = res?;
+            match res.parsed() {
+                Response::Continue { .. } => return Ok(()),
+                Response::Done {
+                    tag,
+                    status,
+                    code,
+                    information,
+                } if tag == id => {
+                    check_status(Some(tag), status, code.as_ref(), information.as_deref())
+                        .map_err(|err| err.with_command(self.current_command.as_deref()))?;
+                    return Err(Error::Parse(ParseError::Unexpected(format!(
+                        "command completed before literal was sent: {:?}",
+                        information
+                    ))));
+                }
+                _ => {
+                    check_bye(&res)?;
+                    check_stray_tag(&res)?;
+                    if let Some(unsolicited) = unsolicited.clone() {
+                        handle_unilateral(res, unsolicited).await?;
+                    }
+                }
+            }
+        }
+
+        Err(Error::ConnectionLost)
+    }
+
+    /// Reads responses until the command `id` completes and checks that it succeeded. Returns the
+    /// tagged completion.
+    pub(crate) async fn check_done_ok(
+        &mut self,
+        id: &RequestId,
+        unsolicited: Option<UnsolicitedSender>,
+    ) -> Result<ResponseData> {
         if let Some(first_res) = self.stream.next().await {
             self.check_done_ok_from(id, unsolicited, first_res?).await
         } else {
@@ -1368,9 +3368,9 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
     pub(crate) async fn check_done_ok_from(
         &mut self,
         id: &RequestId,
-        unsolicited: Option<channel::Sender<UnsolicitedResponse>>,
+        unsolicited: Option<UnsolicitedSender>,
         mut response: ResponseData,
-    ) -> Result<()> {
+    ) -> Result<ResponseData> {
         loop {
             if let Response::Done {
                 status,
@@ -1379,15 +3379,17 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
                 tag,
             } = response.parsed()
             {
-                self.check_status_ok(status, code.as_ref(), information.as_deref())?;
-
                 if tag == id {
-                    return Ok(());
+                    check_status(Some(tag), status, code.as_ref(), information.as_deref())
+                        .map_err(|err| err.with_command(self.current_command.as_deref()))?;
+                    return Ok(response);
                 }
             }
 
+            check_bye(&response)?;
+            check_stray_tag(&response)?;
             if let Some(unsolicited) = unsolicited.clone() {
-                handle_unilateral(response, unsolicited).await;
+                handle_unilateral(response, unsolicited).await?;
             }
 
             if let Some(res) = self.stream.next().await {
@@ -1397,57 +3399,326 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
             }
         }
     }
+}
 
-    pub(crate) fn check_status_ok(
-        &self,
-        status: &imap_proto::Status,
-        code: Option<&imap_proto::ResponseCode<'_>>,
-        information: Option<&str>,
-    ) -> Result<()> {
-        use imap_proto::Status;
-        match status {
-            Status::Ok => Ok(()),
-            Status::Bad => Err(Error::Bad(format!(
-                "code: {:?}, info: {:?}",
-                code, information
-            ))),
-            Status::No => Err(Error::No(format!(
-                "code: {:?}, info: {:?}",
-                code, information
-            ))),
-            _ => Err(Error::Io(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "status: {:?}, code: {:?}, information: {:?}",
-                    status, code, information
-                ),
-            ))),
-        }
-    }
+/// Whether the responses of a command end with an `OK` completion.
+fn completed_ok(responses: &Result<Vec<ResponseData>>) -> bool {
+    matches!(
+        responses
+            .as_ref()
+            .map(|responses| responses.last().map(ResponseData::parsed)),
+        Ok(Some(Response::Done {
+            status: imap_proto::Status::Ok,
+            ..
+        }))
+    )
 }
 
-fn validate_str(value: &str) -> Result<String> {
-    let quoted = quote!(value);
-    if quoted.find('\n').is_some() {
-        return Err(Error::Validate(ValidateError('\n')));
-    }
-    if quoted.find('\r').is_some() {
-        return Err(Error::Validate(ValidateError('\r')));
+/// The `APPENDUID` code of the tagged response to an `APPEND`.
+fn append_uid(done: &ResponseData) -> Result<Option<AppendUid>> {
+    match done.parsed() {
+        Response::Done {
+            code: Some(code), ..
+        } => AppendUid::from_code(code),
+        _ => Ok(None),
     }
-    Ok(quoted)
 }
 
-#[cfg(test)]
-mod tests {
-    use pretty_assertions::assert_eq;
+/// Collects a stream of `FETCH` responses, keeping the messages that arrived before a `NO` or `BAD`
+/// in an [`Error::PartialFetch`].
+async fn collect_fetches<S: Stream<Item = Result<Fetch>>>(fetches: S) -> Result<Vec<Fetch>> {
+    futures::pin_mut!(fetches);
+    let mut collected = Vec::new();
+    while let Some(fetch) = fetches.next().await {
+        match fetch {
+            Ok(fetch) => collected.push(fetch),
+            Err(error @ Error::No(_)) | Err(error @ Error::Bad(_)) if !collected.is_empty() => {
+                return Err(Error::PartialFetch {
+                    fetches: collected,
+                    error: Box::new(error),
+                });
+            }
+            Err(error) => return Err(error),
+        }
+    }
 
-    use super::super::error::Result;
-    use super::super::mock_stream::MockStream;
-    use super::*;
-    use std::borrow::Cow;
+    Ok(collected)
+}
 
-    use async_std::sync::{Arc, Mutex};
-    use imap_proto::Status;
+/// The state of [`Session::uid_pages`].
+struct Pages<'a, T: Read + Write + Unpin + fmt::Debug> {
+    session: &'a mut Session<T>,
+    query: String,
+    items: String,
+    page_size: usize,
+    order: PageOrder,
+    /// The messages of the pages that were not returned yet, in order.
+    uids: VecDeque<Uid>,
+    /// The last message that was returned.
+    cursor: Option<Uid>,
+    /// The number of changes to the mailbox when it was last searched.
+    changes: u64,
+    /// The `UIDVALIDITY` of the selected mailbox when paging started.
+    uid_validity: Option<u32>,
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> Pages<'_, T> {
+    /// Searches the messages of the pages that were not returned yet.
+    async fn search(&mut self) -> Result<()> {
+        let found = self.session.uid_search(&self.query).await?;
+        self.changes = self.session.unsolicited_responses_tx.changes();
+        let mut uids: Vec<Uid> = match self.cursor {
+            Some(cursor) => found
+                .into_iter()
+                .filter(|&uid| self.order.is_after(uid, cursor))
+                .collect(),
+            None => found.into_iter().collect(),
+        };
+        self.order.sort(&mut uids);
+        self.uids = uids.into();
+        Ok(())
+    }
+
+    async fn next_page(&mut self) -> Result<Option<Vec<Fetch>>> {
+        loop {
+            let sender = &self.session.unsolicited_responses_tx;
+            match sender.uid_validity() {
+                Some(uid_validity) if Some(uid_validity) != self.uid_validity => {
+                    return Err(Error::UidValidityChanged(uid_validity));
+                }
+                _ => {}
+            }
+            if sender.changes() != self.changes {
+                self.search().await?;
+            }
+
+            let len = self.page_size.min(self.uids.len());
+            let page: Vec<Uid> = self.uids.drain(..len).collect();
+            let last = match page.last() {
+                Some(&last) => last,
+                None => return Ok(None),
+            };
+            self.cursor = Some(last);
+            let uid_set: SequenceSet = page.iter().copied().collect();
+            // Unsolicited `FETCH` responses for other messages may come along.
+            let mut fetches: Vec<Fetch> = self
+                .session
+                .uid_fetch_all(uid_set, &self.items)
+                .await?
+                .into_iter()
+                .filter(|fetch| matches!(fetch.uid, Some(uid) if page.contains(&uid)))
+                .collect();
+            // All messages of the page may have been expunged in the meantime.
+            if fetches.is_empty() {
+                continue;
+            }
+            fetches.sort_by_key(|fetch| page.iter().position(|&uid| Some(uid) == fetch.uid));
+            return Ok(Some(fetches));
+        }
+    }
+}
+
+/// The name of a command, without any of its arguments, e.g. `UID FETCH` for
+/// `UID FETCH 1:* FLAGS`.
+fn command_name(command: &str) -> String {
+    let mut words = command.split(' ');
+    let name = words.next().unwrap_or_default().to_ascii_uppercase();
+    match words.next() {
+        Some(sub) if name == "UID" => format!("{} {}", name, sub.to_ascii_uppercase()),
+        _ => name,
+    }
+}
+
+/// Adds `UID` to the data items of the `FETCH` `query` unless it asks for it already, expanding
+/// the macros that can not be combined with other items. Modifiers after the data items, e.g.
+/// `(CHANGEDSINCE 5)`, are kept as they are.
+fn with_uid(query: &str) -> String {
+    let query = query.trim();
+    let (items, modifiers) = query.split_at(item_end(query));
+    let items = match items
+        .strip_prefix('(')
+        .and_then(|items| items.strip_suffix(')'))
+    {
+        Some(items) => items.trim(),
+        None => items,
+    };
+    let mut rest = items;
+    while !rest.is_empty() {
+        let (item, after) = rest.split_at(item_end(rest));
+        if item.eq_ignore_ascii_case("UID") {
+            return query.to_string();
+        }
+        rest = after.trim_start();
+    }
+    let items = match items.to_ascii_uppercase().as_str() {
+        "ALL" => "FLAGS INTERNALDATE RFC822.SIZE ENVELOPE",
+        "FAST" => "FLAGS INTERNALDATE RFC822.SIZE",
+        "FULL" => "FLAGS INTERNALDATE RFC822.SIZE ENVELOPE BODY",
+        _ => items,
+    };
+    if items.is_empty() {
+        format!("UID{}", modifiers)
+    } else {
+        format!("(UID {}){}", items, modifiers)
+    }
+}
+
+/// The length of the data item or parenthesized list of data items at the start of a `FETCH`
+/// query, e.g. all of `BODY[HEADER.FIELDS (FROM)]<0.100>`, without looking into section
+/// specifications.
+fn item_end(query: &str) -> usize {
+    let mut depth = 0usize;
+    for (i, c) in query.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 && c == ')' && query.starts_with('(') {
+                    return i + 1;
+                }
+            }
+            ' ' if depth == 0 => return i,
+            _ => {}
+        }
+    }
+    query.len()
+}
+
+/// Whether the command `name` is only valid while a mailbox is selected.
+fn requires_selected(name: &str) -> bool {
+    name.starts_with("UID ")
+        || matches!(
+            name,
+            "CHECK"
+                | "CLOSE"
+                | "UNSELECT"
+                | "EXPUNGE"
+                | "SEARCH"
+                | "FETCH"
+                | "STORE"
+                | "COPY"
+                | "MOVE"
+                | "SORT"
+                | "THREAD"
+        )
+}
+
+/// Whether `command` uses the saved search result `$` (RFC 5182) outside of quoted strings.
+fn uses_saved_search(command: &str) -> bool {
+    let bytes = command.as_bytes();
+    let mut quoted = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if quoted => i += 1,
+            b'"' => quoted = !quoted,
+            b'$' if !quoted => {
+                let before = i.checked_sub(1).map(|j| bytes[j]);
+                let after = bytes.get(i + 1).copied();
+                if matches!(before, None | Some(b' ') | Some(b'('))
+                    && matches!(after, None | Some(b' ') | Some(b')'))
+                {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Whether the `SEARCH` command `command` saves its result as `$`, i.e. has `SAVE` among its
+/// return options.
+fn saves_search(command: &str) -> bool {
+    let options = match command.split_once("RETURN (") {
+        Some((_, rest)) => rest.split(')').next().unwrap_or_default(),
+        None => return false,
+    };
+    options
+        .split(' ')
+        .any(|option| option.eq_ignore_ascii_case("SAVE"))
+}
+
+/// The search keys of a `SEARCH` command, prefixed with `CHARSET UTF-8` if they contain
+/// non-ASCII text and do not name a charset already. Some servers reject `CHARSET` for queries
+/// that do not need it.
+fn search_keys(query: &str) -> Cow<'_, str> {
+    let has_charset =
+        matches!(query.get(..8), Some(prefix) if prefix.eq_ignore_ascii_case("CHARSET "));
+    if query.is_ascii() || has_charset {
+        Cow::Borrowed(query)
+    } else {
+        Cow::Owned(format!("CHARSET UTF-8 {}", query))
+    }
+}
+
+/// The `STORE` data item and value that change flags, e.g. `+FLAGS (\Seen $Junk)`.
+fn flags_query(operation: FlagsOperation, flags: &[Flag<'_>]) -> Result<String> {
+    let flags = flags.iter().map(ToString::to_string).collect::<Vec<_>>();
+    // Flags are atoms, and anything else would end the list or the command.
+    for flag in &flags {
+        if let Some(c) = flag
+            .chars()
+            .find(|c| matches!(c, ' ' | '(' | ')' | '\r' | '\n'))
+        {
+            return Err(Error::Validate(ValidateError(c)));
+        }
+    }
+    Ok(format!("{} ({})", operation.item(), flags.join(" ")))
+}
+
+/// The keywords that the `STORE` `query` adds or sets, e.g. `$Junk` for
+/// `+FLAGS.SILENT (\Seen $Junk)`, after an optional `(UNCHANGEDSINCE n)` modifier.
+fn stored_keywords(query: &str) -> impl Iterator<Item = &str> {
+    let query = query.trim_start();
+    let query = match query.strip_prefix('(') {
+        Some(rest) => rest
+            .split_once(')')
+            .map_or("", |(_, rest)| rest.trim_start()),
+        None => query,
+    };
+    let (item, flags) = query.split_once(' ').unwrap_or((query, ""));
+    let item = item.to_ascii_uppercase();
+    let adds = matches!(item.trim_start_matches('+'), "FLAGS" | "FLAGS.SILENT");
+    flags
+        .split(&[' ', '(', ')'][..])
+        .filter(move |flag| adds && !flag.is_empty() && !flag.starts_with('\\'))
+}
+
+/// The `STORE` data item and value that change Gmail labels, e.g. `+X-GM-LABELS ("\\Inbox")`.
+fn gmail_labels_query<L: AsRef<str>>(operation: LabelsOperation, labels: &[L]) -> Result<String> {
+    let labels = labels
+        .iter()
+        .map(|label| validate_str(&utf7::encode(label.as_ref())))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(format!("{} ({})", operation.item(), labels.join(" ")))
+}
+
+fn validate_str(value: &str) -> Result<String> {
+    let quoted = quote!(value);
+    if quoted.find('\n').is_some() {
+        return Err(Error::Validate(ValidateError('\n')));
+    }
+    if quoted.find('\r').is_some() {
+        return Err(Error::Validate(ValidateError('\r')));
+    }
+    Ok(quoted)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::super::error::Result;
+    use super::super::mock_stream::MockStream;
+    use super::*;
+    use std::borrow::Cow;
+
+    use async_std::sync::{Arc, Mutex};
+    use imap_proto::Status;
+
+    use crate::extensions::notify::NotifyEvent;
 
     macro_rules! mock_client {
         ($s:expr) => {
@@ -1455,10 +3726,13 @@ mod tests {
         };
     }
 
+    /// A session with a mailbox selected, so that all commands are valid.
     macro_rules! mock_session {
-        ($s:expr) => {
-            Session::new(mock_client!($s).conn)
-        };
+        ($s:expr) => {{
+            let mut session = Session::new(mock_client!($s).conn);
+            session.state = SessionState::Selected;
+            session
+        }};
     }
 
     macro_rules! assert_eq_bytes {
@@ -1548,6 +3822,226 @@ mod tests {
         );
     }
 
+    #[async_std::test]
+    async fn authenticate_challenges() {
+        // A made-up mechanism that echoes each challenge in reverse, until the server is done.
+        struct Echo(Vec<Vec<u8>>);
+        impl Authenticator for &mut Echo {
+            type Response = Vec<u8>;
+            fn process(&mut self, challenge: &[u8]) -> Self::Response {
+                self.0.push(challenge.to_vec());
+                challenge.iter().rev().copied().collect()
+            }
+        }
+
+        // `+`, `/` and padding are all part of the base64 alphabet.
+        let challenges: [&[u8]; 3] = [b"<1896.697170952@postoffice>", b"\xfb\xff?", b"ok"];
+        let mut response = String::new();
+        for challenge in &challenges {
+            response.push_str(&format!("+ {}\r\n", base64::encode(challenge)));
+        }
+        response.push_str("A0001 OK Logged in\r\n");
+        let client = mock_client!(MockStream::new(response.into_bytes()));
+        let mut echo = Echo(Vec::new());
+        let session = client
+            .authenticate("X-ECHO", &mut echo)
+            .await
+            .map_err(|e| e.0)
+            .unwrap();
+        assert_eq!(echo.0, challenges);
+        let mut command = "A0001 AUTHENTICATE X-ECHO\r\n".to_string();
+        for challenge in &challenges {
+            let reversed: Vec<_> = challenge.iter().rev().copied().collect();
+            command.push_str(&format!("{}\r\n", base64::encode(reversed)));
+        }
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+
+        // A challenge that is not base64.
+        let response = b"+ not base64!\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let (err, _) = client
+            .authenticate("X-ECHO", &mut Echo(Vec::new()))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::Parse(ParseError::Authentication(ref text, Some(_))) if text == "not base64!"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[async_std::test]
+    async fn authenticate_untagged() {
+        let response = b"* CAPABILITY IMAP4rev1 AUTH=PLAIN IDLE\r\n\
+                         * OK [ALERT] Maintenance tonight\r\n\
+                         + \r\n\
+                         * OK Still there\r\n\
+                         A0001 OK Logged in\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let authenticator = PlainAuthenticator {
+            username: "user",
+            password: "pass",
+        };
+        let session = client
+            .authenticate("PLAIN", authenticator)
+            .await
+            .map_err(|e| e.0)
+            .unwrap();
+        let command = format!(
+            "A0001 AUTHENTICATE PLAIN\r\n{}\r\n",
+            base64::encode("\0user\0pass")
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+        assert!(session.cached_capabilities().unwrap().has_str("IDLE"));
+    }
+
+    #[async_std::test]
+    async fn authenticate_scram() {
+        // The example exchange of RFC 7677, section 3.
+        let scram = || ScramSha256::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO".into());
+        let server_first = base64::encode(
+            "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+        );
+        let server_final = base64::encode("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=");
+
+        let response = format!(
+            "+ \r\n+ {}\r\n+ {}\r\nA0001 OK Logged in\r\n",
+            server_first, server_final
+        );
+        let client = mock_client!(MockStream::new(response.into_bytes()));
+        let session = client
+            .authenticate("SCRAM-SHA-256", scram())
+            .await
+            .map_err(|e| e.0)
+            .unwrap();
+        let command = format!(
+            "A0001 AUTHENTICATE SCRAM-SHA-256\r\n{}\r\n{}\r\n\r\n",
+            base64::encode("n,,n=user,r=rOprNGfwEbeRWgbNEkqO"),
+            base64::encode(
+                "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                 p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+            )
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+
+        // A wrong server signature cancels the exchange.
+        let response = format!(
+            "+ \r\n+ {}\r\n+ {}\r\nA0001 BAD Cancelled\r\n",
+            server_first,
+            base64::encode("v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+        );
+        let client = mock_client!(MockStream::new(response.into_bytes()));
+        let (err, client) = client
+            .authenticate("SCRAM-SHA-256", scram())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Authentication(_)), "{:?}", err);
+        assert!(str::from_utf8(&client.conn.stream.inner.written_buf)
+            .unwrap()
+            .ends_with("\r\n*\r\n"));
+
+        // So does accepting the credentials without a server signature.
+        let response = format!("+ \r\n+ {}\r\nA0001 OK Logged in\r\n", server_first);
+        let client = mock_client!(MockStream::new(response.into_bytes()));
+        let (err, _) = client
+            .authenticate("SCRAM-SHA-256", scram())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Authentication(_)), "{:?}", err);
+    }
+
+    #[async_std::test]
+    async fn authenticate_auto() {
+        let response = b"* CAPABILITY IMAP4rev1 AUTH=PLAIN AUTH=XOAUTH2\r\n\
+                         A0001 OK CAPABILITY completed\r\n\
+                         + \r\n\
+                         A0002 OK Logged in\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let credentials = Credentials::new("me")
+            .password("pass")
+            .access_token("token");
+        let (session, mechanism) = client
+            .authenticate_auto(&credentials)
+            .await
+            .map_err(|e| e.0)
+            .unwrap();
+        assert_eq!(mechanism, AuthMechanism::XOAuth2);
+        let command = format!(
+            "A0001 CAPABILITY\r\nA0002 AUTHENTICATE XOAUTH2\r\n{}\r\n",
+            base64::encode("user=me\x01auth=Bearer token\x01\x01")
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+
+        // Without a token, the password is sent with the preferred mechanism.
+        let response = b"* CAPABILITY IMAP4rev1 AUTH=PLAIN AUTH=XOAUTH2\r\n\
+                         A0001 OK CAPABILITY completed\r\n\
+                         A0002 OK Logged in\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let credentials = Credentials::new("me").password("pass");
+        let preference = [AuthMechanism::Login, AuthMechanism::Plain];
+        let (session, mechanism) = client
+            .authenticate_auto_with(&credentials, &preference)
+            .await
+            .map_err(|e| e.0)
+            .unwrap();
+        assert_eq!(mechanism, AuthMechanism::Login);
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with("A0002 LOGIN \"me\" \"pass\"\r\n"));
+
+        // CRAM-MD5 only if the server offers nothing better.
+        let response = b"* CAPABILITY IMAP4rev1 AUTH=CRAM-MD5\r\n\
+                         A0001 OK CAPABILITY completed\r\n\
+                         + PDE4OTYuNjk3MTcwOTUyQHBvc3RvZmZpY2UucmVzdG9uLm1jaS5uZXQ+\r\n\
+                         A0002 OK Logged in\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let credentials = Credentials::new("tim").password("tanstaaftanstaaf");
+        let (session, mechanism) = client
+            .authenticate_auto(&credentials)
+            .await
+            .map_err(|e| e.0)
+            .unwrap();
+        assert_eq!(mechanism, AuthMechanism::CramMd5);
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with(
+                "A0002 AUTHENTICATE CRAM-MD5\r\n\
+                 dGltIGI5MTNhNjAyYzdlZGE3YTQ5NWI0ZTZlNzMzNGQzODkw\r\n"
+            ));
+
+        let response = b"* CAPABILITY IMAP4rev1 LOGINDISABLED AUTH=XOAUTH2\r\n\
+                         A0001 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let (err, client) = client.authenticate_auto(&credentials).await.unwrap_err();
+        assert!(matches!(err, Error::MissingCapability(_)), "{:?}", err);
+        assert_eq!(
+            client.conn.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n"
+        );
+    }
+
     #[async_std::test]
     async fn login() {
         let response = b"A0001 OK Logged in\r\n".to_vec();
@@ -1567,9 +4061,152 @@ mod tests {
         }
     }
 
+    #[async_std::test]
+    async fn login_capability_code() {
+        let response = b"A0001 OK [CAPABILITY IMAP4rev1 UIDPLUS QUOTA] Logged in\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let mut session = client.login("username", "password").await.unwrap();
+        let caps = session.cached_capabilities().unwrap();
+        assert!(caps.has_str("UIDPLUS"));
+        assert!(caps.has_str("QUOTA"));
+        assert!(!session.imap4rev2());
+
+        assert!(matches!(
+            session.esearch("ALL").await,
+            Err(Error::MissingCapability(cap)) if cap == "ESEARCH"
+        ));
+        assert!(!str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .contains("CAPABILITY"));
+    }
+
+    #[async_std::test]
+    async fn login_capability_code_imap4rev2() {
+        let response = b"A0001 OK [CAPABILITY IMAP4rev2 AUTH=PLAIN] Logged in\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let session = client.login("username", "password").await.unwrap();
+        let caps = session.cached_capabilities().unwrap();
+        assert!(caps.has(&Capability::Imap4rev2));
+        assert!(caps.has_str("AUTH=PLAIN"));
+        assert!(session.imap4rev2());
+    }
+
+    #[async_std::test]
+    async fn failed_command_error() {
+        let response = b"A0001 NO Mailbox doesn't exist\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let err = session.select("Missing").await.unwrap_err();
+        match &err {
+            Error::No(res) => {
+                assert_eq!(res.tag.as_deref(), Some("A0001"));
+                assert_eq!(res.command.as_deref(), Some("SELECT"));
+                assert_eq!(res.information.as_deref(), Some("Mailbox doesn't exist"));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(
+            err.to_string(),
+            "no response: A0001 SELECT: Mailbox doesn't exist"
+        );
+    }
+
+    #[async_std::test]
+    async fn copy_trycreate() {
+        let response = b"A0001 NO [TRYCREATE] No such mailbox\r\n\
+            A0002 OK CREATE completed\r\n\
+            A0003 OK COPY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_create_on_trycreate(true);
+        session.copy("1:2", "Archive").await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 COPY 1:2 \"Archive\"\r\n\
+            A0002 CREATE \"Archive\"\r\n\
+            A0003 COPY 1:2 \"Archive\"\r\n"
+                .to_vec()
+        );
+
+        // Off by default, and retried only once.
+        let response = b"A0001 NO [TRYCREATE] No such mailbox\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(!session.create_on_trycreate());
+        assert!(matches!(
+            session.uid_mv("7", "Archive").await,
+            Err(Error::No(res)) if res.code == Some(ResponseCode::TryCreate)
+        ));
+        let response = b"A0001 NO [TRYCREATE] No such mailbox\r\n\
+            A0002 OK CREATE completed\r\n\
+            A0003 NO [TRYCREATE] Still no such mailbox\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_create_on_trycreate(true);
+        assert!(matches!(
+            session.uid_copy("7", "Archive").await,
+            Err(Error::No(res)) if res.code == Some(ResponseCode::TryCreate)
+        ));
+        assert!(session
+            .stream
+            .inner
+            .written_buf
+            .ends_with(b"A0003 UID COPY 7 \"Archive\"\r\n"));
+    }
+
+    #[async_std::test]
+    async fn failed_command_response_codes() {
+        use crate::error::ResponseCode;
+
+        for (code, expected) in [
+            ("OVERQUOTA", ResponseCode::OverQuota),
+            ("CLIENTBUG", ResponseCode::ClientBug),
+            ("LIMIT", ResponseCode::Limit),
+            ("CANNOT", ResponseCode::Cannot),
+            ("NONEXISTENT", ResponseCode::NonExistent),
+            ("ALREADYEXISTS", ResponseCode::AlreadyExists),
+            ("TRYCREATE", ResponseCode::TryCreate),
+            ("X-SOMETHING 1", ResponseCode::Other("X-SOMETHING 1".into())),
+        ] {
+            let response = format!("A0001 NO [{}] Not done\r\n", code).into_bytes();
+            let mut session = mock_session!(MockStream::new(response));
+            match session.create("Mailbox").await {
+                Err(Error::No(res)) => {
+                    assert_eq!(res.code, Some(expected));
+                    assert_eq!(res.information.as_deref(), Some("Not done"));
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[async_std::test]
+    async fn failed_login_error() {
+        let response = b"A0001 NO [AUTHENTICATIONFAILED] Invalid credentials\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let (err, _client) = client.login("username", "secret").await.unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("A0001 LOGIN"), "{}", msg);
+        assert!(!msg.contains("secret"), "{}", msg);
+    }
+
+    #[async_std::test]
+    async fn failed_uid_fetch_error() {
+        let response = b"A0001 BAD Invalid sequence set\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session.uid_fetch("x", "UID").await.unwrap().collect().await;
+        match fetches.as_slice() {
+            [Err(Error::Bad(res))] => {
+                assert_eq!(res.tag.as_deref(), Some("A0001"));
+                assert_eq!(res.command.as_deref(), Some("UID FETCH"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
     #[async_std::test]
     async fn logout() {
-        let response = b"A0001 OK Logout completed.\r\n".to_vec();
+        let response = b"* BYE IMAP4rev1 Server logging out\r\n\
+            A0001 OK Logout completed.\r\n"
+            .to_vec();
         let command = "A0001 LOGOUT\r\n";
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
@@ -1581,52 +4218,296 @@ mod tests {
     }
 
     #[async_std::test]
-    async fn rename() {
-        let response = b"A0001 OK RENAME completed\r\n".to_vec();
-        let current_mailbox_name = "INBOX";
-        let new_mailbox_name = "NEWINBOX";
-        let command = format!(
-            "A0001 RENAME {} {}\r\n",
-            quote!(current_mailbox_name),
-            quote!(new_mailbox_name)
-        );
+    async fn run_command_with_literals() {
+        let response = b"+ Ready for literal\r\n\
+            + Ready for another\r\n\
+            A0001 OK X-NOTE completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session
-            .rename(current_mailbox_name, new_mailbox_name)
+        let responses = session
+            .run_command_with_literals("X-NOTE INBOX {5}", &["hello {6}", "world!"])
             .await
             .unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid rename command"
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 X-NOTE INBOX {5}\r\nhello {6}\r\nworld!\r\n".to_vec()
         );
+        assert_eq!(responses.len(), 3);
+        assert!(matches!(
+            responses[2].parsed(),
+            Response::Done {
+                status: Status::Ok,
+                ..
+            }
+        ));
+
+        // The server asks for more than there is.
+        let response = b"+ Ready for literal\r\n+ Ready for another\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let err = session
+            .run_command_with_literals("X-NOTE INBOX {5}", &["hello {6}"])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Parse(ParseError::Unexpected(_))));
+
+        // The session checks the command like any other.
+        let mut session = mock_session!(MockStream::default());
+        let err = session
+            .run_command_with_literals("UID FETCH $ BODY.PEEK[HEADER.FIELDS {7}", &["SUBJECT])"])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::SavedSearchInvalid));
+        session.state = SessionState::Logout;
+        let err = session
+            .run_command_with_literals("X-NOTE INBOX {5}", &["hello"])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidState(_)));
+        assert!(session.stream.inner.written_buf.is_empty());
     }
 
     #[async_std::test]
-    async fn subscribe() {
-        let response = b"A0001 OK SUBSCRIBE completed\r\n".to_vec();
-        let mailbox = "INBOX";
-        let command = format!("A0001 SUBSCRIBE {}\r\n", quote!(mailbox));
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.subscribe(mailbox).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid subscribe command"
-        );
+    async fn connection_run_command_and_check_ok() {
+        let response = b"* 3 EXISTS\r\n\
+            * 1 RECENT\r\n\
+            A0001 OK NOOP completed\r\n"
+            .to_vec();
+        let mut client = mock_client!(MockStream::new(response));
+        let (tx, rx) = channel::bounded(2);
+        client
+            .run_command_and_check_ok("NOOP", Some(tx))
+            .await
+            .unwrap();
+        assert_eq!(rx.try_recv().unwrap(), UnsolicitedResponse::Exists(3));
+        assert_eq!(rx.try_recv().unwrap(), UnsolicitedResponse::Recent(1));
+        assert!(rx.try_recv().is_err());
     }
 
     #[async_std::test]
-    async fn unsubscribe() {
-        let response = b"A0001 OK UNSUBSCRIBE completed\r\n".to_vec();
-        let mailbox = "INBOX";
-        let command = format!("A0001 UNSUBSCRIBE {}\r\n", quote!(mailbox));
+    async fn run_command_and_read_responses() {
+        let response = b"* XLIST (\\HasNoChildren \\Inbox) \"/\" \"Inbox\"\r\n\
+            * XLIST (\\HasNoChildren \\Sent) \"/\" \"[Gmail]/Sent Mail\"\r\n\
+            * 3 EXISTS\r\n\
+            A0001 OK XLIST completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.unsubscribe(mailbox).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid unsubscribe command"
+        let responses = session
+            .run_command_and_read_responses("XLIST \"\" \"*\"")
+            .await
+            .unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 XLIST \"\" \"*\"\r\n".to_vec()
+        );
+        assert_eq!(responses.len(), 4);
+
+        let items: Vec<_> = responses[..2]
+            .iter()
+            .flat_map(|response| response.raw_items())
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                (
+                    &b"XLIST"[..],
+                    Some(&b"(\\HasNoChildren \\Inbox) \"/\" \"Inbox\""[..])
+                ),
+                (
+                    &b"XLIST"[..],
+                    Some(&b"(\\HasNoChildren \\Sent) \"/\" \"[Gmail]/Sent Mail\""[..])
+                ),
+            ]
+        );
+        assert_eq!(
+            responses[2].parsed(),
+            &Response::MailboxData(imap_proto::MailboxDatum::Exists(3))
+        );
+        assert!(matches!(
+            responses[3].parsed(),
+            Response::Done {
+                status: imap_proto::Status::Ok,
+                ..
+            }
+        ));
+        assert!(session.unsolicited_responses.try_recv().is_err());
+    }
+
+    #[async_std::test]
+    async fn tls_info() {
+        let client = mock_client!(MockStream::default());
+        assert_eq!(client.tls_info(), None);
+
+        let tls_info = TlsInfo {
+            peer_certificate: Some(vec![0x30, 0x82]),
+        };
+        let response = b"A0001 OK Logged in\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response).with_tls_info(tls_info.clone()));
+        // The session keeps the stream of the client.
+        let session = client.login("username", "password").await.unwrap();
+        assert_eq!(session.tls_info(), Some(tls_info));
+    }
+
+    #[async_std::test]
+    async fn tag_prefix() {
+        let response = b"PFX1 OK NOOP completed\r\n\
+            PFX2 OK CHECK completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.set_tag_prefix("PFX").unwrap();
+        session.noop().await.unwrap();
+        session.check().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"PFX1 NOOP\r\nPFX2 CHECK\r\n".to_vec()
+        );
+
+        assert!(matches!(
+            session.set_tag_prefix("S 1"),
+            Err(Error::Validate(ValidateError(' ')))
+        ));
+    }
+
+    #[async_std::test]
+    async fn bye_mid_command() {
+        let response = b"* 3 EXISTS\r\n\
+            * BYE [UNAVAILABLE] Server going down\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.select("INBOX").await {
+            Err(Error::Bye(reason)) => assert_eq!(reason, "[UNAVAILABLE] Server going down"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let response = b"* 1 FETCH (UID 10)\r\n\
+            * BYE Autologout; idle for too long\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session.fetch("1:*", "UID").await.unwrap().collect().await;
+        assert_eq!(fetches.len(), 2);
+        assert_eq!(fetches[0].as_ref().unwrap().uid, Some(10));
+        assert!(matches!(&fetches[1], Err(Error::Bye(_))));
+
+        let response = b"* BYE Server shutting down\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(matches!(
+            session.get_quota_root("INBOX").await,
+            Err(Error::Bye(reason)) if reason == "Server shutting down"
+        ));
+    }
+
+    #[async_std::test]
+    async fn rename() {
+        let response = b"A0001 OK RENAME completed\r\n".to_vec();
+        let current_mailbox_name = "INBOX";
+        let new_mailbox_name = "NEWINBOX";
+        let command = format!(
+            "A0001 RENAME {} {}\r\n",
+            quote!(current_mailbox_name),
+            quote!(new_mailbox_name)
+        );
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session
+            .rename(current_mailbox_name, new_mailbox_name)
+            .await
+            .unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid rename command"
+        );
+    }
+
+    #[async_std::test]
+    async fn rename_utf7() {
+        let response = b"A0001 OK RENAME completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(!session.encode_mailbox_names());
+        session.set_encode_mailbox_names(true);
+        session.rename("Entwürfe", "Brouillons & Co").await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 RENAME \"Entw&APw-rfe\" \"Brouillons &- Co\"\r\n".to_vec()
+        );
+
+        // Off by default, so that encoded names are passed as they are.
+        let response = b"A0001 OK RENAME completed\r\n\
+            A0002 OK COPY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.rename("Entw&APw-rfe", "Tom & Jerry").await.unwrap();
+        session.copy("1", "Entw&APw-rfe").await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 RENAME \"Entw&APw-rfe\" \"Tom & Jerry\"\r\n\
+            A0002 COPY 1 \"Entw&APw-rfe\"\r\n"
+                .to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn rename_inbox() {
+        // INBOX stays, empty, while its messages move to the new mailbox.
+        let response = b"A0001 OK RENAME completed\r\n\
+            * LIST (\\HasNoChildren) \"/\" INBOX\r\n\
+            * LIST (\\HasNoChildren) \"/\" \"Old Mail\"\r\n\
+            A0002 OK LIST completed\r\n\
+            A0003 NO [ALREADYEXISTS] Mailbox already exists\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.rename("inbox", "Old Mail").await.unwrap();
+        let names: Vec<_> = session
+            .list(Some(""), Some("*"))
+            .await
+            .unwrap()
+            .map(|name| name.unwrap().name().to_string())
+            .collect()
+            .await;
+        assert_eq!(names, vec!["INBOX", "Old Mail"]);
+
+        match session.rename("INBOX", "Old Mail").await {
+            Err(Error::No(res)) => {
+                assert_eq!(res.code, Some(ResponseCode::AlreadyExists));
+                assert_eq!(res.command.as_deref(), Some("RENAME"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 RENAME \"inbox\" \"Old Mail\"\r\n\
+              A0002 LIST \"\" *\r\n\
+              A0003 RENAME \"INBOX\" \"Old Mail\"\r\n"
+                .to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn subscribe() {
+        let response = b"A0001 OK SUBSCRIBE completed\r\n".to_vec();
+        let mailbox = "INBOX";
+        let command = format!("A0001 SUBSCRIBE {}\r\n", quote!(mailbox));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.subscribe(mailbox).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid subscribe command"
+        );
+    }
+
+    #[async_std::test]
+    async fn unsubscribe() {
+        let response = b"A0001 OK UNSUBSCRIBE completed\r\n".to_vec();
+        let mailbox = "INBOX";
+        let command = format!("A0001 UNSUBSCRIBE {}\r\n", quote!(mailbox));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.unsubscribe(mailbox).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid unsubscribe command"
         );
     }
 
@@ -1700,6 +4581,9 @@ mod tests {
             permanent_flags: vec![],
             uid_next: Some(2),
             uid_validity: Some(1257842737),
+            mailbox_id: None,
+            read_only: true,
+            highest_modseq: None,
         };
         let mailbox_name = "INBOX";
         let command = format!("A0001 EXAMINE {}\r\n", quote!(mailbox_name));
@@ -1713,6 +4597,46 @@ mod tests {
         assert_eq!(mailbox, expected_mailbox);
     }
 
+    #[async_std::test]
+    async fn select_condstore() {
+        let response = b"* CAPABILITY IMAP4rev1 CONDSTORE\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * 172 EXISTS\r\n\
+            * OK [UIDVALIDITY 3857529045] UIDs valid\r\n\
+            * OK [HIGHESTMODSEQ 715194045007] Highest\r\n\
+            A0002 OK [READ-WRITE] SELECT completed, CONDSTORE is now enabled\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mailbox = session.select_condstore("INBOX").await.unwrap();
+        assert_eq!(mailbox.exists, 172);
+        assert_eq!(mailbox.highest_modseq, Some(715194045007));
+        assert!(session
+            .stream
+            .inner
+            .written_buf
+            .ends_with(b"A0002 SELECT \"INBOX\" (CONDSTORE)\r\n"));
+
+        let response = b"* CAPABILITY IMAP4rev1\r\nA0001 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(matches!(
+            session.select_condstore("INBOX").await,
+            Err(Error::MissingCapability(_))
+        ));
+    }
+
+    #[async_std::test]
+    async fn select_unseen() {
+        let response = b"* 172 EXISTS\r\n\
+            * 0 RECENT\r\n\
+            * OK [UNSEEN 12] Message 12 is first unseen\r\n\
+            A0001 OK [READ-WRITE] SELECT completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mailbox = session.select("INBOX").await.unwrap();
+        assert_eq!(mailbox.unseen, Some(12));
+        assert_eq!(mailbox.recent, 0);
+    }
+
     #[async_std::test]
     async fn select() {
         let response = b"* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
@@ -1746,6 +4670,9 @@ mod tests {
             ],
             uid_next: Some(2),
             uid_validity: Some(1257842737),
+            mailbox_id: None,
+            read_only: true,
+            highest_modseq: None,
         };
         let mailbox_name = "INBOX";
         let command = format!("A0001 SELECT {}\r\n", quote!(mailbox_name));
@@ -1760,271 +4687,2737 @@ mod tests {
     }
 
     #[async_std::test]
-    async fn search() {
-        let response = b"* SEARCH 1 2 3 4 5\r\n\
-            A0001 OK Search completed\r\n"
+    async fn selected() {
+        let response = b"* 10 EXISTS\r\n\
+            * OK [UIDVALIDITY 42] UIDs valid\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            * 11 EXISTS\r\n\
+            A0002 OK NOOP completed\r\n\
+            * 3 EXPUNGE\r\n\
+            * VANISHED 7:8\r\n\
+            A0003 OK NOOP completed\r\n\
+            * 2 EXPUNGE\r\n\
+            A0004 OK EXPUNGE completed\r\n\
+            A0005 OK CLOSE completed\r\n"
             .to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        let ids = session.search("Unseen").await.unwrap();
-        let ids: HashSet<u32> = ids.iter().cloned().collect();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 SEARCH Unseen\r\n".to_vec(),
-            "Invalid search command"
-        );
-        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(session.selected(), None);
+        session.select("INBOX").await.unwrap();
+        assert_eq!(session.selected_name(), Some("INBOX"));
+        assert_eq!(session.selected().unwrap().exists, 10);
+
+        session.noop().await.unwrap();
+        let selected = session.selected().unwrap();
+        assert_eq!(selected.exists, 11);
+        assert_eq!(selected.uid_validity, Some(42));
+        session.noop().await.unwrap();
+        assert_eq!(session.selected().unwrap().exists, 8);
+        let expunged = session.expunge().await.unwrap().collect::<Vec<_>>().await;
+        assert_eq!(expunged.len(), 1);
+        assert_eq!(session.selected().unwrap().exists, 7);
+
+        session.close().await.unwrap();
+        assert_eq!(session.selected(), None);
+        assert_eq!(session.selected_name(), None);
     }
 
     #[async_std::test]
-    async fn uid_search() {
-        let response = b"* SEARCH 1 2 3 4 5\r\n\
-            A0001 OK Search completed\r\n"
+    async fn selected_vanished_all() {
+        let response = b"* 10 EXISTS\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            * VANISHED 1:4294967295,7:8\r\n\
+            A0002 OK NOOP completed\r\n"
             .to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        let ids = session.uid_search("Unseen").await.unwrap();
-        let ids: HashSet<Uid> = ids.iter().cloned().collect();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 UID SEARCH Unseen\r\n".to_vec(),
-            "Invalid search command"
-        );
-        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+        let mut session = mock_session!(MockStream::new(response));
+        session.select("INBOX").await.unwrap();
+        session.noop().await.unwrap();
+        assert_eq!(session.selected().unwrap().exists, 0);
     }
 
     #[async_std::test]
-    async fn uid_search_unordered() {
-        let response = b"* SEARCH 1 2 3 4 5\r\n\
-            A0002 OK CAPABILITY completed\r\n\
-            A0001 OK Search completed\r\n"
+    async fn track_uids() {
+        let response = b"* 5 EXISTS\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            * 1 FETCH (UID 10 FLAGS ())\r\n\
+            * 2 FETCH (UID 20 FLAGS ())\r\n\
+            * 3 FETCH (UID 30 FLAGS ())\r\n\
+            * 4 FETCH (UID 40 FLAGS ())\r\n\
+            * 5 FETCH (UID 50 FLAGS ())\r\n\
+            A0002 OK FETCH completed\r\n\
+            * 2 EXPUNGE\r\n\
+            * 5 EXISTS\r\n\
+            * 5 FETCH (UID 60 FLAGS (\\Seen))\r\n\
+            A0003 OK NOOP completed\r\n\
+            * 3 EXPUNGE\r\n\
+            * 3 EXPUNGE\r\n\
+            A0004 OK EXPUNGE completed\r\n"
             .to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        let ids = session.uid_search("Unseen").await.unwrap();
-        let ids: HashSet<Uid> = ids.iter().cloned().collect();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 UID SEARCH Unseen\r\n".to_vec(),
-            "Invalid search command"
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(!session.track_uids());
+        session.set_track_uids(true);
+        assert!(session.track_uids());
+        session.select("INBOX").await.unwrap();
+        assert_eq!(session.uid(1), None);
+
+        session.fetch_all("1:5", "FLAGS").await.unwrap();
+        assert_eq!(session.uid(5), Some(50));
+        session.noop().await.unwrap();
+        assert_eq!(session.uid(2), Some(30));
+        assert_eq!(session.uid(5), Some(60));
+
+        let expunged = session.expunge().await.unwrap().collect::<Vec<_>>().await;
+        assert_eq!(expunged.len(), 2);
+        assert_eq!(
+            (1..=4).map(|seq| session.uid(seq)).collect::<Vec<_>>(),
+            vec![Some(10), Some(30), Some(60), None]
         );
-        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+        assert_eq!(session.seq(60), Some(3));
+        assert_eq!(session.seq(40), None);
+
+        session.set_track_uids(false);
+        assert_eq!(session.uid(1), None);
     }
 
     #[async_std::test]
-    async fn capability() {
-        let response = b"* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n\
-            A0001 OK CAPABILITY completed\r\n"
+    async fn invalid_state() {
+        let response = b"A0001 NO Mailbox does not exist\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            * 1 FETCH (UID 7)\r\n\
+            A0003 OK FETCH completed\r\n\
+            A0004 OK CLOSE completed\r\n\
+            * BYE Logging out\r\n\
+            A0005 OK LOGOUT completed\r\n"
             .to_vec();
-        let expected_capabilities = vec!["IMAP4rev1", "STARTTLS", "AUTH=GSSAPI", "LOGINDISABLED"];
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        let capabilities = session.capabilities().await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 CAPABILITY\r\n".to_vec(),
-            "Invalid capability command"
+        // Right after login, without a mailbox selected.
+        let mut session = Session::new(mock_client!(MockStream::new(response)).conn);
+        assert!(matches!(
+            session.fetch("1", "UID").await.err(),
+            Some(Error::InvalidState(command)) if command == "FETCH"
+        ));
+        assert!(matches!(
+            session.uid_store_flags(vec![7], FlagsOperation::Add, &[Flag::Seen]).await,
+            Err(Error::InvalidState(command)) if command == "UID STORE"
+        ));
+        assert!(session.stream.inner.written_buf.is_empty());
+
+        assert!(session.select("Missing").await.is_err());
+        assert!(matches!(
+            session.expunge().await.err(),
+            Some(Error::InvalidState(_))
+        ));
+        session.select("INBOX").await.unwrap();
+        assert_eq!(session.fetch_all("1", "UID").await.unwrap().len(), 1);
+        session.close().await.unwrap();
+        assert!(matches!(session.check().await, Err(Error::InvalidState(_))));
+
+        session.logout().await.unwrap();
+        assert!(matches!(
+            session.noop().await,
+            Err(Error::InvalidState(command)) if command == "NOOP"
+        ));
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 SELECT \"Missing\"\r\n\
+            A0002 SELECT \"INBOX\"\r\n\
+            A0003 FETCH 1 UID\r\n\
+            A0004 CLOSE\r\n\
+            A0005 LOGOUT\r\n"
+                .to_vec()
         );
-        assert_eq!(capabilities.len(), 4);
-        for e in expected_capabilities {
-            assert!(capabilities.has_str(e));
-        }
     }
 
     #[async_std::test]
-    async fn create() {
-        let response = b"A0001 OK CREATE completed\r\n".to_vec();
-        let mailbox_name = "INBOX";
-        let command = format!("A0001 CREATE {}\r\n", quote!(mailbox_name));
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.create(mailbox_name).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid create command"
+    async fn invalid_state_raw_commands() {
+        let response = b"A0001 NO Mailbox does not exist\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            A0003 NO Mailbox does not exist\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(session
+            .run_command_and_check_ok("SELECT Missing")
+            .await
+            .is_err());
+        assert!(matches!(
+            session.fetch("1", "FLAGS").await.err(),
+            Some(Error::InvalidState(command)) if command == "FETCH"
+        ));
+        assert!(matches!(
+            session.run_command_and_read_responses("FETCH 1 FLAGS").await,
+            Err(Error::InvalidState(command)) if command == "FETCH"
+        ));
+
+        session
+            .run_command_and_check_ok("SELECT INBOX")
+            .await
+            .unwrap();
+        let responses = session
+            .run_command_and_read_responses("EXAMINE Missing")
+            .await
+            .unwrap();
+        assert_eq!(responses.len(), 1);
+        assert!(matches!(
+            session
+                .run_command_and_read_responses("FETCH 1 FLAGS")
+                .await,
+            Err(Error::InvalidState(_))
+        ));
+        assert_eq!(session.selected(), None);
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 SELECT Missing\r\nA0002 SELECT INBOX\r\nA0003 EXAMINE Missing\r\n".to_vec()
         );
     }
 
     #[async_std::test]
-    async fn delete() {
-        let response = b"A0001 OK DELETE completed\r\n".to_vec();
-        let mailbox_name = "INBOX";
-        let command = format!("A0001 DELETE {}\r\n", quote!(mailbox_name));
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.delete(mailbox_name).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid delete command"
-        );
+    async fn select_read_only() {
+        let response = b"* 3 EXISTS\r\n\
+            A0001 OK [READ-WRITE] SELECT completed\r\n\
+            * 3 EXISTS\r\n\
+            A0002 OK [READ-ONLY] SELECT completed\r\n\
+            * 3 EXISTS\r\n\
+            A0003 OK EXAMINE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(!session.select("INBOX").await.unwrap().read_only);
+        assert!(session.select("Shared").await.unwrap().read_only);
+        // Read-only even without the response code.
+        assert!(session.examine("INBOX").await.unwrap().read_only);
     }
 
     #[async_std::test]
-    async fn noop() {
-        let response = b"A0001 OK NOOP completed\r\n".to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.noop().await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 NOOP\r\n".to_vec(),
-            "Invalid noop command"
+    async fn select_mailbox_id() {
+        let response = b"* 3 EXISTS\r\n\
+            * OK [UIDVALIDITY 3857529045] UIDs valid\r\n\
+            * OK [MAILBOXID (F2212ea87-6097-4256-9d51-71338625)] Ok\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mailbox = session.select("INBOX").await.unwrap();
+        assert_eq!(mailbox.exists, 3);
+        assert_eq!(mailbox.uid_validity, Some(3857529045));
+        assert_eq!(
+            mailbox.mailbox_id.as_deref(),
+            Some("F2212ea87-6097-4256-9d51-71338625")
         );
     }
 
     #[async_std::test]
-    async fn close() {
-        let response = b"A0001 OK CLOSE completed\r\n".to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.close().await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 CLOSE\r\n".to_vec(),
-            "Invalid close command"
+    async fn select_utf8_accept() {
+        let response = b"* 2 EXISTS\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            * ENABLED UTF8=ACCEPT\r\n\
+            A0002 OK ENABLE completed\r\n\
+            * 3 EXISTS\r\n\
+            A0003 OK [READ-WRITE] Select completed.\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_encode_mailbox_names(true);
+
+        let mailbox = session.select("Entwürfe & Co").await.unwrap();
+        assert_eq!(mailbox.exists, 2);
+        let enabled = session.enable(&["UTF8=ACCEPT"]).await.unwrap();
+        assert!(enabled.has_str("UTF8=ACCEPT"));
+        let mailbox = session.select("Entwürfe & Co").await.unwrap();
+        assert_eq!(mailbox.exists, 3);
+
+        assert_eq!(
+            String::from_utf8(session.stream.inner.written_buf.clone()).unwrap(),
+            "A0001 SELECT \"Entw&APw-rfe &- Co\"\r\n\
+             A0002 ENABLE UTF8=ACCEPT\r\n\
+             A0003 SELECT \"Entwürfe & Co\"\r\n"
         );
     }
 
     #[async_std::test]
-    async fn store() {
-        generic_store(" ", |c, set, query| async move {
-            c.lock()
-                .await
-                .store(set, query)
-                .await?
-                .collect::<Vec<_>>()
-                .await;
-            Ok(())
-        })
-        .await;
-    }
+    async fn list_utf8_names() {
+        let response = "* LIST () \"/\" \"Entw&APw-rfe\"\r\n\
+            A0001 OK LIST completed\r\n\
+            * ENABLED UTF8=ACCEPT\r\n\
+            A0002 OK ENABLE completed\r\n\
+            * LIST () \"/\" \"Entwürfe\"\r\n\
+            * LIST () \"/\" \"Tom & Jerry\"\r\n\
+            A0003 OK LIST completed\r\n"
+            .as_bytes()
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_encode_mailbox_names(true);
 
-    #[async_std::test]
-    async fn uid_store() {
-        generic_store(" UID ", |c, set, query| async move {
-            c.lock()
-                .await
-                .uid_store(set, query)
-                .await?
-                .collect::<Vec<_>>()
-                .await;
-            Ok(())
-        })
-        .await;
+        let names: Vec<_> = session
+            .list(None, Some("*"))
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(names[0].name(), "Entwürfe");
+
+        session.enable(&["UTF8=ACCEPT"]).await.unwrap();
+        let names: Vec<_> = session
+            .list(None, Some("*"))
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            names.iter().map(Name::name).collect::<Vec<_>>(),
+            vec!["Entwürfe", "Tom & Jerry"]
+        );
     }
 
-    async fn generic_store<'a, F, T, K>(prefix: &'a str, op: F)
-    where
-        F: 'a + FnOnce(Arc<Mutex<Session<MockStream>>>, &'a str, &'a str) -> K,
-        K: 'a + Future<Output = Result<T>>,
-    {
-        let res = "* 2 FETCH (FLAGS (\\Deleted \\Seen))\r\n\
-                   * 3 FETCH (FLAGS (\\Deleted))\r\n\
-                   * 4 FETCH (FLAGS (\\Deleted \\Flagged \\Seen))\r\n\
-                   A0001 OK STORE completed\r\n";
+    #[async_std::test]
+    async fn list_owned_names() {
+        let response = "* LIST (\\HasChildren \\Noselect) \"/\" \"Entw&APw-rfe\"\r\n\
+            * LIST () NIL INBOX\r\n\
+            A0001 OK LIST completed\r\n"
+            .as_bytes()
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_encode_mailbox_names(true);
 
-        generic_with_uid(res, "STORE", "2.4", "+FLAGS (\\Deleted)", prefix, op).await;
+        let names: Vec<Name> = session
+            .list(None, Some("*"))
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        let first = names[0].to_owned_name();
+        let owned: Vec<OwnedName> = names.into_iter().map(Name::into_owned).collect();
+        drop(session);
+
+        assert_eq!(owned[0], first);
+        assert_eq!(
+            owned[0],
+            OwnedName {
+                attributes: vec![NameAttribute::HasChildren, NameAttribute::NoSelect],
+                delimiter: Some("/".to_string()),
+                name: "Entwürfe".to_string(),
+            }
+        );
+        assert_eq!(owned[1].delimiter, None);
+        assert_eq!(owned[1].name, "INBOX");
     }
 
     #[async_std::test]
-    async fn copy() {
-        generic_copy(" ", |c, set, query| async move {
-            c.lock().await.copy(set, query).await?;
-            Ok(())
-        })
-        .await;
+    async fn list_extended() {
+        let response = b"* CAPABILITY IMAP4rev1 LIST-EXTENDED\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * LIST (\\Subscribed \\HasNoChildren) \"/\" INBOX\r\n\
+            * LIST (\\HasChildren) \"/\" \"Lists\" (\"CHILDINFO\" (\"SUBSCRIBED\"))\r\n\
+            * LIST (\\Subscribed \\NonExistent) \"/\" \"Lists/old\"\r\n\
+            A0002 OK LIST completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let names: Vec<Name> = session
+            .list_extended(
+                &[ListSelection::Subscribed, ListSelection::RecursiveMatch],
+                None,
+                Some("*"),
+                &[ListReturn::Children],
+            )
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            str::from_utf8(&session.stream.inner.written_buf).unwrap(),
+            "A0001 CAPABILITY\r\n\
+             A0002 LIST (SUBSCRIBED RECURSIVEMATCH) \"\" * RETURN (CHILDREN)\r\n"
+        );
+
+        let subscribed: Vec<_> = names
+            .iter()
+            .filter(|name| name.attributes().contains(&NameAttribute::Subscribed))
+            .map(|name| name.name())
+            .collect();
+        assert_eq!(subscribed, vec!["INBOX", "Lists/old"]);
+        assert_eq!(names[1].name(), "Lists");
+        assert_eq!(names[1].child_info(), vec!["SUBSCRIBED"]);
+        assert!(names[0].child_info().is_empty());
+        assert!(names[2].attributes().contains(&NameAttribute::NonExistent));
+
+        let response = b"* CAPABILITY IMAP4rev1\r\nA0001 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(matches!(
+            session
+                .list_extended(&[ListSelection::Subscribed], None, Some("*"), &[])
+                .await
+                .map(drop),
+            Err(Error::MissingCapability(_))
+        ));
     }
 
     #[async_std::test]
-    async fn uid_copy() {
-        generic_copy(" UID ", |c, set, query| async move {
-            c.lock().await.uid_copy(set, query).await?;
-            Ok(())
-        })
-        .await;
+    async fn list_attribute_parser() {
+        #[derive(Debug, PartialEq)]
+        enum Proprietary<'a> {
+            MyAttr,
+            Label(&'a str),
+        }
+
+        struct Parser;
+
+        impl<'a> NameAttributeParser<'a> for Parser {
+            type Attribute = Proprietary<'a>;
+
+            fn parse(&self, raw: &'a str) -> Option<Proprietary<'a>> {
+                if raw.eq_ignore_ascii_case("\\MyAttr") {
+                    Some(Proprietary::MyAttr)
+                } else {
+                    raw.strip_prefix("\\Label-").map(Proprietary::Label)
+                }
+            }
+        }
+
+        let response = "* LIST (\\MyAttr \\Noselect \\Label-red \\Other) \"/\" Shared\r\n\
+            A0001 OK LIST completed\r\n"
+            .as_bytes()
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let names: Vec<Name> = session
+            .list(None, Some("*"))
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        let expected = vec![
+            ParsedNameAttribute::Parsed(Proprietary::MyAttr),
+            ParsedNameAttribute::Builtin(NameAttribute::NoSelect),
+            ParsedNameAttribute::Parsed(Proprietary::Label("red")),
+            ParsedNameAttribute::Builtin(NameAttribute::Custom("\\Other".into())),
+        ];
+        assert_eq!(names[0].attributes_with(&Parser), expected);
+        assert_eq!(names[0].to_owned_name().attributes_with(&Parser), expected);
+
+        let my_attr = |raw: &str| Some(raw == "\\MyAttr").filter(|known| *known);
+        assert_eq!(
+            names[0].attributes_with(&my_attr)[0],
+            ParsedNameAttribute::Parsed(true)
+        );
+        // Without a parser, the attributes stay as they are.
+        assert_eq!(
+            names[0].attributes()[0],
+            NameAttribute::Custom("\\MyAttr".into())
+        );
     }
 
-    async fn generic_copy<'a, F, T, K>(prefix: &'a str, op: F)
-    where
-        F: 'a + FnOnce(Arc<Mutex<Session<MockStream>>>, &'a str, &'a str) -> K,
-        K: 'a + Future<Output = Result<T>>,
-    {
-        generic_with_uid(
-            "OK COPY completed\r\n",
-            "COPY",
-            "2:4",
-            "MEETING",
-            prefix,
-            op,
-        )
-        .await;
+    #[async_std::test]
+    async fn append() {
+        let response = b"+ Ready for literal data\r\n\
+            * 3 EXISTS\r\n\
+            A0001 OK [APPENDUID 38505 3955] APPEND completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let append_uid = session.append("Drafts", "Hello!\r\n").await.unwrap();
+        assert_eq!(
+            append_uid,
+            Some(AppendUid {
+                uid_validity: 38505,
+                uids: vec![3955],
+            })
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 APPEND \"Drafts\" {8}\r\nHello!\r\n\r\n",
+            "Invalid append command"
+        );
+        assert!(matches!(
+            session.unsolicited_responses.try_recv(),
+            Ok(UnsolicitedResponse::Exists(3))
+        ));
     }
 
     #[async_std::test]
-    async fn mv() {
-        let response = b"* OK [COPYUID 1511554416 142,399 41:42] Moved UIDs.\r\n\
-            * 2 EXPUNGE\r\n\
-            * 1 EXPUNGE\r\n\
-            A0001 OK Move completed\r\n"
+    async fn append_with_exists() {
+        let response = b"* 4 EXISTS\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            + Ready for literal data\r\n\
+            * 5 EXISTS\r\n\
+            A0002 OK APPEND completed\r\n\
+            + Ready for literal data\r\n\
+            A0003 OK APPEND completed\r\n\
+            + Ready for literal data\r\n\
+            * 6 EXISTS\r\n\
+            A0004 OK [APPENDUID 38505 3955] APPEND completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.select("INBOX").await.unwrap();
+        let appended = session
+            .append_with_exists("inbox", "Hello!\r\n")
+            .await
+            .unwrap();
+        assert_eq!(appended.exists, Some(5));
+        assert_eq!(appended.seq(), Some(5));
+        assert_eq!(appended.uid, None);
+        assert!(matches!(
+            session.unsolicited_responses.try_recv(),
+            Ok(UnsolicitedResponse::Exists(5))
+        ));
+
+        // The server did not report the new message.
+        let appended = session
+            .append_with_exists("INBOX", "Hello!\r\n")
+            .await
+            .unwrap();
+        assert_eq!(appended, Appended::default());
+
+        // Not the selected mailbox.
+        let appended = session
+            .append_with_exists("Drafts", "Hello!\r\n")
+            .await
+            .unwrap();
+        assert_eq!(appended.exists, None);
+        assert_eq!(appended.uid.unwrap().uids, vec![3955]);
+    }
+
+    #[async_std::test]
+    async fn append_rejected() {
+        let response = b"A0001 NO [LIMIT] Message too large\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.append("Drafts", &[b'x'; 100]).await {
+            Err(Error::No(res)) => {
+                assert_eq!(res.code, Some(crate::error::ResponseCode::Limit));
+                assert_eq!(res.command.as_deref(), Some("APPEND"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 APPEND \"Drafts\" {100}\r\n",
+            "Literal must not be sent"
+        );
+    }
+
+    #[async_std::test]
+    async fn append_utf8() {
+        let message = "Subject: Grüße\r\n\r\nHallo\r\n";
+        let response = b"* ENABLED UTF8=ACCEPT\r\n\
+            A0001 OK ENABLE completed\r\n\
+            + Ready for literal data\r\n\
+            A0002 OK APPEND completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.enable(&["UTF8=ACCEPT"]).await.unwrap();
+        session.append("Entwürfe", message).await.unwrap();
+        assert_eq!(
+            String::from_utf8(session.stream.inner.written_buf.clone()).unwrap(),
+            format!(
+                "A0001 ENABLE UTF8=ACCEPT\r\n\
+                 A0002 APPEND \"Entwürfe\" UTF8 (~{{{}}}\r\n{})\r\n",
+                message.len(),
+                message
+            )
+        );
+    }
+
+    #[async_std::test]
+    async fn search() {
+        let response = b"* SEARCH 1 2 3 4 5\r\n\
+            A0001 OK Search completed\r\n"
             .to_vec();
-        let mailbox_name = "MEETING";
-        let command = format!("A0001 MOVE 1:2 {}\r\n", quote!(mailbox_name));
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.mv("1:2", mailbox_name).await.unwrap();
+        let ids = session.search("Unseen").await.unwrap();
+        let ids: HashSet<u32> = ids.iter().cloned().collect();
         assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid move command"
+            session.stream.inner.written_buf == b"A0001 SEARCH Unseen\r\n".to_vec(),
+            "Invalid search command"
         );
+        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
     }
 
     #[async_std::test]
-    async fn uid_mv() {
-        let response = b"* OK [COPYUID 1511554416 142,399 41:42] Moved UIDs.\r\n\
-            * 2 EXPUNGE\r\n\
-            * 1 EXPUNGE\r\n\
-            A0001 OK Move completed\r\n"
+    async fn uid_search() {
+        let response = b"* SEARCH 1 2 3 4 5\r\n\
+            A0001 OK Search completed\r\n"
             .to_vec();
-        let mailbox_name = "MEETING";
-        let command = format!("A0001 UID MOVE 41:42 {}\r\n", quote!(mailbox_name));
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.uid_mv("41:42", mailbox_name).await.unwrap();
+        let ids = session.uid_search("Unseen").await.unwrap();
+        let ids: HashSet<Uid> = ids.iter().cloned().collect();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 UID SEARCH Unseen\r\n".to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+    }
+
+    #[async_std::test]
+    async fn search_charset() {
+        let response = "* SEARCH 1\r\n\
+            A0001 OK Search completed\r\n\
+            * SEARCH 2\r\n\
+            A0002 OK Search completed\r\n\
+            A0003 NO [BADCHARSET (US-ASCII ISO-8859-1)] Unsupported charset\r\n"
+            .as_bytes()
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.search("SUBJECT \"hello\"").await.unwrap();
+        session.uid_search("SUBJECT \"grüße\"").await.unwrap();
+        let err = session
+            .search("CHARSET ISO-2022-JP SUBJECT \"日本\"")
+            .await
+            .unwrap_err();
+        match err {
+            Error::No(response) => assert_eq!(
+                response.code,
+                Some(crate::error::ResponseCode::BadCharset(vec![
+                    "US-ASCII".to_string(),
+                    "ISO-8859-1".to_string()
+                ]))
+            ),
+            err => panic!("unexpected error: {:?}", err),
+        }
+        assert_eq!(
+            str::from_utf8(&session.stream.inner.written_buf).unwrap(),
+            "A0001 SEARCH SUBJECT \"hello\"\r\n\
+             A0002 UID SEARCH CHARSET UTF-8 SUBJECT \"grüße\"\r\n\
+             A0003 SEARCH CHARSET ISO-2022-JP SUBJECT \"日本\"\r\n"
+        );
+    }
+
+    #[async_std::test]
+    async fn esearch_charset() {
+        let response = b"* CAPABILITY IMAP4rev1 ESEARCH\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            A0002 OK SEARCH completed\r\n\
+            A0003 OK SEARCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.esearch("FROM \"José\"").await.unwrap();
+        session
+            .uid_esearch("RETURN (COUNT) FROM \"José\"")
+            .await
+            .unwrap();
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with(
+                "A0002 SEARCH RETURN (MIN MAX ALL COUNT) CHARSET UTF-8 FROM \"José\"\r\n\
+                 A0003 UID SEARCH RETURN (COUNT) CHARSET UTF-8 FROM \"José\"\r\n"
+            ));
+    }
+
+    #[async_std::test]
+    async fn uid_search_unordered() {
+        let response = b"* SEARCH 1 2 3 4 5\r\n\
+            A0001 OK Search completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let ids = session.uid_search("Unseen").await.unwrap();
+        let ids: HashSet<Uid> = ids.iter().cloned().collect();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 UID SEARCH Unseen\r\n".to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+    }
+
+    #[async_std::test]
+    async fn capability() {
+        let response = b"* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n\
+            A0001 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let expected_capabilities = vec!["IMAP4rev1", "STARTTLS", "AUTH=GSSAPI", "LOGINDISABLED"];
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let capabilities = session.capabilities().await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 CAPABILITY\r\n".to_vec(),
+            "Invalid capability command"
+        );
+        assert_eq!(capabilities.len(), 4);
+        for e in expected_capabilities {
+            assert!(capabilities.has_str(e));
+        }
+    }
+
+    #[async_std::test]
+    async fn create() {
+        let response = b"A0001 OK CREATE completed\r\n".to_vec();
+        let mailbox_name = "INBOX";
+        let command = format!("A0001 CREATE {}\r\n", quote!(mailbox_name));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        assert_eq!(session.create(mailbox_name).await.unwrap(), None);
         assert!(
             session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid uid move command"
+            "Invalid create command"
         );
     }
 
+    // The warning is only logged in debug builds.
+    #[cfg(debug_assertions)]
     #[async_std::test]
-    async fn fetch() {
-        generic_fetch(" ", |c, seq, query| async move {
-            c.lock()
-                .await
-                .fetch(seq, query)
-                .await?
-                .collect::<Vec<_>>()
-                .await;
+    async fn drop_without_logout() {
+        /// Records the log messages of the current thread.
+        struct CaptureLogger;
 
-            Ok(())
-        })
-        .await;
+        thread_local! {
+            static CAPTURED: std::cell::RefCell<Vec<String>> = Default::default();
+        }
+
+        impl log::Log for CaptureLogger {
+            fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record<'_>) {
+                CAPTURED.with(|captured| captured.borrow_mut().push(record.args().to_string()));
+            }
+
+            fn flush(&self) {}
+        }
+
+        fn captured_warnings() -> usize {
+            CAPTURED.with(|captured| {
+                captured
+                    .borrow_mut()
+                    .drain(..)
+                    .filter(|message| message.contains("without logging out"))
+                    .count()
+            })
+        }
+
+        static LOGGER: CaptureLogger = CaptureLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Warn);
+        captured_warnings();
+
+        let session = mock_session!(MockStream::new(Vec::new()));
+        drop(session);
+        assert_eq!(captured_warnings(), 1);
+
+        let response = b"* BYE Logging out\r\nA0001 OK Logout completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.logout().await.unwrap();
+        drop(session);
+        assert_eq!(captured_warnings(), 0);
     }
 
     #[async_std::test]
-    async fn uid_fetch() {
-        generic_fetch(" UID ", |c, seq, query| async move {
-            c.lock()
-                .await
-                .uid_fetch(seq, query)
-                .await?
-                .collect::<Vec<_>>()
-                .await;
-            Ok(())
-        })
-        .await;
+    async fn starttls_missing() {
+        let response = b"* CAPABILITY IMAP4rev1 AUTH=PLAIN\r\n\
+            A0001 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        match client
+            .starttls("imap.example.org", TlsConnector::new())
+            .await
+        {
+            Err(Error::MissingCapability(cap)) => assert_eq!(cap, "STARTTLS"),
+            other => panic!("unexpected result: {:?}", other.map(|(_, caps)| caps)),
+        }
+    }
+
+    #[async_std::test]
+    async fn stray_tag() {
+        let response = b"A0007 OK NOOP completed\r\nA0001 OK NOOP completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.noop().await {
+            Err(Error::Parse(ParseError::UnexpectedTag(tag))) => assert_eq!(tag, "A0007"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // A duplicate completion of an earlier command.
+        let response = b"A0001 OK NOOP completed\r\n\
+            A0001 NO NOOP completed\r\n\
+            A0002 OK CREATE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.noop().await.unwrap();
+        match session.create("Archive").await {
+            Err(Error::Parse(ParseError::UnexpectedTag(tag))) => assert_eq!(tag, "A0001"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[async_std::test]
+    async fn malformed_response() {
+        let response = b"* 1 FETCH (UID 1)\r\n\
+            * 2 FETCH (UID 2 FLAGS (\\Seen) RFC822.SIZE big)\r\n\
+            A0001 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session
+            .fetch("1:2", "(UID FLAGS RFC822.SIZE)")
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(fetches[0].is_ok());
+        match &fetches[1] {
+            Err(Error::Parse(ParseError::Malformed { snippet, .. })) => {
+                assert!(snippet.contains("RFC822.SIZE big"), "{:?}", snippet)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[async_std::test]
+    async fn create_uid_validity() {
+        let response = b"A0001 OK [UIDVALIDITY 12345] CREATE completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(session.create("Archive").await.unwrap(), Some(12345));
+    }
+
+    #[async_std::test]
+    async fn delete() {
+        let response = b"A0001 OK DELETE completed\r\n".to_vec();
+        let mailbox_name = "INBOX";
+        let command = format!("A0001 DELETE {}\r\n", quote!(mailbox_name));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.delete(mailbox_name).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid delete command"
+        );
+    }
+
+    #[async_std::test]
+    async fn noop() {
+        let response = b"A0001 OK NOOP completed\r\n".to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.noop().await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 NOOP\r\n".to_vec(),
+            "Invalid noop command"
+        );
+    }
+
+    #[async_std::test]
+    async fn dropped_fetch() {
+        use futures::FutureExt;
+
+        let response = b"* 1 FETCH (UID 7 FLAGS (\\Seen))\r\n\
+            A0001 OK FETCH completed\r\n\
+            A0002 OK NOOP completed\r\n"
+            .to_vec();
+        // The server is slower than the timer.
+        let mock_stream = MockStream::new(response).with_pause(0, Duration::from_millis(200));
+        let mut session = mock_session!(mock_stream);
+        {
+            let fetch = async {
+                let fetches = session.fetch("1", "(UID FLAGS)").await.unwrap();
+                fetches.collect::<Vec<_>>().await
+            }
+            .fuse();
+            let timer = async_std::task::sleep(Duration::from_millis(20)).fuse();
+            futures::pin_mut!(fetch, timer);
+            futures::select! {
+                _ = fetch => panic!("fetch completed before the timer"),
+                _ = timer => {}
+            }
+        }
+
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 FETCH 1 (UID FLAGS)\r\nA0002 NOOP\r\n".to_vec()
+        );
+        assert!(matches!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Other(_)
+        ));
+    }
+
+    #[async_std::test]
+    async fn ping() {
+        let response = b"* 3 EXISTS\r\nA0001 OK NOOP completed\r\n".to_vec();
+        let mock_stream = MockStream::new(response).with_pause(0, Duration::from_millis(100));
+        let mut session = mock_session!(mock_stream);
+        let rtt = session.ping().await.unwrap();
+        assert!(rtt >= Duration::from_millis(100), "{:?}", rtt);
+        assert!(rtt < Duration::from_secs(5), "{:?}", rtt);
+        assert_eq!(session.stream.inner.written_buf, b"A0001 NOOP\r\n".to_vec());
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(3)
+        );
+    }
+
+    #[async_std::test]
+    async fn unsolicited_closed() {
+        let response = b"* 4 EXISTS\r\n\
+            * 2 EXPUNGE\r\n\
+            A0001 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.unsolicited_responses.close();
+        session.noop().await.unwrap();
+        assert_eq!(session.unsolicited_dropped(), 2);
+    }
+
+    #[async_std::test]
+    async fn unsolicited_capacity() {
+        let response = b"A0001 OK Logged in\r\n\
+            * 1 EXISTS\r\n\
+            * 2 EXISTS\r\n\
+            * 3 EXISTS\r\n\
+            * 4 EXISTS\r\n\
+            * 5 EXISTS\r\n\
+            A0002 OK NOOP completed\r\n"
+            .to_vec();
+        let mut client = mock_client!(MockStream::new(response));
+        client.set_unsolicited_capacity(2);
+        let mut session = client.login("username", "password").await.unwrap();
+        assert_eq!(session.unsolicited_capacity(), 2);
+
+        session.noop().await.unwrap();
+        assert_eq!(session.unsolicited_dropped(), 3);
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(4)
+        );
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(5)
+        );
+        assert!(session.unsolicited_responses.try_recv().is_err());
+    }
+
+    #[async_std::test]
+    async fn unsolicited_policy() {
+        let response = b"A0001 OK Logged in\r\n\
+            * 1 EXISTS\r\n\
+            * 2 EXISTS\r\n\
+            A0002 OK NOOP completed\r\n\
+            * 3 EXISTS\r\n\
+            A0003 OK NOOP completed\r\n"
+            .to_vec();
+        let mut client = mock_client!(MockStream::new(response));
+        client.set_unsolicited_policy(UnsolicitedPolicy::Drop);
+        let mut session = client.login("username", "password").await.unwrap();
+        assert_eq!(session.unsolicited_policy(), UnsolicitedPolicy::Drop);
+
+        session.noop().await.unwrap();
+        assert_eq!(session.unsolicited_dropped(), 2);
+        assert!(session.unsolicited_responses.try_recv().is_err());
+
+        session.set_unsolicited_policy(UnsolicitedPolicy::Buffer);
+        session.noop().await.unwrap();
+        assert_eq!(session.unsolicited_dropped(), 2);
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(3)
+        );
+    }
+
+    #[async_std::test]
+    async fn subscribe_unsolicited() {
+        let response = b"* 4 EXISTS\r\nA0001 OK NOOP completed\r\n\
+            * 5 EXISTS\r\nA0002 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let first = session.subscribe_unsolicited();
+        let second = session.subscribe_unsolicited();
+        session.noop().await.unwrap();
+        for rx in &[&first, &second, &session.unsolicited_responses] {
+            assert_eq!(rx.try_recv().unwrap(), UnsolicitedResponse::Exists(4));
+            assert!(rx.try_recv().is_err());
+        }
+
+        // A subscriber that closed its channel does not count against the session.
+        first.close();
+        session.noop().await.unwrap();
+        assert_eq!(session.unsolicited_subscribers_dropped(), 1);
+        assert_eq!(session.unsolicited_dropped(), 0);
+        assert_eq!(second.try_recv().unwrap(), UnsolicitedResponse::Exists(5));
+    }
+
+    #[async_std::test]
+    async fn announced_capabilities() {
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * CAPABILITY IMAP4rev1 MOVE SEARCHRES\r\n\
+            * 3 EXISTS\r\n\
+            A0002 OK NOOP completed\r\n\
+            A0003 OK SEARCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let caps = session.capabilities().await.unwrap();
+        assert!(!caps.has(&Capability::Atom("MOVE".into())));
+
+        session.noop().await.unwrap();
+        let caps = session.cached_capabilities().unwrap();
+        assert!(caps.has(&Capability::Atom("MOVE".into())));
+        assert!(caps.has_str("SEARCHRES"));
+        // Not passed on as an unsolicited response.
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(3)
+        );
+        assert!(session.unsolicited_responses.try_recv().is_err());
+
+        // Without asking for the capabilities again.
+        session.search_save("UNSEEN").await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 NOOP\r\n\
+            A0003 SEARCH RETURN (SAVE) UNSEEN\r\n"
+                .to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn search_save() {
+        let response = b"* CAPABILITY IMAP4rev1 SEARCHRES\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            A0002 OK SEARCH completed\r\n\
+            * 2 FETCH (FLAGS (\\Seen))\r\n\
+            A0003 OK FETCH completed\r\n\
+            * 3 EXPUNGE\r\n\
+            A0004 OK NOOP completed\r\n\
+            A0005 OK SEARCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(!session.saved_search_valid());
+        session.search_save("UNSEEN").await.unwrap();
+        assert!(session.saved_search_valid());
+        let fetches = session
+            .fetch("$", "FLAGS")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(fetches.len(), 1);
+
+        session.noop().await.unwrap();
+        assert!(!session.saved_search_valid());
+        assert!(matches!(
+            session.fetch("$", "FLAGS").await.err(),
+            Some(Error::SavedSearchInvalid)
+        ));
+        assert!(matches!(
+            session.uid_search("UID $ SUBJECT \"x\"").await,
+            Err(Error::SavedSearchInvalid)
+        ));
+        // `$` in a quoted string is not the saved result.
+        assert!(!uses_saved_search("SEARCH SUBJECT \"5 $ off\""));
+
+        session.uid_search_save("FLAGGED").await.unwrap();
+        assert!(session.saved_search_valid());
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 SEARCH RETURN (SAVE) UNSEEN\r\n\
+            A0003 FETCH $ (UID FLAGS)\r\n\
+            A0004 NOOP\r\n\
+            A0005 UID SEARCH RETURN (SAVE) FLAGGED\r\n"
+                .to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn search_save_expunge() {
+        let response = b"* CAPABILITY IMAP4rev1 SEARCHRES\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            A0002 OK SEARCH completed\r\n\
+            * 3 EXPUNGE\r\n\
+            * 3 EXPUNGE\r\n\
+            A0003 OK EXPUNGE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.search_save("DELETED").await.unwrap();
+        let changes = session.unsolicited_responses_tx.changes();
+        let expunged = session.expunge().await.unwrap().collect::<Vec<_>>().await;
+        assert_eq!(expunged.len(), 2);
+        // The responses to the command change the mailbox as much as unsolicited ones.
+        assert_eq!(session.unsolicited_responses_tx.changes(), changes + 2);
+        assert!(!session.saved_search_valid());
+        assert!(matches!(
+            session.fetch("$", "FLAGS").await.err(),
+            Some(Error::SavedSearchInvalid)
+        ));
+    }
+
+    #[async_std::test]
+    async fn lenient_parsing() {
+        let response = b"* 1 FETCH (UID 7 BODY[]{5}\r\nhello)\r\n\
+            A0001 OK\n"
+            .to_vec();
+        let fetch = |lenient| {
+            let response = response.clone();
+            async move {
+                let mut session = mock_session!(MockStream::new(response));
+                session.set_lenient_parsing(lenient);
+                assert_eq!(session.lenient_parsing(), lenient);
+                let fetches = session
+                    .fetch("1", "(UID BODY[])")
+                    .await?
+                    .collect::<Result<Vec<_>>>()
+                    .await?;
+                Ok::<_, Error>(fetches)
+            }
+        };
+
+        let fetches = fetch(true).await.unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].uid, Some(7));
+        assert_eq!(fetches[0].body(), Some(&b"hello"[..]));
+
+        assert!(matches!(
+            fetch(false).await,
+            Err(Error::Parse(ParseError::Malformed { .. }))
+        ));
+    }
+
+    #[async_std::test]
+    async fn wire_tracer() {
+        use std::sync::{Arc, Mutex};
+
+        let response = b"* OK [CAPABILITY IMAP4rev1] Logged in\r\n\
+            A0001 OK Logged in\r\n\
+            * 1 EXISTS\r\n\
+            A0002 OK NOOP completed\r\n"
+            .to_vec();
+        let mut client = mock_client!(MockStream::new(response));
+        let traced = Arc::new(Mutex::new(Vec::new()));
+        client.set_wire_tracer({
+            let traced = traced.clone();
+            move |direction, data| {
+                let data = String::from_utf8(data.to_vec()).unwrap();
+                traced.lock().unwrap().push((direction, data));
+            }
+        });
+        let mut session = client.login("username", "hunter2").await.unwrap();
+        session.noop().await.unwrap();
+        assert!(session
+            .stream
+            .inner
+            .written_buf
+            .windows(7)
+            .any(|w| w == b"hunter2"));
+        let traced = traced.lock().unwrap();
+        assert!(traced.iter().all(|(_, data)| !data.contains("hunter2")));
+        // Responses are traced as they are read, which is all at once here.
+        let traced = |wanted| {
+            traced
+                .iter()
+                .filter(|(direction, _)| *direction == wanted)
+                .map(|(_, data)| data.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            traced(Direction::Sent),
+            vec!["A0001 LOGIN <redacted>\r\n", "A0002 NOOP\r\n"]
+        );
+        assert_eq!(
+            traced(Direction::Received),
+            vec![
+                "* OK [CAPABILITY IMAP4rev1] Logged in\r\n",
+                "A0001 OK Logged in\r\n",
+                "* 1 EXISTS\r\n",
+                "A0002 OK NOOP completed\r\n",
+            ]
+        );
+    }
+
+    #[async_std::test]
+    async fn alert_handler() {
+        use std::sync::{Arc, Mutex};
+
+        let response = b"* OK [ALERT] Mailbox nearly full\r\n\
+            * 4 EXISTS\r\n\
+            A0001 OK [ALERT] Maintenance at 22:00 UTC\r\n\
+            * OK Nothing to see\r\n\
+            A0002 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        session.set_alert_handler({
+            let alerts = alerts.clone();
+            move |alert| alerts.lock().unwrap().push(alert.to_string())
+        });
+        session.noop().await.unwrap();
+        session.noop().await.unwrap();
+        assert_eq!(
+            *alerts.lock().unwrap(),
+            vec!["Mailbox nearly full", "Maintenance at 22:00 UTC"]
+        );
+        // The responses are passed on as usual as well.
+        assert!(matches!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Other(_)
+        ));
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(4)
+        );
+    }
+
+    #[async_std::test]
+    async fn idle_done() {
+        let response = b"* 2 RECENT\r\n\
+            + idling\r\n\
+            * 4 EXISTS\r\n\
+            * 1 EXPUNGE\r\n\
+            A0001 OK IDLE terminated\r\n\
+            A0002 OK NOOP completed\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut idle = session.idle();
+        idle.init().await.unwrap();
+        let (mut session, responses) = idle.done_with_responses().await.unwrap();
+        let expected = vec![
+            UnsolicitedResponse::Recent(2),
+            UnsolicitedResponse::Exists(4),
+            UnsolicitedResponse::Expunge(1),
+        ];
+        assert_eq!(responses, expected);
+        // Passed on to the session as well.
+        for response in expected {
+            assert_eq!(session.unsolicited_responses.try_recv().unwrap(), response);
+        }
+
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 IDLE\r\nDONE\r\nA0002 NOOP\r\n".to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn idle_completed_by_server() {
+        use crate::extensions::idle::IdleResponse;
+
+        let response = b"A0001 NO IDLE not allowed now\r\n".to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut idle = session.idle();
+        match idle.init().await {
+            Err(Error::No(res)) => {
+                assert_eq!(res.information.as_deref(), Some("IDLE not allowed now"))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // `DONE` is not sent for a command that is no longer running.
+        let session = idle.done().await.unwrap();
+        assert_eq!(session.stream.inner.written_buf, b"A0001 IDLE\r\n");
+
+        let response = b"+ idling\r\n\
+            A0001 OK IDLE terminated\r\n\
+            A0002 OK NOOP completed\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut idle = session.idle();
+        idle.init().await.unwrap();
+        let (wait, _interrupt) = idle.wait();
+        let res: Result<IdleResponse> = wait.await;
+        assert!(matches!(res, Err(Error::IdleTerminated)), "{:?}", res);
+        let mut session = idle.done().await.unwrap();
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 IDLE\r\nA0002 NOOP\r\n".to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn idle_wait_with_timeout() {
+        use crate::extensions::idle::IdleResponse;
+        use imap_proto::MailboxDatum;
+
+        let quiet = b"+ idling\r\n".len();
+        let response = b"+ idling\r\n\
+            * 4 EXISTS\r\n\
+            A0001 OK IDLE terminated\r\n"
+            .to_vec();
+        let stream = MockStream::new(response).with_pause(quiet, Duration::from_millis(200));
+        let session = mock_session!(stream);
+        let mut idle = session.idle();
+        idle.init().await.unwrap();
+
+        let (wait, _interrupt) = idle.wait_with_timeout(Duration::from_millis(20));
+        assert_eq!(wait.await.unwrap(), IdleResponse::Timeout);
+
+        // Still idling: the next call returns the response the server sends later on.
+        let (wait, _interrupt) = idle.wait_with_timeout(Duration::from_secs(10));
+        match wait.await.unwrap() {
+            IdleResponse::NewData(data) => {
+                assert!(matches!(
+                    data.parsed(),
+                    Response::MailboxData(MailboxDatum::Exists(4))
+                ));
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let session = idle.done().await.unwrap();
+        assert_eq!(session.stream.inner.written_buf, b"A0001 IDLE\r\nDONE\r\n");
+    }
+
+    #[async_std::test]
+    async fn close() {
+        let response = b"A0001 OK CLOSE completed\r\n".to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.close().await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 CLOSE\r\n".to_vec(),
+            "Invalid close command"
+        );
+    }
+
+    #[async_std::test]
+    async fn store() {
+        generic_store(" ", |c, set, query| async move {
+            c.lock()
+                .await
+                .store(set, query)
+                .await?
+                .collect::<Vec<_>>()
+                .await;
+            Ok(())
+        })
+        .await;
+    }
+
+    #[async_std::test]
+    async fn uid_store() {
+        generic_store(" UID ", |c, set, query| async move {
+            c.lock()
+                .await
+                .uid_store(set, query)
+                .await?
+                .collect::<Vec<_>>()
+                .await;
+            Ok(())
+        })
+        .await;
+    }
+
+    #[async_std::test]
+    async fn store_condstore() {
+        let response = b"* 3 FETCH (FLAGS (\\Seen) MODSEQ (9123))\r\n\
+            * 4 FETCH (UID 12 MODSEQ (9124) FLAGS (\\Seen \\Flagged))\r\n\
+            A0001 OK STORE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session
+            .store("3:4", "+FLAGS (\\Seen)")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 STORE 3:4 +FLAGS (\\Seen)\r\n".to_vec()
+        );
+        let updates: Vec<_> = fetches
+            .iter()
+            .map(|fetch| {
+                (
+                    fetch.message,
+                    fetch.modseq,
+                    fetch.flags().collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            updates,
+            vec![
+                (3, Some(9123), vec![Flag::Seen]),
+                (4, Some(9124), vec![Flag::Seen, Flag::Flagged]),
+            ]
+        );
+        assert_eq!(fetches[1].uid, Some(12));
+    }
+
+    async fn generic_store<'a, F, T, K>(prefix: &'a str, op: F)
+    where
+        F: 'a + FnOnce(Arc<Mutex<Session<MockStream>>>, &'a str, &'a str) -> K,
+        K: 'a + Future<Output = Result<T>>,
+    {
+        let res = "* 2 FETCH (FLAGS (\\Deleted \\Seen))\r\n\
+                   * 3 FETCH (FLAGS (\\Deleted))\r\n\
+                   * 4 FETCH (FLAGS (\\Deleted \\Flagged \\Seen))\r\n\
+                   A0001 OK STORE completed\r\n";
+
+        generic_with_uid(res, "STORE", "2.4", "+FLAGS (\\Deleted)", prefix, op).await;
+    }
+
+    #[async_std::test]
+    async fn copy() {
+        generic_copy(" ", |c, set, query| async move {
+            // The mailbox is quoted.
+            c.lock().await.copy(set, query.trim_matches('"')).await?;
+            Ok(())
+        })
+        .await;
+    }
+
+    #[async_std::test]
+    async fn copy_uid() {
+        let response = b"A0001 OK [COPYUID 38505 304,319:320 3956:3958] Done\r\n\
+            A0002 OK COPY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let copy_uid = session.uid_copy("304,319:320", "Archive").await.unwrap();
+        assert_eq!(
+            copy_uid,
+            Some(CopyUid {
+                uid_validity: 38505,
+                source: vec![304, 319, 320],
+                destination: vec![3956, 3957, 3958],
+            })
+        );
+        assert_eq!(session.copy("1", "Archive").await.unwrap(), None);
+
+        // A range that can not be expanded.
+        let response = b"A0001 OK [COPYUID 38505 1:4294967295 1:4294967295] Done\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(matches!(
+            session.uid_copy("1:*", "Archive").await,
+            Err(Error::Parse(ParseError::Unexpected(_)))
+        ));
+    }
+
+    #[async_std::test]
+    async fn uid_copy() {
+        generic_copy(" UID ", |c, set, query| async move {
+            c.lock()
+                .await
+                .uid_copy(set, query.trim_matches('"'))
+                .await?;
+            Ok(())
+        })
+        .await;
+    }
+
+    async fn generic_copy<'a, F, T, K>(prefix: &'a str, op: F)
+    where
+        F: 'a + FnOnce(Arc<Mutex<Session<MockStream>>>, &'a str, &'a str) -> K,
+        K: 'a + Future<Output = Result<T>>,
+    {
+        generic_with_uid(
+            "OK COPY completed\r\n",
+            "COPY",
+            "2:4",
+            "\"MEETING\"",
+            prefix,
+            op,
+        )
+        .await;
+    }
+
+    #[async_std::test]
+    async fn mv() {
+        let response = b"* OK [COPYUID 1511554416 142,399 41:42] Moved UIDs.\r\n\
+            * 2 EXPUNGE\r\n\
+            * 1 EXPUNGE\r\n\
+            A0001 OK Move completed\r\n"
+            .to_vec();
+        let mailbox_name = "MEETING";
+        let command = format!("A0001 MOVE 1:2 {}\r\n", quote!(mailbox_name));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let copy_uid = session.mv("1:2", mailbox_name).await.unwrap().unwrap();
+        assert_eq!(copy_uid.uid_validity, 1511554416);
+        assert_eq!(
+            copy_uid.pairs().collect::<Vec<_>>(),
+            vec![(142, 41), (399, 42)]
+        );
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid move command"
+        );
+    }
+
+    #[async_std::test]
+    async fn uid_mv() {
+        let response = b"* OK [COPYUID 1511554416 142,399 41:42] Moved UIDs.\r\n\
+            * 2 EXPUNGE\r\n\
+            * 1 EXPUNGE\r\n\
+            A0001 OK Move completed\r\n"
+            .to_vec();
+        let mailbox_name = "MEETING";
+        let command = format!("A0001 UID MOVE 41:42 {}\r\n", quote!(mailbox_name));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.uid_mv("41:42", mailbox_name).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid uid move command"
+        );
+    }
+
+    #[async_std::test]
+    async fn fetch() {
+        generic_fetch(" ", "(UID BODY[])", |c, seq, query| async move {
+            c.lock()
+                .await
+                .fetch(seq, query)
+                .await?
+                .collect::<Vec<_>>()
+                .await;
+
+            Ok(())
+        })
+        .await;
+    }
+
+    #[async_std::test]
+    async fn fetch_adds_uid() {
+        let response = b"* 1 FETCH (UID 4 FLAGS (\\Seen))\r\n\
+            * 2 FETCH (FLAGS () UID 9)\r\n\
+            A0001 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches = session.fetch_all("1:2", "FLAGS").await.unwrap();
+        let uids: Vec<_> = fetches.iter().map(|fetch| fetch.uid).collect();
+        assert_eq!(uids, vec![Some(4), Some(9)]);
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 FETCH 1:2 (UID FLAGS)\r\n".to_vec()
+        );
+
+        assert_eq!(
+            with_uid("ALL"),
+            "(UID FLAGS INTERNALDATE RFC822.SIZE ENVELOPE)"
+        );
+        assert_eq!(with_uid("fast"), "(UID FLAGS INTERNALDATE RFC822.SIZE)");
+        assert_eq!(with_uid("(FLAGS BODY.PEEK[])"), "(UID FLAGS BODY.PEEK[])");
+        assert_eq!(with_uid("(FLAGS uid)"), "(FLAGS uid)");
+        assert_eq!(
+            with_uid("BODY[HEADER.FIELDS (FROM)]"),
+            "(UID BODY[HEADER.FIELDS (FROM)])"
+        );
+        assert_eq!(with_uid("()"), "UID");
+        // `UID` within a section is a header field, not the data item.
+        assert_eq!(
+            with_uid("BODY.PEEK[HEADER.FIELDS (UID)]"),
+            "(UID BODY.PEEK[HEADER.FIELDS (UID)])"
+        );
+        assert_eq!(
+            with_uid("(FLAGS BODY[HEADER.FIELDS (UID)]<0.10>)"),
+            "(UID FLAGS BODY[HEADER.FIELDS (UID)]<0.10>)"
+        );
+        assert_eq!(
+            with_uid("FLAGS (CHANGEDSINCE 5)"),
+            "(UID FLAGS) (CHANGEDSINCE 5)"
+        );
+        assert_eq!(
+            with_uid("fast (CHANGEDSINCE 5)"),
+            "(UID FLAGS INTERNALDATE RFC822.SIZE) (CHANGEDSINCE 5)"
+        );
+        assert_eq!(
+            with_uid("(FLAGS UID) (CHANGEDSINCE 5)"),
+            "(FLAGS UID) (CHANGEDSINCE 5)"
+        );
+    }
+
+    #[async_std::test]
+    async fn uid_fetch() {
+        generic_fetch(" UID ", "BODY[]", |c, seq, query| async move {
+            c.lock()
+                .await
+                .uid_fetch(seq, query)
+                .await?
+                .collect::<Vec<_>>()
+                .await;
+            Ok(())
+        })
+        .await;
+    }
+
+    #[async_std::test]
+    async fn uid_fetch_sequence_set() {
+        let response = b"A0001 OK FETCH completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let set: SequenceSet = vec![1, 2, 3, 5].into_iter().collect();
+        let fetches: Vec<_> = session
+            .uid_fetch(&set, "UID")
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(fetches.is_empty());
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID FETCH 1:3,5 UID\r\n".to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn fetch_items() {
+        let response = b"* 2 FETCH (UID 11 FLAGS (\\Seen) RFC822.SIZE 1024)\r\n\
+            A0001 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let query = FetchItems::new().flags().rfc822_size().flags();
+        let fetches = session.fetch_all("2", query).await.unwrap();
+        assert_eq!(fetches[0].message, 2);
+        assert_eq!(fetches[0].uid, Some(11));
+        assert_eq!(fetches[0].size, Some(1024));
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 FETCH 2 (UID FLAGS RFC822.SIZE)\r\n".to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn fetch_header_fields() {
+        let response = b"* 2 FETCH (UID 11 BODY[HEADER.FIELDS (FROM SUBJECT)] {36}\r\n\
+            From: a@example.org\r\nSubject: Hi\r\n\r\n \
+            BODY[HEADER.FIELDS (DATE)] {40}\r\nDate: Mon, 7 Feb 1994 21:52:25 -0800\r\n\r\n)\r\n\
+            A0001 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let query = FetchItems::new()
+            .body_peek_header_fields(&["From", "Subject"])
+            .body_peek_header_fields(&["Date"]);
+        let fetches = session.fetch_all("2", query).await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 FETCH 2 (UID BODY.PEEK[HEADER.FIELDS (From Subject)] \
+              BODY.PEEK[HEADER.FIELDS (Date)])\r\n"
+                .to_vec()
+        );
+        assert_eq!(
+            fetches[0].header_fields(&["subject", "from"]),
+            Some(&b"From: a@example.org\r\nSubject: Hi\r\n\r\n"[..])
+        );
+        assert_eq!(
+            fetches[0].header_fields(&["Date"]),
+            Some(&b"Date: Mon, 7 Feb 1994 21:52:25 -0800\r\n\r\n"[..])
+        );
+        assert_eq!(fetches[0].header_fields(&["From"]), None);
+    }
+
+    #[async_std::test]
+    async fn fetch_all_partial() {
+        let response = b"* 1 FETCH (UID 10 FLAGS (\\Seen))\r\n\
+            * 2 FETCH (UID 11 FLAGS ())\r\n\
+            A0001 NO Some messages could not be FETCHed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.fetch_all("1:3", "(UID FLAGS)").await {
+            Err(Error::PartialFetch { fetches, error }) => {
+                assert_eq!(
+                    fetches.iter().map(|f| f.uid).collect::<Vec<_>>(),
+                    vec![Some(10), Some(11)]
+                );
+                match *error {
+                    Error::No(res) => {
+                        assert_eq!(res.command.as_deref(), Some("FETCH"));
+                        assert_eq!(
+                            res.information.as_deref(),
+                            Some("Some messages could not be FETCHed")
+                        );
+                    }
+                    other => panic!("unexpected error: {:?}", other),
+                }
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    /// The untagged responses of a `UID FETCH UID` of `uids`.
+    fn uid_fetches(uids: impl Iterator<Item = u32>) -> String {
+        uids.map(|uid| format!("* {} FETCH (UID {})\r\n", uid, uid))
+            .collect()
+    }
+
+    #[async_std::test]
+    async fn uid_pages() {
+        let all: Vec<String> = (1..=120).map(|uid| uid.to_string()).collect();
+        let response = format!(
+            "* SEARCH {}\r\nA0001 OK SEARCH completed\r\n\
+            {}A0002 OK FETCH completed\r\n\
+            {}A0003 OK FETCH completed\r\n\
+            {}A0004 OK FETCH completed\r\n",
+            all.join(" "),
+            uid_fetches(71..=120),
+            uid_fetches(21..=70),
+            uid_fetches(1..=20),
+        );
+        let mut session = mock_session!(MockStream::new(response.into_bytes()));
+        let pages = session
+            .uid_pages("ALL", "UID", 50, PageOrder::NewestFirst)
+            .await
+            .unwrap();
+        let pages: Vec<Vec<Uid>> = pages
+            .map(|page| {
+                page.unwrap()
+                    .iter()
+                    .map(|fetch| fetch.uid.unwrap())
+                    .collect()
+            })
+            .collect()
+            .await;
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0], (71..=120).rev().collect::<Vec<_>>());
+        assert_eq!(pages[1], (21..=70).rev().collect::<Vec<_>>());
+        assert_eq!(pages[2], (1..=20).rev().collect::<Vec<_>>());
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID SEARCH ALL\r\n\
+            A0002 UID FETCH 71:120 UID\r\n\
+            A0003 UID FETCH 21:70 UID\r\n\
+            A0004 UID FETCH 1:20 UID\r\n"
+                .to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn uid_pages_uid_validity() {
+        let response = b"* 4 EXISTS\r\n\
+            * OK [UIDVALIDITY 42] UIDs valid\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            * SEARCH 1 2 3 4\r\nA0002 OK SEARCH completed\r\n\
+            * 1 FETCH (UID 1)\r\n* 2 FETCH (UID 2)\r\n* OK [UIDVALIDITY 42] UIDs valid\r\n\
+            A0003 OK FETCH completed\r\n\
+            * 3 FETCH (UID 3)\r\n* 4 FETCH (UID 4)\r\n* OK [UIDVALIDITY 43] Reset\r\n\
+            A0004 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.select("INBOX").await.unwrap();
+        let mut pages = session
+            .uid_pages("ALL", "UID", 2, PageOrder::OldestFirst)
+            .await
+            .unwrap();
+        // The `UIDVALIDITY` the mailbox was selected with is repeated, which is no change.
+        assert_eq!(pages.next().await.unwrap().unwrap().len(), 2);
+        assert_eq!(pages.next().await.unwrap().unwrap().len(), 2);
+        match pages.next().await {
+            Some(Err(Error::UidValidityChanged(43))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[async_std::test]
+    async fn uid_pages_changed() {
+        let response = b"* SEARCH 1 2 3 4 5 6\r\nA0001 OK SEARCH completed\r\n\
+            * 1 FETCH (UID 1)\r\n* 2 FETCH (UID 2)\r\n* 3 EXPUNGE\r\nA0002 OK FETCH completed\r\n\
+            * SEARCH 1 2 4 5 6 7\r\nA0003 OK SEARCH completed\r\n\
+            * 4 FETCH (UID 4)\r\n* 3 FETCH (UID 5)\r\n* OK [UIDVALIDITY 99] Reset\r\n\
+            A0004 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mut pages = session
+            .uid_pages("ALL", "UID", 2, PageOrder::OldestFirst)
+            .await
+            .unwrap();
+        let uids = |page: Vec<Fetch>| -> Vec<Uid> {
+            page.iter().map(|fetch| fetch.uid.unwrap()).collect()
+        };
+        assert_eq!(uids(pages.next().await.unwrap().unwrap()), vec![1, 2]);
+        // The expunged message 3 is skipped, and the order of the fetches restored.
+        assert_eq!(uids(pages.next().await.unwrap().unwrap()), vec![4, 5]);
+        match pages.next().await {
+            Some(Err(Error::UidValidityChanged(99))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(pages.next().await.is_none());
+        drop(pages);
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID SEARCH ALL\r\n\
+            A0002 UID FETCH 1:2 UID\r\n\
+            A0003 UID SEARCH ALL\r\n\
+            A0004 UID FETCH 4:5 UID\r\n"
+                .to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn notify() {
+        let response = b"* CAPABILITY IMAP4rev1 NOTIFY\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * STATUS Archive (MESSAGES 5 UIDNEXT 21)\r\n\
+            A0002 OK NOTIFY completed\r\n\
+            * STATUS \"Lists/rust\" (MESSAGES 9 UIDNEXT 40)\r\n\
+            * 4 EXISTS\r\n\
+            A0003 OK NOOP completed\r\n\
+            A0004 OK NOTIFY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let events = [NotifyEvent::MessageNew, NotifyEvent::MessageExpunge];
+        session
+            .notify(
+                &[
+                    NotifyGroup::new(NotifyMailboxes::Selected, &events),
+                    NotifyGroup::new(
+                        NotifyMailboxes::Mailboxes(vec!["Archive".into(), "Lists/rust".into()]),
+                        &events,
+                    ),
+                    NotifyGroup::new(NotifyMailboxes::Subtree(vec!["Trash".into()]), &[]),
+                ],
+                true,
+            )
+            .await
+            .unwrap();
+        session.noop().await.unwrap();
+        session.notify_none().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 NOTIFY SET STATUS (SELECTED (MessageNew MessageExpunge)) \
+            (MAILBOXES (\"Archive\" \"Lists/rust\") (MessageNew MessageExpunge)) \
+            (SUBTREE (\"Trash\") NONE)\r\n\
+            A0003 NOOP\r\n\
+            A0004 NOTIFY NONE\r\n"
+                .to_vec()
+        );
+
+        let events: Vec<_> =
+            std::iter::from_fn(|| session.unsolicited_responses.try_recv().ok()).collect();
+        assert_eq!(
+            events,
+            vec![
+                UnsolicitedResponse::Status {
+                    mailbox: "Archive".into(),
+                    attributes: vec![StatusAttribute::Messages(5), StatusAttribute::UidNext(21)],
+                },
+                UnsolicitedResponse::Status {
+                    mailbox: "Lists/rust".into(),
+                    attributes: vec![StatusAttribute::Messages(9), StatusAttribute::UidNext(40)],
+                },
+                UnsolicitedResponse::Exists(4),
+            ]
+        );
+    }
+
+    #[async_std::test]
+    async fn validate_keywords() {
+        let response = b"* 3 EXISTS\r\n\
+            * OK [PERMANENTFLAGS (\\Deleted \\Seen $Junk)] Limited\r\n\
+            A0001 OK [READ-WRITE] SELECT completed\r\n\
+            * 1 FETCH (FLAGS (\\Seen $Junk))\r\n\
+            A0002 OK STORE completed\r\n\
+            A0003 OK STORE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_validate_keywords(true);
+        session.select("INBOX").await.unwrap();
+
+        let err = session
+            .store("1", "+FLAGS.SILENT (\\Seen $Phishing)")
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::UnsupportedKeyword(keyword) if keyword == "$Phishing"));
+        let flags = [Flag::Custom("$Phishing".into())];
+        assert!(matches!(
+            session
+                .uid_store_flags(vec![7], FlagsOperation::Replace, &flags)
+                .await,
+            Err(Error::UnsupportedKeyword(_))
+        ));
+
+        let fetches = session.store("1", "+FLAGS (\\Seen $junk)").await.unwrap();
+        assert_eq!(fetches.collect::<Vec<_>>().await.len(), 1);
+        // Removing a keyword is always possible.
+        let fetches = session
+            .uid_store("7", "(UNCHANGEDSINCE 5) -FLAGS ($Phishing)")
+            .await
+            .unwrap();
+        assert!(fetches.collect::<Vec<_>>().await.is_empty());
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 SELECT \"INBOX\"\r\n\
+            A0002 STORE 1 +FLAGS (\\Seen $junk)\r\n\
+            A0003 UID STORE 7 (UNCHANGEDSINCE 5) -FLAGS ($Phishing)\r\n"
+                .to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn uid_store_flags() {
+        let response = b"* 1 FETCH (UID 1 FLAGS (\\Seen))\r\n\
+            * 200 FETCH (UID 380 FLAGS (\\Seen $Done))\r\n\
+            A0001 OK STORE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        // 200 messages, one at a time and out of order.
+        let uids: Vec<Uid> = (301..=380).rev().chain(1..=120).collect();
+        let flags = [Flag::Seen, Flag::Custom("$Done".into())];
+        let fetches = session
+            .uid_store_flags(uids, FlagsOperation::Add, &flags)
+            .await
+            .unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID STORE 1:120,301:380 +FLAGS (\\Seen $Done)\r\n".to_vec()
+        );
+        assert_eq!(fetches.len(), 2);
+        assert_eq!(fetches[1].flags().collect::<Vec<_>>(), flags);
+
+        let empty = session
+            .uid_store_flags(Vec::new(), FlagsOperation::Remove, &flags)
+            .await
+            .unwrap();
+        assert!(empty.is_empty());
+        let invalid = [Flag::Custom("$Done)".into())];
+        assert!(matches!(
+            session
+                .uid_store_flags(vec![1], FlagsOperation::Replace, &invalid)
+                .await,
+            Err(Error::Validate(ValidateError(')')))
+        ));
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID STORE 1:120,301:380 +FLAGS (\\Seen $Done)\r\n".to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn body_parts() {
+        let response = b"* 1 FETCH (UID 7 BODY[]<0> {12}\r\nHello, parti)\r\n\
+            A0001 OK FETCH completed\r\n\
+            * 1 FETCH (UID 7 BODY[]<8> {13}\r\nartial world! BODY[TEXT] NIL)\r\n\
+            A0002 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let first = session.uid_fetch_all(7, "BODY.PEEK[]<0.12>").await.unwrap();
+        // Overlaps the first chunk, and asks for more than is left.
+        let second = session
+            .uid_fetch_all(7, "(BODY.PEEK[]<8.16> BODY.PEEK[TEXT])")
+            .await
+            .unwrap();
+
+        let mut body = Vec::new();
+        for fetch in first.iter().chain(&second) {
+            for part in fetch.body_parts().filter(|part| part.section.is_none()) {
+                let range = part.range();
+                body.resize(body.len().max(range.end), 0);
+                body[range].copy_from_slice(part.data);
+            }
+        }
+        assert_eq!(body, b"Hello, partial world!");
+
+        let parts: Vec<_> = second[0].body_parts().collect();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].origin, Some(8));
+        assert_eq!(parts[0].range(), 8..21);
+        assert_eq!(
+            parts[1].section,
+            Some(&imap_proto::SectionPath::Full(
+                imap_proto::MessageSection::Text
+            ))
+        );
+        assert_eq!(parts[1].origin, None);
+        assert_eq!(parts[1].range(), 0..0);
+    }
+
+    #[async_std::test]
+    async fn decoded_text() {
+        let plain = "Subject: Hi\r\n\
+            Content-Type: text/plain;\r\n\
+            \tcharset=\"utf-8\"\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\
+            \r\n\
+            Caf=C3=A9 au lait, un tr=\r\n\
+            =C3=A8s long =3D 1\r\n";
+        let multipart = "Content-Type: multipart/alternative; boundary=b1\r\n\
+            \r\n\
+            --b1--\r\n";
+        let response = format!(
+            "* 1 FETCH (UID 7 BODY[] {{{}}}\r\n{})\r\n\
+            * 2 FETCH (UID 8 BODY[] {{{}}}\r\n{})\r\n\
+            * 3 FETCH (UID 9 BODYSTRUCTURE (\"TEXT\" \"PLAIN\" (\"CHARSET\" \"ISO-8859-1\") \
+            NIL NIL \"BASE64\" 8 1) BODY[TEXT] {{8}}\r\nRvZnZ2Vy)\r\n\
+            * 4 FETCH (UID 10 BODYSTRUCTURE (\"TEXT\" \"PLAIN\" NIL \
+            NIL NIL \"BASE64\" 5 1) BODY[TEXT] {{5}}\r\nRvZnZ)\r\n\
+            A0001 OK FETCH completed\r\n",
+            plain.len(),
+            plain,
+            multipart.len(),
+            multipart
+        );
+        let mut session = mock_session!(MockStream::new(response.into_bytes()));
+        let fetches = session
+            .uid_fetch_all("7:10", "(BODY.PEEK[] BODYSTRUCTURE BODY.PEEK[TEXT])")
+            .await
+            .unwrap();
+        assert_eq!(
+            fetches[0].decoded_text().unwrap(),
+            "Café au lait, un très long = 1\r\n"
+        );
+        assert_eq!(fetches[1].decoded_text(), None);
+        assert_eq!(fetches[2].decoded_text().unwrap(), "F\u{f6}gger");
+        // Not valid base64, rather than the encoded text.
+        assert_eq!(fetches[3].decoded_text(), None);
+    }
+
+    #[cfg(feature = "mail")]
+    #[async_std::test]
+    async fn uid_fetch_mail() {
+        use mailparse::MailHeaderMap;
+
+        let mail = "From: alice@example.org\r\n\
+            Subject: Report\r\n\
+            MIME-Version: 1.0\r\n\
+            Content-Type: multipart/mixed; boundary=\"b1\"\r\n\
+            \r\n\
+            --b1\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\
+            \r\n\
+            Gr=C3=BC=C3=9Fe aus K=C3=B6ln\r\n\
+            --b1\r\n\
+            Content-Type: text/plain; charset=iso-8859-1\r\n\
+            Content-Transfer-Encoding: 8bit\r\n\
+            \r\n\
+            Caf\u{e9}\r\n\
+            --b1\r\n\
+            Content-Type: application/octet-stream\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            AAEC\r\n\
+            --b1--\r\n";
+        // The `8bit` part is in ISO-8859-1, which is not valid UTF-8.
+        let mail: Vec<u8> = mail.chars().map(|c| c as u8).collect();
+        let mut response = format!("* 1 FETCH (UID 7 BODY[] {{{}}}\r\n", mail.len()).into_bytes();
+        response.extend_from_slice(&mail);
+        response.extend_from_slice(
+            b")\r\nA0001 OK FETCH completed\r\n\
+            A0002 OK FETCH completed\r\n",
+        );
+        let mut session = mock_session!(MockStream::new(response));
+        let fetch = session.uid_fetch_mail(7).await.unwrap().unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID FETCH 7 BODY.PEEK[]\r\n".to_vec()
+        );
+        let mail = fetch.mail().unwrap().unwrap();
+        assert_eq!(mail.headers.get_first_value("Subject").unwrap(), "Report");
+        assert_eq!(mail.subparts.len(), 3);
+        assert_eq!(
+            mail.subparts[0].get_body().unwrap().trim_end(),
+            "Grüße aus Köln"
+        );
+        assert_eq!(mail.subparts[1].get_body().unwrap().trim_end(), "Café");
+        assert_eq!(mail.subparts[2].ctype.mimetype, "application/octet-stream");
+        assert_eq!(mail.subparts[2].get_body_raw().unwrap(), vec![0, 1, 2]);
+
+        assert!(session.uid_fetch_mail(8).await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn uid_fetch_all_failed() {
+        let response = b"A0001 BAD Invalid sequence set\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.uid_fetch_all("x", "UID").await {
+            Err(Error::Bad(res)) => assert_eq!(res.command.as_deref(), Some("UID FETCH")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[async_std::test]
+    async fn imap4rev2_greeting_search() {
+        let response = b"* OK [CAPABILITY IMAP4rev2 AUTH=PLAIN] Server ready\r\n\
+            A0001 OK Logged in\r\n\
+            * ESEARCH (TAG \"A0002\") ALL 1:3,5\r\n\
+            A0002 OK SEARCH completed\r\n\
+            * ESEARCH (TAG \"A0003\") UID MIN 7 MAX 12 ALL 7:8,12 COUNT 3\r\n\
+            A0003 OK SEARCH completed\r\n"
+            .to_vec();
+        let mut client = mock_client!(MockStream::new(response));
+        client.read_greeting().await.unwrap();
+        let mut session = client.login("username", "password").await.unwrap();
+        session.state = SessionState::Selected;
+        assert!(session.imap4rev2());
+
+        let seqs = session.search("ALL").await.unwrap();
+        assert_eq!(seqs, [1, 2, 3, 5].iter().cloned().collect());
+
+        let results = session.uid_esearch("UNSEEN").await.unwrap();
+        assert_eq!(
+            results,
+            ESearch {
+                tag: Some("A0003".to_string()),
+                uid: true,
+                min: Some(7),
+                max: Some(12),
+                all: Some("7:8,12".parse().unwrap()),
+                count: Some(3),
+                partial: None,
+            }
+        );
+        assert_eq!(results.ids().collect::<Vec<_>>(), vec![7, 8, 12]);
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with(
+                "A0002 SEARCH ALL\r\n\
+                 A0003 UID SEARCH RETURN (MIN MAX ALL COUNT) UNSEEN\r\n"
+            ));
+    }
+
+    #[async_std::test]
+    async fn capabilities_imap4rev2() {
+        let response = b"* CAPABILITY IMAP4rev2 AUTH=PLAIN\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            A0002 OK CREATE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(!session.imap4rev2());
+        let capabilities = session.capabilities().await.unwrap();
+        assert!(capabilities.has(&Capability::Imap4rev2));
+        assert!(!capabilities.has_str("IMAP4rev1"));
+        assert!(capabilities.is_imap4rev2_only());
+        assert!(session.imap4rev2());
+
+        session.create("Entwürfe").await.unwrap();
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with("A0002 CREATE \"Entwürfe\"\r\n"));
+    }
+
+    #[async_std::test]
+    async fn esearch_partial() {
+        let response = b"* CAPABILITY IMAP4rev1 ESEARCH PARTIAL\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * ESEARCH (TAG \"A0002\") UID PARTIAL (1:5 200:202,250,301) COUNT 120\r\n\
+            A0002 OK SEARCH completed\r\n\
+            * ESEARCH (TAG \"A0003\") PARTIAL (-1:-50 NIL) COUNT 0\r\n\
+            A0003 OK SEARCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+
+        let results = session.uid_esearch_partial("1:5", "UNSEEN").await.unwrap();
+        let partial = results.partial.as_ref().unwrap();
+        assert_eq!((partial.first, partial.last), (1, 5));
+        assert_eq!(
+            results.ids().collect::<Vec<_>>(),
+            vec![200, 201, 202, 250, 301]
+        );
+        assert_eq!(results.count, Some(120));
+        assert!(results.uid);
+
+        let results = session.esearch_partial("-1:-50", "DELETED").await.unwrap();
+        let partial = results.partial.unwrap();
+        assert_eq!((partial.first, partial.last), (-1, -50));
+        assert_eq!(partial.ids.ranges().count(), 0);
+        assert_eq!(results.count, Some(0));
+
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with(
+                "A0002 UID SEARCH RETURN (PARTIAL 1:5 COUNT) UNSEEN\r\n\
+                 A0003 SEARCH RETURN (PARTIAL -1:-50 COUNT) DELETED\r\n"
+            ));
+    }
+
+    #[async_std::test]
+    async fn esearch_missing_capability() {
+        let response = b"* CAPABILITY IMAP4rev1 IDLE\r\n\
+            A0001 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(matches!(
+            session.esearch("RETURN (COUNT) UNSEEN").await,
+            Err(Error::MissingCapability(cap)) if cap == "ESEARCH"
+        ));
+    }
+
+    #[async_std::test]
+    async fn sort_and_thread() {
+        let response = b"* CAPABILITY IMAP4rev1 SORT THREAD=REFERENCES\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * SORT 5 3 4\r\n\
+            A0002 OK SORT completed\r\n\
+            * SORT 12 7\r\n\
+            A0003 OK SORT completed\r\n\
+            * THREAD (2)(3 6 (4 23)(44 7 96))\r\n\
+            A0004 OK THREAD completed\r\n\
+            * 4 EXISTS\r\n\
+            * THREAD ((12)(7))\r\n\
+            A0005 OK THREAD completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(
+            session.sort("REVERSE DATE", "ALL").await.unwrap(),
+            vec![5, 3, 4]
+        );
+        assert_eq!(
+            session.uid_sort("SUBJECT", "UNSEEN").await.unwrap(),
+            vec![12, 7]
+        );
+
+        let threads = session.thread("REFERENCES", "ALL").await.unwrap();
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0].ids(), vec![2]);
+        assert_eq!(threads[1].ids(), vec![3, 6, 4, 23, 44, 7, 96]);
+
+        let threads = session.uid_thread("REFERENCES", "ALL").await.unwrap();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].id, None);
+        assert_eq!(threads[0].ids(), vec![12, 7]);
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(4)
+        );
+
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with(
+                "A0002 SORT (REVERSE DATE) UTF-8 ALL\r\n\
+                 A0003 UID SORT (SUBJECT) UTF-8 UNSEEN\r\n\
+                 A0004 THREAD REFERENCES UTF-8 ALL\r\n\
+                 A0005 UID THREAD REFERENCES UTF-8 ALL\r\n"
+            ));
+    }
+
+    #[async_std::test]
+    async fn sync_snapshot() {
+        let response = b"* CAPABILITY IMAP4rev1 CONDSTORE\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * STATUS Archive (MESSAGES 3)\r\n\
+            * STATUS INBOX (MESSAGES 231 UIDNEXT 44292 UIDVALIDITY 1 HIGHESTMODSEQ 7011231777)\r\n\
+            A0002 OK STATUS completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let snapshot = session.sync_snapshot("inbox").await.unwrap();
+        assert_eq!(
+            snapshot,
+            SyncSnapshot {
+                messages: 231,
+                uid_next: Some(44292),
+                uid_validity: Some(1),
+                highest_modseq: Some(7011231777),
+            }
+        );
+        assert!(matches!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Status { mailbox, .. } if mailbox == "Archive"
+        ));
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with("A0002 STATUS \"inbox\" (MESSAGES UIDNEXT UIDVALIDITY HIGHESTMODSEQ)\r\n"));
+    }
+
+    #[async_std::test]
+    async fn sync_snapshot_without_condstore() {
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * STATUS Archive (MESSAGES 3 UIDNEXT 4 UIDVALIDITY 9)\r\n\
+            A0002 OK STATUS completed\r\n\
+            A0003 NO Mailbox does not exist\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let snapshot = session.sync_snapshot("Archive").await.unwrap();
+        assert_eq!(snapshot.messages, 3);
+        assert_eq!(snapshot.highest_modseq, None);
+        assert!(matches!(
+            session.sync_snapshot("Missing").await,
+            Err(Error::No(_))
+        ));
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with(
+                "A0002 STATUS \"Archive\" (MESSAGES UIDNEXT UIDVALIDITY)\r\n\
+                 A0003 STATUS \"Missing\" (MESSAGES UIDNEXT UIDVALIDITY)\r\n"
+            ));
+    }
+
+    #[async_std::test]
+    async fn uid_fetch_map() {
+        let envelope = |uid: u32, seq: u32| {
+            format!(
+                "* {} FETCH (UID {} ENVELOPE (NIL \"Subject {}\" NIL NIL NIL NIL NIL NIL NIL NIL))\r\n",
+                seq, uid, uid
+            )
+        };
+        let response = format!(
+            "* CAPABILITY IMAP4rev1 SORT THREAD=REFERENCES\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * THREAD (2)(3 6 (4 23)(44 3))\r\n\
+            A0002 OK THREAD completed\r\n\
+            {}{}{}{}{}* 9 FETCH (FLAGS (\\Seen))\r\n{}A0003 OK FETCH completed\r\n",
+            envelope(44, 6),
+            envelope(2, 1),
+            envelope(3, 2),
+            envelope(4, 3),
+            envelope(6, 4),
+            envelope(23, 5),
+        );
+        let mut session = mock_session!(MockStream::new(response.into_bytes()));
+        let threads = session.uid_thread("REFERENCES", "ALL").await.unwrap();
+        let fetches = session
+            .uid_fetch_map(threads.iter().flat_map(Thread::ids), "ENVELOPE")
+            .await
+            .unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 UID THREAD REFERENCES UTF-8 ALL\r\n\
+            A0003 UID FETCH 2:4,6,23,44 ENVELOPE\r\n"
+                .to_vec()
+        );
+        assert_eq!(fetches.len(), 6);
+
+        fn subjects(thread: &Thread, fetches: &HashMap<Uid, Fetch>, out: &mut Vec<String>) {
+            let envelope = fetches[&thread.id.unwrap()].envelope().unwrap();
+            let subject = envelope.subject.as_deref().unwrap();
+            out.push(String::from_utf8(subject.to_vec()).unwrap());
+            for child in &thread.children {
+                subjects(child, fetches, out);
+            }
+        }
+        let mut walked = Vec::new();
+        for thread in &threads {
+            subjects(thread, &fetches, &mut walked);
+        }
+        assert_eq!(
+            walked,
+            vec![
+                "Subject 2",
+                "Subject 3",
+                "Subject 6",
+                "Subject 4",
+                "Subject 23",
+                "Subject 44",
+                "Subject 3"
+            ]
+        );
+
+        let empty = session.uid_fetch_map(Vec::new(), "ENVELOPE").await.unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[async_std::test]
+    async fn thread_missing_capability() {
+        let response = b"* CAPABILITY IMAP4rev1 SORT THREAD=ORDEREDSUBJECT\r\n\
+            A0001 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(matches!(
+            session.uid_thread("REFERENCES", "ALL").await,
+            Err(Error::MissingCapability(cap)) if cap == "THREAD=REFERENCES"
+        ));
+    }
+
+    #[async_std::test]
+    async fn uid_variants() {
+        let response = b"* CAPABILITY IMAP4rev1 UIDPLUS MOVE ESEARCH SORT THREAD=REFERENCES\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * 1 FETCH (UID 7 FLAGS ())\r\n\
+            A0002 OK FETCH completed\r\n\
+            * 1 FETCH (UID 7 FLAGS (\\Seen))\r\n\
+            A0003 OK STORE completed\r\n\
+            A0004 OK COPY completed\r\n\
+            A0005 OK MOVE completed\r\n\
+            * SEARCH 7\r\n\
+            A0006 OK SEARCH completed\r\n\
+            * ESEARCH (TAG \"A0007\") UID ALL 7\r\n\
+            A0007 OK SEARCH completed\r\n\
+            * SORT 7\r\n\
+            A0008 OK SORT completed\r\n\
+            * THREAD (7)\r\n\
+            A0009 OK THREAD completed\r\n\
+            * 1 EXPUNGE\r\n\
+            A0010 OK EXPUNGE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.capabilities().await.unwrap();
+
+        let fetches: Vec<_> = session
+            .uid_fetch("7", "FLAGS")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(fetches[0].uid, Some(7));
+        let fetches: Vec<_> = session
+            .uid_store("7", "+FLAGS (\\Seen)")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(fetches[0].uid, Some(7));
+        session.uid_copy("7", "Archive").await.unwrap();
+        session.uid_mv("7", "Archive").await.unwrap();
+        assert!(session.uid_search("ALL").await.unwrap().contains(&7));
+        assert!(session.uid_esearch("ALL").await.unwrap().uid);
+        assert_eq!(session.uid_sort("DATE", "ALL").await.unwrap(), vec![7]);
+        assert_eq!(
+            session.uid_thread("REFERENCES", "ALL").await.unwrap()[0].id,
+            Some(7)
+        );
+        let seqs: Vec<_> = session
+            .uid_expunge("7")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(seqs, vec![1]);
+
+        let written = String::from_utf8(session.stream.inner.written_buf.clone()).unwrap();
+        let commands: Vec<_> = written
+            .lines()
+            .skip(1)
+            .map(|line| line.splitn(4, ' ').nth(2).unwrap())
+            .collect();
+        assert!(written
+            .lines()
+            .skip(1)
+            .all(|line| line[6..].starts_with("UID ")));
+        assert_eq!(
+            commands,
+            vec!["FETCH", "STORE", "COPY", "MOVE", "SEARCH", "SEARCH", "SORT", "THREAD", "EXPUNGE"]
+        );
+    }
+
+    #[async_std::test]
+    async fn get_quota_root() {
+        let response = b"* QUOTAROOT INBOX \"\"\r\n\
+            * QUOTA \"\" (STORAGE 10 512)\r\n\
+            A0001 OK Getquotaroot completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let (roots, quotas) = session.get_quota_root("INBOX").await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 GETQUOTAROOT \"INBOX\"\r\n".to_vec()
+        );
+        assert_eq!(roots.len(), 1);
+        assert_eq!(quotas.len(), 1);
+    }
+
+    #[async_std::test]
+    async fn get_quota_root_empty() {
+        let response = b"A0001 OK Getquotaroot completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let (roots, quotas) = session.get_quota_root("INBOX").await.unwrap();
+        assert!(roots.is_empty());
+        assert!(quotas.is_empty());
+    }
+
+    #[async_std::test]
+    async fn get_quota_root_no() {
+        let response = b"A0001 NO Mailbox does not exist\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.get_quota_root("Missing").await {
+            Err(Error::No(res)) => {
+                assert_eq!(res.command.as_deref(), Some("GETQUOTAROOT"));
+                assert_eq!(res.information.as_deref(), Some("Mailbox does not exist"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[async_std::test]
+    async fn current_quota() {
+        let response = b"* 3 EXISTS\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            * CAPABILITY IMAP4rev1 QUOTA\r\n\
+            A0002 OK CAPABILITY completed\r\n\
+            * QUOTAROOT INBOX \"\" Shared\r\n\
+            * QUOTA \"\" (STORAGE 870 1000)\r\n\
+            A0003 OK Getquotaroot completed\r\n\
+            * QUOTA Shared (STORAGE 10 100 MESSAGE 5 0)\r\n\
+            A0004 OK Getquota completed\r\n\
+            * QUOTAROOT INBOX\r\n\
+            A0005 OK Getquotaroot completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(matches!(
+            session.current_quota().await,
+            Err(Error::InvalidState(_))
+        ));
+        session.select("INBOX").await.unwrap();
+        let usage = session.current_quota().await.unwrap().unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[&QuotaResourceName::Storage], 87);
+        assert_eq!(usage[&QuotaResourceName::Message], 100);
+        assert!(str::from_utf8(&session.stream.inner.written_buf)
+            .unwrap()
+            .ends_with("A0003 GETQUOTAROOT \"INBOX\"\r\nA0004 GETQUOTA \"Shared\"\r\n"));
+
+        assert_eq!(session.current_quota().await.unwrap(), None);
+    }
+
+    #[async_std::test]
+    async fn get_quota_bad() {
+        let response = b"A0001 BAD Unknown command\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(matches!(session.get_quota("").await, Err(Error::Bad(_))));
+    }
+
+    #[async_std::test]
+    async fn binary_fetch() {
+        let response = b"* CAPABILITY IMAP4rev1 BINARY\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * 1 FETCH (UID 7 BINARY[1] ~{5}\r\nhello FLAGS ())\r\n\
+            A0002 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session
+            .binary_fetch(1, "1")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].uid, Some(7));
+        assert_eq!(fetches[0].binary("1"), Some(&b"hello"[..]));
+        assert_eq!(fetches[0].flags().count(), 0);
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 FETCH 1 (UID BINARY.PEEK[1])\r\n".to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn uid_binary_size() {
+        let response = b"* CAPABILITY IMAP4rev1 BINARY\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * 1 FETCH (UID 7 BINARY.SIZE[1.2] 1234)\r\n\
+            A0002 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session
+            .uid_binary_size(7, "1.2")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(fetches[0].binary_size("1.2"), Some(1234));
+        assert_eq!(fetches[0].binary("1.2"), None);
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 UID FETCH 7 BINARY.SIZE[1.2]\r\n".to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn binary_fetch_unknown_cte() {
+        let response = b"* CAPABILITY IMAP4rev1 BINARY\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            A0002 NO [UNKNOWN-CTE] Can't decode x-uuencode\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session.binary_fetch(1, "2").await.unwrap().collect().await;
+        match fetches.as_slice() {
+            [Err(Error::UnknownCte(text))] => assert_eq!(text, "Can't decode x-uuencode"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[async_std::test]
+    async fn binary_fetch_missing_capability() {
+        let response = b"* CAPABILITY IMAP4rev1 IDLE\r\n\
+            A0001 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.binary_fetch(1, "1").await {
+            Err(Error::MissingCapability(cap)) => assert_eq!(cap, "BINARY"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n".to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn gmail_fetch() {
+        let response = b"* 1 FETCH (X-GM-THRID 1278455344230334865 X-GM-MSGID 1278455344230334866 \
+            X-GM-LABELS (\\Inbox \\Sent Important \"Muy Importante\" Entw&APw-rfe) UID 5)\r\n\
+            A0001 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session
+            .fetch(1, "(UID X-GM-MSGID X-GM-THRID X-GM-LABELS)")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].uid, Some(5));
+        assert_eq!(fetches[0].gmail_msg_id(), Some(1278455344230334866));
+        assert_eq!(fetches[0].gmail_thread_id(), Some(1278455344230334865));
+        assert_eq!(
+            fetches[0].gmail_labels(),
+            Some(vec![
+                "\\Inbox".to_string(),
+                "\\Sent".to_string(),
+                "Important".to_string(),
+                "Muy Importante".to_string(),
+                "Entwürfe".to_string(),
+            ])
+        );
+    }
+
+    #[async_std::test]
+    async fn fetch_object_ids() {
+        let response = b"* CAPABILITY IMAP4rev1 OBJECTID\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * 1 FETCH (EMAILID (M6d99ac3275bb4e) THREADID (T64b478a75b7ea9) UID 3)\r\n\
+            * 2 FETCH (EMAILID (M5fdc09b49ea703) THREADID NIL UID 4)\r\n\
+            A0002 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session
+            .fetch_object_ids("1:2")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 FETCH 1:2 (UID EMAILID THREADID)\r\n".to_vec()
+        );
+        assert_eq!(fetches.len(), 2);
+        assert_eq!(fetches[0].uid, Some(3));
+        assert_eq!(fetches[0].email_id(), Some("M6d99ac3275bb4e"));
+        assert_eq!(fetches[0].thread_id(), Some("T64b478a75b7ea9"));
+        assert_eq!(fetches[1].email_id(), Some("M5fdc09b49ea703"));
+        assert_eq!(fetches[1].thread_id(), None);
+
+        let response = b"* CAPABILITY IMAP4rev1\r\nA0001 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let res = session.uid_fetch_object_ids("3:4").await.map(|_| ());
+        match res {
+            Err(Error::MissingCapability(capability)) => assert_eq!(capability, "OBJECTID"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[async_std::test]
+    async fn fetch_raw_items() {
+        let response = b"* 1 FETCH (UID 3 X-CUSTOM (value \"two words\") X-EMPTY NIL)\r\n\
+            A0001 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session
+            .fetch("1", "(UID X-CUSTOM X-EMPTY)")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(fetches[0].uid, Some(3));
+        let items: Vec<_> = fetches[0].raw_items().collect();
+        assert_eq!(
+            items,
+            vec![
+                (&b"X-CUSTOM"[..], Some(&b"(value \"two words\")"[..])),
+                (&b"X-EMPTY"[..], None),
+            ]
+        );
+    }
+
+    #[async_std::test]
+    async fn uid_store_gmail_labels() {
+        let response = b"* CAPABILITY IMAP4rev1 X-GM-EXT-1\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * 1 FETCH (X-GM-LABELS (\\Inbox Entw&APw-rfe \"a \\\"b\\\"\") UID 7)\r\n\
+            A0002 OK STORE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session
+            .uid_store_gmail_labels(7, LabelsOperation::Add, &["Entwürfe", "a \"b\""])
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            fetches[0].gmail_labels(),
+            Some(vec![
+                "\\Inbox".to_string(),
+                "Entwürfe".to_string(),
+                "a \"b\"".to_string(),
+            ])
+        );
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 UID STORE 7 +X-GM-LABELS (\"Entw&APw-rfe\" \"a \\\"b\\\"\")\r\n"
+                .to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn store_gmail_labels_missing_capability() {
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session
+            .store_gmail_labels(1, LabelsOperation::Remove, &["Work"])
+            .await
+        {
+            Err(Error::MissingCapability(cap)) => assert_eq!(cap, "X-GM-EXT-1"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n".to_vec()
+        );
+    }
+
+    #[async_std::test]
+    async fn append_catenate() {
+        use crate::extensions::catenate::CatenatePart;
+
+        let response = b"* CAPABILITY IMAP4rev1 CATENATE\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            + Ready for literal data\r\n\
+            A0002 OK [APPENDUID 385759045 21] APPEND completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session
+            .append_catenate(
+                "Drafts",
+                &[
+                    CatenatePart::Url("/Drafts;UIDVALIDITY=385759045/;UID=20/;section=HEADER"),
+                    CatenatePart::Text(b"\r\nHello!\r\n"),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+              A0002 APPEND \"Drafts\" CATENATE \
+              (URL \"/Drafts;UIDVALIDITY=385759045/;UID=20/;section=HEADER\" TEXT {10}\r\n\
+              \r\nHello!\r\n)\r\n",
+            "Invalid append catenate command"
+        );
+    }
+
+    #[async_std::test]
+    async fn append_catenate_rejected() {
+        use crate::extensions::catenate::CatenatePart;
+
+        let response = b"* CAPABILITY IMAP4rev1 CATENATE\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            A0002 NO [TOOBIG] Message too large\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let res = session
+            .append_catenate("Drafts", &[CatenatePart::Text(&[b'x'; 100])])
+            .await;
+        assert!(matches!(res, Err(Error::No(_))), "{:?}", res);
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 APPEND \"Drafts\" CATENATE (TEXT {100}\r\n",
+            "Literal must not be sent"
+        );
     }
 
-    async fn generic_fetch<'a, F, T, K>(prefix: &'a str, op: F)
+    async fn generic_fetch<'a, F, T, K>(prefix: &'a str, query: &'a str, op: F)
     where
         F: 'a + FnOnce(Arc<Mutex<Session<MockStream>>>, &'a str, &'a str) -> K,
         K: 'a + Future<Output = Result<T>>,
     {
-        generic_with_uid("OK FETCH completed\r\n", "FETCH", "1", "BODY[]", prefix, op).await;
+        generic_with_uid("OK FETCH completed\r\n", "FETCH", "1", query, prefix, op).await;
     }
 
     async fn generic_with_uid<'a, F, T, K>(
@@ -1,11 +1,14 @@
 //! IMAP error types.
 
+use std::fmt;
 use std::io::Error as IoError;
 use std::result;
 use std::str::Utf8Error;
 
 use base64::DecodeError;
 
+use crate::types::Fetch;
+
 /// A convenience wrapper around `Result` for `imap::Error`.
 pub type Result<T> = result::Result<T, Error>;
 
@@ -15,16 +18,25 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     /// An `io::Error` that occurred while trying to read or write to a network stream.
     #[error("io: {0}")]
-    Io(#[from] IoError),
+    Io(IoError),
     /// A BAD response from the IMAP server.
     #[error("bad response: {0}")]
-    Bad(String),
+    Bad(ServerResponse),
     /// A NO response from the IMAP server.
     #[error("no response: {0}")]
-    No(String),
+    No(ServerResponse),
     /// The connection was terminated unexpectedly.
     #[error("connection lost")]
     ConnectionLost,
+    /// The server sent an untagged `BYE` while a command was running and is closing the
+    /// connection, e.g. because it is shutting down. Contains the reason the server gave.
+    #[error("server closed the connection: {0}")]
+    Bye(String),
+    /// The server completed the `IDLE` command of an
+    /// [`idle::Handle`](crate::extensions::idle::Handle) with `OK` by itself, so the session is
+    /// no longer idling.
+    #[error("server ended IDLE")]
+    IdleTerminated,
     /// Error parsing a server response.
     #[error("parse: {0}")]
     Parse(#[from] ParseError),
@@ -35,13 +47,352 @@ pub enum Error {
     /// `async_native_tls` error
     #[error("async_native_tls: {0}")]
     NativeTlsError(#[from] async_native_tls::Error),
+    /// The server did not send its greeting within
+    /// [`ClientBuilder::greeting_timeout`](crate::ClientBuilder::greeting_timeout).
+    #[error("timed out waiting for the server")]
+    Timeout,
+    /// A command was not sent because it is not valid in the state the session is in, e.g.
+    /// `FETCH` while no mailbox is selected, or any command after `LOGOUT`. Contains the name of
+    /// the command.
+    #[error("{0} is not valid in the current state")]
+    InvalidState(String),
     /// Error appending an e-mail.
     #[error("could not append mail to mailbox")]
     Append,
+    /// The client cancelled an authentication exchange or rejected its outcome, e.g. because the
+    /// server failed to prove that it knows the credentials, see
+    /// [`Authenticator::check`](crate::Authenticator::check).
+    #[error("authentication failed: {0}")]
+    Authentication(String),
+    /// The server does not advertise a capability that is required for the command.
+    #[error("server does not support {0}")]
+    MissingCapability(String),
+    /// The server could not decode the content transfer encoding of a message part requested via
+    /// [`BINARY`](https://tools.ietf.org/html/rfc3516), as indicated by the `UNKNOWN-CTE`
+    /// response code.
+    #[error("unknown content transfer encoding: {0}")]
+    UnknownCte(String),
+    /// A command used the search result saved as `$` by
+    /// [`Session::search_save`](crate::Session::search_save) after messages were added to or
+    /// expunged from the mailbox, or without a saved result, and was not sent.
+    #[error("saved search result `$` is no longer valid")]
+    SavedSearchInvalid,
+    /// A `STORE` was not sent because it adds the given keyword, which the selected mailbox can
+    /// not store permanently, see
+    /// [`Session::set_validate_keywords`](crate::Session::set_validate_keywords).
+    #[error("mailbox does not allow the keyword {0}")]
+    UnsupportedKeyword(String),
+    /// The `UIDVALIDITY` of the selected mailbox changed to the given value while paging through
+    /// it with [`Session::uid_pages`](crate::Session::uid_pages), so the UIDs of the earlier
+    /// pages no longer refer to the same messages.
+    #[error("UIDVALIDITY of the mailbox changed to {0}")]
+    UidValidityChanged(u32),
+    /// A `FETCH` failed with a `NO` or `BAD` response after the server had already returned some
+    /// of the messages, see [`Session::fetch_all`](crate::Session::fetch_all).
+    #[error("{error} (after {} messages were fetched)", fetches.len())]
+    PartialFetch {
+        /// The messages that were fetched before the command failed.
+        fetches: Vec<Fetch>,
+        /// The reason the command failed.
+        error: Box<Error>,
+    },
+}
+
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Self {
+        // Responses that fail to parse are reported by the stream as I/O errors.
+        if !matches!(err.get_ref(), Some(inner) if inner.is::<ParseError>()) {
+            return Error::Io(err);
+        }
+        match err.into_inner().map(|inner| inner.downcast::<ParseError>()) {
+            Some(Ok(err)) => Error::Parse(*err),
+            _ => unreachable!("checked above"),
+        }
+    }
+}
+
+impl ParseError {
+    /// The error for a response in `buf` that fails to parse at `position`.
+    pub(crate) fn malformed(buf: &[u8], position: usize) -> Self {
+        const CONTEXT: usize = 64;
+
+        let position = position.min(buf.len());
+        let line_start = buf[..position]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let line_end = buf[position..]
+            .iter()
+            .position(|&b| b == b'\r' || b == b'\n')
+            .map_or(buf.len(), |i| position + i);
+        let start = line_start.max(position.saturating_sub(CONTEXT));
+        let end = line_end.min(position + CONTEXT);
+        ParseError::Malformed {
+            position,
+            snippet: String::from_utf8_lossy(&buf[start..end]).into_owned(),
+        }
+    }
+}
+
+impl Error {
+    /// Records the name of the command that failed, unless already known.
+    pub(crate) fn with_command(mut self, command: Option<&str>) -> Self {
+        if let Error::Bad(res) | Error::No(res) = &mut self {
+            if res.command.is_none() {
+                res.command = command.map(Into::into);
+            }
+        }
+        self
+    }
+}
+
+/// The details of a `NO` or `BAD` response from the IMAP server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServerResponse {
+    /// The tag of the command that the server completed with this response, or `None` if the
+    /// response was untagged.
+    pub tag: Option<String>,
+    /// The name of the command that failed, e.g. `SELECT` or `UID FETCH`. This never includes
+    /// the command arguments, so that credentials do not end up in error messages.
+    pub command: Option<String>,
+    /// The response code sent by the server, if any.
+    pub code: Option<ResponseCode>,
+    /// The human-readable text sent by the server.
+    pub information: Option<String>,
+}
+
+impl ServerResponse {
+    pub(crate) fn new(
+        tag: Option<&str>,
+        code: Option<ResponseCode>,
+        information: Option<&str>,
+    ) -> Self {
+        ServerResponse {
+            tag: tag.map(Into::into),
+            command: None,
+            code,
+            information: information.map(Into::into),
+        }
+    }
+}
+
+impl From<String> for ServerResponse {
+    fn from(information: String) -> Self {
+        ServerResponse {
+            information: Some(information),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for ServerResponse {
+    fn from(information: &str) -> Self {
+        information.to_string().into()
+    }
+}
+
+impl fmt::Display for ServerResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.tag, &self.command) {
+            (Some(tag), Some(command)) => write!(f, "{} {}: ", tag, command)?,
+            (Some(tag), None) => write!(f, "{}: ", tag)?,
+            (None, Some(command)) => write!(f, "{}: ", command)?,
+            (None, None) => {}
+        }
+        if let Some(code) = &self.code {
+            write!(f, "[{}] ", code)?;
+        }
+        f.write_str(self.information.as_deref().unwrap_or(""))
+    }
+}
+
+/// A machine-readable [response code](https://tools.ietf.org/html/rfc3501#section-7.1) sent by
+/// the server along with a `NO` or `BAD` response, e.g. to tell apart a full mailbox from a
+/// missing one. Most codes are defined in [RFC 5530](https://tools.ietf.org/html/rfc5530).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ResponseCode {
+    /// `ALERT`: the text must be shown to the user.
+    Alert,
+    /// `BADCHARSET`: the charset of a `SEARCH` is not supported. Lists the supported charsets.
+    BadCharset(Vec<String>),
+    /// `PARSE`: the server failed to parse the headers of a message.
+    Parse,
+    /// `READ-ONLY`: the mailbox is selected read-only.
+    ReadOnly,
+    /// `READ-WRITE`: the mailbox is selected read-write.
+    ReadWrite,
+    /// `TRYCREATE`: the target mailbox does not exist, but could be created.
+    TryCreate,
+    /// `UIDNOTSTICKY`: the mailbox does not support persistent UIDs (RFC 4315).
+    UidNotSticky,
+    /// `UNKNOWN-CTE`: the server cannot decode the content transfer encoding (RFC 3516).
+    UnknownCte,
+    /// `UNAVAILABLE`: a subsystem is down temporarily; the command may succeed later.
+    Unavailable,
+    /// `AUTHENTICATIONFAILED`: the credentials were rejected.
+    AuthenticationFailed,
+    /// `AUTHORIZATIONFAILED`: the credentials are valid, but may not be used for this identity.
+    AuthorizationFailed,
+    /// `EXPIRED`: the credentials have expired.
+    Expired,
+    /// `PRIVACYREQUIRED`: the command requires an encrypted connection.
+    PrivacyRequired,
+    /// `CONTACTADMIN`: the user should contact the system administrator.
+    ContactAdmin,
+    /// `NOPERM`: the access control list does not allow the command.
+    NoPerm,
+    /// `INUSE`: the mailbox is in use by another session; the command may succeed later.
+    InUse,
+    /// `EXPUNGEISSUED`: messages were expunged by another session.
+    ExpungeIssued,
+    /// `CORRUPTION`: the server found corrupted data.
+    Corruption,
+    /// `SERVERBUG`: the server failed because of a bug.
+    ServerBug,
+    /// `CLIENTBUG`: the server detected a bug in the client.
+    ClientBug,
+    /// `CANNOT`: the command can never succeed, e.g. because of a name the server cannot store.
+    Cannot,
+    /// `LIMIT`: the command would exceed a server limit, such as the number of flags.
+    Limit,
+    /// `OVERQUOTA`: the command would exceed a quota (RFC 9208).
+    OverQuota,
+    /// `ALREADYEXISTS`: the mailbox to be created or renamed to already exists.
+    AlreadyExists,
+    /// `NONEXISTENT`: the mailbox or message does not exist.
+    NonExistent,
+    /// Any other response code, as sent by the server without the brackets, e.g.
+    /// `UIDNEXT 4392` or `METADATA TOOMANY`.
+    Other(String),
+}
+
+impl ResponseCode {
+    pub(crate) fn from_imap_proto(code: &imap_proto::ResponseCode<'_>) -> Self {
+        use imap_proto::{ResponseCode as Code, UidSetMember};
+
+        let uid_set = |set: &[UidSetMember]| {
+            set.iter()
+                .map(|member| match member {
+                    UidSetMember::UidRange(range) => format!("{}:{}", range.start(), range.end()),
+                    UidSetMember::Uid(uid) => uid.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        match code {
+            Code::Alert => ResponseCode::Alert,
+            Code::BadCharset(charsets) => ResponseCode::BadCharset(
+                charsets
+                    .iter()
+                    .flatten()
+                    .map(|charset| charset.to_string())
+                    .collect(),
+            ),
+            Code::Parse => ResponseCode::Parse,
+            Code::ReadOnly => ResponseCode::ReadOnly,
+            Code::ReadWrite => ResponseCode::ReadWrite,
+            Code::TryCreate => ResponseCode::TryCreate,
+            Code::UidNotSticky => ResponseCode::UidNotSticky,
+            Code::Capabilities(_) => ResponseCode::Other("CAPABILITY".into()),
+            Code::HighestModSeq(n) => ResponseCode::Other(format!("HIGHESTMODSEQ {}", n)),
+            Code::PermanentFlags(flags) => {
+                ResponseCode::Other(format!("PERMANENTFLAGS ({})", flags.join(" ")))
+            }
+            Code::UidNext(n) => ResponseCode::Other(format!("UIDNEXT {}", n)),
+            Code::UidValidity(n) => ResponseCode::Other(format!("UIDVALIDITY {}", n)),
+            Code::Unseen(n) => ResponseCode::Other(format!("UNSEEN {}", n)),
+            Code::AppendUid(validity, uids) => {
+                ResponseCode::Other(format!("APPENDUID {} {}", validity, uid_set(uids)))
+            }
+            Code::CopyUid(validity, from, to) => ResponseCode::Other(format!(
+                "COPYUID {} {} {}",
+                validity,
+                uid_set(from),
+                uid_set(to)
+            )),
+            Code::MetadataLongEntries(n) => {
+                ResponseCode::Other(format!("METADATA LONGENTRIES {}", n))
+            }
+            Code::MetadataMaxSize(n) => ResponseCode::Other(format!("METADATA MAXSIZE {}", n)),
+            Code::MetadataTooMany => ResponseCode::Other("METADATA TOOMANY".into()),
+            Code::MetadataNoPrivate => ResponseCode::Other("METADATA NOPRIVATE".into()),
+            other => ResponseCode::Other(format!("{:?}", other)),
+        }
+    }
+
+    /// Splits a response code that `imap_proto` does not know off the start of the
+    /// human-readable `text`, where it is left by `imap_proto`.
+    pub(crate) fn split_text(text: &str) -> Option<(Self, &str)> {
+        let (code, rest) = text.strip_prefix('[')?.split_once(']')?;
+        let name = code.split(' ').next().unwrap_or_default();
+        let code = match name.to_ascii_uppercase().as_str() {
+            "UNKNOWN-CTE" => ResponseCode::UnknownCte,
+            "UNAVAILABLE" => ResponseCode::Unavailable,
+            "AUTHENTICATIONFAILED" => ResponseCode::AuthenticationFailed,
+            "AUTHORIZATIONFAILED" => ResponseCode::AuthorizationFailed,
+            "EXPIRED" => ResponseCode::Expired,
+            "PRIVACYREQUIRED" => ResponseCode::PrivacyRequired,
+            "CONTACTADMIN" => ResponseCode::ContactAdmin,
+            "NOPERM" => ResponseCode::NoPerm,
+            "INUSE" => ResponseCode::InUse,
+            "EXPUNGEISSUED" => ResponseCode::ExpungeIssued,
+            "CORRUPTION" => ResponseCode::Corruption,
+            "SERVERBUG" => ResponseCode::ServerBug,
+            "CLIENTBUG" => ResponseCode::ClientBug,
+            "CANNOT" => ResponseCode::Cannot,
+            "LIMIT" => ResponseCode::Limit,
+            "OVERQUOTA" => ResponseCode::OverQuota,
+            "ALREADYEXISTS" => ResponseCode::AlreadyExists,
+            "NONEXISTENT" => ResponseCode::NonExistent,
+            "" => return None,
+            _ => ResponseCode::Other(code.to_string()),
+        };
+        Some((code, rest.trim_start()))
+    }
+}
+
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ResponseCode::Alert => "ALERT",
+            ResponseCode::BadCharset(charsets) if charsets.is_empty() => "BADCHARSET",
+            ResponseCode::BadCharset(charsets) => {
+                return write!(f, "BADCHARSET ({})", charsets.join(" "));
+            }
+            ResponseCode::Parse => "PARSE",
+            ResponseCode::ReadOnly => "READ-ONLY",
+            ResponseCode::ReadWrite => "READ-WRITE",
+            ResponseCode::TryCreate => "TRYCREATE",
+            ResponseCode::UidNotSticky => "UIDNOTSTICKY",
+            ResponseCode::UnknownCte => "UNKNOWN-CTE",
+            ResponseCode::Unavailable => "UNAVAILABLE",
+            ResponseCode::AuthenticationFailed => "AUTHENTICATIONFAILED",
+            ResponseCode::AuthorizationFailed => "AUTHORIZATIONFAILED",
+            ResponseCode::Expired => "EXPIRED",
+            ResponseCode::PrivacyRequired => "PRIVACYREQUIRED",
+            ResponseCode::ContactAdmin => "CONTACTADMIN",
+            ResponseCode::NoPerm => "NOPERM",
+            ResponseCode::InUse => "INUSE",
+            ResponseCode::ExpungeIssued => "EXPUNGEISSUED",
+            ResponseCode::Corruption => "CORRUPTION",
+            ResponseCode::ServerBug => "SERVERBUG",
+            ResponseCode::ClientBug => "CLIENTBUG",
+            ResponseCode::Cannot => "CANNOT",
+            ResponseCode::Limit => "LIMIT",
+            ResponseCode::OverQuota => "OVERQUOTA",
+            ResponseCode::AlreadyExists => "ALREADYEXISTS",
+            ResponseCode::NonExistent => "NONEXISTENT",
+            ResponseCode::Other(code) => code,
+        };
+        f.write_str(name)
+    }
 }
 
 /// An error occured while trying to parse a server response.
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum ParseError {
     /// Indicates an error parsing the status response. Such as OK, NO, and BAD.
     #[error("unable to parse status response")]
@@ -58,6 +409,25 @@ pub enum ParseError {
     /// The expected response for X was not found
     #[error("expected response not found for: {0}")]
     ExpectedResponseNotFound(String),
+    /// A fetched message could not be parsed as MIME, see
+    /// [`Fetch::mail`](crate::types::Fetch::mail).
+    #[cfg(feature = "mail")]
+    #[error("unable to parse message: {0}")]
+    Mail(#[source] mailparse::MailParseError),
+    /// The server sent a response that could not be parsed.
+    #[error("unable to parse response at byte {position}: {snippet:?}")]
+    Malformed {
+        /// The offset into the response at which parsing failed.
+        position: usize,
+        /// The line of the response around `position`, shortened if it is long, with invalid
+        /// UTF-8 replaced.
+        snippet: String,
+    },
+    /// The server sent a tagged response with a tag that no running command was sent with, e.g.
+    /// a duplicate completion. The responses can no longer be matched to their commands, so the
+    /// connection should be closed.
+    #[error("tagged response for unknown command: {0}")]
+    UnexpectedTag(String),
 }
 
 /// An [invalid character](https://tools.ietf.org/html/rfc3501#section-4.3) was found in an input
@@ -72,8 +442,57 @@ mod tests {
 
     fn is_send<T: Send>(_t: T) {}
 
+    #[test]
+    fn malformed_snippet() {
+        let buf = b"* 1 FETCH (UID 1)\r\n* 2 FETCH (UID ?)\r\n";
+        match ParseError::malformed(buf, 34) {
+            ParseError::Malformed { position, snippet } => {
+                assert_eq!(position, 34);
+                assert_eq!(snippet, "* 2 FETCH (UID ?)");
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+
+        let buf = [b'x'; 200];
+        match ParseError::malformed(&buf, 100) {
+            ParseError::Malformed { snippet, .. } => assert_eq!(snippet.len(), 128),
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
     #[test]
     fn test_send() {
         is_send::<Result<usize>>(Ok(3));
     }
+
+    #[test]
+    fn server_response_display() {
+        let res = ServerResponse::new(
+            Some("A0003"),
+            Some(ResponseCode::TryCreate),
+            Some("No such mailbox"),
+        );
+        assert_eq!(
+            Error::No(res.clone()).to_string(),
+            "no response: A0003: [TRYCREATE] No such mailbox"
+        );
+        assert_eq!(
+            Error::No(res).with_command(Some("COPY")).to_string(),
+            "no response: A0003 COPY: [TRYCREATE] No such mailbox"
+        );
+    }
+
+    #[test]
+    fn split_response_code() {
+        assert_eq!(
+            ResponseCode::split_text("[OVERQUOTA] Quota exceeded"),
+            Some((ResponseCode::OverQuota, "Quota exceeded"))
+        );
+        assert_eq!(
+            ResponseCode::split_text("[X-NEW-CODE 12 (a b)] Hmm"),
+            Some((ResponseCode::Other("X-NEW-CODE 12 (a b)".into()), "Hmm"))
+        );
+        assert_eq!(ResponseCode::split_text("No [brackets]"), None);
+        assert_eq!(ResponseCode::split_text("[] text"), None);
+    }
 }
@@ -0,0 +1,14 @@
+//! Adds support for building messages from existing parts with APPEND as specified in
+//! [RFC4469](https://tools.ietf.org/html/rfc4469).
+
+/// A part of a message appended with
+/// [`Session::append_catenate`](crate::Session::append_catenate).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatenatePart<'a> {
+    /// Data that is sent to the server as-is.
+    Text(&'a [u8]),
+    /// An [IMAP URL](https://tools.ietf.org/html/rfc5092) referencing a message, or a section of
+    /// a message, that is already on the server, e.g.
+    /// `/INBOX;UIDVALIDITY=385759045/;UID=20/;section=HEADER`.
+    Url(&'a str),
+}
@@ -0,0 +1,107 @@
+//! Adds support for the Gmail IMAP extensions, advertised with the `X-GM-EXT-1` capability, as
+//! documented at <https://developers.google.com/gmail/imap/imap-extensions>.
+//!
+//! The message and thread ids and the labels of a message are fetched with the `X-GM-MSGID`,
+//! `X-GM-THRID` and `X-GM-LABELS` data items, and read through [`Fetch::gmail_msg_id`],
+//! [`Fetch::gmail_thread_id`] and [`Fetch::gmail_labels`]. Labels are changed with
+//! [`Session::store_gmail_labels`](crate::Session::store_gmail_labels).
+//!
+//! [`Fetch::gmail_msg_id`]: crate::types::Fetch::gmail_msg_id
+//! [`Fetch::gmail_thread_id`]: crate::types::Fetch::gmail_thread_id
+//! [`Fetch::gmail_labels`]: crate::types::Fetch::gmail_labels
+
+use std::str;
+
+use crate::utf7;
+
+/// How [`Session::store_gmail_labels`](crate::Session::store_gmail_labels) changes the labels of
+/// messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelsOperation {
+    /// Add the labels to the messages (`+X-GM-LABELS`).
+    Add,
+    /// Remove the labels from the messages (`-X-GM-LABELS`).
+    Remove,
+    /// Replace all labels of the messages (`X-GM-LABELS`).
+    Replace,
+}
+
+impl LabelsOperation {
+    pub(crate) fn item(self) -> &'static str {
+        match self {
+            LabelsOperation::Add => "+X-GM-LABELS",
+            LabelsOperation::Remove => "-X-GM-LABELS",
+            LabelsOperation::Replace => "X-GM-LABELS",
+        }
+    }
+}
+
+/// Parses the parenthesized list of an `X-GM-LABELS` data item. Labels are quoted strings,
+/// literals or atoms, and are decoded from modified UTF-7. System labels such as `\Inbox` are
+/// returned as-is.
+pub(crate) fn parse_labels(raw: &[u8]) -> Option<Vec<String>> {
+    let raw = str::from_utf8(raw).ok()?.trim();
+    let mut rest = raw.strip_prefix('(')?.strip_suffix(')')?;
+
+    let mut labels = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(' ');
+        if rest.is_empty() {
+            break;
+        }
+
+        let label = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut label = String::new();
+            let mut chars = quoted.char_indices();
+            loop {
+                match chars.next()? {
+                    (_, '\\') => label.push(chars.next()?.1),
+                    (i, '"') => {
+                        rest = &quoted[i + 1..];
+                        break;
+                    }
+                    (_, c) => label.push(c),
+                }
+            }
+            label
+        } else if let Some(literal) = rest.strip_prefix('{') {
+            let (len, data) = literal.split_once("}\r\n")?;
+            let len: usize = len.parse().ok()?;
+            let label = data.get(..len)?.to_string();
+            rest = &data[len..];
+            label
+        } else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            let label = rest[..end].to_string();
+            rest = &rest[end..];
+            label
+        };
+
+        labels.push(utf7::decode(&label).unwrap_or(label));
+    }
+
+    Some(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            parse_labels(b"(\\Inbox \\Sent Important \"Muy Importante\" \"a \\\"b\\\"\" {4}\r\nx yz &AOk-t&AOk-)"),
+            Some(vec![
+                "\\Inbox".to_string(),
+                "\\Sent".to_string(),
+                "Important".to_string(),
+                "Muy Importante".to_string(),
+                "a \"b\"".to_string(),
+                "x yz".to_string(),
+                "été".to_string(),
+            ])
+        );
+        assert_eq!(parse_labels(b"()"), Some(vec![]));
+        assert_eq!(parse_labels(b"(\"unterminated)"), None);
+    }
+}
@@ -4,6 +4,7 @@ use std::fmt;
 use std::pin::Pin;
 use std::time::Duration;
 
+use async_std::channel;
 use async_std::io::{self, Read, Write};
 use async_std::prelude::*;
 use async_std::stream::Stream;
@@ -11,9 +12,9 @@ use futures::task::{Context, Poll};
 use imap_proto::{RequestId, Response, Status};
 
 use crate::client::Session;
-use crate::error::Result;
-use crate::parse::handle_unilateral;
-use crate::types::ResponseData;
+use crate::error::{Error, Result};
+use crate::parse::{check_bye, check_status, handle_unilateral};
+use crate::types::{ResponseData, UnsolicitedResponse};
 
 /// `Handle` allows a client to block waiting for changes to the remote mailbox.
 ///
@@ -29,11 +30,18 @@ use crate::types::ResponseData;
 /// does this. This still allows a client to receive immediate mailbox updates even though it need
 /// only "poll" at half hour intervals.
 ///
-/// As long as a [`Handle`] is active, the mailbox cannot be otherwise accessed.
+/// As long as a [`Handle`] is active, the mailbox cannot be otherwise accessed. Unsolicited
+/// responses that arrive while idling are passed on to [`Session::unsolicited_responses`] as
+/// usual. The handle also keeps a copy of them, up to [`Session::unsolicited_capacity`], which
+/// [`Handle::done_with_responses`] returns.
 #[derive(Debug)]
 pub struct Handle<T: Read + Write + Unpin + fmt::Debug> {
     session: Session<T>,
     id: Option<RequestId>,
+    /// Whether the server already completed the `IDLE` command, so that `DONE` is not sent.
+    completed: bool,
+    /// A copy of the unsolicited responses received while idling.
+    responses: channel::Receiver<UnsolicitedResponse>,
 }
 
 impl<T: Read + Write + Unpin + fmt::Debug> Unpin for Handle<T> {}
@@ -42,7 +50,15 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Stream for Handle<T> {
     type Item = io::Result<ResponseData>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        self.as_mut().session().get_stream().poll_next(cx)
+        let res = self.as_mut().session().get_stream().poll_next(cx);
+        if let Poll::Ready(Some(Ok(response))) = &res {
+            if let Response::Done { tag, .. } = response.parsed() {
+                if self.id.as_ref() == Some(tag) {
+                    self.completed = true;
+                }
+            }
+        }
+        res
     }
 }
 
@@ -92,11 +108,22 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
     unsafe_pinned!(session: Session<T>);
 
     pub(crate) fn new(session: Session<T>) -> Handle<T> {
-        Handle { session, id: None }
+        let responses = session.unsolicited_responses_tx.subscribe();
+        Handle {
+            session,
+            id: None,
+            completed: false,
+            responses,
+        }
     }
 
     /// Start listening to the server side resonses.
     /// Must be called after [Handle::init].
+    ///
+    /// Returns [`Error::Bye`] if the server closes the connection, e.g. because the client idled
+    /// for too long. If the server completes the `IDLE` command by itself, this returns the
+    /// [`Error::No`] or [`Error::Bad`] it was completed with, or [`Error::IdleTerminated`], and
+    /// [`Handle::done`] no longer sends `DONE`.
     pub fn wait(
         &mut self,
     ) -> (
@@ -108,6 +135,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
             "Cannot listen to response without starting IDLE"
         );
         let sender = self.session.unsolicited_responses_tx.clone();
+        let id = self.id.clone();
 
         let interrupt = stop_token::StopSource::new();
         let raw_stream = IdleStream::new(self);
@@ -116,6 +144,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
         let fut = async move {
             while let Some(resp) = interruptible_stream.next().await {
                 let resp = resp?;
+                check_bye(&resp)?;
                 match resp.parsed() {
                     Response::Data { status, .. } if status == &Status::Ok => {
                         // all good continue
@@ -123,8 +152,18 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
                     Response::Continue { .. } => {
                         // continuation, wait for it
                     }
+                    Response::Done {
+                        tag,
+                        status,
+                        code,
+                        information,
+                    } if Some(tag) == id.as_ref() => {
+                        check_status(Some(tag), status, code.as_ref(), information.as_deref())
+                            .map_err(|err| err.with_command(Some("IDLE")))?;
+                        return Err(Error::IdleTerminated);
+                    }
                     Response::Done { .. } => {
-                        handle_unilateral(resp, sender.clone()).await;
+                        handle_unilateral(resp, sender.clone()).await?;
                     }
                     _ => return Ok(IdleResponse::NewData(resp)),
                 }
@@ -138,6 +177,10 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
 
     /// Start listening to the server side resonses, stops latest after the passed in `timeout`.
     /// Must be called after [Handle::init].
+    ///
+    /// Returns [`IdleResponse::Timeout`] if nothing arrived in time. The `IDLE` command keeps
+    /// running either way, so this can be called in a loop to get control back periodically,
+    /// e.g. for housekeeping, without missing any response.
     pub fn wait_with_timeout(
         &mut self,
         timeout: Duration,
@@ -162,6 +205,9 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
     }
 
     /// Initialise the idle connection by sending the `IDLE` command to the server.
+    ///
+    /// Returns the [`Error::No`] or [`Error::Bad`] the server refuses the command with, or
+    /// [`Error::IdleTerminated`] if it completes it right away.
     pub async fn init(&mut self) -> Result<()> {
         let id = self.session.run_command("IDLE").await?;
         self.id = Some(id);
@@ -174,42 +220,53 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
                 Response::Done {
                     tag,
                     status,
+                    code,
                     information,
-                    ..
-                } => {
-                    if tag == self.id.as_ref().unwrap() {
-                        if let Status::Bad = status {
-                            return Err(io::Error::new(
-                                io::ErrorKind::ConnectionRefused,
-                                information.as_ref().unwrap().to_string(),
-                            )
-                            .into());
-                        }
-                    }
-                    handle_unilateral(res, self.session.unsolicited_responses_tx.clone()).await;
+                } if tag == self.id.as_ref().unwrap() => {
+                    self.completed = true;
+                    check_status(Some(tag), status, code.as_ref(), information.as_deref())
+                        .map_err(|err| err.with_command(Some("IDLE")))?;
+                    return Err(Error::IdleTerminated);
                 }
                 _ => {
-                    handle_unilateral(res, self.session.unsolicited_responses_tx.clone()).await;
+                    handle_unilateral(res, self.session.unsolicited_responses_tx.clone()).await?;
                 }
             }
         }
 
-        Err(io::Error::new(io::ErrorKind::ConnectionRefused, "").into())
+        Err(Error::ConnectionLost)
     }
 
     /// Signal that we want to exit the idle connection, by sending the `DONE`
     /// command to the server.
-    pub async fn done(mut self) -> Result<Session<T>> {
+    pub async fn done(self) -> Result<Session<T>> {
+        let (session, _) = self.done_with_responses().await?;
+        Ok(session)
+    }
+
+    /// Like [`Handle::done`], but waits for the server to complete the `IDLE` command and also
+    /// returns the unsolicited responses that arrived while idling and were not returned by
+    /// [`Handle::wait`], such as an `EXISTS` the server sent just before completing it.
+    ///
+    /// The responses were passed on to [`Session::unsolicited_responses`] as well.
+    pub async fn done_with_responses(mut self) -> Result<(Session<T>, Vec<UnsolicitedResponse>)> {
         assert!(
             self.id.is_some(),
             "Cannot call DONE on a non initialized idle connection"
         );
-        self.session.run_command_untagged("DONE").await?;
-        let sender = self.session.unsolicited_responses_tx.clone();
-        self.session
-            .check_done_ok(&self.id.expect("invalid setup"), Some(sender))
-            .await?;
+        if !self.completed {
+            self.session.run_command_untagged("DONE").await?;
+            let sender = self.session.unsolicited_responses_tx.clone();
+            self.session
+                .check_done_ok(&self.id.expect("invalid setup"), Some(sender))
+                .await?;
+        }
+
+        let mut responses = Vec::new();
+        while let Ok(response) = self.responses.try_recv() {
+            responses.push(response);
+        }
 
-        Ok(self.session)
+        Ok((self.session, responses))
     }
 }
@@ -0,0 +1,50 @@
+//! Adds support for the selection and return options of `LIST` as specified in
+//! [RFC5258](https://tools.ietf.org/html/rfc5258).
+
+/// Selects which mailboxes [`Session::list_extended`](crate::Session::list_extended) returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ListSelection {
+    /// Only subscribed mailboxes, including ones that no longer exist, which are returned with
+    /// [`NameAttribute::NonExistent`](crate::types::NameAttribute::NonExistent). Matching names
+    /// are returned with [`NameAttribute::Subscribed`](crate::types::NameAttribute::Subscribed).
+    Subscribed,
+    /// Also mailboxes on remote servers, marked with
+    /// [`NameAttribute::Remote`](crate::types::NameAttribute::Remote).
+    Remote,
+    /// Also names that do not match the other selection options themselves, but have mailboxes
+    /// below them that do, see [`Name::child_info`](crate::types::Name::child_info). Must be
+    /// combined with another selection option.
+    RecursiveMatch,
+}
+
+impl ListSelection {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ListSelection::Subscribed => "SUBSCRIBED",
+            ListSelection::Remote => "REMOTE",
+            ListSelection::RecursiveMatch => "RECURSIVEMATCH",
+        }
+    }
+}
+
+/// Additional data that [`Session::list_extended`](crate::Session::list_extended) returns for
+/// each name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ListReturn {
+    /// Marks subscribed mailboxes with
+    /// [`NameAttribute::Subscribed`](crate::types::NameAttribute::Subscribed).
+    Subscribed,
+    /// Marks mailboxes with
+    /// [`NameAttribute::HasChildren`](crate::types::NameAttribute::HasChildren) or
+    /// [`NameAttribute::HasNoChildren`](crate::types::NameAttribute::HasNoChildren).
+    Children,
+}
+
+impl ListReturn {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ListReturn::Subscribed => "SUBSCRIBED",
+            ListReturn::Children => "CHILDREN",
+        }
+    }
+}
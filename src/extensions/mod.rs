@@ -1,4 +1,12 @@
 //! Implementations of various IMAP extensions.
+pub mod catenate;
+
+pub mod gmail;
+
 pub mod idle;
 
+pub mod list_extended;
+
+pub mod notify;
+
 pub mod quota;
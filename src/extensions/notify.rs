@@ -0,0 +1,80 @@
+//! Adds support for the `NOTIFY` command as specified in
+//! [RFC5465](https://tools.ietf.org/html/rfc5465).
+
+/// The mailboxes a [`NotifyGroup`] asks for events of.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NotifyMailboxes {
+    /// The selected mailbox, whichever it is (`SELECTED`).
+    Selected,
+    /// The selected mailbox, with expunges held back until the server may send them, as after
+    /// a `NOOP` (`SELECTED-DELAYED`).
+    SelectedDelayed,
+    /// The mailboxes new mail is delivered to, e.g. `INBOX` (`INBOXES`).
+    Inboxes,
+    /// All mailboxes of the user (`PERSONAL`).
+    Personal,
+    /// The mailboxes the user subscribed to (`SUBSCRIBED`).
+    Subscribed,
+    /// The given mailboxes and all mailboxes below them (`SUBTREE`).
+    Subtree(Vec<String>),
+    /// The given mailboxes (`MAILBOXES`).
+    Mailboxes(Vec<String>),
+}
+
+/// The events a [`NotifyGroup`] asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NotifyEvent {
+    /// Messages were added.
+    MessageNew,
+    /// Messages were expunged. Required with [`NotifyEvent::MessageNew`].
+    MessageExpunge,
+    /// The flags of messages changed. Requires [`NotifyEvent::MessageNew`] and
+    /// [`NotifyEvent::MessageExpunge`].
+    FlagChange,
+    /// The annotations of messages changed.
+    AnnotationChange,
+    /// Mailboxes were created, deleted or renamed, reported as `LIST` responses.
+    MailboxName,
+    /// Mailboxes were subscribed to or unsubscribed from, reported as `LIST` responses.
+    SubscriptionChange,
+    /// The metadata of mailboxes changed.
+    MailboxMetadataChange,
+    /// The metadata of the server changed.
+    ServerMetadataChange,
+}
+
+impl NotifyEvent {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            NotifyEvent::MessageNew => "MessageNew",
+            NotifyEvent::MessageExpunge => "MessageExpunge",
+            NotifyEvent::FlagChange => "FlagChange",
+            NotifyEvent::AnnotationChange => "AnnotationChange",
+            NotifyEvent::MailboxName => "MailboxName",
+            NotifyEvent::SubscriptionChange => "SubscriptionChange",
+            NotifyEvent::MailboxMetadataChange => "MailboxMetadataChange",
+            NotifyEvent::ServerMetadataChange => "ServerMetadataChange",
+        }
+    }
+}
+
+/// The events to be notified of for a set of mailboxes, see
+/// [`Session::notify`](crate::Session::notify).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NotifyGroup {
+    /// The mailboxes the events are for.
+    pub mailboxes: NotifyMailboxes,
+    /// The events to be notified of, or none to turn off the events of these mailboxes that
+    /// another group asks for (`NONE`).
+    pub events: Vec<NotifyEvent>,
+}
+
+impl NotifyGroup {
+    /// Asks for `events` of `mailboxes`.
+    pub fn new(mailboxes: NotifyMailboxes, events: &[NotifyEvent]) -> Self {
+        NotifyGroup {
+            mailboxes,
+            events: events.to_vec(),
+        }
+    }
+}
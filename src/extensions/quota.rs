@@ -1,6 +1,5 @@
 //! Adds support for the GETQUOTA and GETQUOTAROOT commands specificed in [RFC2087](https://tools.ietf.org/html/rfc2087).
 
-use async_std::channel;
 use async_std::io;
 use async_std::prelude::*;
 use async_std::stream::Stream;
@@ -9,7 +8,7 @@ use imap_proto::{self, RequestId, Response};
 use crate::types::*;
 use crate::{
     error::Result,
-    parse::{filter_sync, handle_unilateral},
+    parse::{check_status, handle_unilateral},
 };
 use crate::{
     error::{Error, ParseError},
@@ -18,20 +17,25 @@ use crate::{
 
 pub(crate) async fn parse_get_quota<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<Quota> {
     let mut quota = None;
-    while let Some(resp) = stream
-        .take_while(|res| filter_sync(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = stream.next().await {
         let resp = resp?;
         match resp.parsed() {
             Response::Quota(q) => quota = Some(q.clone().into()),
+            Response::Done {
+                tag,
+                status,
+                code,
+                information,
+            } if tag == &command_tag => {
+                check_status(Some(tag), status, code.as_ref(), information.as_deref())?;
+                break;
+            }
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -46,17 +50,13 @@ pub(crate) async fn parse_get_quota<T: Stream<Item = io::Result<ResponseData>> +
 
 pub(crate) async fn parse_get_quota_root<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<(Vec<QuotaRoot>, Vec<Quota>)> {
     let mut roots: Vec<QuotaRoot> = Vec::new();
     let mut quotas: Vec<Quota> = Vec::new();
 
-    while let Some(resp) = stream
-        .take_while(|res| filter_sync(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = stream.next().await {
         let resp = resp?;
         match resp.parsed() {
             Response::QuotaRoot(qr) => {
@@ -65,8 +65,19 @@ pub(crate) async fn parse_get_quota_root<T: Stream<Item = io::Result<ResponseDat
             Response::Quota(q) => {
                 quotas.push(q.clone().into());
             }
+            Response::Done {
+                tag,
+                status,
+                code,
+                information,
+            } if tag == &command_tag => {
+                // A tagged `OK` without any `QUOTAROOT` response is valid, it means that no
+                // quota root is configured for the mailbox. A `NO` or `BAD` is an error.
+                check_status(Some(tag), status, code.as_ref(), information.as_deref())?;
+                break;
+            }
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
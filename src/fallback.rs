@@ -0,0 +1,1145 @@
+//! Recovery for responses that `imap_proto` cannot parse.
+//!
+//! `imap_proto` rejects the whole response as soon as a `FETCH` contains a data item it does
+//! not know about, e.g. `BINARY[1]` (RFC 3516). When that happens the response is split up here:
+//! the items `imap_proto` does understand are written into a new `* n FETCH (...)` line which
+//! is parsed as usual, and the remaining items are appended after it as raw name/value pairs,
+//! see [`RawItem`].
+//!
+//! `imap_proto` also only accepts ASCII in quoted strings, while servers send raw UTF-8 in them
+//! once `UTF8=ACCEPT` (RFC 6855) is enabled. Such strings are rewritten as literals, which may
+//! contain any bytes.
+//!
+//! Finally, servers that implement IMAP4rev2 (RFC 9051) send two responses `imap_proto` does not
+//! know: `ESEARCH` is rewritten as an empty `* SEARCH` followed by its return data as raw items,
+//! and a `CAPABILITY` response without `IMAP4rev1` gets that capability inserted, again marked
+//! by a raw item so it can be removed later.
+//!
+//! A `LIST` response with extended data (RFC 5258), e.g. `("CHILDINFO" ("SUBSCRIBED"))` after the
+//! name, is rewritten without it, and each extended item is appended as a raw item with its tag
+//! as the name and its value as the server sent it.
+//!
+//! Any other untagged response `imap_proto` does not know, e.g. `XLIST`, is rewritten as a bare
+//! `* OK` followed by a single raw item: the name of the response, with the rest of the response
+//! as its value, exactly as the server sent it.
+//!
+//! With lenient parsing, see
+//! [`Connection::set_lenient_parsing`](crate::Connection::set_lenient_parsing), a response that
+//! still fails to parse is normalized first, see [`rewrite_lenient`]. `imap_proto` already
+//! accepts tokens and `NIL` in any case, so the deviations fixed there are all about spacing.
+
+use std::ops::Range;
+
+use crate::types::RawItem;
+
+/// The outcome of [`rewrite`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Rewrite {
+    /// More data is needed before the response can be rewritten.
+    Incomplete,
+    /// The response was rewritten.
+    Done {
+        /// Number of bytes of the input that make up the response.
+        consumed: usize,
+        /// A parsable `FETCH` response, followed by the values of `items`.
+        data: Vec<u8>,
+        /// The data items that were split off, as ranges into `data`.
+        items: Vec<RawItem>,
+    },
+}
+
+/// Data items `imap_proto` knows how to parse.
+const KNOWN_ITEMS: &[&str] = &[
+    "BODY",
+    "BODYSTRUCTURE",
+    "ENVELOPE",
+    "FLAGS",
+    "INTERNALDATE",
+    "MODSEQ",
+    "RFC822",
+    "RFC822.HEADER",
+    "RFC822.SIZE",
+    "RFC822.TEXT",
+    "UID",
+];
+
+/// Used when a response contains no known data items at all, as `imap_proto` requires at least
+/// one.
+const PLACEHOLDER_ITEM: &[u8] = b"RFC822 NIL";
+
+/// Parsable response that an `ESEARCH` response is rewritten to.
+const PLACEHOLDER_SEARCH: &[u8] = b"* SEARCH\r\n";
+
+/// The capability `imap_proto` requires in every `CAPABILITY` response.
+const IMAP4REV1: &[u8] = b"IMAP4rev1";
+
+/// Names of the untagged responses `imap_proto` knows how to parse, or that are rewritten above.
+const KNOWN_RESPONSES: &[&str] = &[
+    "BAD",
+    "BYE",
+    "CAPABILITY",
+    "ENABLED",
+    "ESEARCH",
+    "FLAGS",
+    "LIST",
+    "LSUB",
+    "METADATA",
+    "NO",
+    "OK",
+    "PREAUTH",
+    "QUOTA",
+    "QUOTAROOT",
+    "SEARCH",
+    "SORT",
+    "STATUS",
+    "VANISHED",
+];
+
+/// Parsable response that an unknown response is rewritten to.
+const PLACEHOLDER_UNKNOWN: &[u8] = b"* OK \r\n";
+
+/// The status responses, which `imap_proto` requires to be followed by a space even without text.
+const STATUS_RESPONSES: &[&str] = &["OK", "NO", "BAD", "BYE", "PREAUTH"];
+
+/// Rewrites the response at the start of `buf` so that it can be parsed by `imap_proto`, see the
+/// [module documentation](self). Returns `None` if there is nothing to rewrite.
+pub(crate) fn rewrite(buf: &[u8]) -> Option<Rewrite> {
+    if let Some(rewrite) = rewrite_unknown(buf) {
+        return Some(rewrite);
+    }
+    match rewrite_utf8(buf) {
+        None => rewrite_fetch(buf)
+            .or_else(|| rewrite_list(buf))
+            .or_else(|| rewrite_esearch(buf))
+            .or_else(|| rewrite_capability(buf)),
+        Some(Rewrite::Incomplete) => Some(Rewrite::Incomplete),
+        Some(Rewrite::Done { consumed, data, .. }) => {
+            match rewrite_fetch(&data).or_else(|| rewrite_list(&data)) {
+                Some(Rewrite::Done { data, items, .. }) => Some(Rewrite::Done {
+                    consumed,
+                    data,
+                    items,
+                }),
+                _ => Some(Rewrite::Done {
+                    consumed,
+                    data,
+                    items: Vec::new(),
+                }),
+            }
+        }
+    }
+}
+
+/// Rewrites the response at the start of `buf` like [`rewrite`], after fixing these deviations
+/// from the grammar that some servers make:
+///
+/// - a literal that is not preceded by a space, e.g. `BODY[]{5}`,
+/// - spaces in or after the length of a literal, e.g. `{ 5 } \r\n`,
+/// - repeated spaces, and spaces after `(` or before `)`,
+/// - spaces at the end of a line,
+/// - a status response without text, e.g. `A1 OK\r\n`,
+/// - a bare `\n` as the line ending.
+///
+/// Returns `None` if there are no such deviations.
+pub(crate) fn rewrite_lenient(buf: &[u8]) -> Option<Rewrite> {
+    let mut scanner = Scanner { buf, pos: 0 };
+    let (consumed, data) = match scanner.normalize() {
+        Ok(Some(Rewrite::Done { consumed, data, .. })) => (consumed, data),
+        Ok(rewrite) => return rewrite,
+        Err(Incomplete) => return Some(Rewrite::Incomplete),
+    };
+    match rewrite(&data) {
+        Some(Rewrite::Done { data, items, .. }) => Some(Rewrite::Done {
+            consumed,
+            data,
+            items,
+        }),
+        _ => Some(Rewrite::Done {
+            consumed,
+            data,
+            items: Vec::new(),
+        }),
+    }
+}
+
+/// Rewrites any quoted strings with non-ASCII contents in the response at the start of `buf` as
+/// literals. Returns `None` if there are none.
+fn rewrite_utf8(buf: &[u8]) -> Option<Rewrite> {
+    let mut scanner = Scanner { buf, pos: 0 };
+    match scanner.rewrite_utf8() {
+        Ok(rewrite) => rewrite,
+        Err(Incomplete) => Some(Rewrite::Incomplete),
+    }
+}
+
+/// Rewrites an untagged `FETCH` response at the start of `buf` so that it can be parsed by
+/// `imap_proto`. Returns `None` if `buf` does not start with a `FETCH` response, or if the
+/// response has no unknown data items (in which case it failed to parse for another reason).
+fn rewrite_fetch(buf: &[u8]) -> Option<Rewrite> {
+    let mut scanner = Scanner { buf, pos: 0 };
+    match scanner.rewrite() {
+        Ok(rewrite) => rewrite,
+        Err(Incomplete) => Some(Rewrite::Incomplete),
+    }
+}
+
+/// Rewrites a `LIST` or `LSUB` response at the start of `buf` that ends with extended data items
+/// (RFC 5258), which are split off as items. Returns `None` if `buf` does not start with such a
+/// response.
+fn rewrite_list(buf: &[u8]) -> Option<Rewrite> {
+    let mut scanner = Scanner { buf, pos: 0 };
+    match scanner.rewrite_list() {
+        Ok(rewrite) => rewrite,
+        Err(Incomplete) => Some(Rewrite::Incomplete),
+    }
+}
+
+/// Rewrites an `ESEARCH` response (RFC 4731) at the start of `buf` as an empty `SEARCH`
+/// response, with the search correlator as a `TAG` item, a `UID` item without a value if the
+/// results are UIDs, and the return data as further items. Returns `None` if `buf` does not start
+/// with an `ESEARCH` response.
+fn rewrite_esearch(buf: &[u8]) -> Option<Rewrite> {
+    let mut scanner = Scanner { buf, pos: 0 };
+    match scanner.rewrite_esearch() {
+        Ok(rewrite) => rewrite,
+        Err(Incomplete) => Some(Rewrite::Incomplete),
+    }
+}
+
+/// Rewrites a `CAPABILITY` response at the start of `buf` that lacks `IMAP4rev1`, which
+/// `imap_proto` requires, by inserting it. The inserted capability is also returned as an item.
+/// Returns `None` if `buf` does not start with such a response.
+fn rewrite_capability(buf: &[u8]) -> Option<Rewrite> {
+    let mut scanner = Scanner { buf, pos: 0 };
+    match scanner.rewrite_capability() {
+        Ok(rewrite) => rewrite,
+        Err(Incomplete) => Some(Rewrite::Incomplete),
+    }
+}
+
+/// Rewrites an untagged response at the start of `buf` whose name is not in [`KNOWN_RESPONSES`]
+/// as `* OK`, with the name and the rest of the response as an item. Returns `None` if `buf`
+/// does not start with such a response.
+fn rewrite_unknown(buf: &[u8]) -> Option<Rewrite> {
+    let mut scanner = Scanner { buf, pos: 0 };
+    match scanner.rewrite_unknown() {
+        Ok(rewrite) => rewrite,
+        Err(Incomplete) => Some(Rewrite::Incomplete),
+    }
+}
+
+/// The names of the data items of the `FETCH` response at the start of `buf`, in order, e.g.
+/// `BODY[HEADER.FIELDS (FROM)]`, whose section `imap_proto` does not keep in full. Returns an
+/// empty list if `buf` does not start with a complete `FETCH` response.
+pub(crate) fn fetch_item_names(buf: &[u8]) -> Vec<&[u8]> {
+    let mut scanner = Scanner { buf, pos: 0 };
+    match scanner.item_names() {
+        Ok(Some(names)) => names,
+        _ => Vec::new(),
+    }
+}
+
+/// Appends an item to the rewritten response `data`.
+fn push_item(data: &mut Vec<u8>, items: &mut Vec<RawItem>, name: &[u8], value: Option<&[u8]>) {
+    let name_start = data.len();
+    data.extend_from_slice(name);
+    let name = name_start..data.len();
+    let value = value.map(|value| {
+        let value_start = data.len();
+        data.extend_from_slice(value);
+        value_start..data.len()
+    });
+    items.push(RawItem { name, value });
+}
+
+/// Ran out of input.
+struct Incomplete;
+
+struct Scanner<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+/// A data item value: `None` for `NIL`, otherwise the value with any string quoting or literal
+/// framing removed.
+type Value = Option<Vec<u8>>;
+
+impl<'a> Scanner<'a> {
+    fn rewrite(&mut self) -> Result<Option<Rewrite>, Incomplete> {
+        let prefix_start = self.pos;
+        if !self.tag(b"* ")? {
+            return Ok(None);
+        }
+        if self.take_while(|b| b.is_ascii_digit())?.is_empty() || !self.tag_no_case(b" FETCH (")? {
+            return Ok(None);
+        }
+        let prefix = prefix_start..self.pos;
+
+        let mut known: Vec<Range<usize>> = Vec::new();
+        let mut unknown: Vec<(Range<usize>, Value)> = Vec::new();
+        loop {
+            if self.tag(b")")? {
+                break;
+            }
+            if (!known.is_empty() || !unknown.is_empty()) && !self.tag(b" ")? {
+                return Ok(None);
+            }
+
+            let start = self.pos;
+            let name = match self.name()? {
+                Some(name) => name,
+                None => return Ok(None),
+            };
+            if !self.tag(b" ")? {
+                return Ok(None);
+            }
+            let value = match self.value()? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+
+            if is_known(&self.buf[name.clone()]) {
+                known.push(start..self.pos);
+            } else {
+                unknown.push((name, value));
+            }
+        }
+        if !self.tag(b"\r\n")? {
+            return Ok(None);
+        }
+        if unknown.is_empty() {
+            return Ok(None);
+        }
+
+        let mut data = self.buf[prefix].to_vec();
+        if known.is_empty() {
+            data.extend_from_slice(PLACEHOLDER_ITEM);
+        }
+        for (i, range) in known.into_iter().enumerate() {
+            if i > 0 {
+                data.push(b' ');
+            }
+            data.extend_from_slice(&self.buf[range]);
+        }
+        data.extend_from_slice(b")\r\n");
+
+        let mut items = Vec::with_capacity(unknown.len());
+        for (name, value) in unknown {
+            push_item(&mut data, &mut items, &self.buf[name], value.as_deref());
+        }
+
+        Ok(Some(Rewrite::Done {
+            consumed: self.pos,
+            data,
+            items,
+        }))
+    }
+
+    fn item_names(&mut self) -> Result<Option<Vec<&'a [u8]>>, Incomplete> {
+        if !self.tag(b"* ")? {
+            return Ok(None);
+        }
+        if self.take_while(|b| b.is_ascii_digit())?.is_empty() || !self.tag_no_case(b" FETCH (")? {
+            return Ok(None);
+        }
+
+        let mut names = Vec::new();
+        loop {
+            if self.tag(b")")? {
+                return Ok(Some(names));
+            }
+            if !names.is_empty() && !self.tag(b" ")? {
+                return Ok(None);
+            }
+            let name = match self.name()? {
+                Some(name) => name,
+                None => return Ok(None),
+            };
+            if !self.tag(b" ")? || self.value()?.is_none() {
+                return Ok(None);
+            }
+            names.push(&self.buf[name]);
+        }
+    }
+
+    fn rewrite_list(&mut self) -> Result<Option<Rewrite>, Incomplete> {
+        if !self.tag(b"* ")? || !(self.tag_no_case(b"LIST ")? || self.tag_no_case(b"LSUB ")?) {
+            return Ok(None);
+        }
+        if self.peek()? != b'(' || !self.list()? || !self.tag(b" ")? {
+            return Ok(None);
+        }
+        // The delimiter and the name.
+        if self.value()?.is_none() || !self.tag(b" ")? || self.value()?.is_none() {
+            return Ok(None);
+        }
+        let end = self.pos;
+        if !self.tag(b" (")? {
+            return Ok(None);
+        }
+
+        let mut data = self.buf[..end].to_vec();
+        data.extend_from_slice(b"\r\n");
+        let mut items = Vec::new();
+        loop {
+            if self.tag(b")")? {
+                break;
+            }
+            if !items.is_empty() && !self.tag(b" ")? {
+                return Ok(None);
+            }
+            let name = match self.value()? {
+                Some(Some(name)) => name,
+                _ => return Ok(None),
+            };
+            if !self.tag(b" ")? {
+                return Ok(None);
+            }
+            let start = self.pos;
+            if self.value()?.is_none() {
+                return Ok(None);
+            }
+            push_item(
+                &mut data,
+                &mut items,
+                &name,
+                Some(&self.buf[start..self.pos]),
+            );
+        }
+        if !self.tag(b"\r\n")? {
+            return Ok(None);
+        }
+
+        Ok(Some(Rewrite::Done {
+            consumed: self.pos,
+            data,
+            items,
+        }))
+    }
+
+    fn rewrite_esearch(&mut self) -> Result<Option<Rewrite>, Incomplete> {
+        if !self.tag(b"* ")? || !self.tag_no_case(b"ESEARCH")? {
+            return Ok(None);
+        }
+
+        let mut data = PLACEHOLDER_SEARCH.to_vec();
+        let mut items = Vec::new();
+        if self.tag(b" (")? {
+            if !self.tag_no_case(b"TAG ")? {
+                return Ok(None);
+            }
+            let tag = match self.value()? {
+                Some(Some(tag)) => tag,
+                _ => return Ok(None),
+            };
+            if !self.tag(b")")? {
+                return Ok(None);
+            }
+            push_item(&mut data, &mut items, b"TAG", Some(&tag));
+        }
+        loop {
+            if self.tag(b"\r\n")? {
+                break;
+            }
+            if !self.tag(b" ")? {
+                return Ok(None);
+            }
+            let name = self.take_while(|b| {
+                b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b':' | b'_')
+            })?;
+            if name.is_empty() {
+                return Ok(None);
+            }
+            let name = &self.buf[name];
+            if name.eq_ignore_ascii_case(b"UID") {
+                push_item(&mut data, &mut items, name, None);
+                continue;
+            }
+            if !self.tag(b" ")? {
+                return Ok(None);
+            }
+            match self.value()? {
+                Some(Some(value)) => push_item(&mut data, &mut items, name, Some(&value)),
+                _ => return Ok(None),
+            }
+        }
+
+        Ok(Some(Rewrite::Done {
+            consumed: self.pos,
+            data,
+            items,
+        }))
+    }
+
+    fn rewrite_capability(&mut self) -> Result<Option<Rewrite>, Incomplete> {
+        if !self.tag(b"* ")? || !self.tag_no_case(b"CAPABILITY")? {
+            return Ok(None);
+        }
+        let capabilities = self.take_while(|b| !matches!(b, b'\r' | b'\n'))?;
+        if !self.tag(b"\r\n")? {
+            return Ok(None);
+        }
+        let capabilities = &self.buf[capabilities];
+        if capabilities
+            .split(|&b| b == b' ')
+            .any(|c| c.eq_ignore_ascii_case(IMAP4REV1))
+        {
+            return Ok(None);
+        }
+
+        let mut data = b"* CAPABILITY ".to_vec();
+        let mut items = Vec::new();
+        push_item(&mut data, &mut items, IMAP4REV1, None);
+        data.extend_from_slice(capabilities);
+        data.extend_from_slice(b"\r\n");
+
+        Ok(Some(Rewrite::Done {
+            consumed: self.pos,
+            data,
+            items,
+        }))
+    }
+
+    fn rewrite_unknown(&mut self) -> Result<Option<Rewrite>, Incomplete> {
+        if !self.tag(b"* ")? {
+            return Ok(None);
+        }
+        let name =
+            self.take_while(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'))?;
+        let name = &self.buf[name];
+        if !matches!(name.first(), Some(b) if b.is_ascii_alphabetic())
+            || KNOWN_RESPONSES
+                .iter()
+                .any(|known| known.as_bytes().eq_ignore_ascii_case(name))
+        {
+            return Ok(None);
+        }
+
+        let value = if self.tag(b" ")? {
+            let start = self.pos;
+            loop {
+                match self.peek()? {
+                    b'\r' | b'\n' => break,
+                    b'"' => {
+                        if self.quoted()?.is_none() {
+                            return Ok(None);
+                        }
+                    }
+                    b'{' | b'~' => {
+                        if self.literal()?.is_none() {
+                            self.pos += 1;
+                        }
+                    }
+                    _ => self.pos += 1,
+                }
+            }
+            Some(&self.buf[start..self.pos])
+        } else {
+            None
+        };
+        if !self.tag(b"\r\n")? {
+            return Ok(None);
+        }
+
+        let mut data = PLACEHOLDER_UNKNOWN.to_vec();
+        let mut items = Vec::new();
+        push_item(&mut data, &mut items, name, value);
+        Ok(Some(Rewrite::Done {
+            consumed: self.pos,
+            data,
+            items,
+        }))
+    }
+
+    fn rewrite_utf8(&mut self) -> Result<Option<Rewrite>, Incomplete> {
+        let mut data = Vec::new();
+        let mut rewritten = false;
+        loop {
+            let start = self.pos;
+            match self.peek()? {
+                b'\r' if self.tag(b"\r\n")? => {
+                    data.extend_from_slice(b"\r\n");
+                    break;
+                }
+                b'"' => match self.quoted()? {
+                    Some(contents) if !contents.is_ascii() => {
+                        data.extend_from_slice(format!("{{{}}}\r\n", contents.len()).as_bytes());
+                        data.extend_from_slice(&contents);
+                        rewritten = true;
+                    }
+                    Some(_) => data.extend_from_slice(&self.buf[start..self.pos]),
+                    // An unbalanced quote in human-readable text.
+                    None => {
+                        self.pos = start + 1;
+                        data.push(b'"');
+                    }
+                },
+                b'{' => match self.literal()? {
+                    Some(_) => data.extend_from_slice(&self.buf[start..self.pos]),
+                    None => {
+                        self.pos = start + 1;
+                        data.push(b'{');
+                    }
+                },
+                b => {
+                    self.pos += 1;
+                    data.push(b);
+                }
+            }
+        }
+
+        if !rewritten {
+            return Ok(None);
+        }
+        Ok(Some(Rewrite::Done {
+            consumed: self.pos,
+            data,
+            items: Vec::new(),
+        }))
+    }
+
+    fn normalize(&mut self) -> Result<Option<Rewrite>, Incomplete> {
+        let mut data = Vec::new();
+        // The last byte copied outside of strings and literals.
+        let mut prev = None;
+        loop {
+            let start = self.pos;
+            match self.peek()? {
+                b'\r' | b'\n' if self.tag(b"\r\n")? || self.tag(b"\n")? => {
+                    if prev == Some(b' ') {
+                        data.pop();
+                    }
+                    if is_bare_status(&data) {
+                        data.push(b' ');
+                    }
+                    data.extend_from_slice(b"\r\n");
+                    break;
+                }
+                b' ' => {
+                    self.pos += 1;
+                    if !matches!(prev, Some(b' ') | Some(b'(')) {
+                        data.push(b' ');
+                        prev = Some(b' ');
+                    }
+                    continue;
+                }
+                b')' if prev == Some(b' ') => {
+                    data.pop();
+                }
+                b'"' => {
+                    if self.quoted()?.is_some() {
+                        data.extend_from_slice(&self.buf[start..self.pos]);
+                        prev = None;
+                        continue;
+                    }
+                    // An unbalanced quote in human-readable text.
+                    self.pos = start;
+                }
+                b'{' | b'~' => {
+                    let tilde = self.tag(b"~")?;
+                    if let Some(len) = self.loose_literal()? {
+                        if !matches!(prev, Some(b' ') | Some(b'(')) {
+                            data.push(b' ');
+                        }
+                        if tilde {
+                            data.push(b'~');
+                        }
+                        data.extend_from_slice(format!("{{{}}}\r\n", len).as_bytes());
+                        data.extend_from_slice(&self.buf[self.pos..self.pos + len]);
+                        self.pos += len;
+                        prev = None;
+                        continue;
+                    }
+                    self.pos = start;
+                }
+                _ => {}
+            }
+            let b = self.peek()?;
+            self.pos += 1;
+            data.push(b);
+            prev = Some(b);
+        }
+
+        if data[..] == self.buf[..self.pos] {
+            return Ok(None);
+        }
+        Ok(Some(Rewrite::Done {
+            consumed: self.pos,
+            data,
+            items: Vec::new(),
+        }))
+    }
+
+    fn peek(&self) -> Result<u8, Incomplete> {
+        self.buf.get(self.pos).copied().ok_or(Incomplete)
+    }
+
+    fn tag(&mut self, tag: &[u8]) -> Result<bool, Incomplete> {
+        self.tag_by(tag, |a, b| a == b)
+    }
+
+    fn tag_no_case(&mut self, tag: &[u8]) -> Result<bool, Incomplete> {
+        self.tag_by(tag, |a, b| a.eq_ignore_ascii_case(b))
+    }
+
+    fn tag_by(
+        &mut self,
+        tag: &[u8],
+        eq: impl Fn(&[u8], &[u8]) -> bool,
+    ) -> Result<bool, Incomplete> {
+        let rest = &self.buf[self.pos..];
+        let len = rest.len().min(tag.len());
+        if !eq(&rest[..len], &tag[..len]) {
+            return Ok(false);
+        }
+        if len < tag.len() {
+            return Err(Incomplete);
+        }
+        self.pos += len;
+        Ok(true)
+    }
+
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> Result<Range<usize>, Incomplete> {
+        let start = self.pos;
+        while f(self.peek()?) {
+            self.pos += 1;
+        }
+        Ok(start..self.pos)
+    }
+
+    /// A data item name, including any section (`[...]`) and partial (`<...>`) specifier.
+    fn name(&mut self) -> Result<Option<Range<usize>>, Incomplete> {
+        let start = self.pos;
+        let mut depth = 0usize;
+        loop {
+            match self.peek()? {
+                b'[' | b'<' => depth += 1,
+                b']' | b'>' if depth > 0 => depth -= 1,
+                // Header field names in a section may be quoted.
+                b'"' if depth > 0 => {
+                    if self.quoted()?.is_none() {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+                b' ' | b')' if depth == 0 => break,
+                b'\r' | b'\n' | b'{' | b'"' => return Ok(None),
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Ok(None);
+        }
+        Ok(Some(start..self.pos))
+    }
+
+    fn value(&mut self) -> Result<Option<Value>, Incomplete> {
+        match self.peek()? {
+            b'"' => Ok(self.quoted()?.map(Some)),
+            b'{' | b'~' => Ok(self.literal()?.map(|range| Some(self.buf[range].to_vec()))),
+            b'(' => {
+                let start = self.pos;
+                if !self.list()? {
+                    return Ok(None);
+                }
+                Ok(Some(Some(self.buf[start..self.pos].to_vec())))
+            }
+            _ => {
+                let atom = self.take_while(|b| !matches!(b, b' ' | b')' | b'\r' | b'\n'))?;
+                if atom.is_empty() {
+                    return Ok(None);
+                }
+                let atom = &self.buf[atom];
+                if atom.eq_ignore_ascii_case(b"NIL") {
+                    Ok(Some(None))
+                } else {
+                    Ok(Some(Some(atom.to_vec())))
+                }
+            }
+        }
+    }
+
+    /// A quoted string, returning its unescaped contents.
+    fn quoted(&mut self) -> Result<Option<Vec<u8>>, Incomplete> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            match self.peek()? {
+                b'"' => {
+                    self.pos += 1;
+                    return Ok(Some(out));
+                }
+                b'\\' => {
+                    self.pos += 1;
+                    out.push(self.peek()?);
+                }
+                b'\r' | b'\n' => return Ok(None),
+                b => out.push(b),
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// A literal (`{n}`) or, as used for `BINARY` (RFC 3516), a `literal8` (`~{n}`), returning
+    /// the range of its contents.
+    fn literal(&mut self) -> Result<Option<Range<usize>>, Incomplete> {
+        if self.peek()? == b'~' {
+            self.pos += 1;
+        }
+        if !self.tag(b"{")? {
+            return Ok(None);
+        }
+        let digits = self.take_while(|b| b.is_ascii_digit())?;
+        let len = match std::str::from_utf8(&self.buf[digits])
+            .ok()
+            .and_then(|digits| digits.parse::<usize>().ok())
+        {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        if !self.tag(b"}\r\n")? {
+            return Ok(None);
+        }
+        let start = self.pos;
+        if self.buf.len() - start < len {
+            return Err(Incomplete);
+        }
+        self.pos += len;
+        Ok(Some(start..self.pos))
+    }
+
+    /// A literal like [`Scanner::literal`], but allowing spaces in or after its length, e.g.
+    /// `{ 5 } \r\n`, and a bare `\n`. Returns its length, leaving the position at its contents.
+    fn loose_literal(&mut self) -> Result<Option<usize>, Incomplete> {
+        if !self.tag(b"{")? {
+            return Ok(None);
+        }
+        self.take_while(|b| b == b' ')?;
+        let digits = self.take_while(|b| b.is_ascii_digit())?;
+        let len = match std::str::from_utf8(&self.buf[digits])
+            .ok()
+            .and_then(|digits| digits.parse::<usize>().ok())
+        {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        self.take_while(|b| b == b' ')?;
+        if !self.tag(b"}")? {
+            return Ok(None);
+        }
+        self.take_while(|b| b == b' ')?;
+        if !self.tag(b"\r\n")? && !self.tag(b"\n")? {
+            return Ok(None);
+        }
+        if self.buf.len() - self.pos < len {
+            return Err(Incomplete);
+        }
+        Ok(Some(len))
+    }
+
+    /// A parenthesized list, which may contain nested lists, strings and literals.
+    fn list(&mut self) -> Result<bool, Incomplete> {
+        self.pos += 1;
+        loop {
+            match self.peek()? {
+                b')' => {
+                    self.pos += 1;
+                    return Ok(true);
+                }
+                b'(' => {
+                    if !self.list()? {
+                        return Ok(false);
+                    }
+                }
+                b'"' => {
+                    if self.quoted()?.is_none() {
+                        return Ok(false);
+                    }
+                }
+                b'{' | b'~' => {
+                    if self.literal()?.is_none() {
+                        return Ok(false);
+                    }
+                }
+                b'\r' | b'\n' => return Ok(false),
+                _ => self.pos += 1,
+            }
+        }
+    }
+}
+
+/// Whether `line` is a status response without text, e.g. `A1 OK`.
+fn is_bare_status(line: &[u8]) -> bool {
+    let mut words = line.split(|&b| b == b' ');
+    match (words.next(), words.next(), words.next()) {
+        (Some(tag), Some(status), None) => {
+            !tag.is_empty()
+                && STATUS_RESPONSES
+                    .iter()
+                    .any(|known| known.as_bytes().eq_ignore_ascii_case(status))
+        }
+        _ => false,
+    }
+}
+
+fn is_known(name: &[u8]) -> bool {
+    let base = name
+        .iter()
+        .position(|&b| b == b'[' || b == b'<')
+        .map_or(name, |end| &name[..end]);
+    KNOWN_ITEMS
+        .iter()
+        .any(|known| known.as_bytes().eq_ignore_ascii_case(base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(input: &[u8]) -> (usize, String, Vec<(String, Option<String>)>) {
+        match super::rewrite(input) {
+            Some(Rewrite::Done {
+                consumed,
+                data,
+                items,
+            }) => {
+                let items = items
+                    .into_iter()
+                    .map(|item| {
+                        (
+                            String::from_utf8(data[item.name].to_vec()).unwrap(),
+                            item.value
+                                .map(|value| String::from_utf8(data[value].to_vec()).unwrap()),
+                        )
+                    })
+                    .collect();
+                (consumed, String::from_utf8(data).unwrap(), items)
+            }
+            other => panic!("unexpected rewrite: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn item_names() {
+        let input = b"* 1 FETCH (UID 42 BODY[HEADER.FIELDS (FROM \"X-Y\")] {9}\r\nFrom: a\r\n \
+                      FLAGS (\\Seen))\r\n";
+        assert_eq!(
+            fetch_item_names(input),
+            vec![
+                &b"UID"[..],
+                &b"BODY[HEADER.FIELDS (FROM \"X-Y\")]"[..],
+                &b"FLAGS"[..]
+            ]
+        );
+        assert!(fetch_item_names(b"* 1 FETCH (UID 42").is_empty());
+        assert!(fetch_item_names(b"* 2 EXISTS\r\n").is_empty());
+    }
+
+    #[test]
+    fn split_unknown_items() {
+        let input = b"* 1 FETCH (UID 42 BINARY[1] ~{5}\r\nhello BINARY.SIZE[2] 12 \
+                      X-GM-LABELS (\"\\\\Inbox\" foo) FLAGS (\\Seen))\r\n* 2 EXISTS\r\n";
+        let (consumed, data, items) = rewrite(input);
+        assert_eq!(consumed, input.len() - b"* 2 EXISTS\r\n".len());
+        assert!(data.starts_with("* 1 FETCH (UID 42 FLAGS (\\Seen))\r\n"));
+        assert_eq!(
+            items,
+            vec![
+                ("BINARY[1]".to_string(), Some("hello".to_string())),
+                ("BINARY.SIZE[2]".to_string(), Some("12".to_string())),
+                (
+                    "X-GM-LABELS".to_string(),
+                    Some("(\"\\\\Inbox\" foo)".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_unknown_items() {
+        let (_, data, items) = rewrite(b"* 3 FETCH (X-FOO \"a \\\"b\\\"\" X-BAR NIL)\r\n");
+        assert!(data.starts_with("* 3 FETCH (RFC822 NIL)\r\n"));
+        assert_eq!(
+            items,
+            vec![
+                ("X-FOO".to_string(), Some("a \"b\"".to_string())),
+                ("X-BAR".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn incomplete() {
+        assert_eq!(
+            rewrite_fetch(b"* 1 FETCH (BINARY[1] ~{10}\r\nhello"),
+            Some(Rewrite::Incomplete)
+        );
+        assert_eq!(
+            rewrite_fetch(b"* 1 FETCH (BINARY[1] NIL"),
+            Some(Rewrite::Incomplete)
+        );
+    }
+
+    #[test]
+    fn utf8_quoted() {
+        let input = "* LIST () \"/\" \"Entwürfe\"\r\n* 2 EXISTS\r\n".as_bytes();
+        let (consumed, data, items) = rewrite(input);
+        assert_eq!(consumed, input.len() - b"* 2 EXISTS\r\n".len());
+        assert_eq!(data, "* LIST () \"/\" {9}\r\nEntwürfe\r\n");
+        assert!(items.is_empty());
+
+        let input = "* 1 FETCH (X-FOO 1 ENVELOPE (NIL \"Grüße \\\"x\\\"\" {3}\r\nabc))\r\n";
+        let (_, data, items) = rewrite(input.as_bytes());
+        assert_eq!(
+            data,
+            "* 1 FETCH (ENVELOPE (NIL {11}\r\nGrüße \"x\" {3}\r\nabc))\r\nX-FOO1"
+        );
+        assert_eq!(items, vec![("X-FOO".to_string(), Some("1".to_string()))]);
+
+        assert_eq!(super::rewrite("* OK \"Grüße\r\n".as_bytes()), None);
+        assert_eq!(
+            super::rewrite("* LIST () \"/\" \"Entwü".as_bytes()),
+            Some(Rewrite::Incomplete)
+        );
+    }
+
+    #[test]
+    fn list_extended() {
+        let input =
+            b"* LIST (\\Subscribed) \"/\" {3}\r\nFoo (\"CHILDINFO\" (\"SUBSCRIBED\"))\r\n* 2 EXISTS\r\n";
+        let (consumed, data, items) = rewrite(input);
+        assert_eq!(&input[consumed..], b"* 2 EXISTS\r\n");
+        assert!(data.starts_with("* LIST (\\Subscribed) \"/\" {3}\r\nFoo\r\n"));
+        assert_eq!(
+            items,
+            vec![("CHILDINFO".into(), Some("(\"SUBSCRIBED\")".into()))]
+        );
+
+        // Nothing to rewrite without extended data.
+        assert_eq!(super::rewrite(b"* LIST () \"/\" Foo\r\n"), None);
+    }
+
+    #[test]
+    fn esearch() {
+        let input = b"* ESEARCH (TAG \"A0002\") UID MIN 4 ALL 4:6,9 COUNT 4\r\n* 2 EXISTS\r\n";
+        let (consumed, data, items) = rewrite(input);
+        assert_eq!(consumed, input.len() - b"* 2 EXISTS\r\n".len());
+        assert!(data.starts_with("* SEARCH\r\n"));
+        assert_eq!(
+            items,
+            vec![
+                ("TAG".to_string(), Some("A0002".to_string())),
+                ("UID".to_string(), None),
+                ("MIN".to_string(), Some("4".to_string())),
+                ("ALL".to_string(), Some("4:6,9".to_string())),
+                ("COUNT".to_string(), Some("4".to_string())),
+            ]
+        );
+
+        let (_, _, items) = rewrite(b"* ESEARCH\r\n");
+        assert!(items.is_empty());
+        assert_eq!(
+            rewrite_esearch(b"* ESEARCH (TAG \"A1\") ALL 1:"),
+            Some(Rewrite::Incomplete)
+        );
+    }
+
+    #[test]
+    fn capability_imap4rev2() {
+        let (_, data, items) = rewrite(b"* CAPABILITY IMAP4rev2 AUTH=PLAIN\r\n");
+        assert_eq!(data, "* CAPABILITY IMAP4rev1 IMAP4rev2 AUTH=PLAIN\r\n");
+        assert_eq!(items, vec![("IMAP4rev1".to_string(), None)]);
+
+        assert_eq!(
+            rewrite_capability(b"* CAPABILITY IMAP4rev2 imap4rev1\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn unknown_response() {
+        let input = b"* XLIST (\\HasNoChildren \\Inbox) \"/\" {5}\r\nInbox\r\n* 2 EXISTS\r\n";
+        let (consumed, data, items) = rewrite(input);
+        assert_eq!(consumed, input.len() - b"* 2 EXISTS\r\n".len());
+        assert!(data.starts_with("* OK \r\n"));
+        assert_eq!(
+            items,
+            vec![(
+                "XLIST".to_string(),
+                Some("(\\HasNoChildren \\Inbox) \"/\" {5}\r\nInbox".to_string())
+            )]
+        );
+
+        let (_, _, items) = rewrite(b"* X-PING\r\n");
+        assert_eq!(items, vec![("X-PING".to_string(), None)]);
+        assert_eq!(
+            rewrite_unknown(b"* XLIST (\\Inbox) \"/\" {5}\r\nIn"),
+            Some(Rewrite::Incomplete)
+        );
+        assert_eq!(
+            rewrite_unknown(b"* LIST (\\Inbox) \"/\" \"\xc3\"\r\n"),
+            None
+        );
+        assert_eq!(rewrite_unknown(b"* 1 EXISTS\r\n"), None);
+    }
+
+    #[test]
+    fn lenient() {
+        let normalized = |input: &[u8]| match (Scanner { buf: input, pos: 0 }).normalize() {
+            Ok(Some(Rewrite::Done { consumed, data, .. })) => {
+                assert_eq!(consumed, input.len());
+                Some(String::from_utf8(data).unwrap())
+            }
+            _ => None,
+        };
+        assert_eq!(
+            normalized(b"* 1 FETCH (BODY[]{5}\r\nhe)(o UID 3)\r\n").unwrap(),
+            "* 1 FETCH (BODY[] {5}\r\nhe)(o UID 3)\r\n"
+        );
+        assert_eq!(
+            normalized(b"* 1 FETCH ( BODY[] { 5 } \n( x )  FLAGS (\\Seen) )  \n").unwrap(),
+            "* 1 FETCH (BODY[] {5}\r\n( x ) FLAGS (\\Seen))\r\n"
+        );
+        assert_eq!(
+            normalized(b"* 1 FETCH (BINARY[1]~{2}\r\nhi)\r\n").unwrap(),
+            "* 1 FETCH (BINARY[1] ~{2}\r\nhi)\r\n"
+        );
+        // Unknown items are split off after normalizing.
+        let input = b"* 1 FETCH (UID 3 BINARY[1]~{2}\r\nhi)\r\n";
+        match rewrite_lenient(input) {
+            Some(Rewrite::Done {
+                consumed,
+                data,
+                items,
+            }) => {
+                assert_eq!(consumed, input.len());
+                assert!(data.starts_with(b"* 1 FETCH (UID 3)\r\n"));
+                assert_eq!(&data[items[0].name.clone()], b"BINARY[1]");
+                assert_eq!(&data[items[0].value.clone().unwrap()], b"hi");
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(normalized(b"A1 ok\r\n").unwrap(), "A1 ok \r\n");
+        assert_eq!(normalized(b"* OK\n").unwrap(), "* OK \r\n");
+        assert_eq!(
+            normalized(b"* LIST ()  \"/\"  \"a  b\"\r\n").unwrap(),
+            "* LIST () \"/\" \"a  b\"\r\n"
+        );
+        // Nothing to fix.
+        assert_eq!(normalized(b"* OK \r\n"), None);
+        assert_eq!(normalized(b"* OK {json}\r\n"), None);
+        assert_eq!(normalized(b"* 1 FETCH (BODY[] {5}\r\n( x )\r\n"), None);
+        assert_eq!(
+            rewrite_lenient(b"* 1 FETCH (BODY[]{5}\r\nhe"),
+            Some(Rewrite::Incomplete)
+        );
+    }
+
+    #[test]
+    fn not_rewritten() {
+        assert_eq!(rewrite_fetch(b"* 1 EXISTS\r\n"), None);
+        assert_eq!(rewrite_fetch(b"* 1 FETCH (UID 1)\r\n"), None);
+        assert_eq!(rewrite_fetch(b"* 1 FETCH (X-FOO {x}\r\n)\r\n"), None);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::fmt;
 use std::pin::Pin;
 
@@ -7,9 +8,13 @@ use async_std::stream::Stream;
 use async_std::sync::Arc;
 use byte_pool::{Block, BytePool};
 use futures::task::{Context, Poll};
+use imap_proto::{RequestId, Response, ResponseCode};
 use nom::Needed;
 
-use crate::types::{Request, ResponseData};
+use crate::error::ParseError;
+use crate::fallback::{self, Rewrite};
+use crate::secret::{Secret, Wipe};
+use crate::types::{RawItem, Request, ResponseBuffer, ResponseData, WireTracer};
 
 lazy_static::lazy_static! {
     /// The global buffer pool we use for storing incoming data.
@@ -20,16 +25,41 @@ lazy_static::lazy_static! {
 /// as imap client messages.
 #[derive(Debug)]
 pub struct ImapStream<R: Read + Write> {
-    // TODO: write some buffering logic
     /// The underlying stream
     pub(crate) inner: R,
+    /// The command lines that were encoded but not completely written yet. Writing them from
+    /// here rather than from the future that encoded them means that a future dropped in the
+    /// middle of a line leaves the rest of it to the next write.
+    outgoing: Vec<u8>,
+    /// How many bytes of `outgoing` were written.
+    written: usize,
+    /// The tag of the last command sent, until its tagged completion is returned.
+    pub(crate) in_flight: Option<RequestId>,
     /// Number of bytes the next decode operation needs if known.
     decode_needs: Option<usize>,
     /// The buffer.
     buffer: Buffer,
+    /// Responses that were decoded from the buffer but not returned yet.
+    decoded: VecDeque<ResponseData>,
     /// Whether there is any more items to return from the stream.  This is set to true once
     /// all decodable data in the buffer is returned and the underlying stream is closed.
     closed: bool,
+    /// Called with the text of responses with an `ALERT` code.
+    pub(crate) alert_handler: Option<AlertHandler>,
+    /// Shown the command lines sent and the responses received.
+    pub(crate) wire_tracer: Option<WireTracer>,
+    /// Whether responses that still fail to parse are retried after fixing common deviations.
+    pub(crate) lenient: bool,
+}
+
+/// A function that is shown `[ALERT]`s, see
+/// [`Connection::set_alert_handler`](crate::Connection::set_alert_handler).
+pub(crate) struct AlertHandler(pub(crate) Box<dyn FnMut(&str) + Send + Sync>);
+
+impl fmt::Debug for AlertHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AlertHandler")
+    }
 }
 
 impl<R: Read + Write + Unpin> ImapStream<R> {
@@ -37,9 +67,16 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
     pub fn new(inner: R) -> Self {
         ImapStream {
             inner,
+            outgoing: Vec::new(),
+            written: 0,
+            in_flight: None,
             buffer: Buffer::new(),
+            decoded: VecDeque::new(),
             decode_needs: None,
             closed: false,
+            alert_handler: None,
+            wire_tracer: None,
+            lenient: false,
         }
     }
 
@@ -50,19 +87,36 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
                 "inner stream closed",
             ));
         }
-        log::trace!(
-            "encode: input: {:?}, {:?}",
-            msg.0,
-            std::str::from_utf8(&msg.1)
-        );
-
-        if let Some(tag) = msg.0 {
-            self.inner.write_all(tag.as_bytes()).await?;
-            self.inner.write(b" ").await?;
+        let Request(tag, data) = msg;
+        log::trace!("encode: {}", describe_request(tag.as_ref(), &data));
+        let data = Secret(data);
+        if let Some(tracer) = &mut self.wire_tracer {
+            tracer.sent(tag.as_ref().map(|tag| tag.0.as_str()), &data);
         }
-        self.inner.write_all(&msg.1).await?;
-        self.inner.write_all(b"\r\n").await?;
+        // A line that a dropped future did not finish goes first.
+        self.write_outgoing().await?;
+        if let Some(tag) = tag {
+            self.outgoing.extend_from_slice(tag.as_bytes());
+            self.outgoing.push(b' ');
+            self.in_flight = Some(tag);
+        }
+        self.outgoing.extend_from_slice(&data);
+        self.outgoing.extend_from_slice(b"\r\n");
+        self.write_outgoing().await
+    }
 
+    /// Writes the rest of the encoded command lines.
+    async fn write_outgoing(&mut self) -> Result<(), io::Error> {
+        while self.written < self.outgoing.len() {
+            let num_bytes = self.inner.write(&self.outgoing[self.written..]).await?;
+            if num_bytes == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.written += num_bytes;
+        }
+        self.outgoing.wipe();
+        self.outgoing.clear();
+        self.written = 0;
         Ok(())
     }
 
@@ -70,8 +124,9 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
         self.inner
     }
 
-    /// Flushes the underlying stream.
+    /// Writes the rest of the encoded command lines and flushes the underlying stream.
     pub async fn flush(&mut self) -> Result<(), io::Error> {
+        self.write_outgoing().await?;
         self.inner.flush().await
     }
 
@@ -97,59 +152,192 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
 
 impl<R: Read + Write + Unpin> ImapStream<R> {
     fn maybe_decode(&mut self) -> io::Result<Option<ResponseData>> {
-        if self.buffer.used() > self.decode_needs.unwrap_or(0) {
-            self.decode()
-        } else {
-            Ok(None)
+        if self.decoded.is_empty() && self.buffer.used() > self.decode_needs.unwrap_or(0) {
+            self.decode()?;
         }
+        Ok(self.decoded.pop_front())
     }
 
-    fn decode(&mut self) -> io::Result<Option<ResponseData>> {
-        let block: Block<'static> = self.buffer.take_block();
+    /// Decodes all complete responses in the buffer, so that a read that received many of them
+    /// is only followed by another read once they were all returned.
+    ///
+    /// The decoded responses share the block of the buffer, and only the incomplete rest is
+    /// copied to a new block.
+    fn decode(&mut self) -> io::Result<()> {
+        let used = self.buffer.used();
+        let block = Arc::new(self.buffer.take_block());
         // Be aware, now self.buffer is invalid until block is returned or reset!
 
-        let res = ResponseData::try_new_or_recover(block, |buf| {
-            let buf = &buf[..self.buffer.used()];
+        let mut start = 0;
+        let mut error = None;
+        while start < used {
+            let buf = &block[start..used];
             log::trace!("decode: input: {:?}", std::str::from_utf8(buf));
-            match imap_proto::parser::parse_response(buf) {
-                Ok((remaining, response)) => {
+            let consumed = match imap_proto::parser::parse_response(buf) {
+                Ok((remaining, _)) => {
                     // TODO: figure out if we can use a minimum required size for a response.
                     self.decode_needs = None;
-                    self.buffer.reset_with_data(remaining);
-                    Ok(response)
+                    buf.len() - remaining.len()
                 }
                 Err(nom::Err::Incomplete(Needed::Size(min))) => {
                     log::trace!("decode: incomplete data, need minimum {} bytes", min);
                     self.decode_needs = Some(usize::from(min));
-                    Err(None)
+                    break;
                 }
                 Err(nom::Err::Incomplete(_)) => {
                     log::trace!("decode: incomplete data, need unknown number of bytes");
                     self.decode_needs = None;
-                    Err(None)
+                    break;
                 }
-                Err(other) => {
+                Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => {
                     self.decode_needs = None;
-                    Err(Some(io::Error::new(
+                    let position = buf.len() - err.input.len();
+                    error = Some(io::Error::new(
                         io::ErrorKind::Other,
-                        format!("{:?} during parsing of {:?}", other, buf),
-                    )))
+                        ParseError::malformed(buf, position),
+                    ));
+                    break;
                 }
+            };
+            // The response is parsed again from a buffer of its own bytes, as it borrows from it.
+            let raw = Box::new(ResponseBuffer::new(block.clone(), start..start + consumed));
+            let response = ResponseData::try_new(raw, Vec::new(), |buf| {
+                imap_proto::parser::parse_response(buf)
+                    .map(|(_remaining, response)| response)
+                    .map_err(|_| ())
+            })
+            .expect("a response that was parsed before parses again");
+            if let Some(tracer) = &mut self.wire_tracer {
+                tracer.received(&block[start..start + consumed]);
             }
-        });
-        match res {
-            Ok(response) => Ok(Some(response)),
-            Err((err, heads)) => {
-                self.buffer.return_block(heads.raw);
-                match err {
-                    Some(err) => Err(err),
-                    None => Ok(None),
+            self.decoded.push_back(response);
+            start += consumed;
+        }
+
+        match Arc::try_unwrap(block) {
+            // Nothing was decoded.
+            Ok(block) => self.buffer.return_block(block),
+            Err(block) => self.buffer.reset_with_data(&block[start..used]),
+        }
+        match error {
+            // A response that fails to parse after others were decoded is retried once they
+            // were returned.
+            Some(_) if !self.decoded.is_empty() => Ok(()),
+            Some(err) => {
+                if let Some(response) = self.decode_fallback(err)? {
+                    self.decoded.push_back(response);
                 }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Notes whether `response` completes the command in flight, and shows alerts, before it is
+    /// returned from the stream.
+    fn returned(&mut self, response: ResponseData) -> ResponseData {
+        if let Response::Done { tag, .. } = response.parsed() {
+            if self.in_flight.as_ref() == Some(tag) {
+                self.in_flight = None;
+            }
+        }
+        self.alert(response)
+    }
+
+    /// Shows the text of `response` to the alert handler if it has an `ALERT` code.
+    fn alert(&mut self, response: ResponseData) -> ResponseData {
+        if let Some(AlertHandler(handler)) = &mut self.alert_handler {
+            match response.parsed() {
+                Response::Data {
+                    code: Some(ResponseCode::Alert),
+                    information,
+                    ..
+                }
+                | Response::Done {
+                    code: Some(ResponseCode::Alert),
+                    information,
+                    ..
+                } => handler(information.as_deref().unwrap_or_default().trim()),
+                _ => {}
+            }
+        }
+        response
+    }
+
+    /// Retries decoding a response that failed to parse after rewriting the parts `imap_proto`
+    /// does not understand, see [`fallback`], and with lenient parsing after fixing common
+    /// deviations from the grammar. Returns `err` if that does not help.
+    fn decode_fallback(&mut self, err: io::Error) -> io::Result<Option<ResponseData>> {
+        let block: Block<'static> = self.buffer.take_block();
+        let used = self.buffer.used();
+
+        let mut incomplete = false;
+        let mut decoded = match fallback::rewrite(&block[..used]) {
+            Some(Rewrite::Done {
+                consumed,
+                data,
+                items,
+            }) => parse_rewritten(&data, items).map(|response| (consumed, response)),
+            Some(Rewrite::Incomplete) => {
+                incomplete = true;
+                None
+            }
+            None => None,
+        };
+        if decoded.is_none() && self.lenient {
+            match fallback::rewrite_lenient(&block[..used]) {
+                Some(Rewrite::Done {
+                    consumed,
+                    data,
+                    items,
+                }) => {
+                    decoded = parse_rewritten(&data, items).map(|response| (consumed, response));
+                    if decoded.is_some() {
+                        log::warn!(
+                            "decode: normalized non-conforming response {:?}",
+                            String::from_utf8_lossy(&block[..consumed])
+                        );
+                    }
+                }
+                Some(Rewrite::Incomplete) => incomplete = true,
+                None => {}
+            }
+        }
+
+        match decoded {
+            Some((consumed, response)) => {
+                log::trace!("decode: rewrote response");
+                if let Some(tracer) = &mut self.wire_tracer {
+                    tracer.received(&block[..consumed]);
+                }
+                self.buffer.reset_with_data(&block[consumed..used]);
+                Ok(Some(response))
+            }
+            None if incomplete => {
+                log::trace!("decode: incomplete data, need unknown number of bytes");
+                self.buffer.return_block(block);
+                Ok(None)
+            }
+            None => {
+                self.buffer.return_block(block);
+                Err(err)
             }
         }
     }
 }
 
+/// Parses a response rewritten by [`fallback`].
+fn parse_rewritten(data: &[u8], items: Vec<RawItem>) -> Option<ResponseData> {
+    let mut raw = POOL.alloc(data.len());
+    raw.copy_from_slice(data);
+    ResponseData::try_new(Box::new(raw.into()), items, |buf| {
+        imap_proto::parser::parse_response(buf)
+            .map(|(_remaining, response)| response)
+            .map_err(|_| ())
+    })
+    .ok()
+}
+
 /// Abstraction around needed buffer management.
 struct Buffer {
     /// The buffer itself.
@@ -281,7 +469,7 @@ impl<R: Read + Write + Unpin> Stream for ImapStream<R> {
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = &mut *self;
         if let Some(response) = this.maybe_decode()? {
-            return Poll::Ready(Some(Ok(response)));
+            return Poll::Ready(Some(Ok(this.returned(response))));
         }
         if this.closed {
             return Poll::Ready(this.stream_eof_value());
@@ -301,18 +489,36 @@ impl<R: Read + Write + Unpin> Stream for ImapStream<R> {
             }
             this.buffer.extend_used(num_bytes_read);
             if let Some(response) = this.maybe_decode()? {
-                return Poll::Ready(Some(Ok(response)));
+                return Poll::Ready(Some(Ok(this.returned(response))));
             }
         }
     }
 }
 
+/// Describes the command line `data` for the log without its arguments, which may be
+/// credentials, e.g. `A0001 LOGIN (21 bytes)`. Lines without a tag, such as the responses of
+/// `AUTHENTICATE` and literals, are only described by their length.
+fn describe_request(tag: Option<&RequestId>, data: &[u8]) -> String {
+    match tag {
+        Some(tag) => {
+            let verb = data.split(|&b| b == b' ').next().unwrap_or_default();
+            let verb = String::from_utf8_lossy(verb);
+            format!("{} {} ({} bytes)", tag.0, verb, data.len())
+        }
+        None => format!("continuation ({} bytes)", data.len()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use std::io::Write;
 
+    use imap_proto::Response;
+
+    use crate::mock_stream::MockStream;
+
     #[test]
     fn test_buffer_empty() {
         let buf = Buffer::new();
@@ -439,4 +645,65 @@ mod tests {
             format!(r#"Buffer {{ used: 0, capacity: {} }}"#, Buffer::BLOCK_SIZE)
         );
     }
+
+    #[async_std::test]
+    async fn decode_many_per_read() {
+        let mut data = Vec::new();
+        let mut ends = Vec::new();
+        for i in 1..=200 {
+            data.extend_from_slice(format!("* {} FETCH (UID {})\r\n", i, i + 1000).as_bytes());
+            ends.push(data.len());
+        }
+        // The first read fills the buffer, which ends within a response.
+        assert!(data.len() > Buffer::BLOCK_SIZE && data.len() < 2 * Buffer::BLOCK_SIZE);
+        let mut stream = ImapStream::new(MockStream::new(data));
+
+        for (i, end) in (1..).zip(ends) {
+            let response = stream.next().await.unwrap().unwrap();
+            assert!(matches!(response.parsed(), Response::Fetch(n, _) if *n == i));
+            // The raw bytes are those of the response alone.
+            let raw = format!("* {} FETCH (UID {})\r\n", i, i + 1000);
+            assert_eq!(&response.borrow_raw()[..], raw.as_bytes());
+            let reads = if end <= Buffer::BLOCK_SIZE { 1 } else { 2 };
+            assert_eq!(stream.inner.reads, reads);
+        }
+    }
+
+    #[test]
+    fn describe_request_redacts() {
+        let tag = RequestId("A0001".to_string());
+        let login = b"LOGIN \"user\" \"secret\"";
+        assert_eq!(
+            describe_request(Some(&tag), login),
+            "A0001 LOGIN (21 bytes)"
+        );
+        assert_eq!(
+            describe_request(None, b"dXNlcgBzZWNyZXQ="),
+            "continuation (16 bytes)"
+        );
+    }
+
+    // Reading the spare capacity of the buffer back needs `unsafe`.
+    #[allow(unsafe_code)]
+    #[cfg(feature = "zeroize")]
+    #[async_std::test]
+    async fn encode_wipes_outgoing() {
+        let mut stream = ImapStream::new(MockStream::default());
+        let tag = RequestId("A0001".to_string());
+        let login = b"LOGIN \"user\" \"secret\"".to_vec();
+        stream.encode(Request(Some(tag), login)).await.unwrap();
+        stream
+            .encode(Request(None, b"c2VjcmV0".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(
+            stream.inner.written_buf,
+            b"A0001 LOGIN \"user\" \"secret\"\r\nc2VjcmV0\r\n".to_vec()
+        );
+        assert!(stream.outgoing.is_empty());
+        let spare = stream.outgoing.spare_capacity_mut();
+        assert!(spare.len() >= 31);
+        // SAFETY: wiping wrote zeroes to all of the spare capacity, so it is initialized.
+        assert!(spare.iter().all(|b| unsafe { b.assume_init() } == 0));
+    }
 }
@@ -4,8 +4,9 @@
 //! changes. It supports at least the latest three stable Rust releases (possibly even older ones;
 //! check the [CI results](https://travis-ci.com/jonhoo/rust-imap)).
 //!
-//! To connect, use the [`connect`] function. This gives you an unauthenticated [`Client`]. You can
-//! then use [`Client::login`] or [`Client::authenticate`] to perform username/password or
+//! To connect, use the [`connect`] function, [`secure_connect`] to connect with `STARTTLS`, or
+//! [`ClientBuilder`] to connect through a proxy. This gives you an unauthenticated [`Client`].
+//! You can then use [`Client::login`] or [`Client::authenticate`] to perform username/password or
 //! challenge/response authentication respectively. This in turn gives you an authenticated
 //! [`Session`], which lets you access the mailboxes at the server.
 //!
@@ -69,15 +70,35 @@ extern crate pin_utils;
 pub use imap_proto;
 
 mod authenticator;
+mod builder;
 mod client;
 pub mod error;
 pub mod extensions;
+mod fallback;
 mod imap_stream;
 mod parse;
+pub mod pool;
+pub mod reconnect;
+mod scram;
+mod secret;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod types;
+mod utf7;
 
-pub use crate::authenticator::Authenticator;
+pub use crate::authenticator::{AuthMechanism, Authenticator, Credentials};
+pub use crate::builder::{ClientBuilder, Proxy, TcpKeepalive};
 pub use crate::client::*;
+pub use crate::scram::{ChannelBinding, ScramSha256};
+
+/// Re-exported from `zeroize`, for passing credentials that are wiped from memory when dropped.
+#[cfg(feature = "zeroize")]
+pub use zeroize::Zeroizing;
+
+/// Re-exported from `mailparse`, the parsed messages returned with the `mail` feature, see
+/// [`Fetch::mail`](types::Fetch::mail).
+#[cfg(feature = "mail")]
+pub use mailparse::ParsedMail;
 
 #[cfg(test)]
 mod mock_stream;
@@ -1,9 +1,28 @@
 use std::cmp::min;
 use std::pin::Pin;
+use std::time::{Duration, Instant};
 
+use async_native_tls::{Certificate, TlsAcceptor, TlsConnector};
 use async_std::io::{Error, ErrorKind, Read, Result, Write};
 use futures::task::{Context, Poll};
 
+use crate::types::{TlsInfo, TlsPeerInfo};
+
+/// The self-signed certificate for `imap.example.org` and `localhost` the mock TLS servers
+/// present, generated with `openssl req -x509` and bundled with its key in `identity.p12`.
+pub const TLS_CERT: &[u8] = include_bytes!("../tests/tls/cert.pem");
+
+/// Accepts TLS connections with [`TLS_CERT`].
+pub async fn tls_acceptor() -> TlsAcceptor {
+    let identity: &[u8] = include_bytes!("../tests/tls/identity.p12");
+    TlsAcceptor::new(identity, "test").await.unwrap()
+}
+
+/// Connects with TLS, trusting [`TLS_CERT`].
+pub fn tls_connector() -> TlsConnector {
+    TlsConnector::new().add_root_certificate(Certificate::from_pem(TLS_CERT).unwrap())
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct MockStream {
     read_buf: Vec<u8>,
@@ -12,6 +31,12 @@ pub struct MockStream {
     err_on_read: bool,
     eof_on_read: bool,
     read_delay: usize,
+    tls_info: Option<TlsInfo>,
+    /// The number of reads that returned data.
+    pub reads: usize,
+    /// Where to stop returning data, and for how long.
+    pause: Option<(usize, Duration)>,
+    paused_until: Option<Instant>,
 }
 
 impl Default for MockStream {
@@ -23,6 +48,10 @@ impl Default for MockStream {
             err_on_read: false,
             eof_on_read: false,
             read_delay: 0,
+            tls_info: None,
+            reads: 0,
+            pause: None,
+            paused_until: None,
         }
     }
 }
@@ -51,12 +80,30 @@ impl MockStream {
         self.read_delay = 1;
         self
     }
+
+    /// Stops returning data at `pos` of the read buffer for `duration`, counted from the first
+    /// read that reaches it, as if the server was quiet.
+    pub fn with_pause(mut self, pos: usize, duration: Duration) -> MockStream {
+        self.pause = Some((pos, duration));
+        self
+    }
+
+    pub fn with_tls_info(mut self, tls_info: TlsInfo) -> MockStream {
+        self.tls_info = Some(tls_info);
+        self
+    }
+}
+
+impl TlsPeerInfo for MockStream {
+    fn tls_info(&self) -> Option<TlsInfo> {
+        self.tls_info.clone()
+    }
 }
 
 impl Read for MockStream {
     fn poll_read(
         mut self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<Result<usize>> {
         if self.eof_on_read {
@@ -65,10 +112,31 @@ impl Read for MockStream {
         if self.err_on_read {
             return Poll::Ready(Err(Error::new(ErrorKind::Other, "MockStream Error")));
         }
+        if let Some((pos, duration)) = self.pause {
+            if self.read_pos == pos {
+                let until = *self
+                    .paused_until
+                    .get_or_insert_with(|| Instant::now() + duration);
+                let now = Instant::now();
+                if now < until {
+                    let waker = cx.waker().clone();
+                    std::thread::spawn(move || {
+                        std::thread::sleep(until - now);
+                        waker.wake();
+                    });
+                    return Poll::Pending;
+                }
+            }
+        }
         if self.read_pos >= self.read_buf.len() {
             return Poll::Ready(Err(Error::new(ErrorKind::UnexpectedEof, "EOF")));
         }
         let mut write_len = min(buf.len(), self.read_buf.len() - self.read_pos);
+        if let Some((pos, _)) = self.pause {
+            if self.read_pos < pos {
+                write_len = min(write_len, pos - self.read_pos);
+            }
+        }
         if self.read_delay > 0 {
             self.read_delay -= 1;
             write_len = min(write_len, 1);
@@ -78,6 +146,7 @@ impl Read for MockStream {
             buf[x - self.read_pos] = self.read_buf[x];
         }
         self.read_pos += write_len;
+        self.reads += 1;
         Poll::Ready(Ok(write_len))
     }
 }
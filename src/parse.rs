@@ -1,19 +1,19 @@
 use std::collections::HashSet;
 
-use async_std::channel;
 use async_std::io;
 use async_std::prelude::*;
 use async_std::stream::Stream;
 use imap_proto::{self, MailboxDatum, RequestId, Response};
 
-use crate::error::{Error, Result};
+use crate::error::{Error, ParseError, ResponseCode, Result, ServerResponse};
 use crate::types::ResponseData;
 use crate::types::*;
 
 pub(crate) fn parse_names<T: Stream<Item = io::Result<ResponseData>> + Unpin + Send>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
+    decode_utf7: bool,
 ) -> impl Stream<Item = Result<Name>> + '_ + Send + Unpin {
     use futures::{FutureExt, StreamExt};
 
@@ -25,13 +25,10 @@ pub(crate) fn parse_names<T: Stream<Item = io::Result<ResponseData>> + Unpin + S
                 match resp {
                     Ok(resp) => match resp.parsed() {
                         Response::MailboxData(MailboxDatum::List { .. }) => {
-                            let name = Name::from_mailbox_data(resp);
+                            let name = Name::from_mailbox_data(resp, decode_utf7);
                             Some(Ok(name))
                         }
-                        _ => {
-                            handle_unilateral(resp, unsolicited).await;
-                            None
-                        }
+                        _ => handle_unilateral(resp, unsolicited).await.err().map(Err),
                     },
                     Err(err) => Some(Err(err.into())),
                 }
@@ -58,36 +55,59 @@ pub(crate) fn filter_sync(res: &io::Result<ResponseData>, command_tag: &RequestI
 
 pub(crate) fn parse_fetches<T: Stream<Item = io::Result<ResponseData>> + Unpin + Send>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin {
-    use futures::{FutureExt, StreamExt};
-
-    StreamExt::filter_map(
-        StreamExt::take_while(stream, move |res| filter(res, &command_tag)),
-        move |resp| {
-            let unsolicited = unsolicited.clone();
-
-            async move {
-                match resp {
-                    Ok(resp) => match resp.parsed() {
-                        Response::Fetch(..) => Some(Ok(Fetch::new(resp))),
-                        _ => {
-                            handle_unilateral(resp, unsolicited).await;
-                            None
+    // Unlike `take_while` this yields errors from the underlying stream, as well as a tagged
+    // `NO` or `BAD`, to the caller, after which the stream ends.
+    let fetches = futures::stream::unfold(
+        Some((stream, unsolicited, command_tag)),
+        |state| async move {
+            let (stream, unsolicited, command_tag) = state?;
+            loop {
+                let resp = match stream.next().await {
+                    Some(Ok(resp)) => resp,
+                    Some(Err(err)) => return Some((Err(err.into()), None)),
+                    None => return None,
+                };
+                match resp.parsed() {
+                    Response::Fetch(..) => {
+                        let fetch = Fetch::new(resp);
+                        unsolicited.fetched(&fetch);
+                        return Some((Ok(fetch), Some((stream, unsolicited, command_tag))));
+                    }
+                    Response::Done {
+                        tag,
+                        status,
+                        code,
+                        information,
+                    } if tag == &command_tag => {
+                        return match check_status(
+                            Some(tag),
+                            status,
+                            code.as_ref(),
+                            information.as_deref(),
+                        ) {
+                            Ok(()) => None,
+                            Err(err) => Some((Err(err), None)),
+                        };
+                    }
+                    _ => {
+                        if let Err(err) = handle_unilateral(resp, unsolicited.clone()).await {
+                            return Some((Err(err), None));
                         }
-                    },
-                    Err(err) => Some(Err(err.into())),
+                    }
                 }
             }
-            .boxed()
         },
-    )
+    );
+
+    futures::StreamExt::boxed(fetches)
 }
 
 pub(crate) fn parse_expunge<T: Stream<Item = io::Result<ResponseData>> + Unpin + Send>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> impl Stream<Item = Result<u32>> + '_ + Send {
     use futures::StreamExt;
@@ -100,11 +120,11 @@ pub(crate) fn parse_expunge<T: Stream<Item = io::Result<ResponseData>> + Unpin +
             async move {
                 match resp {
                     Ok(resp) => match resp.parsed() {
-                        Response::Expunge(id) => Some(Ok(*id)),
-                        _ => {
-                            handle_unilateral(resp, unsolicited).await;
-                            None
+                        Response::Expunge(id) => {
+                            unsolicited.expunged(*id);
+                            Some(Ok(*id))
                         }
+                        _ => handle_unilateral(resp, unsolicited).await.err().map(Err),
                     },
                     Err(err) => Some(Err(err.into())),
                 }
@@ -115,7 +135,7 @@ pub(crate) fn parse_expunge<T: Stream<Item = io::Result<ResponseData>> + Unpin +
 
 pub(crate) async fn parse_capabilities<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<Capabilities> {
     let mut caps: HashSet<Capability> = HashSet::new();
@@ -128,12 +148,20 @@ pub(crate) async fn parse_capabilities<T: Stream<Item = io::Result<ResponseData>
         let resp = resp?;
         match resp.parsed() {
             Response::Capabilities(cs) => {
+                // The fallback parser inserts `IMAP4rev1` for servers that only implement
+                // IMAP4rev2, and marks it with a raw item.
+                let inserted = resp
+                    .raw_items()
+                    .any(|(name, _)| name.eq_ignore_ascii_case(b"IMAP4rev1"));
                 for c in cs {
+                    if inserted && matches!(c, imap_proto::Capability::Imap4rev1) {
+                        continue;
+                    }
                     caps.insert(Capability::from(c)); // TODO: avoid clone
                 }
             }
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -143,7 +171,7 @@ pub(crate) async fn parse_capabilities<T: Stream<Item = io::Result<ResponseData>
 
 pub(crate) async fn parse_noop<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<()> {
     while let Some(resp) = stream
@@ -152,7 +180,7 @@ pub(crate) async fn parse_noop<T: Stream<Item = io::Result<ResponseData>> + Unpi
         .await
     {
         let resp = resp?;
-        handle_unilateral(resp, unsolicited.clone()).await;
+        handle_unilateral(resp, unsolicited.clone()).await?;
     }
 
     Ok(())
@@ -160,7 +188,7 @@ pub(crate) async fn parse_noop<T: Stream<Item = io::Result<ResponseData>> + Unpi
 
 pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<Mailbox> {
     let mut mailbox = Mailbox::default();
@@ -175,33 +203,9 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
                 information,
                 ..
             } if tag == &command_tag => {
-                use imap_proto::Status;
-                match status {
-                    Status::Ok => {
-                        break;
-                    }
-                    Status::Bad => {
-                        return Err(Error::Bad(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
-                    Status::No => {
-                        return Err(Error::No(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
-                    _ => {
-                        return Err(Error::Io(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
-                                "status: {:?}, code: {:?}, information: {:?}",
-                                status, code, information
-                            ),
-                        )));
-                    }
-                }
+                check_status(Some(tag), status, code.as_ref(), information.as_deref())?;
+                mailbox.read_only = matches!(code, Some(imap_proto::ResponseCode::ReadOnly));
+                break;
             }
             Response::Data {
                 status,
@@ -223,39 +227,31 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
                             Some(ResponseCode::Unseen(n)) => {
                                 mailbox.unseen = Some(*n);
                             }
+                            Some(ResponseCode::HighestModSeq(modseq)) => {
+                                mailbox.highest_modseq = Some(*modseq);
+                            }
                             Some(ResponseCode::PermanentFlags(flags)) => {
                                 mailbox
                                     .permanent_flags
                                     .extend(flags.iter().map(|s| (*s).to_string()).map(Flag::from));
                             }
+                            None => {
+                                if let Some(id) = information.as_deref().and_then(parse_mailbox_id)
+                                {
+                                    mailbox.mailbox_id = Some(id);
+                                }
+                            }
                             _ => {}
                         }
                     }
-                    Status::Bad => {
-                        return Err(Error::Bad(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
-                    Status::No => {
-                        return Err(Error::No(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
                     _ => {
-                        return Err(Error::Io(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
-                                "status: {:?}, code: {:?}, information: {:?}",
-                                status, code, information
-                            ),
-                        )));
+                        check_bye(&resp)?;
+                        check_status(None, status, code.as_ref(), information.as_deref())?;
                     }
                 }
             }
             Response::MailboxData(m) => match m {
-                MailboxDatum::Status { .. } => handle_unilateral(resp, unsolicited.clone()).await,
+                MailboxDatum::Status { .. } => handle_unilateral(resp, unsolicited.clone()).await?,
                 MailboxDatum::Exists(e) => {
                     mailbox.exists = *e;
                 }
@@ -275,7 +271,7 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
                 _ => {}
             },
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -283,27 +279,86 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
     Ok(mailbox)
 }
 
+/// Reads the `STATUS` response for `mailbox_name`, as sent to the server, into a snapshot.
+pub(crate) async fn parse_sync_snapshot<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+    stream: &mut T,
+    unsolicited: UnsolicitedSender,
+    command_tag: RequestId,
+    mailbox_name: &str,
+) -> Result<SyncSnapshot> {
+    let mut snapshot = None;
+
+    while let Some(resp) = stream.next().await {
+        let resp = resp?;
+        match resp.parsed() {
+            Response::MailboxData(MailboxDatum::Status { mailbox, status })
+                if is_same_mailbox(mailbox, mailbox_name) =>
+            {
+                let snapshot = snapshot.get_or_insert_with(SyncSnapshot::default);
+                for attribute in status {
+                    match attribute {
+                        StatusAttribute::Messages(n) => snapshot.messages = *n,
+                        StatusAttribute::UidNext(uid) => snapshot.uid_next = Some(*uid),
+                        StatusAttribute::UidValidity(v) => snapshot.uid_validity = Some(*v),
+                        StatusAttribute::HighestModSeq(m) => snapshot.highest_modseq = Some(*m),
+                        _ => {}
+                    }
+                }
+            }
+            Response::Done {
+                tag,
+                status,
+                code,
+                information,
+            } if tag == &command_tag => {
+                check_status(Some(tag), status, code.as_ref(), information.as_deref())?;
+                break;
+            }
+            _ => {
+                handle_unilateral(resp, unsolicited.clone()).await?;
+            }
+        }
+    }
+
+    snapshot.ok_or_else(|| Error::Parse(ParseError::ExpectedResponseNotFound("STATUS".into())))
+}
+
+/// Whether the mailbox name in a response refers to `name`. `INBOX` is case-insensitive.
+fn is_same_mailbox(mailbox: &str, name: &str) -> bool {
+    mailbox == name || mailbox.eq_ignore_ascii_case("INBOX") && name.eq_ignore_ascii_case("INBOX")
+}
+
 pub(crate) async fn parse_ids<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<HashSet<u32>> {
     let mut ids: HashSet<u32> = HashSet::new();
 
-    while let Some(resp) = stream
-        .take_while(|res| filter_sync(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = stream.next().await {
         let resp = resp?;
         match resp.parsed() {
             Response::MailboxData(MailboxDatum::Search(cs)) => {
-                for c in cs {
-                    ids.insert(*c);
+                // IMAP4rev2 servers reply with `ESEARCH` instead.
+                match ESearch::from_response(&resp) {
+                    Some(esearch) if is_for_command(&esearch, &command_tag) => {
+                        ids.extend(esearch.ids());
+                    }
+                    Some(_) => {}
+                    None => ids.extend(cs),
                 }
             }
+            Response::Done {
+                tag,
+                status,
+                code,
+                information,
+            } if tag == &command_tag => {
+                check_status(Some(tag), status, code.as_ref(), information.as_deref())?;
+                break;
+            }
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -311,51 +366,316 @@ pub(crate) async fn parse_ids<T: Stream<Item = io::Result<ResponseData>> + Unpin
     Ok(ids)
 }
 
-// check if this is simply a unilateral server response
-// (see Section 7 of RFC 3501):
-pub(crate) async fn handle_unilateral(
-    res: ResponseData,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
-) {
-    // ignore these if they are not being consumed
-    if unsolicited.is_full() {
-        return;
+pub(crate) async fn parse_esearch<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+    stream: &mut T,
+    unsolicited: UnsolicitedSender,
+    command_tag: RequestId,
+) -> Result<ESearch> {
+    let mut result = ESearch::default();
+
+    while let Some(resp) = stream.next().await {
+        let resp = resp?;
+        match resp.parsed() {
+            Response::MailboxData(MailboxDatum::Search(cs)) => {
+                match ESearch::from_response(&resp) {
+                    Some(esearch) if is_for_command(&esearch, &command_tag) => result = esearch,
+                    Some(_) => {}
+                    // Servers may ignore `RETURN` and send the plain form.
+                    None => result
+                        .all
+                        .get_or_insert_with(Default::default)
+                        .extend(cs.iter().copied()),
+                }
+            }
+            Response::Done {
+                tag,
+                status,
+                code,
+                information,
+            } if tag == &command_tag => {
+                check_status(Some(tag), status, code.as_ref(), information.as_deref())?;
+                break;
+            }
+            _ => {
+                handle_unilateral(resp, unsolicited.clone()).await?;
+            }
+        }
     }
 
-    match res.parsed() {
-        Response::MailboxData(MailboxDatum::Status { mailbox, status }) => {
-            unsolicited
-                .send(UnsolicitedResponse::Status {
-                    mailbox: (mailbox.as_ref()).into(),
-                    attributes: status.to_vec(),
-                })
-                .await
-                .expect("Channel closed unexpectedly");
+    Ok(result)
+}
+
+pub(crate) async fn parse_sort<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+    stream: &mut T,
+    unsolicited: UnsolicitedSender,
+    command_tag: RequestId,
+) -> Result<Vec<u32>> {
+    let mut ids = Vec::new();
+
+    while let Some(resp) = stream.next().await {
+        let resp = resp?;
+        match resp.parsed() {
+            Response::MailboxData(MailboxDatum::Sort(seqs)) => ids.extend(seqs),
+            Response::Done {
+                tag,
+                status,
+                code,
+                information,
+            } if tag == &command_tag => {
+                check_status(Some(tag), status, code.as_ref(), information.as_deref())?;
+                break;
+            }
+            _ => {
+                handle_unilateral(resp, unsolicited.clone()).await?;
+            }
         }
-        Response::MailboxData(MailboxDatum::Recent(n)) => {
-            unsolicited
-                .send(UnsolicitedResponse::Recent(*n))
-                .await
-                .expect("Channel closed unexpectedly");
+    }
+
+    Ok(ids)
+}
+
+pub(crate) async fn parse_thread<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+    stream: &mut T,
+    unsolicited: UnsolicitedSender,
+    command_tag: RequestId,
+) -> Result<Vec<Thread>> {
+    let mut threads = Vec::new();
+
+    while let Some(resp) = stream.next().await {
+        let resp = resp?;
+        // imap-proto does not know `THREAD`, it arrives as an unknown response.
+        let thread_data = resp
+            .raw_items()
+            .find(|(name, _)| name.eq_ignore_ascii_case(b"THREAD"))
+            .map(|(_, value)| value.unwrap_or_default());
+        if let Some(data) = thread_data {
+            let parsed = Thread::parse_list(data)
+                .ok_or_else(|| Error::Parse(ParseError::Invalid(data.to_vec())))?;
+            threads.extend(parsed);
+            continue;
         }
-        Response::MailboxData(MailboxDatum::Exists(n)) => {
-            unsolicited
-                .send(UnsolicitedResponse::Exists(*n))
-                .await
-                .expect("Channel closed unexpectedly");
+        match resp.parsed() {
+            Response::Done {
+                tag,
+                status,
+                code,
+                information,
+            } if tag == &command_tag => {
+                check_status(Some(tag), status, code.as_ref(), information.as_deref())?;
+                break;
+            }
+            _ => {
+                handle_unilateral(resp, unsolicited.clone()).await?;
+            }
         }
-        Response::Expunge(n) => {
-            unsolicited
-                .send(UnsolicitedResponse::Expunge(*n))
-                .await
-                .expect("Channel closed unexpectedly");
+    }
+
+    Ok(threads)
+}
+
+/// Whether `esearch` holds the results of the command `command_tag`. Results without a tag
+/// are assumed to be.
+fn is_for_command(esearch: &ESearch, command_tag: &RequestId) -> bool {
+    match &esearch.tag {
+        Some(tag) => tag == &command_tag.0,
+        None => true,
+    }
+}
+
+/// Whether the capabilities in a server greeting show that the server only implements
+/// IMAP4rev2.
+pub(crate) fn is_imap4rev2_greeting(greeting: &Response<'_>) -> bool {
+    let text = match greeting {
+        Response::Data {
+            code: None,
+            information: Some(text),
+            ..
+        } => text,
+        _ => return false,
+    };
+    let capabilities = match capability_code_text(text) {
+        Some(capabilities) => capabilities,
+        None => return false,
+    };
+
+    let mut rev1 = false;
+    let mut rev2 = false;
+    for capability in capabilities.split(' ') {
+        rev1 |= capability.eq_ignore_ascii_case("IMAP4rev1");
+        rev2 |= capability.eq_ignore_ascii_case("IMAP4rev2");
+    }
+    rev2 && !rev1
+}
+
+/// The capabilities in a `CAPABILITY` response code of the tagged completion `resp`, which
+/// servers may send after `LOGIN` or `AUTHENTICATE` ([RFC 3501 section
+/// 7.1](https://tools.ietf.org/html/rfc3501#section-7.1)). Returns `None` if there is none.
+pub(crate) fn parse_capability_code(resp: &ResponseData) -> Option<Capabilities> {
+    let caps = match resp.parsed() {
+        Response::Done {
+            code: Some(imap_proto::ResponseCode::Capabilities(cs)),
+            ..
+        } => cs.iter().map(Capability::from).collect(),
+        Response::Done {
+            code: None,
+            information: Some(text),
+            ..
+        } => capability_code_text(text)?
+            .split(' ')
+            .filter(|c| !c.is_empty())
+            .map(|c| {
+                let c = match c.get(..5) {
+                    Some(prefix) if prefix.eq_ignore_ascii_case("AUTH=") => {
+                        imap_proto::Capability::Auth(c[5..].into())
+                    }
+                    _ if c.eq_ignore_ascii_case("IMAP4rev1") => imap_proto::Capability::Imap4rev1,
+                    _ => imap_proto::Capability::Atom(c.into()),
+                };
+                Capability::from(&c)
+            })
+            .collect(),
+        _ => return None,
+    };
+
+    Some(Capabilities(caps))
+}
+
+/// The capabilities of an untagged `CAPABILITY` response, or of an untagged `OK` with a
+/// `CAPABILITY` response code, as servers may send them during `AUTHENTICATE`. Returns `None` for
+/// other responses.
+pub(crate) fn parse_untagged_capabilities(resp: &ResponseData) -> Option<Capabilities> {
+    let caps = match resp.parsed() {
+        Response::Capabilities(cs) => {
+            // See `parse_capabilities` for the `IMAP4rev1` the fallback parser may insert.
+            let inserted = resp
+                .raw_items()
+                .any(|(name, _)| name.eq_ignore_ascii_case(b"IMAP4rev1"));
+            cs.iter()
+                .filter(|c| !(inserted && matches!(c, imap_proto::Capability::Imap4rev1)))
+                .map(Capability::from)
+                .collect()
         }
-        _ => {
-            unsolicited
-                .send(UnsolicitedResponse::Other(res))
-                .await
-                .expect("Channel closed unexpectedly");
+        Response::Data {
+            status: imap_proto::Status::Ok,
+            code: Some(imap_proto::ResponseCode::Capabilities(cs)),
+            ..
+        } => cs.iter().map(Capability::from).collect(),
+        _ => return None,
+    };
+
+    Some(Capabilities(caps))
+}
+
+/// The capabilities in a `CAPABILITY` response code that `imap_proto` left at the start of the
+/// human-readable text, because it only accepts the code if it includes `IMAP4rev1`.
+fn capability_code_text(text: &str) -> Option<&str> {
+    let (capabilities, _) = text.strip_prefix("[CAPABILITY ")?.split_once(']')?;
+    Some(capabilities)
+}
+
+/// Returns [`Error::Bye`] if `res` is an untagged `BYE`, which the server sends before closing
+/// the connection, so that a running command does not wait for a completion that never arrives.
+pub(crate) fn check_bye(res: &ResponseData) -> Result<()> {
+    match res.parsed() {
+        Response::Data {
+            status: imap_proto::Status::Bye,
+            information,
+            ..
+        } => Err(Error::Bye(
+            information
+                .as_deref()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Fails on a tagged response. Commands run one at a time, and each takes its own completion from
+/// the stream before passing the rest on, so any tagged response left over is a stray one.
+pub(crate) fn check_stray_tag(res: &ResponseData) -> Result<()> {
+    match res.parsed() {
+        Response::Done { tag, .. } => Err(Error::Parse(ParseError::UnexpectedTag(tag.0.clone()))),
+        _ => Ok(()),
+    }
+}
+
+// check if this is simply a unilateral server response
+// (see Section 7 of RFC 3501):
+pub(crate) async fn handle_unilateral(
+    res: ResponseData,
+    unsolicited: UnsolicitedSender,
+) -> Result<()> {
+    check_bye(&res)?;
+    check_stray_tag(&res)?;
+
+    let response = match res.parsed() {
+        Response::MailboxData(MailboxDatum::Status { mailbox, status }) => {
+            UnsolicitedResponse::Status {
+                mailbox: (mailbox.as_ref()).into(),
+                attributes: status.to_vec(),
+            }
         }
+        Response::MailboxData(MailboxDatum::Recent(n)) => UnsolicitedResponse::Recent(*n),
+        Response::MailboxData(MailboxDatum::Exists(n)) => UnsolicitedResponse::Exists(*n),
+        Response::Expunge(n) => UnsolicitedResponse::Expunge(*n),
+        _ => UnsolicitedResponse::Other(res),
+    };
+    unsolicited.send(response);
+
+    Ok(())
+}
+
+/// Turns the status of a response to the command `tag` into a `Result`.
+pub(crate) fn check_status(
+    tag: Option<&RequestId>,
+    status: &imap_proto::Status,
+    code: Option<&imap_proto::ResponseCode<'_>>,
+    information: Option<&str>,
+) -> Result<()> {
+    use imap_proto::Status;
+
+    // `imap_proto` only knows the response codes of RFC 3501 and a few extensions, any other code
+    // is left at the start of the human-readable text.
+    let (code, information) = match (code, information) {
+        (Some(code), _) => (Some(ResponseCode::from_imap_proto(code)), information),
+        (None, Some(text)) => match ResponseCode::split_text(text) {
+            Some((code, text)) => (Some(code), Some(text)),
+            None => (None, information),
+        },
+        (None, None) => (None, None),
+    };
+    let response = |code| ServerResponse::new(tag.map(|tag| tag.0.as_str()), code, information);
+    match status {
+        Status::Ok => Ok(()),
+        Status::Bad => Err(Error::Bad(response(code))),
+        Status::No if code == Some(ResponseCode::UnknownCte) => Err(Error::UnknownCte(
+            information.unwrap_or_default().trim().to_string(),
+        )),
+        Status::No => Err(Error::No(response(code))),
+        _ => Err(Error::Io(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "status: {:?}, code: {:?}, information: {:?}",
+                status, code, information
+            ),
+        ))),
+    }
+}
+
+/// Extracts the id from the text of a `* OK [MAILBOXID (<id>)]` response (RFC 8474), as
+/// `imap_proto` does not know this response code.
+fn parse_mailbox_id(text: &str) -> Option<String> {
+    let rest = strip_prefix_no_case(text, "[MAILBOXID (")?;
+    let (id, _) = rest.split_once(")]")?;
+    Some(id.to_string())
+}
+
+fn strip_prefix_no_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    match s.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&s[prefix.len()..]),
+        _ => None,
     }
 }
 
@@ -368,7 +688,8 @@ mod tests {
             .map(|line| {
                 let mut block = crate::imap_stream::POOL.alloc(line.as_bytes().len());
                 block.copy_from_slice(line.as_bytes());
-                ResponseData::try_new(block, |bytes| -> io::Result<_> {
+                let raw = Box::new(block.into());
+                ResponseData::try_new(raw, Vec::new(), |bytes| -> io::Result<_> {
                     let (remaining, response) = imap_proto::parser::parse_response(bytes).unwrap();
                     assert_eq!(remaining.len(), 0);
                     Ok(response)
@@ -384,7 +705,7 @@ mod tests {
             input_stream(&["* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n"]);
 
         let mut stream = async_std::stream::from_iter(responses);
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let id = RequestId("A0001".into());
         let capabilities = parse_capabilities(&mut stream, send, id).await.unwrap();
         // shouldn't be any unexpected responses parsed
@@ -402,7 +723,7 @@ mod tests {
         let responses = input_stream(&["* CAPABILITY IMAP4REV1 STARTTLS\r\n"]);
         let mut stream = async_std::stream::from_iter(responses);
 
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let id = RequestId("A0001".into());
         let capabilities = parse_capabilities(&mut stream, send, id).await.unwrap();
 
@@ -417,7 +738,7 @@ mod tests {
     #[async_std::test]
     #[should_panic]
     async fn parse_capability_invalid_test() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&["* JUNK IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n"]);
         let mut stream = async_std::stream::from_iter(responses);
 
@@ -430,12 +751,12 @@ mod tests {
 
     #[async_std::test]
     async fn parse_names_test() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&["* LIST (\\HasNoChildren) \".\" \"INBOX\"\r\n"]);
         let mut stream = async_std::stream::from_iter(responses);
 
         let id = RequestId("A0001".into());
-        let names: Vec<_> = parse_names(&mut stream, send, id)
+        let names: Vec<_> = parse_names(&mut stream, send, id, false)
             .collect::<Result<Vec<Name>>>()
             .await
             .unwrap();
@@ -449,9 +770,39 @@ mod tests {
         assert_eq!(names[0].name(), "INBOX");
     }
 
+    #[async_std::test]
+    async fn parse_names_extended_attributes() {
+        let (send, recv) = UnsolicitedSender::new(10);
+        let responses = input_stream(&[
+            "* LIST (\\HasChildren \\Subscribed) \"/\" \"Lists\"\r\n",
+            "* LIST (\\NonExistent \\HASNOCHILDREN \\Remote \\Archive) \"/\" \"Gone\"\r\n",
+        ]);
+        let mut stream = async_std::stream::from_iter(responses);
+
+        let id = RequestId("A0001".into());
+        let names: Vec<_> = parse_names(&mut stream, send, id, false)
+            .collect::<Result<Vec<Name>>>()
+            .await
+            .unwrap();
+        assert!(recv.is_empty());
+        assert_eq!(
+            names[0].attributes(),
+            &[NameAttribute::HasChildren, NameAttribute::Subscribed]
+        );
+        assert_eq!(
+            names[1].attributes(),
+            &[
+                NameAttribute::NonExistent,
+                NameAttribute::HasNoChildren,
+                NameAttribute::Remote,
+                NameAttribute::Custom("\\Archive".into())
+            ]
+        );
+    }
+
     #[async_std::test]
     async fn parse_fetches_empty() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&[]);
         let mut stream = async_std::stream::from_iter(responses);
         let id = RequestId("a".into());
@@ -466,7 +817,7 @@ mod tests {
 
     #[async_std::test]
     async fn parse_fetches_test() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&[
             "* 24 FETCH (FLAGS (\\Seen) UID 4827943)\r\n",
             "* 25 FETCH (FLAGS (\\Seen))\r\n",
@@ -496,7 +847,7 @@ mod tests {
     #[async_std::test]
     async fn parse_fetches_w_unilateral() {
         // https://github.com/mattnenterprise/rust-imap/issues/81
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&["* 37 FETCH (UID 74)\r\n", "* 1 RECENT\r\n"]);
         let mut stream = async_std::stream::from_iter(responses);
         let id = RequestId("a".into());
@@ -514,7 +865,7 @@ mod tests {
 
     #[async_std::test]
     async fn parse_names_w_unilateral() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&[
             "* LIST (\\HasNoChildren) \".\" \"INBOX\"\r\n",
             "* 4 EXPUNGE\r\n",
@@ -522,7 +873,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
 
         let id = RequestId("A0001".into());
-        let names = parse_names(&mut stream, send, id)
+        let names = parse_names(&mut stream, send, id, false)
             .collect::<Result<Vec<_>>>()
             .await
             .unwrap();
@@ -540,7 +891,7 @@ mod tests {
 
     #[async_std::test]
     async fn parse_capabilities_w_unilateral() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&[
             "* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n",
             "* STATUS dev.github (MESSAGES 10 UIDNEXT 11 UIDVALIDITY 1408806928 UNSEEN 0)\r\n",
@@ -575,7 +926,7 @@ mod tests {
 
     #[async_std::test]
     async fn parse_ids_w_unilateral() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&[
             "* SEARCH 23 42 4711\r\n",
             "* 1 RECENT\r\n",
@@ -603,9 +954,33 @@ mod tests {
         );
     }
 
+    #[async_std::test]
+    async fn handle_unilateral_exists_recent() {
+        let (send, recv) = UnsolicitedSender::new(10);
+        let responses = input_stream(&[
+            "* 10 EXISTS\r\n",
+            "* 2 RECENT\r\n",
+            "* 3 FETCH (FLAGS (\\Seen))\r\n",
+        ]);
+
+        for res in responses {
+            handle_unilateral(res.unwrap(), send.clone()).await.unwrap();
+        }
+
+        assert_eq!(recv.recv().await.unwrap(), UnsolicitedResponse::Exists(10));
+        assert_eq!(recv.recv().await.unwrap(), UnsolicitedResponse::Recent(2));
+        match recv.recv().await.unwrap() {
+            UnsolicitedResponse::Other(res) => {
+                assert!(matches!(res.parsed(), Response::Fetch(3, _)))
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        assert!(recv.is_empty());
+    }
+
     #[async_std::test]
     async fn parse_ids_test() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&[
                 "* SEARCH 1600 1698 1739 1781 1795 1885 1891 1892 1893 1898 1899 1901 1911 1926 1932 1933 1993 1994 2007 2032 2033 2041 2053 2062 2063 2065 2066 2072 2078 2079 2082 2084 2095 2100 2101 2102 2103 2104 2107 2116 2120 2135 2138 2154 2163 2168 2172 2189 2193 2198 2199 2205 2212 2213 2221 2227 2267 2275 2276 2295 2300 2328 2330 2332 2333 2334\r\n",
                 "* SEARCH 2335 2336 2337 2338 2339 2341 2342 2347 2349 2350 2358 2359 2362 2369 2371 2372 2373 2374 2375 2376 2377 2378 2379 2380 2381 2382 2383 2384 2385 2386 2390 2392 2397 2400 2401 2403 2405 2409 2411 2414 2417 2419 2420 2424 2426 2428 2439 2454 2456 2467 2468 2469 2490 2515 2519 2520 2521\r\n",
@@ -638,7 +1013,7 @@ mod tests {
 
     #[async_std::test]
     async fn parse_ids_search() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&["* SEARCH\r\n"]);
         let mut stream = async_std::stream::from_iter(responses);
 
@@ -652,7 +1027,7 @@ mod tests {
 
     #[async_std::test]
     async fn parse_mailbox_does_not_exist_error() {
-        let (send, recv) = channel::bounded(10);
+        let (send, recv) = UnsolicitedSender::new(10);
         let responses = input_stream(&[
             "A0003 NO Mailbox doesn't exist: DeltaChat (0.001 + 0.140 + 0.139 secs).\r\n",
         ]);
@@ -0,0 +1,521 @@
+//! A pool of authenticated [`Session`]s shared by the tasks of a client that works with several
+//! mailboxes at once.
+//!
+//! IMAP is stateful: a connection has one selected mailbox, and its commands run one after the
+//! other. [`SessionPool`] hands out one session per task with the requested mailbox selected, so
+//! that tasks working on different mailboxes run in parallel on separate connections, while
+//! never opening more connections than the server allows per account. Sessions that are handed
+//! back are reused, preferably by a task that wants the mailbox they already have selected.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use async_std::io::{Read, Write};
+use event_listener::Event;
+
+use crate::client::Session;
+use crate::error::{Error, Result};
+use crate::reconnect::ConnectFuture;
+use crate::secret::Secret;
+
+type ConnectFn<T> = Box<dyn Fn() -> ConnectFuture<T> + Send + Sync>;
+
+/// Hands out up to a fixed number of logged in [`Session`]s, each with a mailbox selected, see
+/// the [module docs](self).
+///
+/// ```no_run
+/// use async_imap::pool::SessionPool;
+/// use futures::FutureExt;
+///
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// let pool = SessionPool::new(
+///     || {
+///         let tls = async_native_tls::TlsConnector::new();
+///         async_imap::connect(("imap.example.org", 993), "imap.example.org", tls).boxed()
+///     },
+///     "user",
+///     "pass",
+///     4,
+/// );
+///
+/// let mut inbox = pool.get("INBOX").await?;
+/// let mut archive = pool.get("Archive").await?;
+/// let (unseen, old) = futures::try_join!(
+///     inbox.uid_search("UNSEEN"),
+///     archive.uid_search("BEFORE 1-Jan-2020"),
+/// )?;
+/// # Ok(())
+/// # }) }
+/// ```
+pub struct SessionPool<T: Read + Write + Unpin + fmt::Debug> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T: Read + Write + Unpin + fmt::Debug> {
+    connect: ConnectFn<T>,
+    username: String,
+    password: Secret<String>,
+    max_sessions: usize,
+    state: Mutex<State<T>>,
+    /// Notified whenever a session is handed back or closed, for tasks waiting for one.
+    returned: Event,
+}
+
+struct State<T: Read + Write + Unpin + fmt::Debug> {
+    /// Sessions that are not handed out, with the mailbox they have selected.
+    idle: Vec<(Session<T>, String)>,
+    /// The number of sessions that are connected or connecting, handed out or not.
+    open: usize,
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> fmt::Debug for SessionPool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.shared.state.lock().unwrap();
+        f.debug_struct("SessionPool")
+            .field("username", &self.shared.username)
+            .field("max_sessions", &self.shared.max_sessions)
+            .field("open", &state.open)
+            .field("idle", &state.idle.len())
+            .finish()
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> Clone for SessionPool<T> {
+    fn clone(&self) -> Self {
+        SessionPool {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> SessionPool<T> {
+    /// Creates a pool that connects using `connect` and logs in with the given credentials,
+    /// keeping at most `max_sessions` connections open, at least one. No connection is opened
+    /// until a session is requested.
+    ///
+    /// `connect` should perform the full connection setup (TCP, TLS and reading the greeting),
+    /// as [`connect`](crate::connect) does.
+    pub fn new<F, U, P>(connect: F, username: U, password: P, max_sessions: usize) -> Self
+    where
+        F: Fn() -> ConnectFuture<T> + Send + Sync + 'static,
+        U: Into<String>,
+        P: Into<String>,
+    {
+        SessionPool {
+            shared: Arc::new(Shared {
+                connect: Box::new(connect),
+                username: username.into(),
+                password: Secret(password.into()),
+                max_sessions: max_sessions.max(1),
+                state: Mutex::new(State {
+                    idle: Vec::new(),
+                    open: 0,
+                }),
+                returned: Event::new(),
+            }),
+        }
+    }
+
+    /// The most connections the pool keeps open.
+    pub fn max_sessions(&self) -> usize {
+        self.shared.max_sessions
+    }
+
+    /// The number of connections that are open, whether handed out or not.
+    pub fn open_sessions(&self) -> usize {
+        self.shared.state.lock().unwrap().open
+    }
+
+    /// Hands out a session with `mailbox_name` selected, until the returned [`PooledSession`]
+    /// is dropped.
+    ///
+    /// A session that is not handed out and already has the mailbox selected is used first,
+    /// then one that has another mailbox selected, which is selected anew. Only if there is no
+    /// such session is a new connection opened. If the pool is at
+    /// [`SessionPool::max_sessions`], this waits for a session to be handed back.
+    ///
+    /// Dropping the returned future is safe: a connection that is still being opened is given
+    /// up, and a session whose mailbox is being selected is handed back to the pool.
+    pub async fn get<S: AsRef<str>>(&self, mailbox_name: S) -> Result<PooledSession<T>> {
+        let mailbox_name = mailbox_name.as_ref();
+        loop {
+            // Listening before looking at the state, so that no session handed back in between
+            // is missed.
+            let returned = self.shared.returned.listen();
+            let reused = {
+                let mut state = self.shared.state.lock().unwrap();
+                let same = state
+                    .idle
+                    .iter()
+                    .position(|(_, selected)| selected == mailbox_name);
+                match same.or_else(|| state.idle.len().checked_sub(1)) {
+                    Some(i) => Some(Some(state.idle.swap_remove(i))),
+                    None if state.open < self.shared.max_sessions => {
+                        state.open += 1;
+                        Some(None)
+                    }
+                    None => None,
+                }
+            };
+
+            let session = match reused {
+                None => {
+                    returned.await;
+                    continue;
+                }
+                Some(Some((session, selected))) if selected == mailbox_name => session,
+                Some(Some((session, _))) => {
+                    let mut slot = Slot::new(&self.shared, Some(session));
+                    let session = slot.session.as_mut().expect("session is taken out");
+                    match session.select(mailbox_name).await {
+                        Ok(_) => slot.take().expect("session is taken out"),
+                        // No mailbox is selected now, but the session can still be used, which
+                        // dropping the slot takes care of.
+                        Err(err @ Error::No(_)) | Err(err @ Error::Bad(_)) => return Err(err),
+                        Err(err) => {
+                            let session = slot.take().expect("session is taken out");
+                            self.shared.closed(session);
+                            return Err(err);
+                        }
+                    }
+                }
+                Some(None) => {
+                    let slot = Slot::new(&self.shared, None);
+                    // Dropping the slot on failure makes room for another connection.
+                    let session = self.open(mailbox_name).await?;
+                    slot.take();
+                    session
+                }
+            };
+            return Ok(PooledSession {
+                session: Some(session),
+                mailbox_name: mailbox_name.to_string(),
+                shared: self.shared.clone(),
+            });
+        }
+    }
+
+    /// Opens a new connection, logs in and selects `mailbox_name`.
+    async fn open(&self, mailbox_name: &str) -> Result<Session<T>> {
+        let client = (self.shared.connect)().await?;
+        let mut session = client
+            .login(&self.shared.username, self.shared.password.as_str())
+            .await
+            .map_err(|(err, _)| err)?;
+        if let Err(err) = session.select(mailbox_name).await {
+            session.logout_guard.disarm();
+            return Err(err);
+        }
+        Ok(session)
+    }
+
+    /// Logs out the sessions that are not handed out. Sessions that are handed out at the time
+    /// are kept once handed back.
+    pub async fn logout_idle(&self) -> Result<()> {
+        let idle = std::mem::take(&mut self.shared.state.lock().unwrap().idle);
+        let mut res = Ok(());
+        for (mut session, _) in idle {
+            if let Err(err) = session.logout().await {
+                res = res.and(Err(err));
+            }
+            self.shared.closed(session);
+        }
+        res
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> Shared<T> {
+    /// Makes a session that was taken out of the pool available again.
+    fn handed_back(&self, session: Session<T>, mailbox_name: String) {
+        self.state
+            .lock()
+            .unwrap()
+            .idle
+            .push((session, mailbox_name));
+        self.returned.notify(usize::MAX);
+    }
+
+    /// Gives up on a session that was taken out of the pool, making room for a new one.
+    fn closed(&self, mut session: Session<T>) {
+        session.logout_guard.disarm();
+        self.state.lock().unwrap().open -= 1;
+        self.returned.notify(usize::MAX);
+    }
+}
+
+/// A place in the pool that [`SessionPool::get`] holds while it opens a connection or selects a
+/// mailbox on an idle session. Unless it is taken, it is given back when dropped, also when the
+/// future of `get` is dropped while waiting for the server.
+struct Slot<'a, T: Read + Write + Unpin + fmt::Debug> {
+    shared: &'a Shared<T>,
+    /// The idle session a mailbox is selected on, or `None` while a connection is opened.
+    session: Option<Session<T>>,
+    taken: bool,
+}
+
+impl<'a, T: Read + Write + Unpin + fmt::Debug> Slot<'a, T> {
+    fn new(shared: &'a Shared<T>, session: Option<Session<T>>) -> Self {
+        Slot {
+            shared,
+            session,
+            taken: false,
+        }
+    }
+
+    /// Keeps the place in the pool for the session being handed out.
+    fn take(mut self) -> Option<Session<T>> {
+        self.taken = true;
+        self.session.take()
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> Drop for Slot<'_, T> {
+    fn drop(&mut self) {
+        if self.taken {
+            return;
+        }
+        match self.session.take() {
+            // The mailbox may or may not be selected, so it is selected anew when handed out.
+            Some(session) => self.shared.handed_back(session, String::new()),
+            None => {
+                self.shared.state.lock().unwrap().open -= 1;
+                self.shared.returned.notify(usize::MAX);
+            }
+        }
+    }
+}
+
+/// A [`Session`] handed out by a [`SessionPool`], which it is handed back to when dropped.
+///
+/// It derefs to the session, so any command can be run on it. Do not select another mailbox with
+/// it, as the pool hands it out again for the mailbox it selected. If the connection failed,
+/// call [`PooledSession::discard`] instead of handing it back.
+pub struct PooledSession<T: Read + Write + Unpin + fmt::Debug> {
+    session: Option<Session<T>>,
+    mailbox_name: String,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> fmt::Debug for PooledSession<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledSession")
+            .field("session", &self.session)
+            .field("mailbox_name", &self.mailbox_name)
+            .finish()
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> PooledSession<T> {
+    /// The mailbox the session has selected.
+    pub fn mailbox_name(&self) -> &str {
+        &self.mailbox_name
+    }
+
+    /// Closes the connection instead of handing the session back, e.g. after an I/O error,
+    /// making room for a new connection in the pool. Logging out is attempted, but errors are
+    /// ignored.
+    pub async fn discard(mut self)
+    where
+        T: Send,
+    {
+        let mut session = self.session.take().expect("session is handed out");
+        let _ = session.logout().await;
+        self.shared.closed(session);
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> Deref for PooledSession<T> {
+    type Target = Session<T>;
+
+    fn deref(&self) -> &Session<T> {
+        self.session.as_ref().expect("session is handed out")
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> DerefMut for PooledSession<T> {
+    fn deref_mut(&mut self) -> &mut Session<T> {
+        self.session.as_mut().expect("session is handed out")
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> Drop for PooledSession<T> {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.take() {
+            let mailbox_name = std::mem::take(&mut self.mailbox_name);
+            self.shared.handed_back(session, mailbox_name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::FutureExt;
+
+    use crate::client::Client;
+    use crate::mock_stream::MockStream;
+
+    fn connector(
+        responses: Vec<&'static [u8]>,
+        connects: Arc<AtomicUsize>,
+    ) -> impl Fn() -> ConnectFuture<MockStream> + Send + Sync + 'static {
+        move || {
+            let next = responses
+                .get(connects.fetch_add(1, Ordering::SeqCst))
+                .copied();
+            async move {
+                match next {
+                    Some(buf) => Ok(Client::new(MockStream::new(buf.to_vec()))),
+                    None => Err(Error::ConnectionLost),
+                }
+            }
+            .boxed()
+        }
+    }
+
+    fn written(session: &PooledSession<MockStream>) -> &str {
+        std::str::from_utf8(&session.stream.inner.written_buf).unwrap()
+    }
+
+    #[async_std::test]
+    async fn max_sessions_and_reuse() {
+        let first = b"A0001 OK Logged in\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            A0003 OK Noop completed.\r\n";
+        let second = b"A0001 OK Logged in\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            A0003 OK [READ-WRITE] Select completed.\r\n";
+        let connects = Arc::new(AtomicUsize::new(0));
+        let pool = SessionPool::new(
+            connector(vec![first, second], connects.clone()),
+            "user",
+            "pass",
+            2,
+        );
+
+        let mut inbox = pool.get("INBOX").await.unwrap();
+        let archive = pool.get("Archive").await.unwrap();
+        assert_eq!(pool.open_sessions(), 2);
+
+        // The pool is full until a session is handed back.
+        let waiting = async_std::future::timeout(Duration::from_millis(50), pool.get("INBOX"));
+        assert!(waiting.await.is_err());
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        inbox.noop().await.unwrap();
+        drop(inbox);
+        let inbox = pool.get("INBOX").await.unwrap();
+        assert_eq!(
+            written(&inbox),
+            "A0001 LOGIN \"user\" \"pass\"\r\n\
+             A0002 SELECT \"INBOX\"\r\n\
+             A0003 NOOP\r\n"
+        );
+
+        // Another mailbox is selected on a session that is handed back.
+        drop(archive);
+        let drafts = pool.get("Drafts").await.unwrap();
+        assert_eq!(drafts.mailbox_name(), "Drafts");
+        assert!(written(&drafts).ends_with("A0003 SELECT \"Drafts\"\r\n"));
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.open_sessions(), 2);
+        drop(inbox);
+        drop(drafts);
+    }
+
+    #[async_std::test]
+    async fn wait_for_returned() {
+        let first = b"A0001 OK Logged in\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            A0003 OK [READ-WRITE] Select completed.\r\n";
+        let connects = Arc::new(AtomicUsize::new(0));
+        let pool = SessionPool::new(connector(vec![first], connects.clone()), "u", "p", 1);
+
+        let inbox = pool.get("INBOX").await.unwrap();
+        let waiting = async_std::task::spawn({
+            let pool = pool.clone();
+            async move { pool.get("Archive").await.map(drop) }
+        });
+        async_std::task::sleep(Duration::from_millis(20)).await;
+        drop(inbox);
+        waiting.await.unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+    }
+
+    #[async_std::test]
+    async fn dropped_get() {
+        let quiet = Duration::from_millis(200);
+        let login = b"A0001 OK Logged in\r\n".to_vec();
+        let second = b"A0001 OK Logged in\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            A0003 OK [READ-WRITE] Select completed.\r\n\
+            A0004 OK [READ-WRITE] Select completed.\r\n"
+            .to_vec();
+        let reselect = second.len() - 2 * b"A0003 OK [READ-WRITE] Select completed.\r\n".len();
+        let streams = Mutex::new(vec![
+            MockStream::new(second).with_pause(reselect, quiet),
+            MockStream::new(login).with_pause(0, quiet),
+        ]);
+        let connects = Arc::new(AtomicUsize::new(0));
+        let pool = SessionPool::new(
+            {
+                let connects = connects.clone();
+                move || {
+                    connects.fetch_add(1, Ordering::SeqCst);
+                    let stream = streams.lock().unwrap().pop();
+                    async move { stream.map(Client::new).ok_or(Error::ConnectionLost) }.boxed()
+                }
+            },
+            "user",
+            "pass",
+            1,
+        );
+
+        // Given up while logging in.
+        let opening = async_std::future::timeout(Duration::from_millis(20), pool.get("INBOX"));
+        assert!(opening.await.is_err());
+        assert_eq!(pool.open_sessions(), 0);
+
+        let inbox = pool.get("INBOX").await.unwrap();
+        drop(inbox);
+        // Given up while selecting another mailbox, the session is handed back.
+        let selecting = async_std::future::timeout(Duration::from_millis(20), pool.get("Drafts"));
+        assert!(selecting.await.is_err());
+        assert_eq!(pool.open_sessions(), 1);
+        let drafts = pool.get("Drafts").await.unwrap();
+        assert!(written(&drafts).ends_with(
+            "A0003 SELECT \"Drafts\"\r\n\
+             A0004 SELECT \"Drafts\"\r\n"
+        ));
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[async_std::test]
+    async fn discard() {
+        let first = b"A0001 OK Logged in\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n";
+        let second = b"A0001 NO Invalid credentials\r\n";
+        let connects = Arc::new(AtomicUsize::new(0));
+        let pool = SessionPool::new(
+            connector(vec![first, second], connects.clone()),
+            "user",
+            "pass",
+            1,
+        );
+
+        let inbox = pool.get("INBOX").await.unwrap();
+        inbox.discard().await;
+        assert_eq!(pool.open_sessions(), 0);
+
+        // A failed connection does not count either.
+        assert!(matches!(pool.get("INBOX").await, Err(Error::No(_))));
+        assert_eq!(pool.open_sessions(), 0);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,398 @@
+//! A [`Session`] wrapper that transparently recovers from dropped connections.
+//!
+//! Long-running clients (e.g. a daemon that sits in `IDLE` for days) will eventually see the
+//! network drop from under them. [`ReconnectingSession`] remembers how to connect, the login
+//! credentials and the currently selected mailbox, so that when a command fails with an error
+//! that is considered retryable it can re-establish the connection, log in again, re-select the
+//! mailbox (and re-issue `IDLE` if that is what was running) and then retry the command once.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use async_std::io::{Read, Write};
+use futures::future::BoxFuture;
+
+use crate::client::{Client, Session};
+use crate::error::{Error, Result};
+use crate::extensions::idle::IdleResponse;
+use crate::secret::Secret;
+use crate::types::{IdGenerator, Mailbox};
+
+/// The future returned by the connect function of a [`ReconnectingSession`].
+pub type ConnectFuture<T> = Pin<Box<dyn Future<Output = Result<Client<T>>> + Send>>;
+
+type ConnectFn<T> = Box<dyn FnMut() -> ConnectFuture<T> + Send>;
+type RetryableFn = Box<dyn Fn(&Error) -> bool + Send + Sync>;
+
+/// The mailbox that was opened last, so it can be re-opened after a reconnect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selected {
+    Select(String),
+    Examine(String),
+}
+
+/// A [`Session`] that reconnects, re-authenticates and re-selects the previously selected mailbox
+/// whenever a command fails with a retryable error, and then retries that command once.
+///
+/// By default [`Error::Io`], [`Error::ConnectionLost`] and [`Error::Bye`] are considered
+/// retryable; use [`ReconnectingSession::retry_if`] to change this.
+///
+/// ```no_run
+/// use async_imap::reconnect::ReconnectingSession;
+/// use futures::FutureExt;
+///
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// let mut session = ReconnectingSession::new(
+///     || {
+///         let tls = async_native_tls::TlsConnector::new();
+///         async_imap::connect(("imap.example.org", 993), "imap.example.org", tls).boxed()
+///     },
+///     "user",
+///     "pass",
+/// )
+/// .await?;
+///
+/// session.select("INBOX").await?;
+/// let uids = session.run(|s| s.uid_search("UNSEEN").boxed()).await?;
+/// # Ok(())
+/// # }) }
+/// ```
+pub struct ReconnectingSession<T: Read + Write + Unpin + fmt::Debug> {
+    session: Option<Session<T>>,
+    connect: ConnectFn<T>,
+    username: String,
+    password: Secret<String>,
+    selected: Option<Selected>,
+    retryable: RetryableFn,
+    /// The tag generator, carried over to the next connection so that tags are never reused.
+    request_ids: IdGenerator,
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> fmt::Debug for ReconnectingSession<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingSession")
+            .field("session", &self.session)
+            .field("username", &self.username)
+            .field("selected", &self.selected)
+            .finish()
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> ReconnectingSession<T> {
+    /// Connects using `connect` and logs in with the given credentials.
+    ///
+    /// `connect` is called again every time the connection has to be re-established, so it
+    /// should perform the full connection setup (TCP, TLS and reading the greeting), as
+    /// [`connect`](crate::connect) does.
+    pub async fn new<F, U, P>(connect: F, username: U, password: P) -> Result<Self>
+    where
+        F: FnMut() -> ConnectFuture<T> + Send + 'static,
+        U: Into<String>,
+        P: Into<String>,
+    {
+        let mut this = ReconnectingSession {
+            session: None,
+            connect: Box::new(connect),
+            username: username.into(),
+            password: Secret(password.into()),
+            selected: None,
+            retryable: Box::new(default_retryable),
+            request_ids: IdGenerator::new(),
+        };
+        this.reconnect().await?;
+
+        Ok(this)
+    }
+
+    /// Sets the function used to decide whether an error should trigger a reconnect and a
+    /// retry of the failed command.
+    pub fn retry_if<F>(mut self, retryable: F) -> Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        self.retryable = Box::new(retryable);
+        self
+    }
+
+    /// The underlying session, if currently connected.
+    pub fn session(&mut self) -> Option<&mut Session<T>> {
+        self.session.as_mut()
+    }
+
+    /// Consumes the wrapper, returning the underlying session if currently connected.
+    pub fn into_inner(self) -> Option<Session<T>> {
+        self.session
+    }
+
+    /// Re-establishes the connection, logs in and re-selects the last selected mailbox.
+    ///
+    /// This is done automatically when a command fails with a retryable error, but may also be
+    /// called manually.
+    ///
+    /// Command tags continue where the previous connection left off, with the same prefix, see
+    /// [`Connection::set_tag_prefix`](crate::Connection::set_tag_prefix).
+    pub async fn reconnect(&mut self) -> Result<()> {
+        if let Some(mut session) = self.session.take() {
+            self.request_ids = std::mem::take(&mut session.request_ids);
+            session.logout_guard.disarm();
+        }
+
+        let mut client = (self.connect)().await?;
+        client.request_ids = std::mem::take(&mut self.request_ids);
+        let mut session = match client.login(&self.username, self.password.as_str()).await {
+            Ok(session) => session,
+            Err((err, mut client)) => {
+                self.request_ids = std::mem::take(&mut client.request_ids);
+                return Err(err);
+            }
+        };
+        let reselected = match &self.selected {
+            Some(Selected::Select(name)) => session.select(name).await.map(drop),
+            Some(Selected::Examine(name)) => session.examine(name).await.map(drop),
+            None => Ok(()),
+        };
+        if let Err(err) = reselected {
+            self.request_ids = std::mem::take(&mut session.request_ids);
+            session.logout_guard.disarm();
+            return Err(err);
+        }
+        self.session = Some(session);
+
+        Ok(())
+    }
+
+    /// Runs `op` on the session. If it fails with a retryable error, the connection is
+    /// re-established and `op` is run once more.
+    pub async fn run<F, R>(&mut self, mut op: F) -> Result<R>
+    where
+        F: for<'a> FnMut(&'a mut Session<T>) -> BoxFuture<'a, Result<R>>,
+    {
+        if let Some(session) = self.session.as_mut() {
+            match op(session).await {
+                Err(err) if (self.retryable)(&err) => {
+                    log::debug!("reconnecting after error: {}", err);
+                }
+                res => return res,
+            }
+        }
+
+        self.reconnect().await?;
+        op(self.session.as_mut().expect("reconnected")).await
+    }
+
+    /// Selects a mailbox, see [`Session::select`]. The mailbox is re-selected after every
+    /// reconnect. If selecting fails, no mailbox is selected, not even the previous one.
+    pub async fn select<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
+        let name = mailbox_name.as_ref().to_string();
+        // The previous mailbox is closed even if the command fails.
+        self.selected = None;
+        let mailbox = self
+            .run(|session| Box::pin(session.select(name.clone())))
+            .await?;
+        self.selected = Some(Selected::Select(name));
+
+        Ok(mailbox)
+    }
+
+    /// Examines a mailbox, see [`Session::examine`]. The mailbox is re-examined after every
+    /// reconnect. If examining fails, no mailbox is selected, not even the previous one.
+    pub async fn examine<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
+        let name = mailbox_name.as_ref().to_string();
+        self.selected = None;
+        let mailbox = self
+            .run(|session| Box::pin(session.examine(name.clone())))
+            .await?;
+        self.selected = Some(Selected::Examine(name));
+
+        Ok(mailbox)
+    }
+
+    /// Closes the currently selected mailbox, see [`Session::close`].
+    pub async fn close(&mut self) -> Result<()> {
+        self.run(|session| Box::pin(session.close())).await?;
+        self.selected = None;
+
+        Ok(())
+    }
+
+    /// Issues `IDLE` and waits for up to `timeout` for the server to report a change, see
+    /// [`Handle::wait_with_timeout`](crate::extensions::idle::Handle::wait_with_timeout).
+    ///
+    /// If the connection drops while idling, it is re-established and `IDLE` is issued again.
+    pub async fn idle(&mut self, timeout: Duration) -> Result<IdleResponse> {
+        match self.idle_once(timeout).await {
+            Err(err) if (self.retryable)(&err) => {
+                log::debug!("reconnecting after error during IDLE: {}", err);
+            }
+            res => return res,
+        }
+
+        self.reconnect().await?;
+        self.idle_once(timeout).await
+    }
+
+    async fn idle_once(&mut self, timeout: Duration) -> Result<IdleResponse> {
+        let session = self.session.take().ok_or(Error::ConnectionLost)?;
+        // The session is lost if idling fails, so keep track of the tags here, including the one
+        // `IDLE` is about to use.
+        self.request_ids = session.request_ids.clone();
+        self.request_ids.next();
+        let mut handle = session.idle();
+        handle.init().await?;
+        let (wait, _interrupt) = handle.wait_with_timeout(timeout);
+        let res = wait.await?;
+        self.session = Some(handle.done().await?);
+
+        Ok(res)
+    }
+
+    /// Logs out, see [`Session::logout`].
+    pub async fn logout(mut self) -> Result<()> {
+        match self.session.as_mut() {
+            Some(session) => session.logout().await,
+            None => Ok(()),
+        }
+    }
+}
+
+fn default_retryable(err: &Error) -> bool {
+    matches!(err, Error::Io(_) | Error::ConnectionLost | Error::Bye(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use async_std::prelude::*;
+    use futures::FutureExt;
+
+    use crate::mock_stream::MockStream;
+
+    fn connector(
+        responses: Vec<&'static [u8]>,
+    ) -> impl FnMut() -> ConnectFuture<MockStream> + Send + 'static {
+        let responses = Arc::new(Mutex::new(responses.into_iter()));
+        move || {
+            let next = responses.lock().unwrap().next();
+            async move {
+                match next {
+                    Some(buf) => Ok(Client::new(MockStream::new(buf.to_vec()))),
+                    None => Err(Error::ConnectionLost),
+                }
+            }
+            .boxed()
+        }
+    }
+
+    #[async_std::test]
+    async fn reconnect_mid_command() {
+        let first = b"A0001 OK Logged in\r\n\
+            * 1 EXISTS\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            * 1 FETCH (UID";
+        let second = b"A0004 OK Logged in\r\n\
+            * 1 EXISTS\r\n\
+            A0005 OK [READ-WRITE] Select completed.\r\n\
+            * 1 FETCH (UID 42)\r\n\
+            A0006 OK Fetch completed.\r\n";
+        let mut session = ReconnectingSession::new(connector(vec![first, second]), "user", "pass")
+            .await
+            .unwrap();
+
+        let mailbox = session.select("INBOX").await.unwrap();
+        assert_eq!(mailbox.exists, 1);
+
+        let fetches = session
+            .run(|s| {
+                async move { s.fetch("1", "UID").await?.collect::<Result<Vec<_>>>().await }.boxed()
+            })
+            .await
+            .unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].uid, Some(42));
+
+        let written = &session.session().unwrap().stream.inner.written_buf;
+        assert_eq!(
+            std::str::from_utf8(written).unwrap(),
+            "A0004 LOGIN \"user\" \"pass\"\r\n\
+             A0005 SELECT \"INBOX\"\r\n\
+             A0006 FETCH 1 UID\r\n"
+        );
+    }
+
+    #[async_std::test]
+    async fn non_retryable_error() {
+        let first = b"A0001 OK Logged in\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            A0003 NO Mailbox doesn't exist\r\n";
+        let second = b"A0004 OK Logged in\r\n";
+        let mut session = ReconnectingSession::new(connector(vec![first, second]), "user", "pass")
+            .await
+            .unwrap();
+
+        session.select("INBOX").await.unwrap();
+        assert!(matches!(session.select("Missing").await, Err(Error::No(_))));
+        assert!(session.session().is_some());
+
+        // The failed `SELECT` closed `INBOX`, so it is not selected again.
+        session.reconnect().await.unwrap();
+        let written = &session.session().unwrap().stream.inner.written_buf;
+        assert_eq!(
+            std::str::from_utf8(written).unwrap(),
+            "A0004 LOGIN \"user\" \"pass\"\r\n"
+        );
+    }
+
+    #[async_std::test]
+    async fn retry_if() {
+        let first = b"A0001 OK Logged in\r\n\
+            A0002 NO Try again later\r\n";
+        let second = b"A0003 OK Logged in\r\n\
+            A0004 OK Noop completed\r\n";
+        let mut session = ReconnectingSession::new(connector(vec![first, second]), "user", "pass")
+            .await
+            .unwrap()
+            .retry_if(|err| matches!(err, Error::No(_)));
+
+        session.run(|s| s.noop().boxed()).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn reissue_idle() {
+        let first = b"A0001 OK Logged in\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            + idling\r\n";
+        let second = b"A0004 OK Logged in\r\n\
+            A0005 OK [READ-WRITE] Select completed.\r\n\
+            + idling\r\n\
+            * 2 EXISTS\r\n\
+            A0006 OK IDLE terminated\r\n";
+        let mut session = ReconnectingSession::new(connector(vec![first, second]), "user", "pass")
+            .await
+            .unwrap();
+        session.select("INBOX").await.unwrap();
+
+        let res = session.idle(Duration::from_secs(5)).await.unwrap();
+        match res {
+            IdleResponse::NewData(data) => assert_eq!(
+                data.parsed(),
+                &imap_proto::Response::MailboxData(imap_proto::MailboxDatum::Exists(2))
+            ),
+            other => panic!("unexpected idle response: {:?}", other),
+        }
+
+        let written = &session.session().unwrap().stream.inner.written_buf;
+        assert_eq!(
+            std::str::from_utf8(written).unwrap(),
+            "A0004 LOGIN \"user\" \"pass\"\r\n\
+             A0005 SELECT \"INBOX\"\r\n\
+             A0006 IDLE\r\n\
+             DONE\r\n"
+        );
+    }
+}
@@ -0,0 +1,413 @@
+//! The client side of the [`SCRAM-SHA-256`](https://tools.ietf.org/html/rfc7677) SASL mechanism.
+
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::authenticator::Authenticator;
+use crate::error::{Error, Result};
+use crate::secret::Secret;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Data that ties a SCRAM exchange to the TLS connection it runs over, see
+/// [RFC 5056](https://tools.ietf.org/html/rfc5056). An exchange bound to a connection cannot be
+/// relayed to the real server by a man in the middle that terminates TLS.
+#[derive(Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChannelBinding {
+    /// The [`tls-server-end-point`](https://tools.ietf.org/html/rfc5929#section-4) binding: the
+    /// hash of the certificate the server presented.
+    TlsServerEndPoint(Vec<u8>),
+}
+
+impl ChannelBinding {
+    /// The `tls-server-end-point` binding for the DER encoded server certificate, e.g.
+    /// [`TlsInfo::peer_certificate`](crate::types::TlsInfo::peer_certificate).
+    ///
+    /// The certificate is hashed with SHA-256, which is correct for certificates signed with
+    /// MD5, SHA-1 or SHA-256. For others, hash it with the hash function of the signature and use
+    /// [`ChannelBinding::TlsServerEndPoint`] directly.
+    pub fn tls_server_end_point(certificate: &[u8]) -> Self {
+        ChannelBinding::TlsServerEndPoint(Sha256::digest(certificate).to_vec())
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ChannelBinding::TlsServerEndPoint(_) => "tls-server-end-point",
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        match self {
+            ChannelBinding::TlsServerEndPoint(data) => data,
+        }
+    }
+}
+
+impl fmt::Debug for ChannelBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple(self.name())
+            .field(&base64::encode(self.data()))
+            .finish()
+    }
+}
+
+/// An [`Authenticator`] for the [`SCRAM-SHA-256`](https://tools.ietf.org/html/rfc7677) SASL
+/// mechanism, which proves the knowledge of the password without sending it, and makes the
+/// server prove that it knows it as well.
+///
+/// ```no_run
+/// use async_imap::ScramSha256;
+///
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// let domain = "imap.example.com";
+/// let tls = async_native_tls::TlsConnector::new();
+/// let client = async_imap::connect((domain, 993), domain, tls).await?;
+///
+/// let scram = ScramSha256::new("me@example.com", "secret");
+/// let session = client
+///     .authenticate(scram.mechanism(), scram)
+///     .await
+///     .map_err(|(err, _client)| err)?;
+/// # Ok(())
+/// # }) }
+/// ```
+///
+/// If the server's signature in the final step does not match, the exchange is cancelled and
+/// [`Client::authenticate`](crate::Client::authenticate) fails with
+/// [`Error::Authentication`], as it does if the server accepts the credentials without sending
+/// its signature.
+///
+/// The password is used as it is; [SASLprep](https://tools.ietf.org/html/rfc4013) is not
+/// applied to it.
+pub struct ScramSha256 {
+    username: String,
+    password: Secret<String>,
+    nonce: String,
+    channel_binding: Option<ChannelBinding>,
+    state: State,
+}
+
+enum State {
+    Start,
+    /// The client-first message was sent.
+    ClientFirst {
+        bare: String,
+    },
+    /// The client-final message was sent, the server has to prove it knows the password.
+    ClientFinal {
+        server_key: Vec<u8>,
+        auth_message: String,
+    },
+    Verified,
+    Failed(String),
+}
+
+impl ScramSha256 {
+    /// Creates the exchange for `username` with a random nonce.
+    pub fn new<U: Into<String>, P: Into<String>>(username: U, password: P) -> Self {
+        let mut nonce = [0; 24];
+        getrandom::getrandom(&mut nonce).expect("no random numbers available for the SCRAM nonce");
+        Self::with_nonce(username, password, base64::encode(nonce))
+    }
+
+    pub(crate) fn with_nonce<U: Into<String>, P: Into<String>>(
+        username: U,
+        password: P,
+        nonce: String,
+    ) -> Self {
+        ScramSha256 {
+            username: username.into(),
+            password: Secret(password.into()),
+            nonce,
+            channel_binding: None,
+            state: State::Start,
+        }
+    }
+
+    /// Binds the exchange to the TLS connection, which requires the server to support
+    /// `SCRAM-SHA-256-PLUS`, see [`ScramSha256::mechanism`].
+    pub fn channel_binding(mut self, binding: ChannelBinding) -> Self {
+        self.channel_binding = Some(binding);
+        self
+    }
+
+    /// The name of the mechanism to pass to [`Client::authenticate`](crate::Client::authenticate):
+    /// `SCRAM-SHA-256`, or `SCRAM-SHA-256-PLUS` with channel binding.
+    pub fn mechanism(&self) -> &'static str {
+        if self.channel_binding.is_some() {
+            "SCRAM-SHA-256-PLUS"
+        } else {
+            "SCRAM-SHA-256"
+        }
+    }
+
+    fn gs2_header(&self) -> String {
+        match &self.channel_binding {
+            Some(binding) => format!("p={},,", binding.name()),
+            None => "n,,".into(),
+        }
+    }
+
+    /// Answers the server-first message with the client-final message.
+    fn client_final(
+        &self,
+        bare: &str,
+        server_first: &[u8],
+    ) -> std::result::Result<(String, State), String> {
+        let server_first = std::str::from_utf8(server_first)
+            .map_err(|_| "the server-first message is not UTF-8".to_string())?;
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+        for attribute in server_first.split(',') {
+            match attribute.split_at(attribute.find('=').unwrap_or(0)) {
+                ("r", value) => nonce = Some(&value[1..]),
+                ("s", value) => {
+                    salt = Some(base64::decode(&value[1..]).map_err(|e| e.to_string())?);
+                }
+                ("i", value) => iterations = value[1..].parse::<u32>().ok(),
+                ("m", _) => return Err("the server requires an unknown extension".into()),
+                _ => {}
+            }
+        }
+        let (nonce, salt, iterations) = match (nonce, salt, iterations) {
+            (Some(nonce), Some(salt), Some(iterations)) if iterations > 0 => {
+                (nonce, salt, iterations)
+            }
+            _ => return Err(format!("invalid server-first message: {}", server_first)),
+        };
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return Err("the server did not extend the client nonce".into());
+        }
+
+        let mut binding = self.gs2_header().into_bytes();
+        if let Some(channel_binding) = &self.channel_binding {
+            binding.extend_from_slice(channel_binding.data());
+        }
+        let without_proof = format!("c={},r={}", base64::encode(binding), nonce);
+        let auth_message = format!("{},{},{}", bare, server_first, without_proof);
+
+        let salted_password = hi(self.password.as_bytes(), &salt, iterations);
+        let client_key = hmac(&salted_password, b"Client Key");
+        let stored_key = Sha256::digest(&client_key);
+        let client_signature = hmac(&stored_key, auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(client_signature.iter())
+            .map(|(key, signature)| key ^ signature)
+            .collect();
+
+        let response = format!("{},p={}", without_proof, base64::encode(proof));
+        let state = State::ClientFinal {
+            server_key: hmac(&salted_password, b"Server Key"),
+            auth_message,
+        };
+        Ok((response, state))
+    }
+
+    /// Checks the server signature of the server-final message.
+    fn verify(
+        server_key: &[u8],
+        auth_message: &str,
+        server_final: &[u8],
+    ) -> std::result::Result<(), String> {
+        let server_final = String::from_utf8_lossy(server_final);
+        let signature = match server_final.split(',').next() {
+            Some(error) if error.starts_with("e=") => {
+                return Err(format!("the server rejected the exchange: {}", &error[2..]))
+            }
+            Some(signature) if signature.starts_with("v=") => {
+                base64::decode(&signature[2..]).map_err(|e| e.to_string())?
+            }
+            _ => return Err(format!("invalid server-final message: {}", server_final)),
+        };
+        let mut mac = HmacSha256::new_from_slice(server_key).expect("HMAC takes keys of any size");
+        mac.update(auth_message.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| "the server signature does not match".to_string())
+    }
+}
+
+impl Authenticator for ScramSha256 {
+    type Response = Vec<u8>;
+
+    fn process(&mut self, challenge: &[u8]) -> Self::Response {
+        match std::mem::replace(&mut self.state, State::Start) {
+            State::Start => {
+                let bare = format!("n={},r={}", sasl_name(&self.username), self.nonce);
+                let response = format!("{}{}", self.gs2_header(), bare);
+                self.state = State::ClientFirst { bare };
+                response.into_bytes()
+            }
+            State::ClientFirst { bare } => match self.client_final(&bare, challenge) {
+                Ok((response, state)) => {
+                    self.state = state;
+                    response.into_bytes()
+                }
+                Err(err) => {
+                    self.state = State::Failed(err);
+                    Vec::new()
+                }
+            },
+            State::ClientFinal {
+                server_key,
+                auth_message,
+            } => {
+                self.state = match Self::verify(&server_key, &auth_message, challenge) {
+                    Ok(()) => State::Verified,
+                    Err(err) => State::Failed(err),
+                };
+                Vec::new()
+            }
+            state => {
+                self.state = state;
+                Vec::new()
+            }
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        match &self.state {
+            State::Failed(err) => Err(Error::Authentication(err.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.check()?;
+        match self.state {
+            State::Verified => Ok(()),
+            _ => Err(Error::Authentication(
+                "the server accepted the credentials without proving it knows them".into(),
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for ScramSha256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScramSha256")
+            .field("username", &self.username)
+            .field("channel_binding", &self.channel_binding)
+            .finish()
+    }
+}
+
+/// Escapes `,` and `=` in a user name.
+fn sasl_name(name: &str) -> String {
+    name.replace('=', "=3D").replace(',', "=2C")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The `Hi` function of SCRAM, i.e. PBKDF2 with HMAC-SHA-256 and a single block.
+fn hi(password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut block = salt.to_vec();
+    block.extend_from_slice(&1u32.to_be_bytes());
+    let mut u = hmac(password, &block);
+    let mut result = u.clone();
+    for _ in 1..iterations {
+        u = hmac(password, &u);
+        for (r, b) in result.iter_mut().zip(u.iter()) {
+            *r ^= b;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example exchange of RFC 7677, section 3.
+    const SERVER_FIRST: &[u8] = b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                                  s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+
+    fn rfc_7677() -> ScramSha256 {
+        ScramSha256::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO".into())
+    }
+
+    #[test]
+    fn exchange() {
+        let mut scram = rfc_7677();
+        assert_eq!(scram.mechanism(), "SCRAM-SHA-256");
+        assert_eq!(scram.process(b""), b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+        assert_eq!(
+            String::from_utf8(scram.process(SERVER_FIRST)).unwrap(),
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        scram.check().unwrap();
+        assert!(scram.finish().is_err());
+        assert_eq!(
+            scram.process(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="),
+            b""
+        );
+        scram.check().unwrap();
+        scram.finish().unwrap();
+    }
+
+    #[test]
+    fn signature_mismatch() {
+        let mut scram = rfc_7677();
+        scram.process(b"");
+        scram.process(SERVER_FIRST);
+        scram.process(b"v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=");
+        assert!(matches!(scram.check(), Err(Error::Authentication(_))));
+        assert!(scram.finish().is_err());
+
+        let mut scram = rfc_7677();
+        scram.process(b"");
+        scram.process(SERVER_FIRST);
+        scram.process(b"e=invalid-proof");
+        assert!(matches!(scram.check(), Err(Error::Authentication(_))));
+    }
+
+    #[test]
+    fn invalid_server_first() {
+        for server_first in [
+            &b"r=someone-else,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"[..],
+            b"r=rOprNGfwEbeRWgbNEkqO,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+            b"r=rOprNGfwEbeRWgbNEkqOxyz,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=0",
+            b"m=ext,r=rOprNGfwEbeRWgbNEkqOxyz,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+            b"r=rOprNGfwEbeRWgbNEkqOxyz,i=4096",
+        ] {
+            let mut scram = rfc_7677();
+            scram.process(b"");
+            assert_eq!(scram.process(server_first), b"");
+            assert!(scram.check().is_err(), "{:?}", server_first);
+        }
+    }
+
+    #[test]
+    fn channel_binding() {
+        let binding = ChannelBinding::TlsServerEndPoint(b"hash".to_vec());
+        let mut scram = rfc_7677().channel_binding(binding);
+        assert_eq!(scram.mechanism(), "SCRAM-SHA-256-PLUS");
+        assert_eq!(
+            scram.process(b""),
+            b"p=tls-server-end-point,,n=user,r=rOprNGfwEbeRWgbNEkqO"
+        );
+        let client_final = String::from_utf8(scram.process(SERVER_FIRST)).unwrap();
+        let c = base64::encode("p=tls-server-end-point,,hash");
+        assert!(
+            client_final.starts_with(&format!("c={},", c)),
+            "{}",
+            client_final
+        );
+    }
+
+    #[test]
+    fn escaped_user_name() {
+        let mut scram = ScramSha256::with_nonce("a=b,c", "pencil", "nonce".into());
+        assert_eq!(scram.process(b""), b"n,,n=a=3Db=2Cc,r=nonce");
+    }
+}
@@ -0,0 +1,80 @@
+//! Buffers that may hold credentials.
+//!
+//! With the `zeroize` feature enabled, these buffers are overwritten with zeroes when dropped so
+//! that passwords and authentication responses do not linger in memory after they have been
+//! sent. Without it, [`Secret`] is a plain wrapper.
+
+use std::ops::Deref;
+
+/// A buffer that is wiped when dropped, if the `zeroize` feature is enabled.
+pub(crate) struct Secret<T: Wipe>(pub(crate) T);
+
+impl<T: Wipe> Deref for Secret<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Wipe> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.wipe();
+    }
+}
+
+/// Buffers that can be wiped.
+pub(crate) trait Wipe {
+    /// Overwrites the buffer with zeroes, if the `zeroize` feature is enabled.
+    fn wipe(&mut self);
+}
+
+impl Wipe for String {
+    fn wipe(&mut self) {
+        #[cfg(feature = "zeroize")]
+        zeroize::Zeroize::zeroize(self);
+    }
+}
+
+impl Wipe for Vec<u8> {
+    fn wipe(&mut self) {
+        #[cfg(feature = "zeroize")]
+        zeroize::Zeroize::zeroize(self);
+    }
+}
+
+#[cfg(all(test, feature = "zeroize"))]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct Tracked(Arc<AtomicBool>);
+
+    impl Wipe for Tracked {
+        fn wipe(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn wiped_on_drop() {
+        let wiped = Arc::new(AtomicBool::new(false));
+        let secret = Secret(Tracked(wiped.clone()));
+        assert!(!wiped.load(Ordering::SeqCst));
+        drop(secret);
+        assert!(wiped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn wipe_buffers() {
+        let mut command = String::from("LOGIN \"user\" \"secret\"");
+        command.wipe();
+        assert!(command.is_empty());
+
+        let mut request = b"LOGIN \"user\" \"secret\"".to_vec();
+        request.wipe();
+        assert!(request.is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+//! A scripted mock IMAP server for testing code that uses this crate, enabled with the `testing`
+//! feature.
+//!
+//! A [`Script`] lists what the server sends and which lines it expects from the client, in
+//! order. It drives a real [`Client`] and [`Session`](crate::Session) over an in-memory stream,
+//! and panics as soon as the client sends something else than the script expects.
+//!
+//! ```
+//! use async_imap::testing::Script;
+//!
+//! # async_std::task::block_on(async {
+//! let (client, script) = Script::new()
+//!     .respond("* OK IMAP4rev1 ready\r\n")
+//!     .expect("A0001 LOGIN \"user\" \"pass\"\r\n")
+//!     .respond("A0001 OK Logged in\r\n")
+//!     .expect("A0002 SELECT \"INBOX\"\r\n")
+//!     .respond("* 3 EXISTS\r\nA0002 OK [READ-WRITE] SELECT completed\r\n")
+//!     .connect()
+//!     .await
+//!     .unwrap();
+//!
+//! let mut session = client.login("user", "pass").await.map_err(|e| e.0).unwrap();
+//! let mailbox = session.select("INBOX").await.unwrap();
+//! assert_eq!(mailbox.exists, 3);
+//! script.assert_finished();
+//! # });
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use async_std::io::{self, Read, Write};
+use futures::task::{Context, Poll};
+
+use crate::client::Client;
+use crate::error::Result;
+use crate::types::{TlsInfo, TlsPeerInfo};
+
+/// One step of a [`Script`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// Data the server sends.
+    Respond(Vec<u8>),
+    /// Data the server expects the client to send.
+    Expect(Vec<u8>),
+}
+
+/// The exchange between a client and the mock server, see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    /// Creates an empty script.
+    pub fn new() -> Self {
+        Script::default()
+    }
+
+    /// The server sends `data`, e.g. the greeting, untagged responses or a tagged completion.
+    /// Lines must end with `\r\n`.
+    pub fn respond<D: AsRef<[u8]>>(mut self, data: D) -> Self {
+        self.steps.push(Step::Respond(data.as_ref().to_vec()));
+        self
+    }
+
+    /// The client must send exactly `data` next, e.g. `A0001 NOOP\r\n`. The server does not
+    /// send anything further until it has.
+    pub fn expect<D: AsRef<[u8]>>(mut self, data: D) -> Self {
+        self.steps.push(Step::Expect(data.as_ref().to_vec()));
+        self
+    }
+
+    /// Creates the stream to pass to [`Client::new`], together with a handle to check that the
+    /// script was played to the end.
+    pub fn build(self) -> (ScriptedStream, ScriptHandle) {
+        let state = Arc::new(Mutex::new(State {
+            steps: self.steps.into(),
+            written: Vec::new(),
+        }));
+        let handle = ScriptHandle {
+            state: state.clone(),
+        };
+        (ScriptedStream { state }, handle)
+    }
+
+    /// Creates a [`Client`] on the scripted stream and reads the server greeting, which must be
+    /// the first step of the script.
+    pub async fn connect(self) -> Result<(Client<ScriptedStream>, ScriptHandle)> {
+        let (stream, handle) = self.build();
+        let mut client = Client::new(stream);
+        client.read_greeting().await?;
+        Ok((client, handle))
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    steps: VecDeque<Step>,
+    /// Data the client sent that does not complete the expected step yet.
+    written: Vec<u8>,
+}
+
+/// Checks the progress of a [`Script`] after the stream was handed to the client.
+#[derive(Debug, Clone)]
+pub struct ScriptHandle {
+    state: Arc<Mutex<State>>,
+}
+
+impl ScriptHandle {
+    /// Panics unless every step of the script was played.
+    pub fn assert_finished(&self) {
+        let state = self.state.lock().unwrap();
+        if let Some(step) = state.steps.front() {
+            panic!(
+                "the script is not finished, the next step is {}",
+                describe(step)
+            );
+        }
+        assert!(
+            state.written.is_empty(),
+            "the client sent unexpected data: {:?}",
+            String::from_utf8_lossy(&state.written)
+        );
+    }
+}
+
+/// The in-memory stream between a [`Client`] and the mock server of a [`Script`].
+pub struct ScriptedStream {
+    state: Arc<Mutex<State>>,
+}
+
+impl fmt::Debug for ScriptedStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptedStream").finish()
+    }
+}
+
+impl TlsPeerInfo for ScriptedStream {
+    fn tls_info(&self) -> Option<TlsInfo> {
+        None
+    }
+}
+
+impl Read for ScriptedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        let read = match state.steps.front_mut() {
+            // The script is over, as if the server closed the connection.
+            None => 0,
+            Some(Step::Expect(expected)) => panic!(
+                "the client reads, but the script expects it to send {:?} first",
+                String::from_utf8_lossy(expected)
+            ),
+            Some(Step::Respond(data)) => {
+                let len = buf.len().min(data.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                data.drain(..len);
+                if data.is_empty() {
+                    state.steps.pop_front();
+                }
+                len
+            }
+        };
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl Write for ScriptedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        state.written.extend_from_slice(buf);
+        while !state.written.is_empty() {
+            let expected = match state.steps.front() {
+                Some(Step::Expect(expected)) => expected.clone(),
+                step => panic!(
+                    "the client sent {:?}, but the next step is {}",
+                    String::from_utf8_lossy(&state.written),
+                    step.map_or_else(|| "the end of the script".into(), describe)
+                ),
+            };
+            let len = expected.len().min(state.written.len());
+            assert_eq!(
+                String::from_utf8_lossy(&state.written[..len]),
+                String::from_utf8_lossy(&expected[..len]),
+                "the client sent something else than the script expects"
+            );
+            if len < expected.len() {
+                break;
+            }
+            state.written.drain(..len);
+            state.steps.pop_front();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn describe(step: &Step) -> String {
+    match step {
+        Step::Respond(data) => format!("to respond {:?}", String::from_utf8_lossy(data)),
+        Step::Expect(data) => format!("to expect {:?}", String::from_utf8_lossy(data)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::prelude::*;
+
+    fn login() -> Script {
+        Script::new()
+            .respond("* OK IMAP4rev1 ready\r\n")
+            .expect("A0001 LOGIN \"user\" \"pass\"\r\n")
+            .respond("A0001 OK Logged in\r\n")
+    }
+
+    #[async_std::test]
+    async fn select() {
+        let (client, script) = login()
+            .expect("A0002 SELECT \"INBOX\"\r\n")
+            .respond(
+                "* FLAGS (\\Answered \\Seen)\r\n\
+                 * 172 EXISTS\r\n\
+                 * OK [UIDVALIDITY 3857529045] UIDs valid\r\n\
+                 A0002 OK [READ-WRITE] SELECT completed\r\n",
+            )
+            .connect()
+            .await
+            .unwrap();
+
+        let mut session = client.login("user", "pass").await.map_err(|e| e.0).unwrap();
+        let mailbox = session.select("INBOX").await.unwrap();
+        assert_eq!(mailbox.exists, 172);
+        assert_eq!(mailbox.uid_validity, Some(3857529045));
+        script.assert_finished();
+    }
+
+    #[async_std::test]
+    async fn append_literal() {
+        let (client, script) = login()
+            .expect("A0002 APPEND \"Drafts\" {8}\r\n")
+            .respond("+ Ready for literal data\r\n")
+            .expect("Hello!\r\n\r\n")
+            .respond("A0002 OK APPEND completed\r\n")
+            .expect("A0003 SELECT \"Drafts\"\r\n")
+            .respond("* 1 EXISTS\r\nA0003 OK [READ-WRITE] SELECT completed\r\n")
+            .expect("A0004 FETCH 1 (UID)\r\n")
+            .respond("* 1 FETCH (UID 7)\r\nA0004 OK FETCH completed\r\n")
+            .connect()
+            .await
+            .unwrap();
+
+        let mut session = client.login("user", "pass").await.map_err(|e| e.0).unwrap();
+        session.append("Drafts", "Hello!\r\n").await.unwrap();
+        session.select("Drafts").await.unwrap();
+        let fetches: Vec<_> = session
+            .fetch("1", "(UID)")
+            .await
+            .unwrap()
+            .collect::<Result<_>>()
+            .await
+            .unwrap();
+        assert_eq!(fetches[0].uid, Some(7));
+        script.assert_finished();
+    }
+
+    #[async_std::test]
+    #[should_panic(expected = "the client sent something else than the script expects")]
+    async fn unexpected_command() {
+        let (client, _) = login().expect("A0002 NOOP\r\n").connect().await.unwrap();
+        let mut session = client.login("user", "pass").await.map_err(|e| e.0).unwrap();
+        let _ = session.capabilities().await;
+    }
+
+    #[async_std::test]
+    #[should_panic(expected = "the script is not finished")]
+    async fn unfinished() {
+        let (_, script) = login().connect().await.unwrap();
+        script.assert_finished();
+    }
+}
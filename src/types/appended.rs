@@ -0,0 +1,22 @@
+use super::{AppendUid, Seq};
+
+/// What the server reported about a message appended with
+/// [`Session::append_with_exists`](crate::Session::append_with_exists).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Appended {
+    /// The [`Uid`](super::Uid) of the message, if the server supports the `UIDPLUS` extension,
+    /// see [`Session::append`](crate::Session::append).
+    pub uid: Option<AppendUid>,
+    /// The number of messages in the selected mailbox from the `EXISTS` the server sent while
+    /// the message was appended to it, or `None` if the message was appended to another mailbox
+    /// or the server did not send one.
+    pub exists: Option<u32>,
+}
+
+impl Appended {
+    /// The [`Seq`] of the appended message, the last one in the selected mailbox. This assumes
+    /// that no other client appended a message at the same time.
+    pub fn seq(&self) -> Option<Seq> {
+        self.exists
+    }
+}
@@ -3,13 +3,38 @@ use std::collections::hash_set::Iter;
 use std::collections::HashSet;
 
 const IMAP4REV1_CAPABILITY: &str = "IMAP4rev1";
+const IMAP4REV2_CAPABILITY: &str = "IMAP4rev2";
 const AUTH_CAPABILITY_PREFIX: &str = "AUTH=";
 
+/// Extensions to IMAP4rev1 that are part of IMAP4rev2, see [appendix E of RFC
+/// 9051](https://tools.ietf.org/html/rfc9051#appendix-E).
+pub(crate) const IMAP4REV2_EXTENSIONS: &[&str] = &[
+    "BINARY",
+    "CHILDREN",
+    "ENABLE",
+    "ESEARCH",
+    "IDLE",
+    "LIST-EXTENDED",
+    "LIST-STATUS",
+    "LITERAL-",
+    "MOVE",
+    "NAMESPACE",
+    "SASL-IR",
+    "SEARCHRES",
+    "SPECIAL-USE",
+    "STATUS=SIZE",
+    "UIDPLUS",
+    "UNSELECT",
+];
+
 /// List of available Capabilities.
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum Capability {
     /// The crucial imap capability.
     Imap4rev1,
+    /// The revision of the protocol that [RFC 9051](https://tools.ietf.org/html/rfc9051)
+    /// specifies, which includes many extensions to IMAP4rev1.
+    Imap4rev2,
     /// Auth type capability.
     Auth(String),
     /// Any other atoms.
@@ -21,6 +46,9 @@ impl From<&CapabilityRef<'_>> for Capability {
         match c {
             CapabilityRef::Imap4rev1 => Capability::Imap4rev1,
             CapabilityRef::Auth(s) => Capability::Auth(s.clone().into_owned()),
+            CapabilityRef::Atom(s) if s.eq_ignore_ascii_case(IMAP4REV2_CAPABILITY) => {
+                Capability::Imap4rev2
+            }
             CapabilityRef::Atom(s) => Capability::Atom(s.clone().into_owned()),
         }
     }
@@ -51,6 +79,7 @@ impl From<&CapabilityRef<'_>> for Capability {
 ///
 /// Client implementations SHOULD NOT require any capability name other than `IMAP4rev1`, and MUST
 /// ignore any unknown capability names.
+#[derive(Debug, Clone)]
 pub struct Capabilities(pub(crate) HashSet<Capability>);
 
 impl Capabilities {
@@ -65,6 +94,9 @@ impl Capabilities {
         if s.eq_ignore_ascii_case(IMAP4REV1_CAPABILITY) {
             return self.has(&Capability::Imap4rev1);
         }
+        if s.eq_ignore_ascii_case(IMAP4REV2_CAPABILITY) {
+            return self.has(&Capability::Imap4rev2);
+        }
         if s.len() > AUTH_CAPABILITY_PREFIX.len() {
             let (pre, val) = s.split_at(AUTH_CAPABILITY_PREFIX.len());
             if pre.eq_ignore_ascii_case(AUTH_CAPABILITY_PREFIX) {
@@ -74,6 +106,12 @@ impl Capabilities {
         self.has(&Capability::Atom(s.into())) // TODO: avoid clone
     }
 
+    /// Check if the server only implements IMAP4rev2, i.e. it advertises `IMAP4rev2` but not
+    /// `IMAP4rev1`.
+    pub fn is_imap4rev2_only(&self) -> bool {
+        self.has(&Capability::Imap4rev2) && !self.has(&Capability::Imap4rev1)
+    }
+
     /// Iterate over all the server's capabilities
     pub fn iter(&self) -> Iter<'_, Capability> {
         self.0.iter()
@@ -0,0 +1,93 @@
+use super::{ResponseData, SequenceSet};
+
+/// The results of [`Session::esearch`](crate::Session::esearch), in the extended form of the
+/// [`ESEARCH` response](https://tools.ietf.org/html/rfc4731#section-3.1) that is also what
+/// `SEARCH` returns on IMAP4rev2 servers (RFC 9051).
+///
+/// Only the data that was requested with `RETURN` is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ESearch {
+    /// The tag of the command the results are for, if the server included it.
+    pub tag: Option<String>,
+    /// Whether the results are [`Uid`](super::Uid)s rather than [`Seq`](super::Seq)s.
+    pub uid: bool,
+    /// The lowest matching id (`MIN`).
+    pub min: Option<u32>,
+    /// The highest matching id (`MAX`).
+    pub max: Option<u32>,
+    /// All matching ids (`ALL`).
+    pub all: Option<SequenceSet>,
+    /// The number of matching messages (`COUNT`).
+    pub count: Option<u32>,
+    /// A window of the matching ids (`PARTIAL`), see
+    /// [`Session::esearch_partial`](crate::Session::esearch_partial).
+    pub partial: Option<Partial>,
+}
+
+/// A window of the results of a search, as returned with the [`PARTIAL` return
+/// option](https://tools.ietf.org/html/rfc9394#section-3.1).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Partial {
+    /// The position of the first result in the window, counted from 1 for the first match, or
+    /// from -1 for the last match.
+    pub first: i64,
+    /// The position of the last result in the window, counted like [`Partial::first`].
+    pub last: i64,
+    /// The matching ids in the window. Empty if there are none.
+    pub ids: SequenceSet,
+}
+
+impl Partial {
+    /// Parses the value of `PARTIAL`, e.g. `(1:50 4:6,9)` or `(-1:-50 NIL)`.
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.strip_prefix('(')?.strip_suffix(')')?;
+        let (range, ids) = value.split_once(' ')?;
+        let (first, last) = range.split_once(':')?;
+        let ids = if ids.eq_ignore_ascii_case("NIL") {
+            SequenceSet::default()
+        } else {
+            ids.parse().ok()?
+        };
+        Some(Partial {
+            first: first.parse().ok()?,
+            last: last.parse().ok()?,
+            ids,
+        })
+    }
+}
+
+impl ESearch {
+    /// All matching ids, in ascending order. Empty unless `ALL` or `PARTIAL` was returned.
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.all
+            .iter()
+            .chain(self.partial.iter().map(|partial| &partial.ids))
+            .flat_map(SequenceSet::ranges)
+            .flatten()
+    }
+
+    /// Reads the extended results from a `SEARCH` response that the fallback parser rewrote from
+    /// an `ESEARCH` response. Returns `None` for other responses.
+    pub(crate) fn from_response(resp: &ResponseData) -> Option<Self> {
+        let mut items = resp.raw_items().peekable();
+        items.peek()?;
+
+        let mut esearch = ESearch::default();
+        for (name, value) in items {
+            let value = value.and_then(|value| std::str::from_utf8(value).ok());
+            let number = || value.and_then(|value| value.parse().ok());
+            match name.to_ascii_uppercase().as_slice() {
+                b"TAG" => esearch.tag = value.map(Into::into),
+                b"UID" => esearch.uid = true,
+                b"MIN" => esearch.min = number(),
+                b"MAX" => esearch.max = number(),
+                b"ALL" => esearch.all = value.and_then(|value| value.parse().ok()),
+                b"COUNT" => esearch.count = number(),
+                b"PARTIAL" => esearch.partial = value.and_then(Partial::parse),
+                _ => {}
+            }
+        }
+
+        Some(esearch)
+    }
+}
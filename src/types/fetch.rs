@@ -1,9 +1,13 @@
+use std::ops::Range;
+use std::str;
+
 use chrono::{DateTime, FixedOffset};
 use imap_proto::types::{
-    AttributeValue, BodyStructure, Envelope, MessageSection, Response, SectionPath,
+    AttributeValue, BodyStructure, ContentEncoding, Envelope, MessageSection, Response, SectionPath,
 };
 
 use super::{Flag, Seq, Uid};
+use crate::fallback;
 use crate::types::ResponseData;
 
 /// Format of Date and Time as defined RFC3501.
@@ -28,31 +32,63 @@ pub struct Fetch {
     /// A number expressing the [RFC-2822](https://tools.ietf.org/html/rfc2822) size of the message.
     /// Only present if `RFC822.SIZE` was specified in the query argument to `FETCH`.
     pub size: Option<u32>,
+
+    /// The modification sequence of the message, the `MODSEQ` of the [`CONDSTORE`
+    /// extension](https://tools.ietf.org/html/rfc7162#section-3.1.4). Only present if `MODSEQ`
+    /// was specified in the query argument to `FETCH`, or in the response to a `STORE` once
+    /// `CONDSTORE` is enabled, where it is the new value after the change.
+    pub modseq: Option<u64>,
+}
+
+/// A `BODY[<section>]` of a `FETCH` response, with the origin the server echoed for a partial
+/// fetch such as `BODY[]<1024.2048>`, see [`Fetch::body_parts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyPart<'a> {
+    /// The section, or `None` for the whole message, `BODY[]`.
+    pub section: Option<&'a SectionPath>,
+    /// The octet the data starts at, e.g. `1024` for `BODY[]<1024>`, or `None` if the section was
+    /// not fetched partially.
+    pub origin: Option<u32>,
+    /// The data, or an empty slice for `NIL`.
+    pub data: &'a [u8],
+}
+
+impl BodyPart<'_> {
+    /// The octets of the section that [`BodyPart::data`] holds. It can end before the requested
+    /// range did, if the section ends sooner; it is empty if the origin is beyond its end.
+    pub fn range(&self) -> Range<usize> {
+        let start = self.origin.unwrap_or(0) as usize;
+        start..start + self.data.len()
+    }
 }
 
 impl Fetch {
     pub(crate) fn new(response: ResponseData) -> Self {
-        let (message, uid, size) = if let Response::Fetch(message, attrs) = response.parsed() {
-            let mut uid = None;
-            let mut size = None;
-
-            for attr in attrs {
-                match attr {
-                    AttributeValue::Uid(id) => uid = Some(*id),
-                    AttributeValue::Rfc822Size(sz) => size = Some(*sz),
-                    _ => {}
+        let (message, uid, size, modseq) =
+            if let Response::Fetch(message, attrs) = response.parsed() {
+                let mut uid = None;
+                let mut size = None;
+                let mut modseq = None;
+
+                for attr in attrs {
+                    match attr {
+                        AttributeValue::Uid(id) => uid = Some(*id),
+                        AttributeValue::Rfc822Size(sz) => size = Some(*sz),
+                        AttributeValue::ModSeq(value) => modseq = Some(*value),
+                        _ => {}
+                    }
                 }
-            }
-            (*message, uid, size)
-        } else {
-            unreachable!()
-        };
+                (*message, uid, size, modseq)
+            } else {
+                unreachable!()
+            };
 
         Fetch {
             response,
             message,
             uid,
             size,
+            modseq,
         }
     }
 
@@ -75,6 +111,9 @@ impl Fetch {
 
     /// The bytes that make up the header of this message, if `BODY[HEADER]`, `BODY.PEEK[HEADER]`,
     /// or `RFC822.HEADER` was included in the `query` argument to `FETCH`.
+    ///
+    /// Header fields fetched with `BODY[HEADER.FIELDS (...)]` are returned here as well, use
+    /// [`Fetch::header_fields`] to tell several of them apart.
     pub fn header(&self) -> Option<&[u8]> {
         if let Response::Fetch(_, attrs) = self.response.parsed() {
             attrs
@@ -94,6 +133,33 @@ impl Fetch {
         }
     }
 
+    /// The header fields of this message that were fetched with `BODY[HEADER.FIELDS (<fields>)]`
+    /// or `BODY.PEEK[HEADER.FIELDS (<fields>)]`, see [`FetchItems::body_peek_header_fields`].
+    /// `fields` are the requested field names, in any order and case.
+    ///
+    /// [`FetchItems::body_peek_header_fields`]: super::FetchItems::body_peek_header_fields
+    pub fn header_fields<S: AsRef<str>>(&self, fields: &[S]) -> Option<&[u8]> {
+        // `imap_proto` reports every `HEADER.FIELDS` section as the plain header, so the
+        // sections are matched up with the item names in the response, in order.
+        let names = fallback::fetch_item_names(self.response.borrow_raw());
+        let index = names
+            .into_iter()
+            .filter(|name| starts_with_no_case(name, b"BODY["))
+            .position(|name| is_header_fields(name, fields))?;
+        if let Response::Fetch(_, attrs) = self.response.parsed() {
+            attrs
+                .iter()
+                .filter_map(|av| match av {
+                    AttributeValue::BodySection { data, .. } => Some(data),
+                    _ => None,
+                })
+                .nth(index)?
+                .as_deref()
+        } else {
+            unreachable!()
+        }
+    }
+
     /// The bytes that make up this message, included if `BODY[]` or `RFC822` was included in the
     /// `query` argument to `FETCH`. The bytes SHOULD be interpreted by the client according to the
     /// content transfer encoding, body type, and subtype.
@@ -116,6 +182,24 @@ impl Fetch {
         }
     }
 
+    /// This message parsed into its headers and MIME parts, included if `BODY[]` or `RFC822` was
+    /// included in the `query` argument to `FETCH`, see [`Fetch::body`]. Requires the `mail`
+    /// feature.
+    ///
+    /// The message is parsed from the raw bytes, so `8bit` parts in other charsets are kept
+    /// intact. The parts are decoded with [`ParsedMail::get_body`] or
+    /// [`ParsedMail::get_body_raw`].
+    ///
+    /// [`ParsedMail::get_body`]: crate::ParsedMail::get_body
+    /// [`ParsedMail::get_body_raw`]: crate::ParsedMail::get_body_raw
+    #[cfg(feature = "mail")]
+    pub fn mail(&self) -> Option<crate::error::Result<mailparse::ParsedMail<'_>>> {
+        Some(
+            mailparse::parse_mail(self.body()?)
+                .map_err(|err| crate::error::ParseError::Mail(err).into()),
+        )
+    }
+
     /// The bytes that make up the text of this message, included if `BODY[TEXT]`, `RFC822.TEXT`,
     /// or `BODY.PEEK[TEXT]` was included in the `query` argument to `FETCH`. The bytes SHOULD be
     /// interpreted by the client according to the content transfer encoding, body type, and
@@ -139,6 +223,50 @@ impl Fetch {
         }
     }
 
+    /// All `BODY[<section>]` data items of the response, in the order the server sent them.
+    ///
+    /// Unlike [`Fetch::body`], [`Fetch::text`] and [`Fetch::section`], these tell a partial fetch
+    /// such as `BODY.PEEK[]<0.65536>` apart from a full one, so that a large message can be
+    /// downloaded in chunks and put back together from [`BodyPart::range`]:
+    ///
+    /// ```no_run
+    /// use async_imap::{types::Uid, Session, error::Result};
+    /// use async_std::net::TcpStream;
+    ///
+    /// async fn download(uid: Uid, s: &mut Session<TcpStream>) -> Result<Vec<u8>> {
+    ///     const CHUNK: usize = 65536;
+    ///     let mut body = Vec::new();
+    ///     loop {
+    ///         let query = format!("BODY.PEEK[]<{}.{}>", body.len(), CHUNK);
+    ///         let fetches = s.uid_fetch_all(uid, query).await?;
+    ///         let part = fetches.iter().flat_map(|fetch| fetch.body_parts()).next();
+    ///         let data = part.map(|part| part.data).unwrap_or_default();
+    ///         body.extend_from_slice(data);
+    ///         if data.len() < CHUNK {
+    ///             return Ok(body);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn body_parts(&self) -> impl Iterator<Item = BodyPart<'_>> {
+        if let Response::Fetch(_, attrs) = self.response.parsed() {
+            attrs.iter().filter_map(|av| match av {
+                AttributeValue::BodySection {
+                    section,
+                    index,
+                    data,
+                } => Some(BodyPart {
+                    section: section.as_ref(),
+                    origin: *index,
+                    data: data.as_deref().unwrap_or_default(),
+                }),
+                _ => None,
+            })
+        } else {
+            unreachable!()
+        }
+    }
+
     /// The envelope of this message, if `ENVELOPE` was included in the `query` argument to
     /// `FETCH`. This is computed by the server by parsing the
     /// [RFC-2822](https://tools.ietf.org/html/rfc2822) header into the component parts, defaulting
@@ -182,6 +310,89 @@ impl Fetch {
         }
     }
 
+    /// Extract the decoded bytes of the given `BINARY[<section>]` of a `FETCH` response, see
+    /// [`Session::binary_fetch`](crate::Session::binary_fetch).
+    ///
+    /// `section` is a part number such as `1` or `1.2`, or empty for the whole message. See
+    /// [RFC 3516](https://tools.ietf.org/html/rfc3516) for details.
+    pub fn binary(&self, section: &str) -> Option<&[u8]> {
+        self.raw_item(&format!("BINARY[{}]", section)).flatten()
+    }
+
+    /// Extract the decoded size of the given `BINARY.SIZE[<section>]` of a `FETCH` response, see
+    /// [`Session::binary_size`](crate::Session::binary_size).
+    pub fn binary_size(&self, section: &str) -> Option<u32> {
+        self.raw_number(&format!("BINARY.SIZE[{}]", section))
+    }
+
+    /// Extract the Gmail message id (`X-GM-MSGID`) of a `FETCH` response, which uniquely
+    /// identifies the message across all mailboxes of the account. See
+    /// [`extensions::gmail`](crate::extensions::gmail).
+    pub fn gmail_msg_id(&self) -> Option<u64> {
+        self.raw_number("X-GM-MSGID")
+    }
+
+    /// Extract the Gmail thread id (`X-GM-THRID`) of a `FETCH` response, which is shared by all
+    /// messages of a conversation. See [`extensions::gmail`](crate::extensions::gmail).
+    pub fn gmail_thread_id(&self) -> Option<u64> {
+        self.raw_number("X-GM-THRID")
+    }
+
+    /// Extract the Gmail labels (`X-GM-LABELS`) of a `FETCH` response, decoded from modified
+    /// UTF-7. System labels keep their leading backslash, e.g. `\Inbox` or `\Important`.
+    /// See [`extensions::gmail`](crate::extensions::gmail).
+    pub fn gmail_labels(&self) -> Option<Vec<String>> {
+        self.raw_item("X-GM-LABELS")
+            .flatten()
+            .and_then(crate::extensions::gmail::parse_labels)
+    }
+
+    /// Extract the `EMAILID` of a `FETCH` response, a server-assigned id that identifies the
+    /// message content, also across mailboxes and after moves. This requires the [`OBJECTID`
+    /// extension](https://tools.ietf.org/html/rfc8474), see
+    /// [`Session::fetch_object_ids`](crate::Session::fetch_object_ids).
+    pub fn email_id(&self) -> Option<&str> {
+        self.raw_object_id("EMAILID")
+    }
+
+    /// Extract the `THREADID` of a `FETCH` response, a server-assigned id shared by all messages
+    /// of a thread. This is `None` if the server does not thread messages. Requires the
+    /// [`OBJECTID` extension](https://tools.ietf.org/html/rfc8474), see
+    /// [`Session::fetch_object_ids`](crate::Session::fetch_object_ids).
+    pub fn thread_id(&self) -> Option<&str> {
+        self.raw_object_id("THREADID")
+    }
+
+    /// The id in a parenthesized `objectid` data item, or `None` if it is `NIL`.
+    fn raw_object_id(&self, name: &str) -> Option<&str> {
+        self.raw_item(name)
+            .flatten()
+            .and_then(|value| str::from_utf8(value).ok())
+            .and_then(|value| value.strip_prefix('(')?.strip_suffix(')'))
+            .map(str::trim)
+    }
+
+    fn raw_number<T: str::FromStr>(&self, name: &str) -> Option<T> {
+        self.raw_item(name)
+            .flatten()
+            .and_then(|value| str::from_utf8(value).ok()?.parse().ok())
+    }
+
+    /// The data items of the response that this crate does not model, e.g. those of a
+    /// proprietary extension, as name/value pairs borrowed from the response. Names include any
+    /// section, e.g. `BINARY[1]`. Values are `None` for `NIL`, have quoting and literal framing
+    /// removed, and are otherwise as the server sent them, including the parentheses of a list.
+    pub fn raw_items(&self) -> impl Iterator<Item = (&[u8], Option<&[u8]>)> + '_ {
+        self.response.raw_items()
+    }
+
+    /// The value of a data item that `imap_proto` could not parse.
+    fn raw_item(&self, name: &str) -> Option<Option<&[u8]>> {
+        self.raw_items()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| value)
+    }
+
     /// Extract the `INTERNALDATE` of a `FETCH` response
     ///
     /// See [section 2.3.3 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.3.3) for
@@ -206,6 +417,73 @@ impl Fetch {
         }
     }
 
+    /// The text of a message that is a single `text/*` part, such as a plain `text/plain` e-mail,
+    /// with the content transfer encoding removed and decoded from its charset. Charsets other
+    /// than UTF-8, US-ASCII and ISO-8859-1 are read as UTF-8, and invalid text is replaced with
+    /// `U+FFFD`.
+    ///
+    /// The type and encoding are taken from the header if `BODY[]` or `RFC822` was fetched, see
+    /// [`Fetch::body`], and otherwise from the `BODYSTRUCTURE` fetched along with the
+    /// [`Fetch::text`]. Returns `None` if neither was fetched, if base64 text is not valid, or if
+    /// the message is not a single text part, e.g. `multipart/alternative`; its parts can be found
+    /// in [`Fetch::bodystructure`] and fetched by [`Fetch::section`] then.
+    pub fn decoded_text(&self) -> Option<String> {
+        let (charset, encoding, data) = match self.body() {
+            Some(body) => {
+                let (header, data) = split_header(body);
+                let content_type = header_field(header, "Content-Type")
+                    .unwrap_or_else(|| "text/plain".to_string());
+                let mut params = content_type.split(';');
+                let ty = params.next().unwrap_or_default().trim();
+                if !starts_with_no_case(ty.as_bytes(), b"text/") {
+                    return None;
+                }
+                let charset = params.find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    let value = value.trim().trim_matches('"');
+                    name.trim()
+                        .eq_ignore_ascii_case("charset")
+                        .then(|| value.to_string())
+                });
+                let encoding = header_field(header, "Content-Transfer-Encoding");
+                (charset, encoding.unwrap_or_default(), data)
+            }
+            None => match self.bodystructure()? {
+                BodyStructure::Text { common, other, .. } => {
+                    let params = common.ty.params.as_deref().unwrap_or_default();
+                    let charset = params
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case("charset"))
+                        .map(|(_, value)| value.to_string());
+                    let encoding = match &other.transfer_encoding {
+                        ContentEncoding::Base64 => "base64",
+                        ContentEncoding::QuotedPrintable => "quoted-printable",
+                        ContentEncoding::Other(encoding) => encoding,
+                        _ => "",
+                    };
+                    (charset, encoding.to_string(), self.text()?)
+                }
+                _ => return None,
+            },
+        };
+        let data = match encoding.trim().to_ascii_lowercase().as_str() {
+            "base64" => decode_base64(data)?,
+            "quoted-printable" => decode_quoted_printable(data),
+            _ => data.to_vec(),
+        };
+        let latin1 = match charset {
+            Some(charset) => ["ISO-8859-1", "ISO_8859-1", "LATIN1"]
+                .iter()
+                .any(|name| charset.eq_ignore_ascii_case(name)),
+            None => false,
+        };
+        if latin1 {
+            Some(data.iter().map(|&b| char::from(b)).collect())
+        } else {
+            Some(String::from_utf8_lossy(&data).into_owned())
+        }
+    }
+
     /// Extract the `BODYSTRUCTURE` of a `FETCH` response
     ///
     /// See [section 2.3.6 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.3.6) for
@@ -224,3 +502,122 @@ impl Fetch {
         }
     }
 }
+
+/// Splits a message at the blank line after its header.
+fn split_header(message: &[u8]) -> (&[u8], &[u8]) {
+    for separator in &[&b"\r\n\r\n"[..], &b"\n\n"[..]] {
+        if let Some(i) = message
+            .windows(separator.len())
+            .position(|window| window == *separator)
+        {
+            return (&message[..i], &message[i + separator.len()..]);
+        }
+    }
+    (message, &[])
+}
+
+/// The unfolded value of the first field called `name` in `header`.
+fn header_field(header: &[u8], name: &str) -> Option<String> {
+    let header = String::from_utf8_lossy(header);
+    let mut lines = header.split('\n').map(|line| line.trim_end_matches('\r'));
+    let first = lines.find_map(|line| {
+        let (field, value) = line.split_once(':')?;
+        field.trim().eq_ignore_ascii_case(name).then_some(value)
+    })?;
+    let mut value = first.trim().to_string();
+    for line in lines.take_while(|line| line.starts_with(&[' ', '\t'][..])) {
+        value.push(' ');
+        value.push_str(line.trim());
+    }
+    Some(value)
+}
+
+/// Decodes base64 text, ignoring line breaks and other characters outside the alphabet. Returns
+/// `None` if it is still not valid.
+fn decode_base64(data: &[u8]) -> Option<Vec<u8>> {
+    let encoded: Vec<u8> = data
+        .iter()
+        .copied()
+        .filter(|&b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+        .collect();
+    base64::decode(&encoded).ok()
+}
+
+/// Decodes quoted-printable text, see [RFC 2045](https://tools.ietf.org/html/rfc2045#section-6.7).
+/// An `=` that does not start an escape is kept.
+fn decode_quoted_printable(data: &[u8]) -> Vec<u8> {
+    let hex = |b: u8| char::from(b).to_digit(16);
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == b'=' {
+            match data.get(i + 1..i + 3) {
+                // A soft line break.
+                Some(b"\r\n") => {
+                    i += 3;
+                    continue;
+                }
+                _ if data.get(i + 1) == Some(&b'\n') => {
+                    i += 2;
+                    continue;
+                }
+                Some(&[high, low]) => {
+                    if let (Some(high), Some(low)) = (hex(high), hex(low)) {
+                        decoded.push((high * 16 + low) as u8);
+                        i += 3;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+        }
+        decoded.push(data[i]);
+        i += 1;
+    }
+    decoded
+}
+
+fn starts_with_no_case(data: &[u8], prefix: &[u8]) -> bool {
+    data.len() >= prefix.len() && data[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+/// Whether the item `name` is `BODY[HEADER.FIELDS (<fields>)]`.
+fn is_header_fields<S: AsRef<str>>(name: &[u8], fields: &[S]) -> bool {
+    const PREFIX: &[u8] = b"BODY[HEADER.FIELDS (";
+    if !starts_with_no_case(name, PREFIX) {
+        return false;
+    }
+    let mut listed = Vec::new();
+    let mut field = Vec::new();
+    let mut bytes = name[PREFIX.len()..].iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'"' => {
+                while let Some(&b) = bytes.next() {
+                    match b {
+                        b'"' => break,
+                        b'\\' => field.extend(bytes.next()),
+                        _ => field.push(b),
+                    }
+                }
+            }
+            b' ' | b')' => {
+                if !field.is_empty() {
+                    listed.push(field.to_ascii_uppercase());
+                    field.clear();
+                }
+                if b == b')' {
+                    break;
+                }
+            }
+            _ => field.push(b),
+        }
+    }
+    let mut requested: Vec<_> = fields
+        .iter()
+        .map(|field| field.as_ref().to_ascii_uppercase().into_bytes())
+        .collect();
+    listed.sort();
+    requested.sort();
+    listed == requested
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+
+/// The data items to fetch with [`Session::fetch`](crate::Session::fetch) and
+/// [`Session::uid_fetch`](crate::Session::uid_fetch), rendered as a parenthesized list.
+///
+/// `UID` is always fetched, so that the results of a fetch by sequence number can be correlated
+/// with the messages later on. Items that are added more than once are only fetched once, and
+/// `BODY.PEEK[<section>]` is dropped if `BODY[<section>]` is fetched as well.
+///
+/// Body sections are fetched with `BODY.PEEK` by default, so that fetching a message, e.g. to
+/// show a preview, does not mark it as [`Flag::Seen`](super::Flag::Seen). Call
+/// [`FetchItems::peek`] with `false` to fetch the sections added with
+/// [`FetchItems::body_section`] with `BODY` instead, which sets the flag.
+///
+/// ```
+/// use async_imap::types::FetchItems;
+///
+/// let items = FetchItems::new()
+///     .flags()
+///     .envelope()
+///     .body_section("HEADER")
+///     .flags();
+/// assert_eq!(items.to_string(), "(UID FLAGS ENVELOPE BODY.PEEK[HEADER])");
+/// assert_eq!(items.peek(false).to_string(), "(UID FLAGS ENVELOPE BODY[HEADER])");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchItems {
+    /// The data items in the order they were added, starting with `UID`.
+    items: Vec<Item>,
+    /// Whether sections added with [`FetchItems::body_section`] are fetched with `BODY.PEEK`.
+    peek: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    /// A data item without arguments, e.g. `FLAGS`.
+    Name(&'static str),
+    /// A body section, e.g. `HEADER`, and whether it is always fetched with `BODY.PEEK`.
+    Section(String, bool),
+}
+
+impl Default for FetchItems {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FetchItems {
+    /// Creates a list that only fetches `UID`.
+    pub fn new() -> Self {
+        FetchItems {
+            items: vec![Item::Name("UID")],
+            peek: true,
+        }
+    }
+
+    /// Fetches the unique identifier of the message (`UID`). This is always fetched.
+    pub fn uid(self) -> Self {
+        self.item("UID")
+    }
+
+    /// Fetches the flags of the message (`FLAGS`).
+    pub fn flags(self) -> Self {
+        self.item("FLAGS")
+    }
+
+    /// Fetches the envelope structure of the message (`ENVELOPE`).
+    pub fn envelope(self) -> Self {
+        self.item("ENVELOPE")
+    }
+
+    /// Fetches the internal date of the message (`INTERNALDATE`).
+    pub fn internal_date(self) -> Self {
+        self.item("INTERNALDATE")
+    }
+
+    /// Fetches the MIME body structure of the message (`BODYSTRUCTURE`).
+    pub fn body_structure(self) -> Self {
+        self.item("BODYSTRUCTURE")
+    }
+
+    /// Fetches the size of the message (`RFC822.SIZE`).
+    pub fn rfc822_size(self) -> Self {
+        self.item("RFC822.SIZE")
+    }
+
+    /// Sets whether the sections added with [`FetchItems::body_section`], before or after this
+    /// call, are fetched with `BODY.PEEK[<section>]`, which leaves the flags of the message alone,
+    /// or with `BODY[<section>]`, which sets [`Flag::Seen`](super::Flag::Seen). This is `true`
+    /// by default.
+    pub fn peek(mut self, peek: bool) -> Self {
+        self.peek = peek;
+        self
+    }
+
+    /// Fetches a body section of the message, e.g. `""` for the entire message or `"1.2"` for a
+    /// part. This uses `BODY.PEEK[<section>]` unless [`FetchItems::peek`] is turned off, in which
+    /// case `BODY[<section>]` is used and [`Flag::Seen`](super::Flag::Seen) set on the message.
+    pub fn body_section<S: AsRef<str>>(self, section: S) -> Self {
+        self.section(section.as_ref(), false)
+    }
+
+    /// Fetches a body section of the message like [`FetchItems::body_section`], but always
+    /// without setting [`Flag::Seen`](super::Flag::Seen) (`BODY.PEEK[<section>]`), unless the
+    /// same section is also fetched with `body_section`.
+    pub fn body_peek_section<S: AsRef<str>>(self, section: S) -> Self {
+        self.section(section.as_ref(), true)
+    }
+
+    /// Fetches only the given header fields of the message, without setting
+    /// [`Flag::Seen`](super::Flag::Seen) (`BODY.PEEK[HEADER.FIELDS (<fields>)]`), e.g. to list
+    /// messages by `From`, `Subject` and `Date`. They are returned by
+    /// [`Fetch::header_fields`](super::Fetch::header_fields).
+    pub fn body_peek_header_fields<S: AsRef<str>>(self, fields: &[S]) -> Self {
+        let fields: Vec<_> = fields.iter().map(|field| astring(field.as_ref())).collect();
+        self.body_peek_section(format!("HEADER.FIELDS ({})", fields.join(" ")))
+    }
+
+    /// Adds a data item unless it is already in the list.
+    fn item(mut self, name: &'static str) -> Self {
+        if !self.items.contains(&Item::Name(name)) {
+            self.items.push(Item::Name(name));
+        }
+        self
+    }
+
+    /// Adds a body section, or makes it follow [`FetchItems::peek`] if it is already in the list
+    /// and `always_peek` is `false`.
+    fn section(mut self, section: &str, always_peek: bool) -> Self {
+        let existing = self.items.iter_mut().find_map(|item| match item {
+            Item::Section(s, peek) if s.eq_ignore_ascii_case(section) => Some(peek),
+            _ => None,
+        });
+        match existing {
+            Some(peek) => *peek &= always_peek,
+            None => self
+                .items
+                .push(Item::Section(section.to_string(), always_peek)),
+        }
+        self
+    }
+}
+
+/// Quotes `s` unless it is a valid atom.
+fn astring(s: &str) -> String {
+    let is_atom = !s.is_empty()
+        && s.bytes().all(|b| {
+            b.is_ascii_graphic()
+                && !matches!(b, b'(' | b')' | b'{' | b'%' | b'*' | b'"' | b'\\' | b']')
+        });
+    if is_atom {
+        s.to_string()
+    } else {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+impl fmt::Display for FetchItems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(")?;
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            match item {
+                Item::Name(name) => f.write_str(name)?,
+                Item::Section(section, always_peek) if *always_peek || self.peek => {
+                    write!(f, "BODY.PEEK[{}]", section)?
+                }
+                Item::Section(section, _) => write!(f, "BODY[{}]", section)?,
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedup() {
+        let items = FetchItems::new()
+            .flags()
+            .rfc822_size()
+            .flags()
+            .body_structure()
+            .rfc822_size();
+        assert_eq!(items.to_string(), "(UID FLAGS RFC822.SIZE BODYSTRUCTURE)");
+
+        let items = FetchItems::new()
+            .peek(false)
+            .body_peek_section("1.2")
+            .body_section("1.2")
+            .body_peek_section("1.2")
+            .body_section("TEXT");
+        assert_eq!(items.to_string(), "(UID BODY[1.2] BODY[TEXT])");
+    }
+
+    #[test]
+    fn peek() {
+        let items = FetchItems::new().body_section("").body_peek_section("TEXT");
+        assert_eq!(items.to_string(), "(UID BODY.PEEK[] BODY.PEEK[TEXT])");
+        let items = items.peek(false);
+        assert_eq!(items.to_string(), "(UID BODY[] BODY.PEEK[TEXT])");
+        let items = items.body_section("text").peek(true);
+        assert_eq!(items.to_string(), "(UID BODY.PEEK[] BODY.PEEK[TEXT])");
+        assert_eq!(items.peek(false).to_string(), "(UID BODY[] BODY[TEXT])");
+    }
+
+    #[test]
+    fn header_fields() {
+        let items = FetchItems::new().body_peek_header_fields(&["From", "Subject", "Message-ID"]);
+        assert_eq!(
+            items.to_string(),
+            "(UID BODY.PEEK[HEADER.FIELDS (From Subject Message-ID)])"
+        );
+        let items = FetchItems::new().body_peek_header_fields(&["X Odd", "Date"]);
+        assert_eq!(
+            items.to_string(),
+            "(UID BODY.PEEK[HEADER.FIELDS (\"X Odd\" Date)])"
+        );
+    }
+
+    #[test]
+    fn implicit_uid() {
+        assert_eq!(FetchItems::new().to_string(), "(UID)");
+        assert_eq!(
+            FetchItems::new().envelope().uid().to_string(),
+            "(UID ENVELOPE)"
+        );
+    }
+}
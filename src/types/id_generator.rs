@@ -1,13 +1,24 @@
 use imap_proto::RequestId;
 
-#[derive(Debug)]
+/// Generates the tags of commands: `A0001`, `A0002` and so on, or `<prefix>1`, `<prefix>2` and so
+/// on once a prefix is set. Tags are never reused.
+#[derive(Debug, Clone)]
 pub struct IdGenerator {
+    prefix: Option<String>,
     next: u64,
 }
 
 impl IdGenerator {
     pub fn new() -> Self {
-        Self { next: 0 }
+        Self {
+            prefix: None,
+            next: 0,
+        }
+    }
+
+    /// Sets the prefix of the following tags. Numbering continues where it left off.
+    pub fn set_prefix(&mut self, prefix: String) {
+        self.prefix = Some(prefix);
     }
 }
 
@@ -21,6 +32,9 @@ impl Iterator for IdGenerator {
     type Item = RequestId;
     fn next(&mut self) -> Option<Self::Item> {
         self.next += 1;
-        Some(RequestId(format!("A{:04}", self.next % 10_000)))
+        match &self.prefix {
+            Some(prefix) => Some(RequestId(format!("{}{}", prefix, self.next))),
+            None => Some(RequestId(format!("A{:04}", self.next))),
+        }
     }
 }
@@ -3,7 +3,11 @@ use std::fmt;
 
 /// Meta-information about an IMAP mailbox, as returned by
 /// [`SELECT`](https://tools.ietf.org/html/rfc3501#section-6.3.1) and friends.
+///
+/// More fields may be added as further extensions are supported, so it can only be built from
+/// [`Mailbox::default`] outside this crate.
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Default)]
+#[non_exhaustive]
 pub struct Mailbox {
     /// Defined flags in the mailbox.  See the description of the [FLAGS
     /// response](https://tools.ietf.org/html/rfc3501#section-7.2.6) for more detail.
@@ -17,9 +21,12 @@ pub struct Mailbox {
     /// response](https://tools.ietf.org/html/rfc3501#section-7.3.2) for more detail.
     pub recent: u32,
 
-    /// The message sequence number of the first unseen message in the mailbox.  If this is
+    /// The message sequence number of the first unseen message in the mailbox, from the `UNSEEN`
+    /// response code.  This is a position, not a count: it is unrelated to [`Mailbox::recent`],
+    /// as messages can be unseen without being recent and the other way around.  If this is
     /// missing, the client can not make any assumptions about the first unseen message in the
-    /// mailbox, and needs to issue a `SEARCH` command if it wants to find it.
+    /// mailbox, and needs to issue a `SEARCH` command if it wants to find it.  IMAP4rev2 servers
+    /// never send it.
     pub unseen: Option<u32>,
 
     /// A list of message flags that the client can change permanently.  If this is missing, the
@@ -35,6 +42,93 @@ pub struct Mailbox {
     /// The unique identifier validity value.  See [`Uid`] for more details.  If this is missing,
     /// the server does not support unique identifiers.
     pub uid_validity: Option<u32>,
+
+    /// The server-assigned id of the mailbox, which stays the same when the mailbox is renamed.
+    /// This is only sent by servers that support the [`OBJECTID`
+    /// extension](https://tools.ietf.org/html/rfc8474).
+    pub mailbox_id: Option<String>,
+
+    /// Whether the mailbox was opened read-only, from the `READ-ONLY` response code of the
+    /// completion.  Mailboxes opened with [`Session::examine`](crate::Session::examine) are
+    /// always read-only; a mailbox opened with [`Session::select`](crate::Session::select) can
+    /// still be read-only, e.g. if the user lacks the rights to change it.  No `STORE`, `EXPUNGE`
+    /// or other changes are allowed in a read-only mailbox.
+    pub read_only: bool,
+
+    /// The highest mod-sequence of the messages in the mailbox, from the `HIGHESTMODSEQ`
+    /// response code of the [`CONDSTORE` extension](https://tools.ietf.org/html/rfc7162). It is
+    /// sent once `CONDSTORE` is enabled, e.g. by
+    /// [`Session::select_condstore`](crate::Session::select_condstore), unless the mailbox does
+    /// not support mod-sequences.
+    pub highest_modseq: Option<u64>,
+}
+
+/// What changed between two selects of a mailbox, see [`Mailbox::diff`]. Each field holds the
+/// later value if it differs from the earlier one, and `None` if it is unchanged or unknown.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct MailboxDiff {
+    /// The number of messages in the mailbox.
+    pub exists: Option<u32>,
+    /// The next unique identifier value: messages were appended if it grew.
+    pub uid_next: Option<Uid>,
+    /// The unique identifier validity value.
+    pub uid_validity: Option<u32>,
+    /// Whether the `UIDVALIDITY` changed, or is no longer or only now known. The cached [`Uid`]s
+    /// of the mailbox are then invalid, and it has to be synchronized from scratch.
+    pub resync: bool,
+    /// The highest mod-sequence: messages changed if it grew.
+    pub highest_modseq: Option<u64>,
+}
+
+impl Mailbox {
+    /// Whether `keyword`, e.g. `$Junk`, can be stored permanently on the messages of this
+    /// mailbox: it is listed in [`Mailbox::permanent_flags`], which are compared
+    /// case-insensitively, or they include [`Flag::MayCreate`] (`\*`) so that new keywords can be
+    /// created. If the server did not send `PERMANENTFLAGS`, all flags are assumed to be
+    /// permanent.
+    pub fn can_store_keyword(&self, keyword: &str) -> bool {
+        self.permanent_flags.is_empty()
+            || self.permanent_flags.iter().any(|flag| match flag {
+                Flag::MayCreate => true,
+                Flag::Custom(flag) => flag.eq_ignore_ascii_case(keyword),
+                _ => false,
+            })
+    }
+
+    /// Compares this mailbox with `later`, the same mailbox selected again, e.g. to find out what
+    /// to synchronize.
+    ///
+    /// ```
+    /// use async_imap::types::Mailbox;
+    ///
+    /// let mut before = Mailbox::default();
+    /// before.exists = 3;
+    /// before.uid_validity = Some(7);
+    /// let mut after = before.clone();
+    /// after.exists = 5;
+    /// let diff = before.diff(&after);
+    /// assert_eq!(diff.exists, Some(5));
+    /// assert!(!diff.resync);
+    /// ```
+    pub fn diff(&self, later: &Mailbox) -> MailboxDiff {
+        fn changed<T: PartialEq + Copy>(earlier: Option<T>, later: Option<T>) -> Option<T> {
+            later.filter(|_| earlier != later)
+        }
+        MailboxDiff {
+            exists: changed(Some(self.exists), Some(later.exists)),
+            uid_next: changed(self.uid_next, later.uid_next),
+            uid_validity: changed(self.uid_validity, later.uid_validity),
+            resync: self.uid_validity != later.uid_validity,
+            highest_modseq: changed(self.highest_modseq, later.highest_modseq),
+        }
+    }
+}
+
+impl MailboxDiff {
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        *self == MailboxDiff::default()
+    }
 }
 
 impl fmt::Display for Mailbox {
@@ -42,14 +136,87 @@ impl fmt::Display for Mailbox {
         write!(
             f,
             "flags: {:?}, exists: {}, recent: {}, unseen: {:?}, permanent_flags: {:?},\
-             uid_next: {:?}, uid_validity: {:?}",
+             uid_next: {:?}, uid_validity: {:?}, mailbox_id: {:?}, read_only: {}, \
+             highest_modseq: {:?}",
             self.flags,
             self.exists,
             self.recent,
             self.unseen,
             self.permanent_flags,
             self.uid_next,
-            self.uid_validity
+            self.uid_validity,
+            self.mailbox_id,
+            self.read_only,
+            self.highest_modseq
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_store_keyword() {
+        let mut mailbox = Mailbox {
+            permanent_flags: vec![Flag::Deleted, Flag::Seen, Flag::Custom("$Junk".into())],
+            ..Mailbox::default()
+        };
+        assert!(mailbox.can_store_keyword("$Junk"));
+        assert!(mailbox.can_store_keyword("$junk"));
+        assert!(!mailbox.can_store_keyword("$Phishing"));
+
+        mailbox.permanent_flags.push(Flag::MayCreate);
+        assert!(mailbox.can_store_keyword("$Phishing"));
+
+        // Without `PERMANENTFLAGS`, all flags are permanent.
+        assert!(Mailbox::default().can_store_keyword("$Phishing"));
+    }
+
+    #[test]
+    fn diff() {
+        let before = Mailbox {
+            exists: 10,
+            uid_next: Some(21),
+            uid_validity: Some(1),
+            highest_modseq: Some(100),
+            ..Mailbox::default()
+        };
+        assert!(before.diff(&before.clone()).is_empty());
+
+        let after = Mailbox {
+            exists: 12,
+            uid_next: Some(23),
+            highest_modseq: Some(104),
+            ..before.clone()
+        };
+        assert_eq!(
+            before.diff(&after),
+            MailboxDiff {
+                exists: Some(12),
+                uid_next: Some(23),
+                uid_validity: None,
+                resync: false,
+                highest_modseq: Some(104),
+            }
+        );
+
+        let recreated = Mailbox {
+            uid_validity: Some(2),
+            ..before.clone()
+        };
+        let diff = before.diff(&recreated);
+        assert!(diff.resync);
+        assert_eq!(diff.uid_validity, Some(2));
+        assert_eq!(diff.exists, None);
+
+        // The server no longer sends `UIDVALIDITY`.
+        let unknown = Mailbox {
+            uid_validity: None,
+            ..before.clone()
+        };
+        let diff = before.diff(&unknown);
+        assert!(diff.resync);
+        assert_eq!(diff.uid_validity, None);
+    }
+}
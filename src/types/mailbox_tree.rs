@@ -0,0 +1,257 @@
+use std::iter::FromIterator;
+
+use super::{NameAttribute, OwnedName};
+
+/// The mailbox hierarchy built from the [`Name`](super::Name)s returned by
+/// [`Session::list`](crate::Session::list), split at each name's delimiter.
+///
+/// ```
+/// use async_imap::types::{MailboxTree, OwnedName};
+///
+/// let name = |name: &str| OwnedName {
+///     attributes: Vec::new(),
+///     delimiter: Some("/".into()),
+///     name: name.into(),
+/// };
+/// let tree: MailboxTree = vec![name("inbox"), name("INBOX/Lists/rust")].into_iter().collect();
+/// let lists = tree.get("INBOX/Lists").unwrap();
+/// assert_eq!(lists.label, "Lists");
+/// assert!(!lists.is_selectable());
+/// assert_eq!(lists.children[0].path, "INBOX/Lists/rust");
+/// ```
+///
+/// Levels that were not listed themselves, like `INBOX/Lists` above, are added as nodes without a
+/// [`MailboxNode::name`]. `INBOX` is matched case-insensitively, as it is the same mailbox however
+/// it is spelled, and empty levels from leading, trailing or doubled delimiters are skipped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MailboxTree {
+    /// The top-level mailboxes, in the order they were first listed.
+    pub roots: Vec<MailboxNode>,
+}
+
+/// A mailbox in a [`MailboxTree`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MailboxNode {
+    /// The last level of the name, e.g. `rust` for `INBOX/Lists/rust`.
+    pub label: String,
+    /// The full name to select the mailbox with, e.g. `INBOX/Lists/rust`.
+    pub path: String,
+    /// The name as the server listed it, or `None` if it only listed names below this one.
+    pub name: Option<OwnedName>,
+    /// The mailboxes one level below this one, in the order they were first listed.
+    pub children: Vec<MailboxNode>,
+}
+
+impl MailboxTree {
+    /// Builds the tree from listed names, e.g. the [`Name`](super::Name)s of a `LIST` command.
+    pub fn from_names<I>(names: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<OwnedName>,
+    {
+        let mut tree = MailboxTree::default();
+        for name in names {
+            tree.insert(name.into());
+        }
+        tree
+    }
+
+    /// Adds a listed name to the tree, replacing the name of its node if it was listed before.
+    pub fn insert(&mut self, name: OwnedName) {
+        let mut levels = levels(&name.name, name.delimiter.as_deref());
+        let (last_label, last_end) = match levels.pop() {
+            Some(last) => last,
+            None => return,
+        };
+        let mut nodes = &mut self.roots;
+        for (label, end) in levels {
+            let path = &name.name[..end];
+            nodes = &mut node(nodes, label, path).children;
+        }
+        let path = name.name[..last_end].to_string();
+        let node = node(nodes, last_label, &path);
+        node.path = name.name.clone();
+        node.name = Some(name);
+    }
+
+    /// Looks up the mailbox with the given full name.
+    pub fn get(&self, path: &str) -> Option<&MailboxNode> {
+        self.iter()
+            .find(|node| node.path == path || is_inbox(&node.path) && is_inbox(path))
+    }
+
+    /// All mailboxes of the tree, depth-first with parents before their children.
+    pub fn iter(&self) -> impl Iterator<Item = &MailboxNode> + '_ {
+        let mut stack: Vec<&MailboxNode> = self.roots.iter().rev().collect();
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+}
+
+impl<N: Into<OwnedName>> FromIterator<N> for MailboxTree {
+    fn from_iter<I: IntoIterator<Item = N>>(names: I) -> Self {
+        MailboxTree::from_names(names)
+    }
+}
+
+impl MailboxNode {
+    /// Whether the mailbox can be selected: the server listed it, and not as `\Noselect` or
+    /// `\NonExistent`.
+    pub fn is_selectable(&self) -> bool {
+        let name = match &self.name {
+            Some(name) => name,
+            None => return false,
+        };
+        !name.attributes.iter().any(|attribute| {
+            matches!(
+                attribute,
+                NameAttribute::NoSelect | NameAttribute::NonExistent
+            )
+        })
+    }
+}
+
+/// The non-empty levels of `name`, each with the offset where it ends.
+fn levels<'a>(name: &'a str, delimiter: Option<&str>) -> Vec<(&'a str, usize)> {
+    let delimiter = match delimiter {
+        Some(delimiter) if !delimiter.is_empty() => delimiter,
+        _ => return vec![(name, name.len())],
+    };
+    let mut levels = Vec::new();
+    let mut start = 0;
+    for label in name.split(delimiter) {
+        start += label.len();
+        if !label.is_empty() {
+            levels.push((label, start));
+        }
+        start += delimiter.len();
+    }
+    levels
+}
+
+/// Finds or appends the node for `label` among `nodes`.
+fn node<'a>(nodes: &'a mut Vec<MailboxNode>, label: &str, path: &str) -> &'a mut MailboxNode {
+    let inbox = is_inbox(path);
+    let position = nodes
+        .iter()
+        .position(|node| node.label == label || inbox && is_inbox(&node.path));
+    let index = match position {
+        Some(index) => index,
+        None => {
+            nodes.push(MailboxNode {
+                label: if inbox { "INBOX" } else { label }.to_string(),
+                path: if inbox { "INBOX" } else { path }.to_string(),
+                name: None,
+                children: Vec::new(),
+            });
+            nodes.len() - 1
+        }
+    };
+    &mut nodes[index]
+}
+
+fn is_inbox(path: &str) -> bool {
+    path.eq_ignore_ascii_case("INBOX")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str, attributes: Vec<NameAttribute<'static>>) -> OwnedName {
+        OwnedName {
+            attributes,
+            delimiter: Some("/".into()),
+            name: name.into(),
+        }
+    }
+
+    fn labels(nodes: &[MailboxNode]) -> Vec<&str> {
+        nodes.iter().map(|node| node.label.as_str()).collect()
+    }
+
+    #[test]
+    fn hierarchy() {
+        let tree = MailboxTree::from_names(vec![
+            name("INBOX", vec![]),
+            name("Archive", vec![]),
+            name("Archive/2020", vec![]),
+            name("Archive/2021/Q1", vec![]),
+            name("inbox/Receipts", vec![]),
+            name("Archive/2021", vec![NameAttribute::Marked]),
+        ]);
+        assert_eq!(labels(&tree.roots), vec!["INBOX", "Archive"]);
+        assert_eq!(labels(&tree.roots[0].children), vec!["Receipts"]);
+        assert_eq!(tree.roots[0].children[0].path, "inbox/Receipts");
+
+        let archive = tree.get("Archive").unwrap();
+        assert_eq!(labels(&archive.children), vec!["2020", "2021"]);
+        let year = tree.get("Archive/2021").unwrap();
+        assert_eq!(
+            year.name.as_ref().unwrap().attributes,
+            vec![NameAttribute::Marked]
+        );
+        assert_eq!(labels(&year.children), vec!["Q1"]);
+        assert!(tree.get("Archive/2021/Q1").unwrap().is_selectable());
+        assert!(tree.get("Archive/2022").is_none());
+        assert!(tree.get("Inbox").is_some());
+
+        let paths: Vec<_> = tree.iter().map(|node| node.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "INBOX",
+                "inbox/Receipts",
+                "Archive",
+                "Archive/2020",
+                "Archive/2021",
+                "Archive/2021/Q1"
+            ]
+        );
+    }
+
+    #[test]
+    fn no_select() {
+        let tree: MailboxTree = vec![
+            name("Lists", vec![NameAttribute::NoSelect]),
+            name("Lists/rust", vec![]),
+            name("Projects/2021", vec![]),
+        ]
+        .into_iter()
+        .collect();
+        let lists = tree.get("Lists").unwrap();
+        assert!(!lists.is_selectable());
+        assert!(lists.children[0].is_selectable());
+
+        // Not listed at all.
+        let projects = tree.get("Projects").unwrap();
+        assert_eq!(projects.name, None);
+        assert!(!projects.is_selectable());
+        assert!(tree.get("Projects/2021").unwrap().is_selectable());
+    }
+
+    #[test]
+    fn delimiters() {
+        let flat = OwnedName {
+            attributes: Vec::new(),
+            delimiter: None,
+            name: "a/b".into(),
+        };
+        let tree = MailboxTree::from_names(vec![
+            flat,
+            name("/leading", vec![]),
+            name("trailing/", vec![]),
+            name("double//level", vec![]),
+        ]);
+        assert_eq!(
+            labels(&tree.roots),
+            vec!["a/b", "leading", "trailing", "double"]
+        );
+        assert_eq!(tree.roots[1].path, "/leading");
+        assert_eq!(tree.roots[2].path, "trailing/");
+        assert_eq!(tree.get("double//level").unwrap().label, "level");
+    }
+}
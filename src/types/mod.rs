@@ -1,6 +1,7 @@
 //! This module contains types used throughout the IMAP protocol.
 
 use std::borrow::Cow;
+use std::fmt;
 
 /// From section [2.3.1.1 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.3.1.1).
 ///
@@ -189,16 +190,58 @@ impl<'a> From<&'a str> for Flag<'a> {
     }
 }
 
+impl fmt::Display for Flag<'_> {
+    /// Formats the flag as it is sent to the server, e.g. `\Seen`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Flag::Seen => f.write_str("\\Seen"),
+            Flag::Answered => f.write_str("\\Answered"),
+            Flag::Flagged => f.write_str("\\Flagged"),
+            Flag::Deleted => f.write_str("\\Deleted"),
+            Flag::Draft => f.write_str("\\Draft"),
+            Flag::Recent => f.write_str("\\Recent"),
+            Flag::MayCreate => f.write_str("\\*"),
+            Flag::Custom(flag) => f.write_str(flag),
+        }
+    }
+}
+
+/// How [`Session::uid_store_flags`](crate::Session::uid_store_flags) changes the flags of
+/// messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagsOperation {
+    /// Add the flags to the messages (`+FLAGS`).
+    Add,
+    /// Remove the flags from the messages (`-FLAGS`).
+    Remove,
+    /// Replace all flags of the messages (`FLAGS`).
+    Replace,
+}
+
+impl FlagsOperation {
+    pub(crate) fn item(self) -> &'static str {
+        match self {
+            FlagsOperation::Add => "+FLAGS",
+            FlagsOperation::Remove => "-FLAGS",
+            FlagsOperation::Replace => "FLAGS",
+        }
+    }
+}
+
 mod mailbox;
-pub use self::mailbox::Mailbox;
+pub use self::mailbox::{Mailbox, MailboxDiff};
 
 mod fetch;
-pub use self::fetch::Fetch;
+pub use self::fetch::{BodyPart, Fetch};
 
 mod name;
-pub use self::name::{Name, NameAttribute};
+pub use self::name::{Name, NameAttribute, NameAttributeParser, OwnedName, ParsedNameAttribute};
+
+mod mailbox_tree;
+pub use self::mailbox_tree::{MailboxNode, MailboxTree};
 
 mod capabilities;
+pub(crate) use self::capabilities::IMAP4REV2_EXTENSIONS;
 pub use self::capabilities::{Capabilities, Capability};
 
 /// re-exported from imap_proto;
@@ -208,7 +251,7 @@ mod id_generator;
 pub(crate) use self::id_generator::IdGenerator;
 
 mod response_data;
-pub(crate) use self::response_data::ResponseData;
+pub(crate) use self::response_data::{RawItem, ResponseBuffer, ResponseData};
 
 mod request;
 pub(crate) use self::request::Request;
@@ -216,13 +259,54 @@ pub(crate) use self::request::Request;
 mod quota;
 pub use self::quota::*;
 
+mod sequence_set;
+pub use self::sequence_set::SequenceSet;
+
+mod esearch;
+pub use self::esearch::{ESearch, Partial};
+
+mod fetch_items;
+pub use self::fetch_items::FetchItems;
+
+mod tls_info;
+pub use self::tls_info::{TlsInfo, TlsPeerInfo};
+
+mod thread;
+pub use self::thread::Thread;
+
+mod uidplus;
+pub use self::uidplus::{AppendUid, CopyUid};
+
+mod appended;
+pub use self::appended::Appended;
+
+mod page_order;
+pub use self::page_order::PageOrder;
+
+mod seq_map;
+pub use self::seq_map::SeqMap;
+
+mod sync_snapshot;
+pub use self::sync_snapshot::SyncSnapshot;
+
+mod unsolicited_policy;
+pub use self::unsolicited_policy::UnsolicitedPolicy;
+
+mod unsolicited_sender;
+pub use self::unsolicited_sender::UnsolicitedSender;
+pub(crate) use self::unsolicited_sender::DEFAULT_UNSOLICITED_CAPACITY;
+
+mod wire;
+pub use self::wire::Direction;
+pub(crate) use self::wire::WireTracer;
+
 /// Responses that the server sends that are not related to the current command.
 /// [RFC 3501](https://tools.ietf.org/html/rfc3501#section-7) states that clients need to be able
 /// to accept any response at any time. These are the ones we've encountered in the wild.
 ///
 /// Note that `Recent`, `Exists` and `Expunge` responses refer to the currently `SELECT`ed folder,
 /// so the user must take care when interpreting these.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum UnsolicitedResponse {
     /// An unsolicited [`STATUS response`](https://tools.ietf.org/html/rfc3501#section-7.2.4).
     Status {
@@ -254,6 +338,10 @@ pub enum UnsolicitedResponse {
     /// An unsolicited [`EXISTS` response](https://tools.ietf.org/html/rfc3501#section-7.3.1) that
     /// reports the number of messages in the mailbox. This response occurs if the size of the
     /// mailbox changes (e.g., new messages arrive).
+    ///
+    /// The count supersedes [`Mailbox::exists`] as returned when the mailbox was selected, so a
+    /// client that keeps track of the number of messages should replace its cached value with
+    /// this one rather than adding to it.
     Exists(u32),
 
     /// An unsolicited [`EXPUNGE` response](https://tools.ietf.org/html/rfc3501#section-7.4.1) that
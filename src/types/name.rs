@@ -3,6 +3,7 @@ use std::borrow::Cow;
 use imap_proto::{MailboxDatum, Response};
 
 use crate::types::ResponseData;
+use crate::utf7;
 
 /// A name that matches a `LIST` or `LSUB` command.
 #[ouroboros::self_referencing(pub_extras)]
@@ -18,11 +19,28 @@ pub struct Name {
 pub struct InnerName<'a> {
     attributes: Vec<NameAttribute<'a>>,
     delimiter: Option<&'a str>,
-    name: &'a str,
+    name: Cow<'a, str>,
+}
+
+/// A [`Name`] that owns its data, so that it can be kept without the response it was parsed
+/// from, e.g. to build a tree of folders. Created with [`Name::to_owned_name`] or
+/// [`Name::into_owned`].
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct OwnedName {
+    /// Attributes of this name, see [`Name::attributes`].
+    pub attributes: Vec<NameAttribute<'static>>,
+    /// The hierarchy delimiter, see [`Name::delimiter`].
+    pub delimiter: Option<String>,
+    /// The decoded name, see [`Name::name`].
+    pub name: String,
 }
 
 /// An attribute set for an IMAP name.
+///
+/// More attributes may be parsed into their own variants in future versions, rather than into
+/// [`NameAttribute::Custom`].
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum NameAttribute<'a> {
     /// It is not possible for any child levels of hierarchy to exist
     /// under this name; no child levels exist now and none can be
@@ -41,22 +59,118 @@ pub enum NameAttribute<'a> {
     /// last time the mailbox was selected.
     Unmarked,
 
+    /// The mailbox has child mailboxes that are accessible to the user, see [RFC
+    /// 3348](https://tools.ietf.org/html/rfc3348).
+    HasChildren,
+
+    /// The mailbox has no child mailboxes that are accessible to the user, see [RFC
+    /// 3348](https://tools.ietf.org/html/rfc3348).
+    HasNoChildren,
+
+    /// The mailbox does not exist, and is only listed because mailboxes below it do, or because
+    /// it is subscribed. It implies [`NameAttribute::NoSelect`]. See [RFC
+    /// 5258](https://tools.ietf.org/html/rfc5258#section-3).
+    NonExistent,
+
+    /// The mailbox is subscribed, as returned by a `LIST (SUBSCRIBED)` command. See [RFC
+    /// 5258](https://tools.ietf.org/html/rfc5258#section-3).
+    Subscribed,
+
+    /// The mailbox is on a remote server, as returned by a `LIST (REMOTE)` command. See [RFC
+    /// 5258](https://tools.ietf.org/html/rfc5258#section-3).
+    Remote,
+
     /// A non-standard user- or server-defined name attribute.
     Custom(Cow<'a, str>),
 }
 
 impl NameAttribute<'static> {
     fn system(s: &str) -> Option<Self> {
-        match s {
-            "\\Noinferiors" => Some(NameAttribute::NoInferiors),
-            "\\Noselect" => Some(NameAttribute::NoSelect),
-            "\\Marked" => Some(NameAttribute::Marked),
-            "\\Unmarked" => Some(NameAttribute::Unmarked),
-            _ => None,
+        // Attributes are case-insensitive.
+        let attributes = [
+            ("\\Noinferiors", NameAttribute::NoInferiors),
+            ("\\Noselect", NameAttribute::NoSelect),
+            ("\\Marked", NameAttribute::Marked),
+            ("\\Unmarked", NameAttribute::Unmarked),
+            ("\\HasChildren", NameAttribute::HasChildren),
+            ("\\HasNoChildren", NameAttribute::HasNoChildren),
+            ("\\NonExistent", NameAttribute::NonExistent),
+            ("\\Subscribed", NameAttribute::Subscribed),
+            ("\\Remote", NameAttribute::Remote),
+        ];
+        attributes
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, attribute)| attribute.clone())
+    }
+}
+
+impl<'a> NameAttribute<'a> {
+    /// Copies any borrowed data, so that the attribute no longer borrows from a response.
+    pub fn into_owned(self) -> NameAttribute<'static> {
+        match self {
+            NameAttribute::NoInferiors => NameAttribute::NoInferiors,
+            NameAttribute::NoSelect => NameAttribute::NoSelect,
+            NameAttribute::Marked => NameAttribute::Marked,
+            NameAttribute::Unmarked => NameAttribute::Unmarked,
+            NameAttribute::HasChildren => NameAttribute::HasChildren,
+            NameAttribute::HasNoChildren => NameAttribute::HasNoChildren,
+            NameAttribute::NonExistent => NameAttribute::NonExistent,
+            NameAttribute::Subscribed => NameAttribute::Subscribed,
+            NameAttribute::Remote => NameAttribute::Remote,
+            NameAttribute::Custom(s) => NameAttribute::Custom(Cow::Owned(s.into_owned())),
         }
     }
 }
 
+/// Parses the name attributes that this crate does not know into typed values, e.g. proprietary
+/// attributes of a server, see [`Name::attributes_with`].
+///
+/// It is implemented for closures that take the raw attribute, e.g. `\MyAttr`, and return
+/// `None` for attributes they do not know either.
+pub trait NameAttributeParser<'a> {
+    /// The typed attribute, which may borrow from the response.
+    type Attribute;
+
+    /// Parses a [`NameAttribute::Custom`] attribute, or returns `None` to leave it as it is.
+    fn parse(&self, raw: &'a str) -> Option<Self::Attribute>;
+}
+
+impl<'a, T, F: Fn(&'a str) -> Option<T>> NameAttributeParser<'a> for F {
+    type Attribute = T;
+
+    fn parse(&self, raw: &'a str) -> Option<T> {
+        self(raw)
+    }
+}
+
+/// A name attribute returned by [`Name::attributes_with`].
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum ParsedNameAttribute<'a, T> {
+    /// An attribute the [`NameAttributeParser`] did not parse, as it is returned by
+    /// [`Name::attributes`].
+    Builtin(NameAttribute<'a>),
+    /// An attribute parsed by the [`NameAttributeParser`].
+    Parsed(T),
+}
+
+/// Runs `parser` on the custom attributes among `attributes`.
+fn parse_attributes<'a, P: NameAttributeParser<'a>>(
+    attributes: &'a [NameAttribute<'_>],
+    parser: &P,
+) -> Vec<ParsedNameAttribute<'a, P::Attribute>> {
+    attributes
+        .iter()
+        .map(|attribute| match attribute {
+            NameAttribute::Custom(raw) => match parser.parse(raw) {
+                Some(parsed) => ParsedNameAttribute::Parsed(parsed),
+                None => ParsedNameAttribute::Builtin(NameAttribute::Custom(Cow::Borrowed(raw))),
+            },
+            attribute => ParsedNameAttribute::Builtin(attribute.clone()),
+        })
+        .collect()
+}
+
 impl<'a> From<String> for NameAttribute<'a> {
     fn from(s: String) -> Self {
         if let Some(f) = NameAttribute::system(&s) {
@@ -78,7 +192,9 @@ impl<'a> From<&'a str> for NameAttribute<'a> {
 }
 
 impl Name {
-    pub(crate) fn from_mailbox_data(resp: ResponseData) -> Self {
+    /// If `decode_utf7` is set, the name is decoded from modified UTF-7, see
+    /// [`Session::set_encode_mailbox_names`](crate::Session::set_encode_mailbox_names).
+    pub(crate) fn from_mailbox_data(resp: ResponseData, decode_utf7: bool) -> Self {
         Name::new(Box::new(resp), |response| match response.parsed() {
             Response::MailboxData(MailboxDatum::List {
                 flags,
@@ -90,7 +206,10 @@ impl Name {
                     .map(|s| NameAttribute::from(s.as_ref()))
                     .collect(),
                 delimiter: delimiter.as_deref(),
-                name,
+                name: match utf7::decode(name) {
+                    Some(decoded) if decode_utf7 && name.contains('&') => Cow::Owned(decoded),
+                    _ => Cow::Borrowed(name),
+                },
             },
             _ => panic!("cannot construct from non mailbox data"),
         })
@@ -101,6 +220,33 @@ impl Name {
         &self.borrow_inner().attributes[..]
     }
 
+    /// Attributes of this name, with those this crate does not know passed through `parser`:
+    ///
+    /// ```
+    /// # use async_imap::types::{Name, ParsedNameAttribute};
+    /// #[derive(Debug, PartialEq)]
+    /// struct Shared<'a>(&'a str);
+    ///
+    /// fn parse(raw: &str) -> Option<Shared<'_>> {
+    ///     raw.strip_prefix("\\Shared-").map(Shared)
+    /// }
+    ///
+    /// fn shared(name: &Name) -> Option<Shared<'_>> {
+    ///     name.attributes_with(&parse)
+    ///         .into_iter()
+    ///         .find_map(|attribute| match attribute {
+    ///             ParsedNameAttribute::Parsed(shared) => Some(shared),
+    ///             ParsedNameAttribute::Builtin(_) => None,
+    ///         })
+    /// }
+    /// ```
+    pub fn attributes_with<'a, P: NameAttributeParser<'a>>(
+        &'a self,
+        parser: &P,
+    ) -> Vec<ParsedNameAttribute<'a, P::Attribute>> {
+        parse_attributes(self.attributes(), parser)
+    }
+
     /// The hierarchy delimiter is a character used to delimit levels of hierarchy in a mailbox
     /// name.  A client can use it to create child mailboxes, and to search higher or lower levels
     /// of naming hierarchy.  All children of a top-level hierarchy node use the same
@@ -113,7 +259,66 @@ impl Name {
     /// reference in `LIST` and `LSUB` commands. Unless [`NameAttribute::NoSelect`] is indicated,
     /// the name is also valid as an argument for commands, such as `SELECT`, that accept mailbox
     /// names.
+    ///
+    /// If [`Session::set_encode_mailbox_names`](crate::Session::set_encode_mailbox_names) is
+    /// turned on, the name is decoded from the modified UTF-7 that servers use for non-ASCII
+    /// names, so that e.g. `Entwürfe` is returned rather than `Entw&APw-rfe`.
     pub fn name(&self) -> &str {
-        self.borrow_inner().name
+        &self.borrow_inner().name
+    }
+
+    /// The selection options of [`Session::list_extended`](crate::Session::list_extended) that
+    /// mailboxes below this one match, from its `CHILDINFO` extended data, e.g. `SUBSCRIBED` if
+    /// the name is only listed because a mailbox below it is subscribed. This is only sent for
+    /// [`ListSelection::RecursiveMatch`](crate::extensions::list_extended::ListSelection::RecursiveMatch).
+    pub fn child_info(&self) -> Vec<&str> {
+        self.borrow_response()
+            .raw_items()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(b"CHILDINFO"))
+            .filter_map(|(_, value)| std::str::from_utf8(value?).ok())
+            .flat_map(|value| {
+                value
+                    .trim_start_matches('(')
+                    .trim_end_matches(')')
+                    .split_whitespace()
+                    .map(|option| option.trim_matches('"'))
+            })
+            .collect()
+    }
+
+    /// Copies the name into an [`OwnedName`], which does not keep the response alive.
+    pub fn to_owned_name(&self) -> OwnedName {
+        OwnedName {
+            attributes: self
+                .attributes()
+                .iter()
+                .cloned()
+                .map(NameAttribute::into_owned)
+                .collect(),
+            delimiter: self.delimiter().map(str::to_string),
+            name: self.name().to_string(),
+        }
+    }
+
+    /// Converts the name into an [`OwnedName`], dropping the response it was parsed from.
+    pub fn into_owned(self) -> OwnedName {
+        self.to_owned_name()
+    }
+}
+
+impl OwnedName {
+    /// Attributes of this name, with those this crate does not know passed through `parser`, see
+    /// [`Name::attributes_with`].
+    pub fn attributes_with<'a, P: NameAttributeParser<'a>>(
+        &'a self,
+        parser: &P,
+    ) -> Vec<ParsedNameAttribute<'a, P::Attribute>> {
+        parse_attributes(&self.attributes, parser)
+    }
+}
+
+impl From<Name> for OwnedName {
+    fn from(name: Name) -> Self {
+        name.into_owned()
     }
 }
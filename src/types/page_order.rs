@@ -0,0 +1,29 @@
+use super::Uid;
+
+/// The order in which [`Session::uid_pages`](crate::Session::uid_pages) pages through the
+/// messages of a mailbox.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PageOrder {
+    /// The newest messages, with the highest [`Uid`]s, first, as in most message lists.
+    NewestFirst,
+    /// The oldest messages, with the lowest [`Uid`]s, first.
+    OldestFirst,
+}
+
+impl PageOrder {
+    /// Sorts `uids` in this order.
+    pub(crate) fn sort(self, uids: &mut [Uid]) {
+        match self {
+            PageOrder::NewestFirst => uids.sort_unstable_by(|a, b| b.cmp(a)),
+            PageOrder::OldestFirst => uids.sort_unstable(),
+        }
+    }
+
+    /// Whether `uid` comes after `cursor` in this order.
+    pub(crate) fn is_after(self, uid: Uid, cursor: Uid) -> bool {
+        match self {
+            PageOrder::NewestFirst => uid < cursor,
+            PageOrder::OldestFirst => uid > cursor,
+        }
+    }
+}
@@ -46,9 +46,12 @@ impl<'a> From<QuotaResourceRef<'a>> for QuotaResource {
 }
 
 impl QuotaResource {
-    /// gets the usage percentage of a QuotaResource
+    /// gets the usage percentage of a QuotaResource, 100 if the limit is 0
     pub fn get_usage_percentage(&self) -> u64 {
-        self.usage.saturating_mul(100) / self.limit
+        match self.limit {
+            0 => 100,
+            limit => self.usage.saturating_mul(100) / limit,
+        }
     }
 }
 
@@ -1,16 +1,83 @@
 use std::fmt;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
 
 use byte_pool::Block;
 use imap_proto::{RequestId, Response};
 
 #[ouroboros::self_referencing(pub_extras)]
 pub struct ResponseData {
-    pub raw: Block<'static>,
+    pub raw: Box<ResponseBuffer>,
+    /// Data items that were split off a `FETCH` response because `imap_proto` could not parse
+    /// them, or the contents of a response it does not know at all, see `crate::fallback`.
+    pub raw_items: Vec<RawItem>,
     #[borrows(raw)]
     #[covariant]
     response: Response<'this>,
 }
 
+/// An unparsed `FETCH` data item, as ranges into [`ResponseData::raw`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawItem {
+    /// The name of the data item, including any section and partial specifier.
+    pub name: Range<usize>,
+    /// The value of the data item, with string quoting and literal framing removed, or `None`
+    /// if the value was `NIL`.
+    pub value: Option<Range<usize>>,
+}
+
+/// The bytes a [`ResponseData`] was parsed from. Responses that were received in the same read
+/// share the block of the buffer, but each only covers its own bytes.
+#[derive(Clone)]
+pub struct ResponseBuffer {
+    block: Arc<Block<'static>>,
+    range: Range<usize>,
+}
+
+impl ResponseBuffer {
+    pub(crate) fn new(block: Arc<Block<'static>>, range: Range<usize>) -> Self {
+        ResponseBuffer { block, range }
+    }
+}
+
+impl From<Block<'static>> for ResponseBuffer {
+    fn from(block: Block<'static>) -> Self {
+        let len = block.len();
+        ResponseBuffer::new(Arc::new(block), 0..len)
+    }
+}
+
+impl Deref for ResponseBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.block[self.range.clone()]
+    }
+}
+
+impl fmt::Debug for ResponseBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseBuffer")
+            .field("len", &self.range.len())
+            .finish()
+    }
+}
+
+impl Clone for ResponseData {
+    /// Shares the buffer of the response and parses it again, as the parsed response borrows
+    /// from the buffer it belongs to.
+    fn clone(&self) -> Self {
+        let raw = self.borrow_raw().clone();
+        let raw_items = self.borrow_raw_items().clone();
+        ResponseData::try_new(raw, raw_items, |buf| {
+            imap_proto::parser::parse_response(buf)
+                .map(|(_remaining, response)| response)
+                .map_err(|_| ())
+        })
+        .expect("a response that was parsed before parses again")
+    }
+}
+
 impl std::cmp::PartialEq for ResponseData {
     fn eq(&self, other: &Self) -> bool {
         self.parsed() == other.parsed()
@@ -39,4 +106,17 @@ impl ResponseData {
     pub fn parsed(&self) -> &Response<'_> {
         self.borrow_response()
     }
+
+    /// The data items of a `FETCH` response that could not be parsed, as name/value pairs. For
+    /// an untagged response that could not be parsed at all, which is then parsed as a bare
+    /// `* OK`, this is its name and the rest of the response as the server sent it.
+    pub fn raw_items(&self) -> impl Iterator<Item = (&[u8], Option<&[u8]>)> + '_ {
+        let raw = self.borrow_raw();
+        self.borrow_raw_items().iter().map(move |item| {
+            (
+                &raw[item.name.clone()],
+                item.value.clone().map(|value| &raw[value]),
+            )
+        })
+    }
 }
@@ -0,0 +1,193 @@
+use super::{Seq, Uid};
+
+/// The [`Uid`] of each message of a mailbox by its [`Seq`], as far as they are known, kept
+/// consistent as messages are added and expunged. See
+/// [`Session::set_track_uids`](crate::Session::set_track_uids) for one the session keeps.
+///
+/// ```
+/// use async_imap::types::SeqMap;
+///
+/// let mut map = SeqMap::new(3);
+/// map.insert(2, 20);
+/// map.insert(3, 30);
+/// // `* 1 EXPUNGE`
+/// assert_eq!(map.expunge(1), None);
+/// assert_eq!(map.len(), 2);
+/// assert_eq!(map.uid(1), Some(20));
+/// assert_eq!(map.seq(30), Some(2));
+/// ```
+///
+/// Sequence numbers are assigned in the order of the [`Uid`]s, so the messages after an expunged
+/// one all move down by one. [`SeqMap::renumber`] does the same for a single [`Seq`], for state
+/// kept elsewhere.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SeqMap {
+    /// The [`Uid`] of the message with sequence number `i + 1` at `i`.
+    uids: Vec<Option<Uid>>,
+}
+
+impl SeqMap {
+    /// A map of a mailbox with `exists` messages, none of whose [`Uid`]s are known yet.
+    pub fn new(exists: u32) -> Self {
+        SeqMap {
+            uids: vec![None; exists as usize],
+        }
+    }
+
+    /// The sequence number that message `seq` has after message `expunged` was expunged, or
+    /// `None` if it was `seq` itself.
+    pub fn renumber(seq: Seq, expunged: Seq) -> Option<Seq> {
+        match seq {
+            _ if seq == expunged => None,
+            _ if seq > expunged => Some(seq - 1),
+            _ => Some(seq),
+        }
+    }
+
+    /// The number of messages in the mailbox.
+    pub fn len(&self) -> u32 {
+        self.uids.len() as u32
+    }
+
+    /// Whether the mailbox is empty.
+    pub fn is_empty(&self) -> bool {
+        self.uids.is_empty()
+    }
+
+    /// The [`Uid`] of message `seq`, if it is known.
+    pub fn uid(&self, seq: Seq) -> Option<Uid> {
+        let index = (seq as usize).checked_sub(1)?;
+        self.uids.get(index).copied().flatten()
+    }
+
+    /// The sequence number of the message with `uid`, if it is known.
+    pub fn seq(&self, uid: Uid) -> Option<Seq> {
+        let index = self.uids.iter().position(|known| *known == Some(uid))?;
+        Some(index as Seq + 1)
+    }
+
+    /// Records the [`Uid`] of message `seq`, e.g. from a `FETCH` response. A `seq` beyond the end
+    /// of the mailbox adds the messages up to it.
+    pub fn insert(&mut self, seq: Seq, uid: Uid) {
+        let index = match (seq as usize).checked_sub(1) {
+            Some(index) => index,
+            None => return,
+        };
+        if index >= self.uids.len() {
+            self.uids.resize(index + 1, None);
+        }
+        self.uids[index] = Some(uid);
+    }
+
+    /// Sets the number of messages from an `EXISTS` response, adding the new ones at the end.
+    pub fn exists(&mut self, exists: u32) {
+        self.uids.resize(exists as usize, None);
+    }
+
+    /// Removes message `seq` after an `EXPUNGE` response, moving all later messages down by one.
+    /// Returns its [`Uid`] if it was known.
+    pub fn expunge(&mut self, seq: Seq) -> Option<Uid> {
+        let index = (seq as usize).checked_sub(1)?;
+        if index >= self.uids.len() {
+            return None;
+        }
+        self.uids.remove(index)
+    }
+
+    /// Removes the message with `uid` after a `VANISHED` response of the [`QRESYNC`
+    /// extension](https://tools.ietf.org/html/rfc7162#section-3.2.10), moving all later messages
+    /// down by one. Returns its former sequence number if it could be told.
+    ///
+    /// If `uid` is not known, it is somewhere between the closest known [`Uid`]s below and above
+    /// it. The last of the messages between them is removed then, and the [`Uid`]s of the others
+    /// are forgotten, unless only one message is between them.
+    pub fn vanish(&mut self, uid: Uid) -> Option<Seq> {
+        if let Some(seq) = self.seq(uid) {
+            self.uids.remove(seq as usize - 1);
+            return Some(seq);
+        }
+        // The messages from `start` to before `end` are the ones that could have `uid`.
+        let below = |known: &Option<Uid>| matches!(known, Some(known) if *known < uid);
+        let start = match self.uids.iter().rposition(below) {
+            Some(index) => index + 1,
+            None => 0,
+        };
+        let end = self.uids[start..]
+            .iter()
+            .position(Option::is_some)
+            .map_or(self.uids.len(), |index| start + index);
+        match end - start {
+            0 => None,
+            1 => {
+                self.uids.remove(start);
+                Some(start as Seq + 1)
+            }
+            _ => {
+                self.uids.remove(end - 1);
+                for known in &mut self.uids[start..end - 1] {
+                    *known = None;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expunge() {
+        let mut map = SeqMap::new(5);
+        for seq in 1..=5 {
+            map.insert(seq, seq * 10);
+        }
+        // Expunging 2, 2 and 3 removes the messages that were 2, 3 and 5.
+        assert_eq!(map.expunge(2), Some(20));
+        assert_eq!(map.expunge(2), Some(30));
+        assert_eq!(map.expunge(3), Some(50));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.uid(1), Some(10));
+        assert_eq!(map.uid(2), Some(40));
+        assert_eq!(map.seq(40), Some(2));
+        assert_eq!(map.seq(30), None);
+
+        assert_eq!(map.expunge(3), None);
+        assert_eq!(map.expunge(0), None);
+        assert_eq!(map.len(), 2);
+
+        map.exists(4);
+        assert_eq!(map.uid(4), None);
+        map.insert(4, 70);
+        assert_eq!(map.expunge(3), None);
+        assert_eq!(map.seq(70), Some(3));
+
+        assert_eq!(SeqMap::renumber(1, 2), Some(1));
+        assert_eq!(SeqMap::renumber(2, 2), None);
+        assert_eq!(SeqMap::renumber(5, 2), Some(4));
+    }
+
+    #[test]
+    fn vanish() {
+        let mut map = SeqMap::new(7);
+        for &(seq, uid) in &[(1, 10), (3, 30), (4, 40), (7, 70)] {
+            map.insert(seq, uid);
+        }
+        assert_eq!(map.vanish(30), Some(3));
+        assert_eq!(map.seq(70), Some(6));
+        // The only message between 10 and 40.
+        assert_eq!(map.vanish(20), Some(2));
+        assert_eq!(map.seq(40), Some(2));
+        // One of the two messages between 40 and 70, but not which one.
+        assert_eq!(map.vanish(50), None);
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.uid(3), None);
+        assert_eq!(map.seq(70), Some(4));
+        // The only message above 70.
+        map.exists(5);
+        assert_eq!(map.vanish(80), Some(5));
+        assert_eq!(map.vanish(90), None);
+        assert_eq!(map.len(), 4);
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{RangeFrom, RangeInclusive};
+use std::str::FromStr;
+
+use crate::error::ParseError;
+
+/// A set of message sequence numbers or [`Uid`](super::Uid)s, as taken by commands such as
+/// [`Session::fetch`](crate::Session::fetch) or [`Session::uid_store`](crate::Session::uid_store).
+///
+/// The set is kept normalized: overlapping and adjacent ids are coalesced into ranges, so that
+/// it renders in its most compact form, e.g. `1,2,3,5` is sent as `1:3,5`.
+///
+/// ```
+/// use async_imap::types::SequenceSet;
+///
+/// let set: SequenceSet = vec![1, 2, 3, 5].into_iter().collect();
+/// assert_eq!(set.to_string(), "1:3,5");
+///
+/// assert_eq!(SequenceSet::from(10..).to_string(), "10:*");
+/// assert_eq!("4,1:2".parse::<SequenceSet>().unwrap().to_string(), "1:2,4");
+/// ```
+///
+/// As in [RFC 3501](https://tools.ietf.org/html/rfc3501#section-9), `*` stands for the largest id
+/// in use in the mailbox, so `n:*` is every id from `n` up to it, or just the largest one if `n` is
+/// above it. Ids are never 0, and inserting 0 panics.
+///
+/// Note that an empty set is not a valid argument to any command.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SequenceSet {
+    /// Sorted, non-overlapping and non-adjacent inclusive ranges.
+    ranges: Vec<(u32, u32)>,
+    /// All ids from this one up to and including the largest id in use (`n:*`).
+    open_from: Option<u32>,
+}
+
+impl SequenceSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The set of all messages in the mailbox (`1:*`).
+    pub fn all() -> Self {
+        Self::from(1..)
+    }
+
+    /// Whether the set contains no ids.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty() && self.open_from.is_none()
+    }
+
+    /// Whether `id` is in the set, with `largest` the largest id in use, which `*` stands for. No
+    /// id above `largest` is in it, and `n:*` with `n` above `largest` holds `largest` alone.
+    pub fn contains(&self, id: u32, largest: u32) -> bool {
+        if id > largest {
+            return false;
+        }
+        matches!(self.open_from, Some(start) if id >= start.min(largest))
+            || self
+                .ranges
+                .iter()
+                .any(|&(start, end)| start <= id && id <= end)
+    }
+
+    /// Adds a single id to the set.
+    ///
+    /// # Panics
+    ///
+    /// If `id` is 0.
+    pub fn insert(&mut self, id: u32) {
+        self.insert_range(id..=id);
+    }
+
+    /// Adds all ids in `range` to the set.
+    ///
+    /// # Panics
+    ///
+    /// If `range` is not empty and starts at 0.
+    pub fn insert_range(&mut self, range: RangeInclusive<u32>) {
+        let (start, end) = range.into_inner();
+        if start > end {
+            return;
+        }
+        assert!(start > 0, "0 is not a valid id");
+
+        let pos = self.ranges.partition_point(|&(s, _)| s < start);
+        self.ranges.insert(pos, (start, end));
+        self.normalize();
+    }
+
+    /// Adds all ids from `start` up to and including the largest id in use (`start:*`).
+    ///
+    /// # Panics
+    ///
+    /// If `start` is 0.
+    pub fn insert_from(&mut self, start: u32) {
+        assert!(start > 0, "0 is not a valid id");
+        self.open_from = Some(self.open_from.map_or(start, |s| s.min(start)));
+        self.normalize();
+    }
+
+    /// The bounded ranges in the set, in ascending order. This does not include the open-ended
+    /// range, see [`SequenceSet::open_from`].
+    pub fn ranges(&self) -> impl Iterator<Item = RangeInclusive<u32>> + '_ {
+        self.ranges.iter().map(|&(start, end)| start..=end)
+    }
+
+    /// The start of the open-ended range (`start:*`), if the set has one.
+    pub fn open_from(&self) -> Option<u32> {
+        self.open_from
+    }
+
+    fn normalize(&mut self) {
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(self.ranges.len());
+        for &(start, end) in &self.ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        if let Some(mut open) = self.open_from {
+            while let Some(&(start, end)) = merged.last() {
+                if end.saturating_add(1) < open {
+                    break;
+                }
+                open = open.min(start);
+                merged.pop();
+            }
+            self.open_from = Some(open);
+        }
+
+        self.ranges = merged;
+    }
+}
+
+impl fmt::Display for SequenceSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for &(start, end) in &self.ranges {
+            if !first {
+                f.write_str(",")?;
+            }
+            first = false;
+            if start == end {
+                write!(f, "{}", start)?;
+            } else {
+                write!(f, "{}:{}", start, end)?;
+            }
+        }
+        if let Some(start) = self.open_from {
+            if !first {
+                f.write_str(",")?;
+            }
+            if start == u32::MAX {
+                f.write_str("*")?;
+            } else {
+                write!(f, "{}:*", start)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for SequenceSet {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::Invalid(s.as_bytes().to_vec());
+        let number = |n: &str| match n.parse::<u32>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(invalid()),
+        };
+
+        let mut set = SequenceSet::new();
+        for part in s.split(',') {
+            match part.split_once(':') {
+                None if part == "*" => set.insert_from(u32::MAX),
+                None => set.insert(number(part)?),
+                Some(("*", "*")) => set.insert_from(u32::MAX),
+                Some((start, "*")) | Some(("*", start)) => set.insert_from(number(start)?),
+                Some((start, end)) => {
+                    let (start, end) = (number(start)?, number(end)?);
+                    set.insert_range(start.min(end)..=start.max(end));
+                }
+            }
+        }
+
+        Ok(set)
+    }
+}
+
+impl From<u32> for SequenceSet {
+    fn from(id: u32) -> Self {
+        let mut set = SequenceSet::new();
+        set.insert(id);
+        set
+    }
+}
+
+impl From<RangeInclusive<u32>> for SequenceSet {
+    fn from(range: RangeInclusive<u32>) -> Self {
+        let mut set = SequenceSet::new();
+        set.insert_range(range);
+        set
+    }
+}
+
+impl From<RangeFrom<u32>> for SequenceSet {
+    fn from(range: RangeFrom<u32>) -> Self {
+        let mut set = SequenceSet::new();
+        set.insert_from(range.start);
+        set
+    }
+}
+
+impl FromIterator<u32> for SequenceSet {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        let mut set = SequenceSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<u32> for SequenceSet {
+    fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+        self.ranges.extend(iter.into_iter().map(|id| {
+            assert!(id > 0, "0 is not a valid id");
+            (id, id)
+        }));
+        self.ranges.sort_unstable();
+        self.normalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce() {
+        let set: SequenceSet = vec![5, 1, 3, 2].into_iter().collect();
+        assert_eq!(set.to_string(), "1:3,5");
+
+        let mut set = SequenceSet::from(10..=20);
+        set.insert(21);
+        set.insert(9);
+        set.insert_range(30..=40);
+        set.insert_range(35..=45);
+        set.insert(1);
+        assert_eq!(set.to_string(), "1,9:21,30:45");
+
+        set.insert_range(2..=29);
+        assert_eq!(set.to_string(), "1:45");
+    }
+
+    #[test]
+    fn open_ended() {
+        assert_eq!(SequenceSet::all().to_string(), "1:*");
+
+        let mut set: SequenceSet = vec![1, 2, 8, 9].into_iter().collect();
+        set.insert_from(10);
+        assert_eq!(set.to_string(), "1:2,8:*");
+        assert!(set.contains(1_000_000, 1_000_000));
+        assert!(!set.contains(1_000_001, 1_000_000));
+        assert!(!set.contains(5, 1_000_000));
+        // `10:*` is just the largest id if that is below 10.
+        assert!(set.contains(6, 6));
+        assert!(!set.contains(5, 6));
+        assert!(set.contains(2, 6));
+        assert!(SequenceSet::from(u32::MAX).contains(u32::MAX, u32::MAX));
+
+        set.insert(100);
+        assert_eq!(set.to_string(), "1:2,8:*");
+        set.insert_from(3);
+        assert_eq!(set.to_string(), "1:*");
+    }
+
+    #[test]
+    fn parse() {
+        let set: SequenceSet = "7,1:3,4,20:*,9:8".parse().unwrap();
+        assert_eq!(set.to_string(), "1:4,7:9,20:*");
+        assert_eq!("*:5".parse::<SequenceSet>().unwrap().to_string(), "5:*");
+        assert_eq!("3,*".parse::<SequenceSet>().unwrap().to_string(), "3,*");
+
+        assert!("".parse::<SequenceSet>().is_err());
+        assert!("0".parse::<SequenceSet>().is_err());
+        assert!("1,,2".parse::<SequenceSet>().is_err());
+        assert!("1:a".parse::<SequenceSet>().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "0 is not a valid id")]
+    fn insert_zero() {
+        SequenceSet::new().insert(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "0 is not a valid id")]
+    fn insert_range_zero() {
+        let _ = SequenceSet::from(0..=3);
+    }
+
+    #[test]
+    fn empty() {
+        let set = SequenceSet::new();
+        assert!(set.is_empty());
+        assert_eq!(set.to_string(), "");
+        assert!(!SequenceSet::from(1).is_empty());
+    }
+}
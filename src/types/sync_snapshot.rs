@@ -0,0 +1,21 @@
+use super::Uid;
+
+/// The state of a mailbox that decides whether a client needs to resynchronize it, as returned by
+/// [`Session::sync_snapshot`](crate::Session::sync_snapshot).
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct SyncSnapshot {
+    /// The number of messages in the mailbox.
+    pub messages: u32,
+
+    /// The next unique identifier value, if the server reported it.
+    pub uid_next: Option<Uid>,
+
+    /// The unique identifier validity value, if the server reported it. If it changed, all
+    /// cached [`Uid`]s of the mailbox are invalid.
+    pub uid_validity: Option<u32>,
+
+    /// The highest mod-sequence value of all messages in the mailbox, see the [`CONDSTORE`
+    /// extension](https://tools.ietf.org/html/rfc7162#section-3.1.1). `None` if the server does
+    /// not support `CONDSTORE`.
+    pub highest_modseq: Option<u64>,
+}
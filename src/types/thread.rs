@@ -0,0 +1,143 @@
+/// A message and the replies to it, as returned by [`Session::thread`](crate::Session::thread)
+/// from the [`THREAD` response](https://tools.ietf.org/html/rfc5256#section-4).
+///
+/// `(3 6 (4 23)(44 7 96))` is message 3, which has a single reply 6, which in turn has the two
+/// replies 4 and 44, and so on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Thread {
+    /// The [`Seq`](super::Seq) or [`Uid`](super::Uid) of the message, or `None` if the message
+    /// the replies refer to is not in the mailbox.
+    pub id: Option<u32>,
+    /// The replies to the message.
+    pub children: Vec<Thread>,
+}
+
+impl Thread {
+    /// The ids of all messages in the thread, depth-first.
+    pub fn ids(&self) -> Vec<u32> {
+        let mut ids = Vec::new();
+        self.collect_ids(&mut ids);
+        ids
+    }
+
+    fn collect_ids(&self, ids: &mut Vec<u32>) {
+        ids.extend(self.id);
+        for child in &self.children {
+            child.collect_ids(ids);
+        }
+    }
+
+    /// Parses the threads of a `THREAD` response, without the leading `THREAD`. Returns `None` if
+    /// they are malformed.
+    pub(crate) fn parse_list(data: &[u8]) -> Option<Vec<Thread>> {
+        let mut pos = 0;
+        let mut threads = Vec::new();
+        while pos < data.len() {
+            match data[pos] {
+                b' ' => pos += 1,
+                b'(' => threads.push(Self::parse(data, &mut pos)?),
+                _ => return None,
+            }
+        }
+        Some(threads)
+    }
+
+    /// Parses a parenthesized thread starting at `pos`.
+    fn parse(data: &[u8], pos: &mut usize) -> Option<Thread> {
+        *pos += 1;
+        let mut ids = Vec::new();
+        let mut children = Vec::new();
+        loop {
+            match *data.get(*pos)? {
+                b')' => {
+                    *pos += 1;
+                    break;
+                }
+                b' ' => *pos += 1,
+                b'(' => children.push(Self::parse(data, pos)?),
+                b'0'..=b'9' if children.is_empty() => {
+                    let start = *pos;
+                    while matches!(data.get(*pos), Some(b) if b.is_ascii_digit()) {
+                        *pos += 1;
+                    }
+                    ids.push(std::str::from_utf8(&data[start..*pos]).ok()?.parse().ok()?);
+                }
+                _ => return None,
+            }
+        }
+
+        // Each id is the only reply to the one before it, the last one has the branches.
+        let mut thread = Thread {
+            id: ids.pop(),
+            children,
+        };
+        for id in ids.into_iter().rev() {
+            thread = Thread {
+                id: Some(id),
+                children: vec![thread],
+            };
+        }
+        Some(thread)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(id: u32) -> Thread {
+        Thread {
+            id: Some(id),
+            children: Vec::new(),
+        }
+    }
+
+    fn node(id: u32, children: Vec<Thread>) -> Thread {
+        Thread {
+            id: Some(id),
+            children,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let threads = Thread::parse_list(b"(2)(3 6 (4 23)(44 7 96))").unwrap();
+        assert_eq!(
+            threads,
+            vec![
+                leaf(2),
+                node(
+                    3,
+                    vec![node(
+                        6,
+                        vec![
+                            node(4, vec![leaf(23)]),
+                            node(44, vec![node(7, vec![leaf(96)])])
+                        ]
+                    )]
+                ),
+            ]
+        );
+        assert_eq!(threads[1].ids(), vec![3, 6, 4, 23, 44, 7, 96]);
+    }
+
+    #[test]
+    fn missing_parent() {
+        let threads = Thread::parse_list(b"((3)(5))").unwrap();
+        assert_eq!(
+            threads,
+            vec![Thread {
+                id: None,
+                children: vec![leaf(3), leaf(5)],
+            }]
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(Thread::parse_list(b""), Some(Vec::new()));
+        assert_eq!(Thread::parse_list(b"(1 2"), None);
+        assert_eq!(Thread::parse_list(b"(1 (2) 3)"), None);
+        assert_eq!(Thread::parse_list(b"1"), None);
+    }
+}
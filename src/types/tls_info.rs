@@ -0,0 +1,93 @@
+use async_native_tls::TlsStream;
+use async_std::io::{Read, Write};
+use async_std::net::TcpStream;
+
+/// Details of the TLS session of a connection, see
+/// [`Connection::tls_info`](crate::Connection::tls_info).
+///
+/// Only the peer certificate is included, as `native-tls`, which [`connect`] uses, does not
+/// expose the negotiated protocol version or cipher suite. With another TLS implementation,
+/// these can be read from the stream returned by [`Client::into_inner`](crate::Client::into_inner)
+/// or kept before it is passed to [`Client::new`](crate::Client::new).
+///
+/// [`connect`]: crate::connect
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TlsInfo {
+    /// The DER encoding of the certificate the server presented, from which its subject and
+    /// expiry can be read.
+    pub peer_certificate: Option<Vec<u8>>,
+}
+
+/// A stream that may carry a TLS session, so that its details can be read with
+/// [`Connection::tls_info`](crate::Connection::tls_info).
+///
+/// This is implemented for the `native-tls` stream returned by [`connect`], and for plain TCP
+/// streams, which return `None`. Implement it for other streams passed to
+/// [`Client::new`](crate::Client::new), e.g. from `rustls`:
+///
+/// ```ignore
+/// impl TlsPeerInfo for MyRustlsStream {
+///     fn tls_info(&self) -> Option<TlsInfo> {
+///         let (_, conn) = self.get_ref();
+///         Some(TlsInfo {
+///             peer_certificate: conn
+///                 .peer_certificates()
+///                 .and_then(|certs| certs.first())
+///                 .map(|cert| cert.0.clone()),
+///         })
+///     }
+/// }
+/// ```
+///
+/// [`connect`]: crate::connect
+pub trait TlsPeerInfo {
+    /// The details of the TLS session, or `None` if the stream is not encrypted.
+    fn tls_info(&self) -> Option<TlsInfo>;
+}
+
+impl<S: Read + Write + Unpin> TlsPeerInfo for TlsStream<S> {
+    fn tls_info(&self) -> Option<TlsInfo> {
+        let peer_certificate = self
+            .peer_certificate()
+            .ok()
+            .flatten()
+            .and_then(|cert| cert.to_der().ok());
+        Some(TlsInfo { peer_certificate })
+    }
+}
+
+impl TlsPeerInfo for TcpStream {
+    fn tls_info(&self) -> Option<TlsInfo> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_stream::{tls_acceptor, tls_connector, TLS_CERT};
+    use crate::Client;
+    use async_std::net::TcpListener;
+    use async_std::task;
+
+    #[async_std::test]
+    async fn tls_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            tls_acceptor().await.accept(stream).await.unwrap()
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let stream = tls_connector()
+            .connect("imap.example.org", stream)
+            .await
+            .unwrap();
+        server.await;
+        let client = Client::new(stream);
+        let info = client.tls_info().unwrap();
+        let cert = async_native_tls::Certificate::from_pem(TLS_CERT).unwrap();
+        assert_eq!(info.peer_certificate, Some(cert.to_der().unwrap()));
+    }
+}
@@ -0,0 +1,92 @@
+use imap_proto::{ResponseCode, UidSetMember};
+
+use super::Uid;
+use crate::error::{Error, ParseError, Result};
+
+/// The most [`Uid`]s the sets of an `APPENDUID` or `COPYUID` code are expanded to, so that a
+/// range such as `1:4294967295` can not make the client allocate without bound.
+const MAX_UIDS: u64 = 1 << 20;
+
+/// The [`APPENDUID` response code](https://tools.ietf.org/html/rfc4315#section-3) of the
+/// `UIDPLUS` extension, returned by [`Session::append`](crate::Session::append).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AppendUid {
+    /// The `UIDVALIDITY` of the mailbox the message was appended to. If it differs from the one
+    /// cached for that mailbox, the cached [`Uid`]s of the mailbox are invalid.
+    pub uid_validity: u32,
+    /// The [`Uid`]s of the appended messages.
+    pub uids: Vec<Uid>,
+}
+
+/// The [`COPYUID` response code](https://tools.ietf.org/html/rfc4315#section-3) of the
+/// `UIDPLUS` extension, returned by [`Session::copy`](crate::Session::copy) and
+/// [`Session::mv`](crate::Session::mv).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CopyUid {
+    /// The `UIDVALIDITY` of the destination mailbox. If it differs from the one cached for that
+    /// mailbox, the cached [`Uid`]s of the mailbox are invalid.
+    pub uid_validity: u32,
+    /// The [`Uid`]s of the copied messages in the source mailbox.
+    pub source: Vec<Uid>,
+    /// The [`Uid`]s of the copies in the destination mailbox, in the same order as `source`.
+    pub destination: Vec<Uid>,
+}
+
+impl AppendUid {
+    /// The `APPENDUID` of `code`, if it is one. Fails if it has more than [`MAX_UIDS`] UIDs.
+    pub(crate) fn from_code(code: &ResponseCode<'_>) -> Result<Option<Self>> {
+        Ok(match code {
+            ResponseCode::AppendUid(uid_validity, uids) => Some(AppendUid {
+                uid_validity: *uid_validity,
+                uids: uid_list(uids)?,
+            }),
+            _ => None,
+        })
+    }
+}
+
+impl CopyUid {
+    /// The `COPYUID` of `code`, if it is one. Fails if a set has more than [`MAX_UIDS`] UIDs.
+    pub(crate) fn from_code(code: &ResponseCode<'_>) -> Result<Option<Self>> {
+        Ok(match code {
+            ResponseCode::CopyUid(uid_validity, source, destination) => Some(CopyUid {
+                uid_validity: *uid_validity,
+                source: uid_list(source)?,
+                destination: uid_list(destination)?,
+            }),
+            _ => None,
+        })
+    }
+
+    /// Pairs each source [`Uid`] with the [`Uid`] of its copy.
+    pub fn pairs(&self) -> impl Iterator<Item = (Uid, Uid)> + '_ {
+        self.source
+            .iter()
+            .copied()
+            .zip(self.destination.iter().copied())
+    }
+}
+
+/// The [`Uid`]s of a set, in the order the server sent them.
+fn uid_list(members: &[UidSetMember]) -> Result<Vec<Uid>> {
+    let mut uids = Vec::new();
+    for member in members {
+        let len = match member {
+            UidSetMember::Uid(_) => 1,
+            UidSetMember::UidRange(range) => {
+                u64::from(*range.end()).saturating_sub(u64::from(*range.start())) + 1
+            }
+        };
+        if uids.len() as u64 + len > MAX_UIDS {
+            return Err(Error::Parse(ParseError::Unexpected(format!(
+                "UID set with more than {} UIDs",
+                MAX_UIDS
+            ))));
+        }
+        match member {
+            UidSetMember::Uid(uid) => uids.push(*uid),
+            UidSetMember::UidRange(range) => uids.extend(range.clone()),
+        }
+    }
+    Ok(uids)
+}
@@ -0,0 +1,12 @@
+/// What happens to the unsolicited responses of a session, see
+/// [`Client::set_unsolicited_policy`](crate::Client::set_unsolicited_policy).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum UnsolicitedPolicy {
+    /// Keep the responses in [`Session::unsolicited_responses`](crate::Session) until they are
+    /// received, dropping the oldest one once the channel is full. This is the default.
+    #[default]
+    Buffer,
+    /// Drop the responses right away, for sessions that never receive them. They are counted in
+    /// [`Session::unsolicited_dropped`](crate::Session::unsolicited_dropped).
+    Drop,
+}
@@ -0,0 +1,477 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_std::channel;
+use imap_proto::{AttributeValue, Response, ResponseCode};
+
+use super::{Capabilities, Fetch, Seq, SeqMap, Uid, UnsolicitedPolicy, UnsolicitedResponse};
+use crate::parse::parse_untagged_capabilities;
+
+/// The number of unsolicited responses a session keeps by default, see
+/// [`Client::set_unsolicited_capacity`](crate::Client::set_unsolicited_capacity).
+pub(crate) const DEFAULT_UNSOLICITED_CAPACITY: usize = 100;
+
+/// The sending half of the bounded channel that unsolicited responses are passed on with.
+///
+/// Sending never waits for the receiver: once the channel is full, the oldest response in it is
+/// dropped to make room and counted, so that a slow or absent consumer cannot stall the
+/// connection or make the channel grow without limit. Responses sent after the receiver closed
+/// the channel are counted as dropped as well, and so are all responses with
+/// [`UnsolicitedPolicy::Drop`].
+///
+/// Every response is also copied to the channels returned by [`UnsolicitedSender::subscribe`],
+/// each of which drops its oldest response once it is full in the same way.
+#[derive(Debug, Clone)]
+pub struct UnsolicitedSender {
+    tx: channel::Sender<UnsolicitedResponse>,
+    /// Used to take the oldest response out of a full channel.
+    rx: channel::Receiver<UnsolicitedResponse>,
+    counters: Arc<Counters>,
+}
+
+/// What an [`UnsolicitedSender`] keeps track of, shared with its clones.
+#[derive(Debug, Default)]
+struct Counters {
+    /// The number of responses dropped from the channel of the session, see
+    /// [`UnsolicitedSender::dropped`].
+    dropped: AtomicU64,
+    /// The number of responses dropped from the channels of the subscribers, see
+    /// [`UnsolicitedSender::subscribers_dropped`].
+    subscribers_dropped: AtomicU64,
+    /// Whether responses are dropped right away, see [`UnsolicitedPolicy::Drop`].
+    discard: AtomicBool,
+    /// The number of responses sent that report messages added to or expunged from the selected
+    /// mailbox.
+    changes: AtomicU64,
+    /// The `UIDVALIDITY` of the selected mailbox, or 0 if it is not known.
+    uid_validity: AtomicU64,
+    /// The number of messages in the selected mailbox, as of the last `EXISTS` minus the
+    /// messages expunged since.
+    exists: AtomicU32,
+    /// The number of `EXISTS` responses sent.
+    exists_updates: AtomicU64,
+    /// The [`Uid`](super::Uid)s of the messages in the selected mailbox, if they are tracked, see
+    /// [`UnsolicitedSender::set_track_uids`].
+    seq_map: Mutex<Option<SeqMap>>,
+    /// The capabilities the server announced last in an untagged `CAPABILITY`, until the session
+    /// takes them.
+    capabilities: Mutex<Option<Capabilities>>,
+    /// The channels of the subscribers, see [`UnsolicitedSender::subscribe`].
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+/// A channel that gets a copy of every response.
+#[derive(Debug)]
+struct Subscriber {
+    tx: channel::Sender<UnsolicitedResponse>,
+    /// Used to take the oldest response out of a full channel.
+    rx: channel::Receiver<UnsolicitedResponse>,
+}
+
+impl UnsolicitedSender {
+    /// Creates a channel that holds up to `capacity` responses, at least one.
+    pub(crate) fn new(capacity: usize) -> (Self, channel::Receiver<UnsolicitedResponse>) {
+        let (tx, rx) = channel::bounded(capacity.max(1));
+        let sender = UnsolicitedSender {
+            tx,
+            rx: rx.clone(),
+            counters: Arc::default(),
+        };
+        (sender, rx)
+    }
+
+    /// Passes on `response`, dropping the oldest response if the channel is full. Capabilities
+    /// are kept for the session instead, see [`UnsolicitedSender::take_capabilities`].
+    pub(crate) fn send(&self, response: UnsolicitedResponse) {
+        if let UnsolicitedResponse::Other(data) = &response {
+            if let Some(capabilities) = parse_untagged_capabilities(data) {
+                *self.counters.capabilities.lock().unwrap() = Some(capabilities);
+                return;
+            }
+        }
+        if changes_messages(&response) {
+            self.counters.changes.fetch_add(1, Ordering::Relaxed);
+        }
+        self.count_exists(&response);
+        self.track_uids(&response);
+        if let Some(uid_validity) = uid_validity(&response) {
+            let counter = &self.counters.uid_validity;
+            counter.store(u64::from(uid_validity), Ordering::Relaxed);
+            // The known `Uid`s are no longer valid.
+            self.reset_seq_map();
+        }
+        {
+            let mut subscribers = self.counters.subscribers.lock().unwrap();
+            // Forget the subscribers that dropped their receivers, leaving only ours.
+            subscribers.retain(|subscriber| subscriber.tx.receiver_count() > 1);
+            let dropped = &self.counters.subscribers_dropped;
+            for subscriber in subscribers.iter() {
+                push(&subscriber.tx, &subscriber.rx, dropped, response.clone());
+            }
+        }
+        if self.counters.discard.load(Ordering::Relaxed) {
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        push(&self.tx, &self.rx, &self.counters.dropped, response);
+    }
+
+    /// Creates another channel that gets a copy of every response sent from now on, by this
+    /// sender and its clones, so that several consumers can each receive all of them. It holds
+    /// as many responses as this channel; once it is full, its oldest response is dropped and
+    /// counted in [`UnsolicitedSender::subscribers_dropped`]. It is closed once the session is
+    /// dropped.
+    ///
+    /// Subscribers receive the responses whatever the [`UnsolicitedPolicy`], and are forgotten
+    /// once their receivers are dropped.
+    pub fn subscribe(&self) -> channel::Receiver<UnsolicitedResponse> {
+        let (tx, rx) = channel::bounded(self.capacity());
+        let subscriber = Subscriber { tx, rx: rx.clone() };
+        self.counters.subscribers.lock().unwrap().push(subscriber);
+        rx
+    }
+
+    /// The number of responses the channel holds.
+    pub fn capacity(&self) -> usize {
+        self.tx.capacity().unwrap_or(DEFAULT_UNSOLICITED_CAPACITY)
+    }
+
+    /// The number of responses that were dropped because the channel was full or closed, or
+    /// because of [`UnsolicitedPolicy::Drop`].
+    pub fn dropped(&self) -> u64 {
+        self.counters.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The number of responses that were dropped from the channels of all subscribers because
+    /// they were full or closed, see [`UnsolicitedSender::subscribe`].
+    pub fn subscribers_dropped(&self) -> u64 {
+        self.counters.subscribers_dropped.load(Ordering::Relaxed)
+    }
+
+    /// What happens to the responses sent, shared with the clones of this sender.
+    pub fn policy(&self) -> UnsolicitedPolicy {
+        if self.counters.discard.load(Ordering::Relaxed) {
+            UnsolicitedPolicy::Drop
+        } else {
+            UnsolicitedPolicy::Buffer
+        }
+    }
+
+    /// Sets what happens to the responses sent from now on, by this sender and its clones.
+    pub fn set_policy(&self, policy: UnsolicitedPolicy) {
+        let discard = policy == UnsolicitedPolicy::Drop;
+        self.counters.discard.store(discard, Ordering::Relaxed);
+    }
+
+    /// The number of `EXISTS`, `EXPUNGE` and `VANISHED` responses sent, whether they were
+    /// dropped or not, and of messages [expunged](Self::expunged) by an `EXPUNGE` command.
+    pub(crate) fn changes(&self) -> u64 {
+        self.counters.changes.load(Ordering::Relaxed)
+    }
+
+    /// Keeps the number of messages in the selected mailbox up to date with `response`.
+    fn count_exists(&self, response: &UnsolicitedResponse) {
+        let expunged = match response {
+            UnsolicitedResponse::Exists(exists) => {
+                self.counters.exists.store(*exists, Ordering::Relaxed);
+                self.counters.exists_updates.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            UnsolicitedResponse::Expunge(_) => 1,
+            // `VANISHED (EARLIER)` reports messages that were already gone when the mailbox
+            // was selected.
+            UnsolicitedResponse::Other(response) => match response.parsed() {
+                Response::Vanished {
+                    earlier: false,
+                    uids,
+                } => uids
+                    .iter()
+                    .map(|uids| u64::from(*uids.end()).saturating_sub(u64::from(*uids.start())) + 1)
+                    .sum(),
+                _ => return,
+            },
+            _ => return,
+        };
+        let exists = &self.counters.exists;
+        let _ = exists.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |exists| {
+            Some(u64::from(exists).saturating_sub(expunged) as u32)
+        });
+    }
+
+    /// Keeps the tracked [`Uid`](super::Uid)s of the selected mailbox up to date with `response`.
+    fn track_uids(&self, response: &UnsolicitedResponse) {
+        let mut seq_map = self.counters.seq_map.lock().unwrap();
+        let seq_map = match seq_map.as_mut() {
+            Some(seq_map) => seq_map,
+            None => return,
+        };
+        match response {
+            UnsolicitedResponse::Exists(exists) => seq_map.exists(*exists),
+            UnsolicitedResponse::Expunge(seq) => {
+                seq_map.expunge(*seq);
+            }
+            UnsolicitedResponse::Other(response) => match response.parsed() {
+                Response::Vanished {
+                    earlier: false,
+                    uids,
+                } => {
+                    for uid in uids.iter().flat_map(|uids| uids.clone()) {
+                        seq_map.vanish(uid);
+                    }
+                }
+                Response::Fetch(seq, attributes) => {
+                    for attribute in attributes {
+                        if let AttributeValue::Uid(uid) = attribute {
+                            seq_map.insert(*seq, *uid);
+                        }
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Keeps track of message `seq` being expunged in response to an `EXPUNGE` command, which is
+    /// not passed on but changes the messages of the mailbox all the same.
+    pub(crate) fn expunged(&self, seq: Seq) {
+        let response = UnsolicitedResponse::Expunge(seq);
+        self.counters.changes.fetch_add(1, Ordering::Relaxed);
+        self.count_exists(&response);
+        self.track_uids(&response);
+    }
+
+    /// Records the [`Uid`](super::Uid) of a message fetched by a command, if it is tracked.
+    pub(crate) fn fetched(&self, fetch: &Fetch) {
+        if let (Some(seq_map), Some(uid)) =
+            (self.counters.seq_map.lock().unwrap().as_mut(), fetch.uid)
+        {
+            seq_map.insert(fetch.message, uid);
+        }
+    }
+
+    /// Sets whether the [`Uid`](super::Uid)s of the messages in the selected mailbox are kept
+    /// track of, see [`Session::set_track_uids`](crate::Session::set_track_uids).
+    pub(crate) fn set_track_uids(&self, track: bool) {
+        let mut seq_map = self.counters.seq_map.lock().unwrap();
+        match (track, seq_map.is_some()) {
+            (true, false) => *seq_map = Some(SeqMap::new(self.exists())),
+            (false, true) => *seq_map = None,
+            _ => {}
+        }
+    }
+
+    /// Whether the [`Uid`](super::Uid)s of the messages in the selected mailbox are tracked.
+    pub(crate) fn tracks_uids(&self) -> bool {
+        self.counters.seq_map.lock().unwrap().is_some()
+    }
+
+    /// The tracked [`Uid`](super::Uid) of message `seq`, see [`SeqMap::uid`].
+    pub(crate) fn uid(&self, seq: Seq) -> Option<Uid> {
+        self.counters.seq_map.lock().unwrap().as_ref()?.uid(seq)
+    }
+
+    /// The sequence number of the message with the tracked `uid`, see [`SeqMap::seq`].
+    pub(crate) fn seq(&self, uid: Uid) -> Option<Seq> {
+        self.counters.seq_map.lock().unwrap().as_ref()?.seq(uid)
+    }
+
+    /// Forgets the tracked [`Uid`](super::Uid)s, e.g. because another mailbox was selected.
+    fn reset_seq_map(&self) {
+        if let Some(seq_map) = self.counters.seq_map.lock().unwrap().as_mut() {
+            *seq_map = SeqMap::new(self.exists());
+        }
+    }
+
+    /// The number of messages in the selected mailbox.
+    pub(crate) fn exists(&self) -> u32 {
+        self.counters.exists.load(Ordering::Relaxed)
+    }
+
+    /// The number of `EXISTS` responses sent, whether they were dropped or not.
+    pub(crate) fn exists_updates(&self) -> u64 {
+        self.counters.exists_updates.load(Ordering::Relaxed)
+    }
+
+    /// Sets the number of messages and the `UIDVALIDITY` of the selected mailbox once it was
+    /// selected, forgetting the tracked [`Uid`](super::Uid)s of the one before.
+    pub(crate) fn set_selected(&self, exists: u32, uid_validity: Option<u32>) {
+        self.counters.exists.store(exists, Ordering::Relaxed);
+        let uid_validity = u64::from(uid_validity.unwrap_or_default());
+        self.counters
+            .uid_validity
+            .store(uid_validity, Ordering::Relaxed);
+        self.reset_seq_map();
+    }
+
+    /// The capabilities the server announced since this was last called, e.g. after a change of
+    /// its configuration.
+    pub(crate) fn take_capabilities(&self) -> Option<Capabilities> {
+        self.counters.capabilities.lock().unwrap().take()
+    }
+
+    /// The `UIDVALIDITY` of the selected mailbox, as it was when the mailbox was selected or as
+    /// last sent in an untagged `OK` since, which servers send when it changes while the mailbox
+    /// is selected.
+    pub(crate) fn uid_validity(&self) -> Option<u32> {
+        match self.counters.uid_validity.load(Ordering::Relaxed) {
+            0 => None,
+            uid_validity => Some(uid_validity as u32),
+        }
+    }
+}
+
+/// The `UIDVALIDITY` of an untagged `OK [UIDVALIDITY n]`.
+fn uid_validity(response: &UnsolicitedResponse) -> Option<u32> {
+    match response {
+        UnsolicitedResponse::Other(response) => match response.parsed() {
+            Response::Data {
+                code: Some(ResponseCode::UidValidity(uid_validity)),
+                ..
+            } => Some(*uid_validity),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Sends `response` on `tx`, dropping the oldest response in it if it is full, and counts the
+/// responses dropped in `dropped`.
+fn push(
+    tx: &channel::Sender<UnsolicitedResponse>,
+    rx: &channel::Receiver<UnsolicitedResponse>,
+    dropped: &AtomicU64,
+    mut response: UnsolicitedResponse,
+) {
+    loop {
+        match tx.try_send(response) {
+            Err(channel::TrySendError::Full(rejected)) => {
+                if rx.try_recv().is_ok() {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                response = rejected;
+            }
+            // The consumer closed the channel, see `Receiver::close`.
+            Err(channel::TrySendError::Closed(_)) => {
+                dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            Ok(()) => return,
+        }
+    }
+}
+
+/// Whether `response` reports messages added to or expunged from the selected mailbox.
+fn changes_messages(response: &UnsolicitedResponse) -> bool {
+    match response {
+        UnsolicitedResponse::Exists(_) | UnsolicitedResponse::Expunge(_) => true,
+        UnsolicitedResponse::Other(response) => {
+            matches!(response.parsed(), Response::Vanished { .. })
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_oldest() {
+        let (tx, rx) = UnsolicitedSender::new(2);
+        for n in 1..=5 {
+            tx.send(UnsolicitedResponse::Exists(n));
+        }
+        assert_eq!(tx.dropped(), 3);
+        assert_eq!(rx.try_recv().unwrap(), UnsolicitedResponse::Exists(4));
+        assert_eq!(rx.try_recv().unwrap(), UnsolicitedResponse::Exists(5));
+        assert!(rx.try_recv().is_err());
+
+        let clone = tx.clone();
+        assert_eq!(clone.capacity(), 2);
+        clone.send(UnsolicitedResponse::Recent(1));
+        clone.send(UnsolicitedResponse::Recent(2));
+        clone.send(UnsolicitedResponse::Recent(3));
+        assert_eq!(tx.dropped(), 4);
+    }
+
+    #[test]
+    fn changes() {
+        let (tx, _rx) = UnsolicitedSender::new(1);
+        tx.send(UnsolicitedResponse::Recent(1));
+        assert_eq!(tx.changes(), 0);
+        tx.send(UnsolicitedResponse::Exists(2));
+        tx.send(UnsolicitedResponse::Expunge(1));
+        assert_eq!(tx.changes(), 2);
+        let clone = tx.clone();
+        clone.send(UnsolicitedResponse::Exists(1));
+        assert_eq!(tx.changes(), 3);
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let (tx, rx) = UnsolicitedSender::new(0);
+        tx.send(UnsolicitedResponse::Exists(1));
+        tx.send(UnsolicitedResponse::Exists(2));
+        assert_eq!(tx.capacity(), 1);
+        assert_eq!(tx.dropped(), 1);
+        assert_eq!(rx.try_recv().unwrap(), UnsolicitedResponse::Exists(2));
+    }
+
+    #[test]
+    fn drop_policy() {
+        let (tx, rx) = UnsolicitedSender::new(2);
+        tx.set_policy(UnsolicitedPolicy::Drop);
+        tx.send(UnsolicitedResponse::Exists(1));
+        tx.send(UnsolicitedResponse::Expunge(1));
+        assert_eq!(tx.dropped(), 2);
+        assert_eq!(tx.changes(), 2);
+        assert!(rx.try_recv().is_err());
+
+        let clone = tx.clone();
+        assert_eq!(clone.policy(), UnsolicitedPolicy::Drop);
+        clone.set_policy(UnsolicitedPolicy::Buffer);
+        tx.send(UnsolicitedResponse::Exists(3));
+        assert_eq!(rx.try_recv().unwrap(), UnsolicitedResponse::Exists(3));
+    }
+
+    #[test]
+    fn subscribe() {
+        let (tx, rx) = UnsolicitedSender::new(2);
+        let first = tx.subscribe();
+        let clone = tx.clone();
+        let second = clone.subscribe();
+        tx.set_policy(UnsolicitedPolicy::Drop);
+        for n in 1..=3 {
+            clone.send(UnsolicitedResponse::Exists(n));
+        }
+        assert!(rx.try_recv().is_err());
+        for subscriber in &[&first, &second] {
+            assert_eq!(
+                subscriber.try_recv().unwrap(),
+                UnsolicitedResponse::Exists(2)
+            );
+            assert_eq!(
+                subscriber.try_recv().unwrap(),
+                UnsolicitedResponse::Exists(3)
+            );
+        }
+        // One for each full subscriber, and three for the policy, counted apart.
+        assert_eq!(tx.subscribers_dropped(), 2);
+        assert_eq!(tx.dropped(), 3);
+
+        drop(first);
+        tx.send(UnsolicitedResponse::Recent(1));
+        assert_eq!(tx.counters.subscribers.lock().unwrap().len(), 1);
+        assert_eq!(second.try_recv().unwrap(), UnsolicitedResponse::Recent(1));
+    }
+
+    #[test]
+    fn closed() {
+        let (tx, rx) = UnsolicitedSender::new(2);
+        rx.close();
+        tx.send(UnsolicitedResponse::Exists(1));
+        tx.send(UnsolicitedResponse::Exists(2));
+        assert_eq!(tx.dropped(), 2);
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+
+/// Which way the bytes shown to a wire tracer went, see
+/// [`Connection::set_wire_tracer`](crate::Connection::set_wire_tracer).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// A command line sent to the server.
+    Sent,
+    /// A response received from the server.
+    Received,
+}
+
+/// What credentials are replaced with in traced commands.
+const REDACTED: &[u8] = b"<redacted>";
+
+type Trace = dyn FnMut(Direction, &[u8]) + Send + Sync;
+
+/// A function that is shown the traffic of a connection, with credentials redacted.
+pub(crate) struct WireTracer {
+    trace: Box<Trace>,
+    /// Whether the last tagged command was `AUTHENTICATE`, so that untagged lines are responses
+    /// to its challenges.
+    authenticating: bool,
+}
+
+impl WireTracer {
+    pub(crate) fn new<F>(trace: F) -> Self
+    where
+        F: FnMut(Direction, &[u8]) + Send + Sync + 'static,
+    {
+        WireTracer {
+            trace: Box::new(trace),
+            authenticating: false,
+        }
+    }
+
+    /// Traces a command line, with the arguments of `LOGIN` and the responses of `AUTHENTICATE`
+    /// redacted.
+    pub(crate) fn sent(&mut self, tag: Option<&str>, data: &[u8]) {
+        let mut line = Vec::with_capacity(data.len() + 16);
+        if let Some(tag) = tag {
+            line.extend_from_slice(tag.as_bytes());
+            line.push(b' ');
+            self.authenticating = verb(data).eq_ignore_ascii_case(b"AUTHENTICATE");
+            redact_command(data, &mut line);
+        } else if self.authenticating && data != b"*" {
+            line.extend_from_slice(REDACTED);
+        } else {
+            line.extend_from_slice(data);
+        }
+        line.extend_from_slice(b"\r\n");
+        (self.trace)(Direction::Sent, &line);
+    }
+
+    /// Traces a response as it was received.
+    pub(crate) fn received(&mut self, data: &[u8]) {
+        (self.trace)(Direction::Received, data);
+    }
+}
+
+impl fmt::Debug for WireTracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WireTracer")
+            .field("authenticating", &self.authenticating)
+            .finish()
+    }
+}
+
+/// The first word of `command`.
+fn verb(command: &[u8]) -> &[u8] {
+    command.split(|&b| b == b' ').next().unwrap_or_default()
+}
+
+/// Appends `command` to `line`, with the arguments of `LOGIN` and an initial response of
+/// `AUTHENTICATE` redacted.
+fn redact_command(command: &[u8], line: &mut Vec<u8>) {
+    let mut words = command.splitn(3, |&b| b == b' ');
+    let verb = words.next().unwrap_or_default();
+    // The length of the part that is kept.
+    let kept = match (words.next(), words.next()) {
+        (Some(_), _) if verb.eq_ignore_ascii_case(b"LOGIN") => Some(verb.len() + 1),
+        (Some(mechanism), Some(_)) if verb.eq_ignore_ascii_case(b"AUTHENTICATE") => {
+            Some(verb.len() + mechanism.len() + 2)
+        }
+        _ => None,
+    };
+    match kept {
+        Some(kept) => {
+            line.extend_from_slice(&command[..kept]);
+            line.extend_from_slice(REDACTED);
+        }
+        None => line.extend_from_slice(command),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    fn traced(lines: &[(Option<&str>, &str)]) -> Vec<String> {
+        let traced = Arc::new(Mutex::new(Vec::new()));
+        let mut tracer = WireTracer::new({
+            let traced = traced.clone();
+            move |direction, data: &[u8]| {
+                assert_eq!(direction, Direction::Sent);
+                traced
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(data.to_vec()).unwrap());
+            }
+        });
+        for (tag, data) in lines {
+            tracer.sent(*tag, data.as_bytes());
+        }
+        drop(tracer);
+        Arc::try_unwrap(traced).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn redact() {
+        assert_eq!(
+            traced(&[
+                (Some("A1"), "login \"user\" \"secret\""),
+                (Some("A2"), "AUTHENTICATE PLAIN"),
+                (None, "AHVzZXIAc2VjcmV0"),
+                (None, "*"),
+                (Some("A3"), "AUTHENTICATE PLAIN AHVzZXIAc2VjcmV0"),
+                (Some("A4"), "LOGINX foo"),
+                (Some("A5"), "SELECT LOGIN"),
+                (None, "DONE"),
+            ]),
+            vec![
+                "A1 login <redacted>\r\n",
+                "A2 AUTHENTICATE PLAIN\r\n",
+                "<redacted>\r\n",
+                "*\r\n",
+                "A3 AUTHENTICATE PLAIN <redacted>\r\n",
+                "A4 LOGINX foo\r\n",
+                "A5 SELECT LOGIN\r\n",
+                "DONE\r\n",
+            ]
+        );
+    }
+}
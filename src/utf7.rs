@@ -0,0 +1,92 @@
+//! Modified UTF-7, the encoding of mailbox names (and Gmail labels) in IMAP, as defined in
+//! [section 5.1.3 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-5.1.3).
+
+/// Encodes `s` in modified UTF-7.
+pub(crate) fn encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut pending: Vec<u16> = Vec::new();
+    for c in s.chars() {
+        if (' '..='~').contains(&c) {
+            flush(&mut out, &mut pending);
+            if c == '&' {
+                out.push_str("&-");
+            } else {
+                out.push(c);
+            }
+        } else {
+            let mut buf = [0u16; 2];
+            pending.extend_from_slice(c.encode_utf16(&mut buf));
+        }
+    }
+    flush(&mut out, &mut pending);
+
+    out
+}
+
+fn flush(out: &mut String, pending: &mut Vec<u16>) {
+    if pending.is_empty() {
+        return;
+    }
+    let bytes: Vec<u8> = pending.iter().flat_map(|unit| unit.to_be_bytes()).collect();
+    out.push('&');
+    out.push_str(&base64::encode_config(&bytes, base64::IMAP_MUTF7));
+    out.push('-');
+    pending.clear();
+}
+
+/// Decodes modified UTF-7, returning `None` if `s` is not validly encoded.
+pub(crate) fn decode(s: &str) -> Option<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest.find('-')?;
+        let encoded = &rest[..end];
+        rest = &rest[end + 1..];
+
+        if encoded.is_empty() {
+            out.push('&');
+            continue;
+        }
+        let bytes = base64::decode_config(encoded, base64::IMAP_MUTF7).ok()?;
+        if bytes.len() % 2 != 0 {
+            return None;
+        }
+        let units: Vec<u16> = bytes
+            .chunks(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+            .collect();
+        out.push_str(&String::from_utf16(&units).ok()?);
+    }
+    out.push_str(rest);
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        for (decoded, encoded) in &[
+            ("INBOX", "INBOX"),
+            ("Entwürfe", "Entw&APw-rfe"),
+            ("Tom & Jerry", "Tom &- Jerry"),
+            ("日本語", "&ZeVnLIqe-"),
+            ("~peter/mail/台北/日本語", "~peter/mail/&U,BTFw-/&ZeVnLIqe-"),
+            ("📧", "&2D3c5w-"),
+        ] {
+            assert_eq!(encode(decoded), *encoded);
+            assert_eq!(decode(encoded).as_deref(), Some(*decoded));
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode("&Jjo"), None);
+        assert_eq!(decode("&*-"), None);
+        assert_eq!(decode("&AA-"), None);
+    }
+}